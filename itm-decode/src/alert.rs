@@ -0,0 +1,168 @@
+//! Alerts on watched data values: a line is output to stderr for each
+//! fired `--alert` expression, or with `--alert-exec`, a command is run
+//! through the shell with the alert in its environment: `ITM_ALERT`,
+//! the alert line, `ITM_COMPARATOR`, `ITM_VALUE`, and if any,
+//! `ITM_PREVIOUS` and `ITM_TIME`, the trace time in seconds. Commands
+//! run in the background while decoding continues.
+
+use anyhow::{Context, Result};
+use itm::watch::{Alert, Watch, Watcher};
+use itm::TracePacket;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+pub struct Alerts {
+    watcher: Watcher,
+    exec: Option<String>,
+    /// Commands that were running at the last alert.
+    children: Vec<Child>,
+}
+
+impl Alerts {
+    pub fn new(watches: Vec<Watch>, exec: Option<String>) -> Self {
+        Self {
+            watcher: Watcher::new(watches),
+            exec,
+            children: vec![],
+        }
+    }
+
+    /// Evaluates the watches on `packet` at `time`, if timestamped, and
+    /// outputs the alerts that fire.
+    pub fn push(&mut self, packet: &TracePacket, time: Option<Duration>) -> Result<()> {
+        self.watcher.push(packet);
+        while let Some(alert) = self.watcher.pull() {
+            let line = line(&alert, time);
+            let exec = match &self.exec {
+                Some(exec) => exec,
+                None => {
+                    eprintln!("{}", line);
+                    continue;
+                }
+            };
+            // Reap the commands that have exited.
+            self.children = std::mem::take(&mut self.children)
+                .into_iter()
+                .filter_map(|mut child| match child.try_wait() {
+                    Ok(Some(_)) => None,
+                    _ => Some(child),
+                })
+                .collect();
+            let mut command = shell(exec);
+            command
+                .env("ITM_ALERT", &line)
+                .env("ITM_COMPARATOR", alert.comparator.to_string())
+                .env("ITM_VALUE", alert.value.to_string());
+            if let Some(previous) = alert.previous {
+                command.env("ITM_PREVIOUS", previous.to_string());
+            }
+            if let Some(time) = time {
+                command.env("ITM_TIME", format!("{}", time.as_secs_f64()));
+            }
+            let child = command
+                .spawn()
+                .with_context(|| format!("failed to run --alert-exec command {:?}", exec))?;
+            self.children.push(child);
+        }
+        Ok(())
+    }
+
+    /// Waits for the commands that are still running.
+    pub fn finish(&mut self) -> Result<()> {
+        for mut child in self.children.drain(..) {
+            child
+                .wait()
+                .context("failed to wait for --alert-exec command")?;
+        }
+        Ok(())
+    }
+}
+
+/// The line output for `alert` at `time`, if timestamped.
+fn line(alert: &Alert, time: Option<Duration>) -> String {
+    match time {
+        Some(time) => format!(
+            "alert at {}.{:09}: {}",
+            time.as_secs(),
+            time.subsec_nanos(),
+            alert
+        ),
+        None => format!("alert: {}", alert),
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itm::MemoryAccessType;
+
+    fn value(comparator: u8, value: u16) -> TracePacket {
+        TracePacket::DataTraceValue {
+            comparator,
+            access_type: MemoryAccessType::Write,
+            value: value.to_le_bytes().into(),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let alert = Alert {
+            expression: "value > 1000".to_string(),
+            comparator: 1,
+            value: 1200,
+            previous: Some(999),
+        };
+        assert_eq!(
+            line(&alert, None),
+            "alert: comparator 1 value > 1000: 1200 (was 999)"
+        );
+        assert_eq!(
+            line(&alert, Some(Duration::from_micros(1_500_250))),
+            "alert at 1.500250000: comparator 1 value > 1000: 1200 (was 999)"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec() {
+        let path = std::env::temp_dir().join(format!("itm-decode-alerts-{}", std::process::id()));
+        let mut alerts = Alerts::new(
+            vec!["comparator 1 value > 1000".parse().unwrap()],
+            Some(format!(
+                "echo \"$ITM_ALERT|$ITM_COMPARATOR|$ITM_VALUE|${{ITM_PREVIOUS-none}}|${{ITM_TIME-none}}\" >> {}",
+                path.display()
+            )),
+        );
+        alerts.push(&value(1, 1200), None).unwrap();
+        alerts.finish().unwrap();
+        for (comparator, v) in [(1, 999), (2, 2000), (1, 1001), (1, 1300)] {
+            alerts
+                .push(&value(comparator, v), Some(Duration::from_millis(250)))
+                .unwrap();
+        }
+        alerts.finish().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            concat!(
+                "alert: comparator 1 value > 1000: 1200|1|1200|none|none\n",
+                "alert at 0.250000000: comparator 1 value > 1000: 1001 (was 999)|1|1001|999|0.25\n",
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{bail, Error, Result};
+use itm::cbor::CborWriter;
+use itm::msgpack::MsgpackWriter;
+use itm::wall_clock::WallClock;
+use itm::{CsvWriter, Field, PortMap, Sequenced, Timestamp, TracePacket};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Output format of decoded packets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Aligned, optionally colored columns of each packet's time, kind
+    /// and details. See [`Human`](crate::human::Human).
+    Human,
+
+    /// `Debug` representation of each packet (or set of timestamped
+    /// packets).
+    Debug,
+
+    /// Human-readable description of each packet.
+    Text,
+
+    /// Whitespace-aligned columns.
+    Pretty,
+
+    /// Comma-separated values, with a header row.
+    Csv,
+
+    /// One JSON object per line for each packet (or set of timestamped
+    /// packets), with the field names of their `serde` representation,
+    /// and a `seq` field.
+    Json,
+
+    /// The [`Json`](Format::Json) records as a binary CBOR sequence.
+    Cbor,
+
+    /// The [`Json`](Format::Json) records as consecutive MessagePack
+    /// objects.
+    Msgpack,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "human" => Format::Human,
+            "debug" => Format::Debug,
+            "text" => Format::Text,
+            "pretty" => Format::Pretty,
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            "cbor" => Format::Cbor,
+            "msgpack" => Format::Msgpack,
+            _ => bail!(
+                "{} is not a valid format; valid formats are: human, debug, text, pretty, csv, json, cbor, msgpack",
+                s
+            ),
+        })
+    }
+}
+
+/// Format of the trace input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// The raw trace stream.
+    Raw,
+
+    /// A sigrok session file of the SWO pin.
+    Sigrok,
+
+    /// A Saleae Logic CSV export of the SWO pin.
+    Saleae,
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "raw" => InputFormat::Raw,
+            "sigrok" => InputFormat::Sigrok,
+            "saleae" => InputFormat::Saleae,
+            _ => bail!(
+                "{} is not a valid input format; valid input formats are: raw, sigrok, saleae",
+                s
+            ),
+        })
+    }
+}
+
+/// Writes packets as rows of the selected [`Field`]s.
+pub enum Table<W: Write> {
+    Pretty {
+        out: W,
+        fields: Vec<Field>,
+        ports: PortMap,
+        wall_clock: Option<WallClock>,
+    },
+    Csv(CsvWriter<W>),
+}
+
+impl<W: Write> Table<W> {
+    pub fn new(out: W, format: Format, fields: Vec<Field>, ports: PortMap) -> Self {
+        match format {
+            Format::Pretty => Table::Pretty {
+                out,
+                fields,
+                ports,
+                wall_clock: None,
+            },
+            Format::Csv => Table::Csv(CsvWriter::new(out, fields).ports(ports)),
+            Format::Human
+            | Format::Debug
+            | Format::Text
+            | Format::Json
+            | Format::Cbor
+            | Format::Msgpack => {
+                panic!("{:?} format is not tabular", format)
+            }
+        }
+    }
+
+    /// Outputs the time column as wall-clock times.
+    pub fn wall_clock(self, wall_clock: Option<WallClock>) -> Self {
+        match self {
+            Table::Pretty {
+                out, fields, ports, ..
+            } => Table::Pretty {
+                out,
+                fields,
+                ports,
+                wall_clock,
+            },
+            Table::Csv(csv) => Table::Csv(csv.wall_clock(wall_clock)),
+        }
+    }
+
+    pub fn header(&mut self) -> io::Result<()> {
+        match self {
+            Table::Pretty { fields, .. } => {
+                let names: Vec<String> = fields.iter().map(|f| f.name().to_string()).collect();
+                self.write_pretty(names)
+            }
+            Table::Csv(csv) => csv.header(),
+        }
+    }
+
+    pub fn row(
+        &mut self,
+        seq: u64,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> io::Result<()> {
+        match self {
+            Table::Pretty {
+                fields,
+                ports,
+                wall_clock,
+                ..
+            } => {
+                let values: Vec<String> = fields
+                    .iter()
+                    .map(|f| match (f, &wall_clock, timestamp) {
+                        (Field::Time, Some(wall_clock), Some(ts)) => wall_clock.rfc3339(ts),
+                        _ => f.extract_named(seq, packet, timestamp, ports),
+                    })
+                    .collect();
+                self.write_pretty(values)
+            }
+            Table::Csv(csv) => csv.row(seq, packet, timestamp),
+        }
+    }
+
+    fn write_pretty(&mut self, values: Vec<String>) -> io::Result<()> {
+        let (out, fields) = match self {
+            Table::Pretty { out, fields, .. } => (out, fields),
+            Table::Csv(_) => unreachable!(),
+        };
+        let line = fields
+            .iter()
+            .zip(values)
+            .map(|(f, v)| format!("{:<width$}", v, width = f.width()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(out, "{}", line.trim_end())
+    }
+}
+
+/// A record with the wall-clock time of its timestamp.
+#[derive(Serialize)]
+struct WallClocked<'a, T> {
+    wall_clock: String,
+    #[serde(flatten)]
+    item: &'a T,
+}
+
+/// Writes packets, or sets of timestamped packets, as self-describing
+/// records with a `seq` field: the sequence number of the packet, or of
+/// the first packet of the set.
+pub enum Records<W: Write> {
+    Json(W),
+    Cbor(CborWriter<io::BufWriter<W>>),
+    Msgpack(MsgpackWriter<io::BufWriter<W>>),
+}
+
+impl<W: Write> Records<W> {
+    /// The writer of `format` to `out`, if it is a record format.
+    pub fn new(format: Format, out: W) -> Option<Self> {
+        match format {
+            Format::Json => Some(Records::Json(out)),
+            Format::Cbor => Some(Records::Cbor(CborWriter::new(io::BufWriter::new(out)))),
+            Format::Msgpack => Some(Records::Msgpack(MsgpackWriter::new(io::BufWriter::new(
+                out,
+            )))),
+            Format::Human | Format::Debug | Format::Text | Format::Pretty | Format::Csv => None,
+        }
+    }
+
+    pub fn write<T: Serialize>(&mut self, item: &Sequenced<T>) -> Result<()> {
+        match self {
+            Records::Json(out) => writeln!(out, "{}", serde_json::to_string(item)?)?,
+            Records::Cbor(cbor) => cbor.write(item)?,
+            Records::Msgpack(msgpack) => msgpack.write(item)?,
+        }
+        Ok(())
+    }
+
+    /// Writes `item` with a `wall_clock` field: the RFC 3339 time of
+    /// `timestamp` by `wall_clock`, if given.
+    pub fn write_at<T: Serialize>(
+        &mut self,
+        item: &Sequenced<T>,
+        timestamp: &Timestamp,
+        wall_clock: Option<&WallClock>,
+    ) -> Result<()> {
+        match wall_clock {
+            Some(wall_clock) => self.write(&Sequenced {
+                seq: item.seq,
+                item: WallClocked {
+                    wall_clock: wall_clock.rfc3339(timestamp),
+                    item: &item.item,
+                },
+            }),
+            None => self.write(item),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Records::Json(out) => out.flush(),
+            Records::Cbor(cbor) => cbor.flush(),
+            Records::Msgpack(msgpack) => msgpack.flush(),
+        }
+    }
+}
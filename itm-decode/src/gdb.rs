@@ -0,0 +1,262 @@
+//! Minimal client of the GDB Remote Serial Protocol, with which trace
+//! registers are written through a GDB server, e.g. OpenOCD's, pyOCD's
+//! or a J-Link GDB Server.
+
+use anyhow::{bail, Context, Result};
+use itm::config::{address, Registers};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// How long the server may take to respond.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a packet is sent before giving up if the server keeps
+/// asking for retransmission.
+const ATTEMPTS: usize = 3;
+
+/// A connection to a GDB server.
+pub struct Remote {
+    stream: BufReader<TcpStream>,
+}
+
+impl Remote {
+    /// Connects to the GDB server at `address`, e.g. `localhost:3333`.
+    pub fn connect(address: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address)
+            .with_context(|| format!("failed to connect to GDB server at {}", address))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    /// Sends `command` and returns the response to it.
+    fn request(&mut self, command: &str) -> Result<String> {
+        let packet = format!("${}#{:02x}", command, checksum(command.as_bytes()));
+        for _ in 0..ATTEMPTS {
+            self.stream.get_mut().write_all(packet.as_bytes())?;
+            match self.byte()? {
+                b'+' => return self.response(),
+                b'-' => continue,
+                b => bail!("unexpected {:?} from GDB server", b as char),
+            }
+        }
+        bail!("GDB server rejected {:?}", command);
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.stream
+            .read_exact(&mut byte)
+            .context("no response from GDB server")?;
+        Ok(byte[0])
+    }
+
+    /// Reads the next response packet, and acknowledges it.
+    fn response(&mut self) -> Result<String> {
+        loop {
+            // Skip anything before the start of the packet, e.g. stray
+            // acknowledgments.
+            while self.byte()? != b'$' {}
+            let mut data = vec![];
+            self.stream.read_until(b'#', &mut data)?;
+            data.pop();
+            let mut digits = [0; 2];
+            self.stream.read_exact(&mut digits)?;
+            let expected = std::str::from_utf8(&digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+            if expected != Some(checksum(&data)) {
+                self.stream.get_mut().write_all(b"-")?;
+                continue;
+            }
+            self.stream.get_mut().write_all(b"+")?;
+            // Console output may precede the response.
+            if data.first() == Some(&b'O') && data.len() > 1 && data != b"OK" {
+                continue;
+            }
+            return Ok(String::from_utf8_lossy(&expand(&data)).into_owned());
+        }
+    }
+
+    /// Reads the 32-bit word at `address`.
+    pub fn read_u32(&mut self, address: u32) -> Result<u32> {
+        let response = self.request(&format!("m{:x},4", address))?;
+        let bytes = (0..response.len())
+            .step_by(2)
+            .map(|i| {
+                response
+                    .get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>();
+        match bytes.as_deref() {
+            Some(&[a, b, c, d]) => Ok(u32::from_le_bytes([a, b, c, d])),
+            _ => bail!("failed to read {:#010x}: {}", address, response),
+        }
+    }
+
+    /// Writes the 32-bit word `value` to `address`.
+    pub fn write_u32(&mut self, address: u32, value: u32) -> Result<()> {
+        let data: String = value
+            .to_le_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        match self
+            .request(&format!("M{:x},4:{}", address, data))?
+            .as_str()
+        {
+            "OK" => Ok(()),
+            response => bail!("failed to write {:#010x}: {}", address, response),
+        }
+    }
+
+    /// Detaches from the target, which resumes it.
+    pub fn detach(mut self) -> Result<()> {
+        self.request("D")?;
+        Ok(())
+    }
+}
+
+/// Checksum of the `data` of a packet: the modulo 256 sum of its bytes.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Expands the run-length encoding of response `data`, in which `c*n`
+/// repeats `c` another `n - 29` times.
+fn expand(data: &[u8]) -> Vec<u8> {
+    let mut expanded: Vec<u8> = vec![];
+    let mut bytes = data.iter();
+    while let Some(b) = bytes.next() {
+        match (b, expanded.last().copied()) {
+            (b'*', Some(last)) => {
+                let count = bytes.next().map_or(0, |n| n.saturating_sub(29));
+                expanded.resize(expanded.len() + usize::from(count), last);
+            }
+            _ => expanded.push(*b),
+        }
+    }
+    expanded
+}
+
+/// Programs the trace `registers` of the target through the GDB server
+/// at `address`, after enabling trace in `DEMCR`, and detaches.
+pub fn program(address: &str, registers: &Registers) -> Result<()> {
+    let mut remote = Remote::connect(address)?;
+    let demcr = remote.read_u32(address::DEMCR)?;
+    remote.write_u32(address::DEMCR, demcr | address::DEMCR_TRCENA)?;
+    for (address, value) in registers.writes() {
+        remote.write_u32(address, value)?;
+    }
+    remote.detach()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Frames `data` as a packet.
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    /// Reads a packet or acknowledgment sent by the client.
+    fn receive(client: &mut BufReader<TcpStream>) -> String {
+        let mut byte = [0];
+        client.read_exact(&mut byte).unwrap();
+        if byte[0] != b'$' {
+            return (byte[0] as char).to_string();
+        }
+        let mut data = vec![b'$'];
+        client.read_until(b'#', &mut data).unwrap();
+        let mut checksum = [0; 2];
+        client.read_exact(&mut checksum).unwrap();
+        data.extend_from_slice(&checksum);
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Connects to a GDB server that runs `script` on the connection.
+    fn serve<F>(script: F) -> (Remote, thread::JoinHandle<()>)
+    where
+        F: FnOnce(&mut BufReader<TcpStream>) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            script(&mut BufReader::new(stream));
+        });
+        (Remote::connect(&address).unwrap(), server)
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"m20000000,4"), 0x4f);
+
+        assert_eq!(expand(b"0* "), b"0000");
+        assert_eq!(expand(b"ab*!c"), b"abbbbbc");
+        // A leading `*` repeats nothing
+        assert_eq!(expand(b"*!"), b"*!");
+    }
+
+    #[test]
+    fn replies() {
+        let (mut remote, server) = serve(|client| {
+            // A rejected request is retransmitted.
+            assert_eq!(receive(client), "$me0001000,4#83");
+            client.get_mut().write_all(b"-").unwrap();
+            assert_eq!(receive(client), "$me0001000,4#83");
+            // Console output and a corrupt response precede the reply,
+            // which is run-length encoded.
+            write!(client.get_mut(), "+{}", packet("O6869")).unwrap();
+            assert_eq!(receive(client), "+");
+            client.get_mut().write_all(b"$010*\"#00").unwrap();
+            assert_eq!(receive(client), "-");
+            client
+                .get_mut()
+                .write_all(packet("010*\"").as_bytes())
+                .unwrap();
+            assert_eq!(receive(client), "+");
+
+            assert_eq!(receive(client), "$Me0001000,4:01000001#1f");
+            write!(client.get_mut(), "+{}", packet("OK")).unwrap();
+            assert_eq!(receive(client), "+");
+
+            assert_eq!(receive(client), "$Me0001000,4:00000000#1d");
+            write!(client.get_mut(), "+{}", packet("E01")).unwrap();
+            assert_eq!(receive(client), "+");
+
+            assert_eq!(receive(client), "$me0001000,4#83");
+            write!(client.get_mut(), "+{}", packet("0102")).unwrap();
+            assert_eq!(receive(client), "+");
+
+            for _ in 0..ATTEMPTS {
+                assert_eq!(receive(client), "$D#44");
+                client.get_mut().write_all(b"-").unwrap();
+            }
+        });
+
+        assert_eq!(remote.read_u32(0xe000_1000).unwrap(), 0x0000_0001);
+        remote.write_u32(0xe000_1000, 0x0100_0001).unwrap();
+        assert_eq!(
+            remote.write_u32(0xe000_1000, 0).unwrap_err().to_string(),
+            "failed to write 0xe0001000: E01"
+        );
+        assert_eq!(
+            remote.read_u32(0xe000_1000).unwrap_err().to_string(),
+            "failed to read 0xe0001000: 0102"
+        );
+        assert_eq!(
+            remote.detach().unwrap_err().to_string(),
+            "GDB server rejected \"D\""
+        );
+        server.join().unwrap();
+    }
+}
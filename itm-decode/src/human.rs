@@ -0,0 +1,538 @@
+//! Human-oriented output of decoded packets: one line per packet of
+//! its sequence number, its time, its kind, colored by category, and
+//! its details.
+//! Instrumentation payloads are output as hex with an ASCII gutter, or
+//! as the records, frames or lines of text decoded from them, and
+//! exceptions by name.
+
+use crate::payloads::Decoded;
+use anyhow::{bail, Error, Result};
+use itm::wall_clock::WallClock;
+use itm::{
+    ExceptionAction, ExceptionType, MalformedPacket, PortMap, Sequenced, Timestamp, TracePacket,
+    VectActive,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Whether output is colored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// Colored if stdout is a terminal, and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => ColorChoice::Auto,
+            "always" => ColorChoice::Always,
+            "never" => ColorChoice::Never,
+            _ => bail!(
+                "{} is not a valid color choice; valid choices are: auto, always, never",
+                s
+            ),
+        })
+    }
+}
+
+impl ColorChoice {
+    /// Whether output to stdout, if `to_stdout`, is colored.
+    pub fn enabled(self, to_stdout: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                to_stdout && std::env::var_os("NO_COLOR").is_none() && stdout_is_terminal()
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn stdout_is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdout_is_terminal() -> bool {
+    false
+}
+
+/// Width of the sequence number column.
+const SEQ_WIDTH: usize = 8;
+
+/// Width of the longest packet kind, e.g. `global-timestamp-1`.
+const KIND_WIDTH: usize = 18;
+
+/// Number of payload bytes for which space is left before the ASCII
+/// gutter, so that gutters of payloads up to that size line up.
+const PAYLOAD_WIDTH: usize = 4;
+
+const RESET: &str = "\x1b[0m";
+
+/// ANSI color of instrumentation packets and the data decoded from
+/// them.
+const INSTRUMENTATION: &str = "\x1b[32m";
+
+/// ANSI color of the kind of `packet`.
+fn color(packet: &TracePacket) -> &'static str {
+    match packet {
+        TracePacket::Instrumentation { .. } => INSTRUMENTATION,
+        TracePacket::ExceptionTrace { .. } => "\x1b[33m",
+        TracePacket::PCSample { .. } => "\x1b[34m",
+        TracePacket::DataTracePC { .. }
+        | TracePacket::DataTraceAddress { .. }
+        | TracePacket::DataTraceMatch { .. }
+        | TracePacket::DataTraceValue { .. } => "\x1b[35m",
+        TracePacket::EventCounterWrap { .. } => "\x1b[36m",
+        TracePacket::Overflow | TracePacket::Unknown { .. } => "\x1b[31m",
+        TracePacket::Sync
+        | TracePacket::Extension { .. }
+        | TracePacket::LocalTimestamp1 { .. }
+        | TracePacket::LocalTimestamp2 { .. }
+        | TracePacket::GlobalTimestamp1 { .. }
+        | TracePacket::GlobalTimestamp2 { .. } => "\x1b[2m",
+    }
+}
+
+/// Writes packets in the human-oriented format. See the [module
+/// documentation](self).
+pub struct Human<W: Write> {
+    out: W,
+    color: bool,
+    compact: bool,
+    ports: PortMap,
+    /// Device interrupt names by exception number.
+    exceptions: BTreeMap<u16, String>,
+    wall_clock: Option<WallClock>,
+}
+
+impl<W: Write> Human<W> {
+    /// Writes to `out`, naming ports by `ports`, and device interrupts
+    /// by `irq_names`, a map of names to interrupt numbers, of which
+    /// those beyond the exception numbers are ignored. Unless
+    /// `compact`, columns are aligned and payloads have an ASCII
+    /// gutter.
+    pub fn new(
+        out: W,
+        color: bool,
+        compact: bool,
+        ports: PortMap,
+        irq_names: &BTreeMap<String, u16>,
+    ) -> Self {
+        Self {
+            out,
+            color,
+            compact,
+            ports,
+            exceptions: irq_names
+                .iter()
+                .filter_map(|(name, irqn)| Some((irqn.checked_add(16)?, name.clone())))
+                .collect(),
+            wall_clock: None,
+        }
+    }
+
+    /// Writes times as RFC 3339 wall-clock times by `wall_clock`, if
+    /// given, instead of as offsets from trace clock start.
+    pub fn wall_clock(mut self, wall_clock: Option<WallClock>) -> Self {
+        self.wall_clock = wall_clock;
+        self
+    }
+
+    /// Writes a line of `packet` at `time`, if timestamped, followed by
+    /// `suffix`.
+    pub fn packet(
+        &mut self,
+        time: Option<&Timestamp>,
+        packet: Sequenced<&TracePacket>,
+        suffix: &str,
+    ) -> io::Result<()> {
+        let Sequenced { seq, item: packet } = packet;
+        let details = self.details(packet);
+        let seq = seq.to_string();
+        self.line(&seq, time, packet.kind(), color(packet), &details, suffix)
+    }
+
+    /// Writes a line of the data `decoded` from instrumentation
+    /// payloads, completed by packet `seq` at `time`, if timestamped.
+    pub fn decoded(
+        &mut self,
+        time: Option<&Timestamp>,
+        seq: u64,
+        decoded: &Decoded,
+    ) -> io::Result<()> {
+        let details = format!(
+            "{} {}",
+            self.ports.label(decoded.port()),
+            decoded.fields().join(" ")
+        );
+        let seq = seq.to_string();
+        self.line(&seq, time, decoded.kind(), INSTRUMENTATION, &details, "")
+    }
+
+    /// Writes a line of a malformed packet at `time`, if timestamped.
+    pub fn malformed(
+        &mut self,
+        time: Option<&Timestamp>,
+        malformed: &MalformedPacket,
+    ) -> io::Result<()> {
+        // Malformed packets are not numbered
+        self.line(
+            "-",
+            time,
+            "malformed",
+            "\x1b[31m",
+            &malformed.to_string(),
+            "",
+        )
+    }
+
+    fn line(
+        &mut self,
+        seq: &str,
+        time: Option<&Timestamp>,
+        kind: &str,
+        color: &str,
+        details: &str,
+        suffix: &str,
+    ) -> io::Result<()> {
+        match self.compact {
+            true => write!(self.out, "{} ", seq)?,
+            false => write!(self.out, "{:>width$} ", seq, width = SEQ_WIDTH)?,
+        }
+        if let Some(timestamp) = time {
+            let time = match &self.wall_clock {
+                Some(wall_clock) => wall_clock.rfc3339(timestamp),
+                None => {
+                    let offset = timestamp.offset();
+                    format!("{}.{:09}", offset.as_secs(), offset.subsec_nanos())
+                }
+            };
+            match self.compact {
+                true => write!(self.out, "{} ", time)?,
+                false => write!(self.out, "{:>16} ", time)?,
+            }
+        }
+        let (color, reset) = match self.color {
+            true => (color, RESET),
+            false => ("", ""),
+        };
+        let width = if self.compact { 0 } else { KIND_WIDTH };
+        let line = format!(
+            "{}{:<width$}{} {}{}",
+            color,
+            kind,
+            reset,
+            details,
+            suffix,
+            width = width
+        );
+        writeln!(self.out, "{}", line.trim_end())
+    }
+
+    fn details(&self, packet: &TracePacket) -> String {
+        match packet {
+            TracePacket::Instrumentation { port, payload } => {
+                let hex: Vec<String> = payload.iter().map(|b| format!("{:02x}", b)).collect();
+                let label = self.ports.label(*port);
+                if self.compact {
+                    return format!("{} {}", label, hex.concat());
+                }
+                let ascii: String = payload
+                    .iter()
+                    .map(|b| match b {
+                        0x20..=0x7e => *b as char,
+                        _ => '.',
+                    })
+                    .collect();
+                format!(
+                    "{:<4} {:<width$}  |{}|",
+                    label,
+                    hex.join(" "),
+                    ascii,
+                    width = PAYLOAD_WIDTH * 3 - 1
+                )
+            }
+            TracePacket::ExceptionTrace { exception, action } => format!(
+                "{} {}",
+                self.exception(exception),
+                match action {
+                    ExceptionAction::Entered => "entered",
+                    ExceptionAction::Exited => "exited",
+                    ExceptionAction::Returned => "returned to",
+                }
+            ),
+            packet => packet.to_string(),
+        }
+    }
+
+    /// Name of `exception`, by its device interrupt name if known.
+    fn exception(&self, exception: &VectActive) -> String {
+        let exception = ExceptionType::from(*exception);
+        match self.exceptions.get(&u16::from(exception)) {
+            Some(name) => name.clone(),
+            None => exception.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itm::{MemoryAccessType, PortInfo, TimestampDataRelation};
+    use std::time::Duration;
+
+    fn packets() -> Vec<TracePacket> {
+        vec![
+            TracePacket::Sync,
+            TracePacket::Overflow,
+            TracePacket::LocalTimestamp1 {
+                ts: 1000,
+                data_relation: TimestampDataRelation::Sync,
+            },
+            TracePacket::LocalTimestamp2 { ts: 5 },
+            TracePacket::GlobalTimestamp1 {
+                ts: 0x1234,
+                wrap: false,
+                clkch: true,
+            },
+            TracePacket::GlobalTimestamp2 { ts: 0x56 },
+            TracePacket::Extension { page: 2 },
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: (*b"hi\n").into(),
+            },
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: [0xde, 0xad, 0xbe, 0xef].into(),
+            },
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: false,
+                sleep: true,
+                exc: false,
+                cpi: false,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::from(15).unwrap(),
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::from(21).unwrap(),
+                action: ExceptionAction::Exited,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::from(22).unwrap(),
+                action: ExceptionAction::Returned,
+            },
+            TracePacket::PCSample {
+                pc: Some(0x0800_0100),
+            },
+            TracePacket::PCSample { pc: None },
+            TracePacket::DataTracePC {
+                comparator: 1,
+                pc: 0x0800_0200,
+            },
+            TracePacket::DataTraceAddress {
+                comparator: 1,
+                data: [0x34, 0x12].into(),
+            },
+            TracePacket::DataTraceMatch { comparator: 2 },
+            TracePacket::DataTraceValue {
+                comparator: 3,
+                access_type: MemoryAccessType::Write,
+                value: [0x2a, 0, 0, 0].into(),
+            },
+            TracePacket::Unknown {
+                header: 0x74,
+                payload: vec![0x01],
+            },
+        ]
+    }
+
+    fn human(color: bool, compact: bool) -> String {
+        let mut ports = PortMap::new();
+        ports.insert(1, PortInfo::named("telemetry"));
+        let irq_names = [("USART1".to_string(), 5)].iter().cloned().collect();
+        let mut out = vec![];
+        let mut human = Human::new(&mut out, color, compact, ports, &irq_names);
+        for (seq, packet) in packets().iter().enumerate() {
+            let time = Timestamp::Sync(Duration::from_micros(seq as u64 * 1500));
+            human
+                .packet(
+                    Some(&time),
+                    Sequenced {
+                        seq: seq as u64,
+                        item: packet,
+                    },
+                    "",
+                )
+                .unwrap();
+        }
+        human
+            .malformed(
+                None,
+                &MalformedPacket::InvalidHardwarePacket {
+                    disc_id: 31,
+                    payload: vec![],
+                },
+            )
+            .unwrap();
+        human
+            .packet(
+                None,
+                Sequenced {
+                    seq: 20,
+                    item: &TracePacket::DataTraceValue {
+                        comparator: 0,
+                        access_type: MemoryAccessType::Read,
+                        value: [0xb0, 0x04].into(),
+                    },
+                },
+                " (speed = 1200)",
+            )
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn aligned() {
+        assert_eq!(
+            human(false, false),
+            concat!(
+                "       0      0.000000000 sync               Synchronization\n",
+                "       1      0.001500000 overflow           Overflow\n",
+                "       2      0.003000000 local-timestamp-1  Local timestamp 1000 (synchronous)\n",
+                "       3      0.004500000 local-timestamp-2  Local timestamp 5\n",
+                "       4      0.006000000 global-timestamp-1 Global timestamp bits[25:0] 4660, clock changed\n",
+                "       5      0.007500000 global-timestamp-2 Global timestamp bits[63:26] 86\n",
+                "       6      0.009000000 extension          Stimulus port page 2\n",
+                "       7      0.010500000 instrumentation    0    68 69 0a     |hi.|\n",
+                "       8      0.012000000 instrumentation    telemetry de ad be ef  |....|\n",
+                "       9      0.013500000 event-counter-wrap Event counter wrap: CYC SLEEP\n",
+                "      10      0.015000000 exception-trace    SysTick entered\n",
+                "      11      0.016500000 exception-trace    USART1 exited\n",
+                "      12      0.018000000 exception-trace    IRQ6 returned to\n",
+                "      13      0.019500000 pc-sample          PC sample 0x08000100\n",
+                "      14      0.021000000 pc-sample          PC sample: sleeping\n",
+                "      15      0.022500000 data-trace-pc      Comparator 1 matched PC 0x08000200\n",
+                "      16      0.024000000 data-trace-address Comparator 1 matched address 0x1234\n",
+                "      17      0.025500000 data-trace-match   Comparator 2 matched\n",
+                "      18      0.027000000 data-trace-value   Comparator 3 matched write of 0x0000002a\n",
+                "      19      0.028500000 unknown            Unknown packet: 74 01\n",
+                "       - malformed          Hardware source packet type discriminator ID (31) or payload length (0) is invalid\n",
+                "      20 data-trace-value   Comparator 0 matched read of 0x04b0 (speed = 1200)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn compact() {
+        assert_eq!(
+            human(false, true),
+            concat!(
+                "0 0.000000000 sync Synchronization\n",
+                "1 0.001500000 overflow Overflow\n",
+                "2 0.003000000 local-timestamp-1 Local timestamp 1000 (synchronous)\n",
+                "3 0.004500000 local-timestamp-2 Local timestamp 5\n",
+                "4 0.006000000 global-timestamp-1 Global timestamp bits[25:0] 4660, clock changed\n",
+                "5 0.007500000 global-timestamp-2 Global timestamp bits[63:26] 86\n",
+                "6 0.009000000 extension Stimulus port page 2\n",
+                "7 0.010500000 instrumentation 0 68690a\n",
+                "8 0.012000000 instrumentation telemetry deadbeef\n",
+                "9 0.013500000 event-counter-wrap Event counter wrap: CYC SLEEP\n",
+                "10 0.015000000 exception-trace SysTick entered\n",
+                "11 0.016500000 exception-trace USART1 exited\n",
+                "12 0.018000000 exception-trace IRQ6 returned to\n",
+                "13 0.019500000 pc-sample PC sample 0x08000100\n",
+                "14 0.021000000 pc-sample PC sample: sleeping\n",
+                "15 0.022500000 data-trace-pc Comparator 1 matched PC 0x08000200\n",
+                "16 0.024000000 data-trace-address Comparator 1 matched address 0x1234\n",
+                "17 0.025500000 data-trace-match Comparator 2 matched\n",
+                "18 0.027000000 data-trace-value Comparator 3 matched write of 0x0000002a\n",
+                "19 0.028500000 unknown Unknown packet: 74 01\n",
+                "- malformed Hardware source packet type discriminator ID (31) or payload length (0) is invalid\n",
+                "20 data-trace-value Comparator 0 matched read of 0x04b0 (speed = 1200)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn colored() {
+        let colored = human(true, false);
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(
+            lines[..2],
+            [
+                "       0      0.000000000 \x1b[2msync              \x1b[0m Synchronization",
+                "       1      0.001500000 \x1b[31moverflow          \x1b[0m Overflow",
+            ]
+        );
+        for (line, color) in lines.iter().zip([
+            "2", "31", "2", "2", "2", "2", "2", "32", "32", "36", "33", "33", "33", "34", "34",
+            "35", "35", "35", "35", "31", "31", "35",
+        ]) {
+            assert!(line.contains(&format!("\x1b[{}m", color)), "{}", line);
+        }
+        // Uncolored, the lines are the same.
+        let plain = colored.replace(RESET, "");
+        let plain = (0..=36).fold(plain, |plain, color| {
+            plain.replace(&format!("\x1b[{}m", color), "")
+        });
+        assert_eq!(plain, human(false, false));
+    }
+
+    #[test]
+    fn decoded_and_wall_clock() {
+        let mut out = vec![];
+        {
+            let mut human = Human::new(&mut out, false, false, PortMap::new(), &BTreeMap::new())
+                .wall_clock(Some(WallClock::new(
+                    std::time::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                )));
+            let time = Timestamp::Sync(Duration::from_micros(1_500_250));
+            human
+                .decoded(
+                    Some(&time),
+                    3,
+                    &Decoded::Line(itm::Line {
+                        port: 0,
+                        text: "hello".to_string(),
+                        timestamp: None,
+                    }),
+                )
+                .unwrap();
+            human
+                .decoded(None, 4, &Decoded::Frame(1, vec![0xab, 0xcd]))
+                .unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "       3 2020-09-13T12:26:41.500250000Z text               0 hello\n",
+                "       4 frame              1 abcd\n",
+            )
+        );
+    }
+
+    #[test]
+    fn large_irq_numbers() {
+        let irq_names = [("USART1".to_string(), 5), ("BOGUS".to_string(), 65530)]
+            .iter()
+            .cloned()
+            .collect();
+        let human = Human::new(vec![], false, false, PortMap::new(), &irq_names);
+        assert_eq!(
+            human.exceptions.into_iter().collect::<Vec<_>>(),
+            [(21, "USART1".to_string())]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Delivery of InfluxDB line protocol output.
+//!
+//! Lines are written to a file, stdout, or posted in batches to an HTTP
+//! write endpoint, e.g. `http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET`
+//! of InfluxDB 2, or the `/write` endpoint of InfluxDB 1 or Telegraf's
+//! `http_listener_v2`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use itm::influx::LineProtocol;
+use itm::TracePacket;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Destination of line protocol output.
+pub enum Sink {
+    Writer(Box<dyn Write>),
+    Http {
+        /// `host:port` to connect to.
+        address: String,
+        host: String,
+        /// Path and query of the endpoint.
+        path: String,
+        token: Option<String>,
+        /// Lines not yet posted.
+        body: String,
+    },
+}
+
+impl Sink {
+    /// Opens `dest`: an `http://` URL, `-` for stdout, or a file path.
+    /// The token of the `INFLUX_TOKEN` environment variable, if set, is
+    /// sent with HTTP requests.
+    pub fn open(dest: &str) -> Result<Self> {
+        if let Some(url) = dest.strip_prefix("http://") {
+            let (host, path) = match url.find('/') {
+                Some(i) => (&url[..i], &url[i..]),
+                None => (url, "/"),
+            };
+            if host.is_empty() {
+                bail!("{:?} is not a valid URL", dest);
+            }
+            let address = if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{}:80", host)
+            };
+            Ok(Sink::Http {
+                address,
+                host: host.to_string(),
+                path: path.to_string(),
+                token: std::env::var("INFLUX_TOKEN").ok(),
+                body: String::new(),
+            })
+        } else if dest.contains("://") {
+            bail!("{:?} is not supported; only http:// URLs are", dest)
+        } else if dest == "-" {
+            Ok(Sink::Writer(Box::new(io::stdout())))
+        } else {
+            let file = File::create(dest).context("failed to create InfluxDB output file")?;
+            Ok(Sink::Writer(Box::new(io::BufWriter::new(file))))
+        }
+    }
+
+    pub fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Writer(out) => writeln!(out, "{}", line),
+            Sink::Http { body, .. } => {
+                body.push_str(line);
+                body.push('\n');
+                Ok(())
+            }
+        }
+    }
+
+    /// Flushes the written lines, or posts them to the endpoint.
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Writer(out) => Ok(out.flush()?),
+            Sink::Http {
+                address,
+                host,
+                path,
+                token,
+                body,
+            } => {
+                if body.is_empty() {
+                    return Ok(());
+                }
+                post(address, host, path, token.as_deref(), body)
+                    .with_context(|| format!("failed to post to http://{}{}", host, path))?;
+                body.clear();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Posts `body` in an HTTP/1.1 request, and checks the response status.
+fn post(address: &str, host: &str, path: &str, token: Option<&str>, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n",
+        path,
+        host,
+        body.len()
+    );
+    if let Some(token) = token {
+        request += &format!("Authorization: Token {}\r\n", token);
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+
+    let mut status = String::new();
+    io::BufReader::new(stream).read_line(&mut status)?;
+    let code = status
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("malformed response {:?}", status.trim_end()))?;
+    if !code.starts_with('2') {
+        bail!("server responded {}", status.trim_end());
+    }
+    Ok(())
+}
+
+/// Outputs the lines of a [`LineProtocol`] converter to a [`Sink`],
+/// with the port counters at an interval of host time.
+pub struct Monitor {
+    lines: LineProtocol,
+    sink: Sink,
+    interval: Duration,
+    last: Instant,
+}
+
+impl Monitor {
+    pub fn new(lines: LineProtocol, sink: Sink, interval: Duration) -> Self {
+        Self {
+            lines,
+            sink,
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    /// Processes a packet that was generated at `time`.
+    pub fn push(&mut self, packet: &TracePacket, time: SystemTime) -> Result<()> {
+        self.lines.push(packet, Some(time));
+        if self.last.elapsed() < self.interval {
+            return self.drain();
+        }
+        self.last = Instant::now();
+        self.finish()
+    }
+
+    /// Outputs the port counters, and flushes the sink.
+    pub fn finish(&mut self) -> Result<()> {
+        self.lines.counters(Some(SystemTime::now()));
+        self.drain()?;
+        self.sink.flush()
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        while let Some(line) = self.lines.pull() {
+            self.sink.write(&line)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,688 @@
+//! Sources of trace data.
+
+use anyhow::{bail, Context, Result};
+use itm::compression::from_compressed_reader;
+#[cfg(feature = "probe-rs")]
+use itm::probe;
+use itm::{cmsis_dap, stlink, Discontinuity};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::net::{TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// Delay between attempts to re-establish a lost connection.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval at which a followed file is checked for new data.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where trace data is read from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Standard input, e.g. at the end of a pipeline. Decompressed if
+    /// gzip or Zstandard compressed.
+    Stdin,
+
+    /// A file or device. Decompressed if gzip or Zstandard compressed.
+    File(PathBuf),
+
+    /// A file that is read as it grows, e.g. as OpenOCD appends to it.
+    Follow(PathBuf),
+
+    /// A TCP trace server at `host:port`, e.g. OpenOCD's or
+    /// orbuculum's.
+    Tcp(String),
+
+    /// The SWO port of a J-Link GDB Server at `host:port`.
+    JLink(String),
+
+    /// SWO captured by the CMSIS-DAP v2 probe matched by `selector`, or
+    /// the single probe if none.
+    CmsisDap {
+        selector: Option<String>,
+        options: cmsis_dap::SwoOptions,
+    },
+
+    /// SWO captured at `baud_rate` by the ST-Link matched by
+    /// `selector`, or the single ST-Link if none.
+    StLink {
+        selector: Option<String>,
+        baud_rate: u32,
+    },
+
+    /// SWO captured through probe-rs from the target that the probe
+    /// matched by `selector`, or the single probe if none, attaches to.
+    #[cfg(feature = "probe-rs")]
+    Probe {
+        selector: Option<String>,
+        options: probe::CaptureOptions,
+    },
+
+    /// UDP datagrams received at `address`, each holding a chunk of the
+    /// stream, prefixed by a sequence number if `sequenced`.
+    Udp { address: String, sequenced: bool },
+
+    /// A Unix domain socket at `path`, of type `SOCK_SEQPACKET` if
+    /// `seqpacket`, or else `SOCK_STREAM`.
+    Unix { path: PathBuf, seqpacket: bool },
+}
+
+impl Source {
+    /// The source of a FILE argument: standard input if absent or `-`.
+    pub fn new(file: Option<PathBuf>) -> Self {
+        match file {
+            Some(path) if path.as_os_str() != "-" => Self::File(path),
+            _ => Self::Stdin,
+        }
+    }
+
+    /// Opens the source for reading. A live source signals a
+    /// `discontinuity` whenever it had to reconnect.
+    pub fn open(&self, discontinuity: &Discontinuity) -> Result<Box<dyn Read>> {
+        Ok(match self {
+            Self::Stdin => Box::new(
+                from_compressed_reader(io::stdin()).context("failed to read standard input")?,
+            ),
+            Self::File(path) => Box::new(
+                from_compressed_reader(File::open(path).context("failed to open file")?)
+                    .context("failed to read file")?,
+            ),
+            Self::Follow(path) => Box::new(Follow::open(path.clone(), discontinuity.clone())?),
+            Self::Tcp(address) => {
+                let peer = address.clone();
+                Box::new(Reconnecting::connect(
+                    address.clone(),
+                    Box::new(move || Ok(Box::new(TcpStream::connect(&peer)?) as Box<dyn Read>)),
+                    discontinuity.clone(),
+                )?)
+            }
+            Self::JLink(address) => {
+                let peer = address.clone();
+                Box::new(Reconnecting::connect(
+                    address.clone(),
+                    Box::new(move || {
+                        Ok(Box::new(JLinkSwo::new(TcpStream::connect(&peer)?)) as Box<dyn Read>)
+                    }),
+                    discontinuity.clone(),
+                )?)
+            }
+            Self::CmsisDap { selector, options } => {
+                let probe = cmsis_dap::find(selector.as_deref())?;
+                Box::new(
+                    cmsis_dap::open(&probe, options)
+                        .with_context(|| format!("failed to start SWO capture on {}", probe))?
+                        .signal_overruns(discontinuity.clone()),
+                )
+            }
+            Self::StLink {
+                selector,
+                baud_rate,
+            } => {
+                let probe = stlink::find(selector.as_deref())?;
+                Box::new(
+                    stlink::open(&probe, *baud_rate)
+                        .with_context(|| format!("failed to start SWO capture on {}", probe))?,
+                )
+            }
+            #[cfg(feature = "probe-rs")]
+            Self::Probe { selector, options } => {
+                let probe = probe::find(selector.as_deref())?;
+                Box::new(
+                    probe::attach(&probe, options)
+                        .with_context(|| format!("failed to start SWO capture on {}", probe))?,
+                )
+            }
+            #[cfg(unix)]
+            Self::Unix { path, seqpacket } => {
+                use std::os::unix::net::UnixStream;
+
+                let (peer, seqpacket) = (path.clone(), *seqpacket);
+                Box::new(Reconnecting::connect(
+                    path.display().to_string(),
+                    Box::new(move || {
+                        Ok(if seqpacket {
+                            Box::new(SeqPacket::connect(&peer)?) as Box<dyn Read>
+                        } else {
+                            Box::new(UnixStream::connect(&peer)?)
+                        })
+                    }),
+                    discontinuity.clone(),
+                )?)
+            }
+            #[cfg(not(unix))]
+            Self::Unix { .. } => bail!("Unix domain sockets are not supported on this platform"),
+            Self::Udp { address, sequenced } => {
+                Box::new(UdpInput::bind(address, *sequenced, discontinuity.clone())?)
+            }
+        })
+    }
+
+    /// Reads the source to its end.
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Stdin => {
+                let mut data = vec![];
+                from_compressed_reader(io::stdin())
+                    .and_then(|mut stdin| stdin.read_to_end(&mut data))
+                    .context("failed to read standard input")?;
+                Ok(data)
+            }
+            Self::File(path) => {
+                let mut data = vec![];
+                from_compressed_reader(File::open(path).context("failed to open file")?)
+                    .and_then(|mut file| file.read_to_end(&mut data))
+                    .context("failed to read file")?;
+                Ok(data)
+            }
+            Self::Tcp(address) => {
+                let mut data = vec![];
+                TcpStream::connect(address)
+                    .with_context(|| format!("failed to connect to {}", address))?
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("failed to read from {}", address))?;
+                Ok(data)
+            }
+            Self::Follow(_)
+            | Self::JLink(_)
+            | Self::CmsisDap { .. }
+            | Self::StLink { .. }
+            | Self::Udp { .. }
+            | Self::Unix { .. } => {
+                bail!("{} has no end to read to", self)
+            }
+            #[cfg(feature = "probe-rs")]
+            Self::Probe { .. } => bail!("{} has no end to read to", self),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdin => write!(f, "stdin"),
+            Self::File(path) | Self::Follow(path) => write!(f, "{}", path.display()),
+            Self::Tcp(address) | Self::JLink(address) => write!(f, "{}", address),
+            Self::CmsisDap { selector, .. } => {
+                write!(f, "CMSIS-DAP probe {}", selector.as_deref().unwrap_or(""))
+            }
+            Self::StLink { selector, .. } => {
+                write!(f, "ST-Link {}", selector.as_deref().unwrap_or(""))
+            }
+            #[cfg(feature = "probe-rs")]
+            Self::Probe { selector, options } => write!(
+                f,
+                "{} through probe {}",
+                options.chip,
+                selector.as_deref().unwrap_or("")
+            ),
+            Self::Udp { address, .. } => write!(f, "udp://{}", address),
+            Self::Unix { path, .. } => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A connection that is re-established whenever it is lost, so that
+/// reads never reach the end of the stream.
+struct Reconnecting {
+    /// Name of the peer, for messages.
+    name: String,
+    connect: Box<dyn Fn() -> io::Result<Box<dyn Read>>>,
+    connection: Box<dyn Read>,
+    discontinuity: Discontinuity,
+}
+
+impl Reconnecting {
+    fn connect(
+        name: String,
+        connect: Box<dyn Fn() -> io::Result<Box<dyn Read>>>,
+        discontinuity: Discontinuity,
+    ) -> Result<Self> {
+        let connection = connect().with_context(|| format!("failed to connect to {}", name))?;
+        Ok(Self {
+            name,
+            connect,
+            connection,
+            discontinuity,
+        })
+    }
+
+    fn reconnect(&mut self) {
+        loop {
+            thread::sleep(RECONNECT_INTERVAL);
+            if let Ok(connection) = (self.connect)() {
+                eprintln!("reconnected to {}", self.name);
+                self.connection = connection;
+                // The stream resumes at an arbitrary point.
+                self.discontinuity.signal();
+                return;
+            }
+        }
+    }
+}
+
+impl Read for Reconnecting {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.connection.read(buf) {
+                Ok(0) => eprintln!("connection to {} closed; reconnecting", self.name),
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => eprintln!("connection to {} lost: {}; reconnecting", self.name, e),
+            }
+            self.reconnect();
+        }
+    }
+}
+
+/// A file that is read as it grows, like with `tail -f`, so that reads
+/// never reach the end of the stream. If the file is truncated or
+/// replaced, e.g. by log rotation, it is read again from its start.
+struct Follow {
+    path: PathBuf,
+    file: File,
+    /// Number of bytes read from `file`.
+    position: u64,
+    discontinuity: Discontinuity,
+}
+
+impl Follow {
+    fn open(path: PathBuf, discontinuity: Discontinuity) -> Result<Self> {
+        let file = File::open(&path).context("failed to open file")?;
+        Ok(Self {
+            path,
+            file,
+            position: 0,
+            discontinuity,
+        })
+    }
+
+    /// Whether the file at `path` has been truncated, or is no longer
+    /// the file being read. A removed file is not considered replaced
+    /// until another one is created in its place.
+    fn replaced(&self) -> bool {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        if metadata.len() < self.position {
+            return true;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if let Ok(current) = self.file.metadata() {
+                return (metadata.dev(), metadata.ino()) != (current.dev(), current.ino());
+            }
+        }
+        false
+    }
+
+    fn reopen(&mut self) {
+        if let Ok(file) = File::open(&self.path) {
+            eprintln!(
+                "{} was truncated or replaced; reading from its start",
+                self.path.display()
+            );
+            self.file = file;
+            self.position = 0;
+            // The data read so far may end in the middle of a packet.
+            self.discontinuity.signal();
+        }
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.file.read(buf)? {
+                0 if self.replaced() => self.reopen(),
+                0 => thread::sleep(FOLLOW_INTERVAL),
+                n => {
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+            }
+        }
+    }
+}
+
+/// Start of the text banner with which a J-Link GDB Server may greet
+/// clients of its ports.
+const JLINK_BANNER: &[u8] = b"SEGGER";
+
+/// A connection to the SWO port of a J-Link GDB Server, which carries
+/// the raw SWO stream once SWO has been enabled, e.g. with `monitor SWO
+/// EnableTarget` from GDB. A text banner the server greets with is
+/// dropped.
+struct JLinkSwo {
+    stream: TcpStream,
+    /// Data read but not yet returned.
+    pending: Vec<u8>,
+    /// Whether the start of the connection has been checked for a
+    /// banner.
+    greeted: bool,
+}
+
+impl JLinkSwo {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            pending: vec![],
+            greeted: false,
+        }
+    }
+
+    /// Reads until the banner, if any, has been dropped.
+    fn greet(&mut self) -> io::Result<()> {
+        let mut buf = [0; 512];
+        while !self.greeted {
+            let n = self.stream.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.pending.extend_from_slice(&buf[..n]);
+
+            let prefix = self.pending.len().min(JLINK_BANNER.len());
+            if self.pending[..prefix] != JLINK_BANNER[..prefix] {
+                self.greeted = true;
+            } else if prefix == JLINK_BANNER.len() {
+                // The banner is text, unlike the start of the stream.
+                let text = |b: &u8| b.is_ascii_graphic() || b" \t\r\n".contains(b);
+                if let Some(end) = self.pending.iter().position(|b| !text(b)) {
+                    let start = self.pending[..end]
+                        .iter()
+                        .rposition(|b| *b == b'\n')
+                        .map_or(0, |i| i + 1);
+                    self.pending.drain(..start);
+                    self.greeted = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for JLinkSwo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.greet()?;
+        if self.pending.is_empty() {
+            return self.stream.read(buf);
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// A connected `SOCK_SEQPACKET` Unix domain socket. Each read of the
+/// socket receives a whole packet, which is then read in parts as
+/// needed.
+#[cfg(unix)]
+struct SeqPacket {
+    socket: std::os::unix::net::UnixStream,
+    packet: Vec<u8>,
+    /// Range of `packet` yet to be read.
+    pending: std::ops::Range<usize>,
+}
+
+#[cfg(unix)]
+impl SeqPacket {
+    fn connect(path: &std::path::Path) -> io::Result<Self> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::FromRawFd;
+
+        let path = path.as_os_str().as_bytes();
+        // SAFETY: sockaddr_un is plain old data.
+        let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        if path.len() >= address.sun_path.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "socket path too long",
+            ));
+        }
+        address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in address.sun_path.iter_mut().zip(path) {
+            *dst = *src as libc::c_char;
+        }
+
+        // SAFETY: the file descriptor is owned by the returned socket,
+        // which closes it on drop, and `address` outlives the call.
+        unsafe {
+            let fd = libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = std::os::unix::net::UnixStream::from_raw_fd(fd);
+            let len = std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+            if libc::connect(fd, &address as *const _ as *const libc::sockaddr, len) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                socket,
+                packet: vec![0; 65536],
+                pending: 0..0,
+            })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Read for SeqPacket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let n = self.socket.read(&mut self.packet)?;
+            self.pending = 0..n;
+        }
+        let n = buf.len().min(self.pending.len());
+        let start = self.pending.start;
+        buf[..n].copy_from_slice(&self.packet[start..start + n]);
+        self.pending.start += n;
+        Ok(n)
+    }
+}
+
+/// Size of the sequence number that prefixes sequenced datagrams.
+const SEQUENCE_BYTES: usize = 4;
+
+/// A UDP socket from which the stream is read one datagram at a time.
+/// If sequenced, each datagram starts with a 32-bit little-endian
+/// sequence number, incremented per datagram, so that lost datagrams are
+/// detected; late and duplicate ones are dropped.
+struct UdpInput {
+    socket: UdpSocket,
+    sequenced: bool,
+    /// Sequence number of the next datagram.
+    expected: Option<u32>,
+    datagram: Vec<u8>,
+    /// Range of `datagram` yet to be read.
+    pending: std::ops::Range<usize>,
+    discontinuity: Discontinuity,
+}
+
+impl UdpInput {
+    fn bind(address: &str, sequenced: bool, discontinuity: Discontinuity) -> Result<Self> {
+        let socket =
+            UdpSocket::bind(address).with_context(|| format!("failed to bind to {}", address))?;
+        Ok(Self {
+            socket,
+            sequenced,
+            expected: None,
+            datagram: vec![0; 65536],
+            pending: 0..0,
+            discontinuity,
+        })
+    }
+
+    /// Receives the next datagram to read from.
+    fn receive(&mut self) -> io::Result<()> {
+        let n = self.socket.recv(&mut self.datagram)?;
+        if !self.sequenced {
+            self.pending = 0..n;
+            return Ok(());
+        }
+        if n < SEQUENCE_BYTES {
+            eprintln!("dropped datagram of {} bytes without a sequence number", n);
+            return Ok(());
+        }
+        let mut sequence = [0; SEQUENCE_BYTES];
+        sequence.copy_from_slice(&self.datagram[..SEQUENCE_BYTES]);
+        let sequence = u32::from_le_bytes(sequence);
+        if let Some(expected) = self.expected {
+            match sequence.wrapping_sub(expected) {
+                0 => (),
+                lost if lost < 1 << 31 => {
+                    eprintln!("lost {} datagrams before datagram {}", lost, sequence);
+                    self.discontinuity.signal();
+                }
+                _ => {
+                    eprintln!("dropped late datagram {}", sequence);
+                    return Ok(());
+                }
+            }
+        }
+        self.expected = Some(sequence.wrapping_add(1));
+        self.pending = SEQUENCE_BYTES..n;
+        Ok(())
+    }
+}
+
+impl Read for UdpInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            self.receive()?;
+        }
+        let n = buf.len().min(self.pending.len());
+        let start = self.pending.start;
+        buf[..n].copy_from_slice(&self.datagram[start..start + n]);
+        self.pending.start += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itm::{Decoder, DecoderOptions, TracePacket};
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn sources() {
+        assert!(matches!(Source::new(None), Source::Stdin));
+        assert!(matches!(Source::new(Some("-".into())), Source::Stdin));
+        assert!(matches!(
+            Source::new(Some("trace.bin".into())),
+            Source::File(path) if path.as_os_str() == "trace.bin"
+        ));
+        assert_eq!(Source::Stdin.to_string(), "stdin");
+        assert_eq!(
+            Source::Tcp("localhost:3443".to_string()).to_string(),
+            "localhost:3443"
+        );
+        assert_eq!(
+            Source::Udp {
+                address: "0.0.0.0:4000".to_string(),
+                sequenced: false
+            }
+            .to_string(),
+            "udp://0.0.0.0:4000"
+        );
+        assert!(Source::JLink("localhost:2332".to_string())
+            .read_all()
+            .is_err());
+    }
+
+    /// Wraps `data` into a gzip member of a single stored block.
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let crc = !data.iter().fold(!0u32, |crc, b| {
+            (0..8).fold(crc ^ u32::from(*b), |crc, _| {
+                (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+            })
+        });
+        let len = data.len() as u16;
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff, 1];
+        gzip.extend_from_slice(&len.to_le_bytes());
+        gzip.extend_from_slice(&(!len).to_le_bytes());
+        gzip.extend_from_slice(data);
+        gzip.extend_from_slice(&crc.to_le_bytes());
+        gzip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        gzip
+    }
+
+    #[test]
+    fn compressed_file() {
+        let path = std::env::temp_dir().join(format!("itm-decode-input-{}", std::process::id()));
+        let data = b"\x01a\x00\x00\x00\x00\x00\x80";
+        for contents in [&data[..], &gzip(data)] {
+            fs::write(&path, contents).unwrap();
+            let source = Source::File(path.clone());
+            assert_eq!(source.read_all().unwrap(), data);
+            let mut read = vec![];
+            source
+                .open(&Discontinuity::new())
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, data);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Serves each of `connections` to a client in turn, and closes it.
+    fn serve(connections: Vec<&'static [u8]>) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            for data in connections {
+                let (mut stream, _) = listener.accept().unwrap();
+                stream.write_all(data).unwrap();
+            }
+        });
+        (address, server)
+    }
+
+    #[test]
+    fn tcp() {
+        let (address, server) = serve(vec![b"\x01a"]);
+        assert_eq!(Source::Tcp(address).read_all().unwrap(), b"\x01a");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tcp_reconnect() {
+        // The first connection is lost in the middle of an
+        // instrumentation packet.
+        let (address, server) = serve(vec![b"\x01a\x03b", b"c\x00\x00\x00\x00\x00\x80\x01d"]);
+        let discontinuity = Discontinuity::new();
+        let input = Source::Tcp(address).open(&discontinuity).unwrap();
+        let packets: Vec<_> = Decoder::new(input, DecoderOptions::default())
+            .resync_on(discontinuity)
+            .singles()
+            .take(3)
+            .collect();
+        let packets: Vec<_> = packets.into_iter().filter_map(Result::ok).collect();
+        assert_eq!(
+            packets,
+            [
+                TracePacket::Instrumentation {
+                    port: 0,
+                    payload: [b'a'].into()
+                },
+                TracePacket::Sync,
+                TracePacket::Instrumentation {
+                    port: 0,
+                    payload: [b'd'].into()
+                },
+            ]
+        );
+        server.join().unwrap();
+    }
+}
@@ -1,12 +1,67 @@
 use anyhow::{bail, Context, Result};
+#[cfg(feature = "probe-rs")]
+use itm::probe;
 use itm::{
-    serial, Decoder, DecoderOptions, LocalTimestampOptions, TimestampsConfiguration, TracePacket,
+    analysis::{
+        chrome::ChromeTrace,
+        exceptions::ExceptionStats,
+        pprof,
+        profile::{CollapsedStacks, Profile},
+        stats::{CountingReader, TraceStats},
+    },
+    cmsis_dap,
+    compression::ZstdTee,
+    config::{ComparatorRegisters, ConfigCheck, Registers, TraceConfig},
+    emulator::HostTimestamps,
+    influx::{InfluxOptions, LineProtocol},
+    latency::{ArrivalReader, LinkLatency},
+    logic, manchester,
+    nrz::{self, NrzDecoder},
+    parquet::{ParquetOptions, ParquetWriter},
+    pcapng::{PcapngOptions, PcapngTee, PcapngWriter},
+    repair::trim_corrupt_tail,
+    schema::SchemaDecoder,
+    serial,
+    sqlite::SqliteWriter,
+    stlink,
+    symbols::{SymbolTable, Symbolizer, VariableRef, VariableType},
+    trigger::{Condition, Trigger},
+    wall_clock::WallClock,
+    watch::Watch,
+    ArchVersion, ClockDrift, Decoder, DecoderError, DecoderOptions, Discontinuity, ExceptionFilter,
+    Field, LocalTimestampOptions, PacketFilter, PortMap, RecoveryPolicy, Sequence, Sequenced,
+    TimestampedTracePackets, Timestamps, TimestampsConfiguration, TracePacket, Window,
 };
-use std::fs::File;
-use std::path::PathBuf;
-use std::str;
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use structopt::StructOpt;
 
+mod alert;
+use alert::Alerts;
+mod format;
+use format::{Format, InputFormat, Records, Table};
+mod gdb;
+mod human;
+use human::{ColorChoice, Human};
+mod influx;
+use influx::{Monitor, Sink};
+mod input;
+use input::Source;
+mod output;
+use output::{Output, PortOutputs, RotateOptions};
+mod payloads;
+use payloads::Payloads;
+mod ports;
+mod recorder;
+use recorder::{Recorder, RecorderOptions};
+mod svd;
+mod websocket;
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     about = "An ITM/DWT packet protocol decoder, as specified in the ARMv7-M architecture reference manual, Appendix D4. See <https://developer.arm.com/documentation/ddi0403/ed/>. Report bugs and request features at <https://github.com/rust-embedded/itm>."
@@ -15,88 +70,2117 @@ struct Opt {
     #[structopt(long = "--ignore-eof")]
     ignore_eof: bool,
 
-    #[structopt(long = "--timestamps", requires("freq"))]
+    #[structopt(long = "--timestamps")]
     timestamps: bool,
 
+    #[structopt(
+        long = "--epoch",
+        requires("timestamps"),
+        parse(try_from_str = parse_epoch),
+        help = "Output wall-clock times instead of offsets, with the trace clock start anchored to the given RFC 3339 time, or to the start of decoding if \"now\". Records of the json, cbor and msgpack formats keep their offsets and gain a wall_clock field."
+    )]
+    epoch: Option<WallClock>,
+
+    #[structopt(
+        long = "--from",
+        requires("timestamps"),
+        parse(try_from_str = humantime::parse_duration),
+        help = "Only output packets timestamped at or after the given offset from trace clock start, e.g. \"1s 500ms\"."
+    )]
+    from: Option<Duration>,
+
+    #[structopt(
+        long = "--to",
+        requires("timestamps"),
+        parse(try_from_str = humantime::parse_duration),
+        help = "Only output packets timestamped before the given offset from trace clock start, and stop decoding after it."
+    )]
+    to: Option<Duration>,
+
+    #[structopt(
+        long = "--count",
+        value_name = "N",
+        help = "Stop decoding after N packets have been output."
+    )]
+    count: Option<usize>,
+
+    #[structopt(
+        long = "--duration",
+        requires("timestamps"),
+        parse(try_from_str = humantime::parse_duration),
+        help = "Stop decoding after the given amount of trace time from the first output packet, e.g. \"5s\"."
+    )]
+    duration: Option<Duration>,
+
+    #[structopt(
+        long = "--start-on",
+        value_name = "CONDITION",
+        help = "Start output at the first packet that satisfies CONDITION: a packet kind, followed by any of an exception name and action, `port N`, `comparator N`, and `payload`, `value`, `address` or `pc == V`, e.g. \"ExceptionTrace HardFault entered\"."
+    )]
+    start_on: Option<String>,
+
+    #[structopt(
+        long = "--stop-after",
+        value_name = "CONDITION",
+        help = "Stop decoding after the first packet from the start of output that satisfies CONDITION, e.g. \"Instrumentation port 2 payload == 0xDEAD\". See --start-on."
+    )]
+    stop_after: Option<String>,
+
+    #[structopt(
+        long = "--pre-trigger",
+        value_name = "N",
+        requires("start-on"),
+        help = "Also output the N packets (or with --timestamps, sets of packets) before the --start-on packet."
+    )]
+    pre_trigger: Option<usize>,
+
+    #[structopt(
+        long = "--flight-recorder",
+        value_name = "DIR",
+        parse(from_os_str),
+        help = "Keep the latest packets in memory instead of outputting them, and dump them to a JSON file in DIR when a --dump-on condition is satisfied or, on Unix, SIGUSR1 is received. By default, the last 10 seconds are kept."
+    )]
+    flight_recorder: Option<PathBuf>,
+
+    #[structopt(
+        long = "--recorder-duration",
+        requires("flight-recorder"),
+        parse(try_from_str = humantime::parse_duration),
+        help = "Keep the packets of the given trace time (or without --timestamps, host time) before the latest in the --flight-recorder, e.g. \"30s\"."
+    )]
+    recorder_duration: Option<Duration>,
+
+    #[structopt(
+        long = "--recorder-size",
+        value_name = "SIZE",
+        requires("flight-recorder"),
+        parse(try_from_str = parse_size),
+        help = "Keep packets of up to SIZE bytes of trace data in the --flight-recorder, e.g. 16M."
+    )]
+    recorder_size: Option<u64>,
+
+    #[structopt(
+        long = "--dump-on",
+        value_name = "CONDITION",
+        number_of_values = 1,
+        requires("flight-recorder"),
+        help = "Dump the --flight-recorder when a packet satisfies CONDITION, e.g. \"ExceptionTrace HardFault entered\". See --start-on. May be given more than once."
+    )]
+    dump_on: Vec<String>,
+
+    #[structopt(
+        long = "--alert",
+        value_name = "EXPR",
+        number_of_values = 1,
+        parse(try_from_str),
+        help = "Output an alert line to stderr when a traced data value satisfies EXPR: `value` followed by <, <=, >, >=, == or != and a number, or `value changed`, optionally preceded by `comparator N`, e.g. \"comparator 1 value > 1000\". A comparison alerts when it becomes true. May be given more than once."
+    )]
+    alert: Vec<Watch>,
+
+    #[structopt(
+        long = "--alert-exec",
+        value_name = "COMMAND",
+        requires("alert"),
+        help = "Run COMMAND through the shell for each --alert instead of outputting a line, with the alert in ITM_ALERT, ITM_COMPARATOR, ITM_VALUE, and if any, ITM_PREVIOUS and ITM_TIME."
+    )]
+    alert_exec: Option<String>,
+
+    #[structopt(
+        long = "--replay",
+        requires("timestamps"),
+        help = "Output each set of packets at the time of its timestamp, relative to the first, to reproduce the timing of a recorded capture."
+    )]
+    replay: bool,
+
+    #[structopt(
+        long = "--speed",
+        requires("replay"),
+        parse(try_from_str = parse_speed),
+        help = "Speed of --replay relative to the original timing, e.g. \"10x\" or \"0.5x\". Defaults to 1x."
+    )]
+    speed: Option<f64>,
+
     #[structopt(long = "--itm-prescaler")]
     prescaler: Option<u8>,
 
-    #[structopt(long = "--itm-freq", name = "freq")]
+    #[structopt(
+        long = "--register",
+        value_name = "NAME=VALUE",
+        number_of_values = 1,
+        parse(try_from_str = parse_register),
+        help = "Value of a trace register as read from the target, e.g. ITM_TCR=0x1000b: ITM_TCR, ITM_TER, DWT_CTRL, or DWT_COMPn, DWT_MASKn or DWT_FUNCTIONn of comparator n. ITM_TCR, ITM_TER and DWT_CTRL are required if any is given. The local timestamp prescaler is then taken from ITM_TCR, unless given by --itm-prescaler, and a warning is output for each kind of packet that the registers rule out. May be given multiple times."
+    )]
+    register: Vec<(String, u32)>,
+
+    #[structopt(
+        long = "--itm-config",
+        parse(from_os_str),
+        requires = "gdb-remote",
+        conflicts_with = "register",
+        help = "TOML file of the ITM, DWT and TPIU configuration to program the target with through --gdb-remote before decoding, e.g. `[itm]` with `ports = 1` and `local_timestamps = \"EnabledDiv4\"`, and `[dwt]` with `pc_sampling = 1024`. The local timestamp prescaler is then taken from it, unless given by --itm-prescaler, and a warning is output for each kind of packet that it rules out."
+    )]
+    itm_config: Option<PathBuf>,
+
+    #[structopt(
+        long = "--gdb-remote",
+        value_name = "HOST:PORT",
+        requires = "itm-config",
+        help = "GDB server through which the target is programmed with --itm-config, e.g. OpenOCD's at localhost:3333. The target is resumed afterwards."
+    )]
+    gdb_remote: Option<String>,
+
+    #[structopt(
+        long = "--itm-freq",
+        name = "freq",
+        help = "Deprecated alias of --clock-frequency. It does not set the bit rate of a serial device, which is given by --baud."
+    )]
     freq: Option<u32>,
 
+    #[structopt(
+        long = "--clock-frequency",
+        help = "Frequency of the timestamp clock, usually the core clock, in Hz. Required by --timestamps."
+    )]
+    clock_frequency: Option<u32>,
+
     #[structopt(long = "--expect-malformed")]
     expect_malformed: bool,
 
-    #[structopt(name = "FILE", parse(from_os_str), help = "Raw trace input file.")]
-    file: PathBuf,
+    #[structopt(
+        long = "--best-effort",
+        requires = "timestamps",
+        help = "Do not stop at malformed packets; output the packets decoded before them with a gap timestamp instead."
+    )]
+    best_effort: bool,
+
+    #[structopt(
+        long = "--drift",
+        requires = "timestamps",
+        help = "Report the estimated drift between the local and global timestamp clocks when done."
+    )]
+    drift: bool,
+
+    #[structopt(
+        long = "--correct-drift",
+        requires = "timestamps",
+        help = "Scale local timestamps by the estimated drift between the local and global timestamp clocks."
+    )]
+    correct_drift: bool,
+
+    #[structopt(
+        long = "--exception-stats",
+        requires = "timestamps",
+        help = "Report per-exception handler counts, execution times and preemptions when done."
+    )]
+    exception_stats: bool,
+
+    #[structopt(
+        long = "--stats",
+        help = "Report packets per kind, bytes, decode errors, overflows, payload bytes per stimulus port and, with --timestamps, the time span covered and the average bandwidth when done."
+    )]
+    stats: bool,
+
+    #[structopt(
+        long = "--stats-format",
+        possible_values = &["text", "json"],
+        requires = "stats",
+        help = "Format of the --stats report on stderr: text (the default) or json."
+    )]
+    stats_format: Option<String>,
+
+    #[structopt(
+        long = "--armv8m",
+        help = "Decode the trace of an ARMv8-M target, which uses packet encodings that are invalid on ARMv7-M."
+    )]
+    armv8m: bool,
+
+    #[structopt(
+        long = "--emulator",
+        conflicts_with_all = &["clock-frequency", "prescaler", "drift", "correct-drift", "latency"],
+        help = "Decode the trace of an emulator, e.g. QEMU or Renode: accept synchronization packets that are shorter than the architecture requires, and with --timestamps, drop timestamp packets and timestamp each packet with the host time at which it arrived instead."
+    )]
+    emulator: bool,
+
+    #[structopt(
+        long = "--track-stimulus-page",
+        help = "Report the effective stimulus port number 0-255 of instrumentation packets, as selected by the preceding extension packet."
+    )]
+    track_stimulus_page: bool,
+
+    #[structopt(
+        long = "--skip-to-sync",
+        help = "On a malformed packet, drop all data until the next synchronization packet and report the number of dropped bytes on stderr, instead of aborting."
+    )]
+    skip_to_sync: bool,
+
+    #[structopt(
+        long = "--report-unknown",
+        conflicts_with("skip-to-sync"),
+        help = "Output malformed packets as unknown packets with their raw bytes, instead of aborting."
+    )]
+    report_unknown: bool,
+
+    #[structopt(
+        long = "--latency",
+        requires("timestamps"),
+        help = "Report the SWO link latency and jitter on stderr."
+    )]
+    latency: bool,
+
+    #[structopt(
+        long = "--format",
+        default_value = "human",
+        possible_values = &["human", "debug", "text", "pretty", "csv", "json", "cbor", "msgpack"],
+        help = "Output format of decoded packets."
+    )]
+    format: Format,
+
+    #[structopt(
+        long = "--color",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"],
+        help = "Whether to color the packet kinds of the human format. By default, output is colored if it is written to a terminal and NO_COLOR is not set."
+    )]
+    color: ColorChoice,
+
+    #[structopt(
+        long = "--compact",
+        help = "Output the human format without column alignment or ASCII gutters."
+    )]
+    compact: bool,
+
+    #[structopt(
+        long = "--skip-bytes",
+        value_name = "SIZE",
+        parse(try_from_str = parse_size),
+        help = "Skip the first SIZE bytes of the input, e.g. 1G, with a K, M or G suffix for KiB, MiB or GiB, and start decoding at the next synchronization packet after them."
+    )]
+    skip_bytes: Option<u64>,
+
+    #[structopt(
+        long = "--max-bytes",
+        value_name = "SIZE",
+        parse(try_from_str = parse_size),
+        help = "Decode at most SIZE bytes of the input, after --skip-bytes, as --skip-bytes takes SIZE."
+    )]
+    max_bytes: Option<u64>,
+
+    #[structopt(
+        long = "--output",
+        short = "o",
+        parse(from_os_str),
+        help = "Write decoded output to FILE instead of stdout."
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long = "--rotate-size",
+        value_name = "SIZE",
+        requires = "output",
+        parse(try_from_str = parse_size),
+        help = "Split --output into files of about SIZE bytes, e.g. 256M, with a K, M or G suffix for KiB, MiB or GiB. Files after the first are numbered before the extension, e.g. out.1.json, and each starts at a synchronization packet, so that it can be used on its own."
+    )]
+    rotate_size: Option<u64>,
+
+    #[structopt(
+        long = "--rotate-interval",
+        value_name = "DURATION",
+        requires = "output",
+        parse(try_from_str = humantime::parse_duration),
+        help = "Split --output into files that span about DURATION each, e.g. \"1h\", as --rotate-size does by size."
+    )]
+    rotate_interval: Option<Duration>,
+
+    #[structopt(
+        long = "--fields",
+        alias = "columns",
+        help = "Comma-separated list of columns to output in pretty/csv format: seq, time (or ts), quality, kind, port, comparator, value, payload_hex, payload_text. All but the payload columns are output by default."
+    )]
+    fields: Option<String>,
+
+    #[structopt(
+        long = "--exception",
+        help = "Comma-separated list of exceptions to output traces of, e.g. SysTick,IRQ11. All exceptions are output by default."
+    )]
+    exception: Option<String>,
+
+    #[structopt(
+        long = "--exclude-exception",
+        help = "Comma-separated list of exceptions to not output traces of."
+    )]
+    exclude_exception: Option<String>,
+
+    #[structopt(
+        long = "--port",
+        value_name = "PORTS",
+        help = "Comma-separated list of stimulus ports, by number or --ports name, to output instrumentation packets of. All ports are output by default."
+    )]
+    port: Option<String>,
+
+    #[structopt(
+        long = "--only",
+        value_name = "KINDS",
+        help = "Comma-separated list of packet kinds to output, e.g. instrumentation,exception-trace. \"timestamp\" names all timestamp kinds. All kinds are output by default."
+    )]
+    only: Option<String>,
+
+    #[structopt(
+        long = "--exclude",
+        value_name = "KINDS",
+        help = "Comma-separated list of packet kinds to not output, e.g. pc-sample,timestamp."
+    )]
+    exclude: Option<String>,
+
+    #[structopt(
+        long = "--port-output",
+        value_name = "ROUTES",
+        help = "Write the instrumentation payloads of the given stimulus ports to outputs of their own instead, as a comma-separated list of PORT=DEST, e.g. 0=console,1=adc.bin,2=log.txt. PORT is a number or --ports name; DEST is \"console\" or a file, to which payloads are written as is. On the console, payloads of binary ports are written in hex."
+    )]
+    port_output: Option<String>,
+
+    #[structopt(
+        long = "--svd",
+        parse(from_os_str),
+        help = "SVD file of the target device, from which interrupt names are resolved in --exception and --exclude-exception."
+    )]
+    svd: Option<PathBuf>,
+
+    #[structopt(
+        long = "--schema",
+        parse(from_os_str),
+        help = "TOML file of per-port payload schemas. Instrumentation packets on ports with a schema are output as decoded records."
+    )]
+    schema: Option<PathBuf>,
+
+    #[structopt(
+        long = "--ports",
+        parse(from_os_str),
+        help = "TOML file of stimulus port names, encodings (text, binary or cobs) and descriptions. Port names are used in all output formats."
+    )]
+    ports: Option<PathBuf>,
+
+    #[structopt(
+        long = "--profile",
+        conflicts_with = "timestamps",
+        help = "Report a histogram of sampled PC values when done, by function if --elf is given."
+    )]
+    profile: bool,
+
+    #[structopt(
+        long = "--collapsed",
+        parse(from_os_str),
+        conflicts_with = "timestamps",
+        help = "Write sampled PC values to FILE in the folded stack format of flamegraph.pl and inferno, by function if --elf is given."
+    )]
+    collapsed: Option<PathBuf>,
+
+    #[structopt(
+        long = "--collapsed-exceptions",
+        help = "Root each --collapsed stack at the exception that was active when the PC was sampled."
+    )]
+    collapsed_exceptions: bool,
+
+    #[structopt(
+        long = "--chrome-trace",
+        requires("timestamps"),
+        parse(from_os_str),
+        help = "Write exception handler executions and instrumentation messages to FILE in the Chrome trace event format, for chrome://tracing or the Perfetto UI."
+    )]
+    chrome_trace: Option<PathBuf>,
+
+    #[structopt(
+        long = "--parquet",
+        parse(from_os_str),
+        help = "Write decoded packets, with their timestamps if --timestamps is given, to FILE as a Parquet table."
+    )]
+    parquet: Option<PathBuf>,
+
+    #[structopt(
+        long = "--sqlite",
+        parse(from_os_str),
+        help = "Write decoded packets, exception handler executions (if --timestamps is given) and PC samples (by function if --elf is given) to the SQLite database FILE."
+    )]
+    sqlite: Option<PathBuf>,
+
+    #[structopt(
+        long = "--pcapng",
+        parse(from_os_str),
+        help = "Archive the raw trace data to FILE as a pcapng capture, with the host time at which each chunk of data arrived."
+    )]
+    pcapng: Option<PathBuf>,
+
+    #[structopt(
+        long = "--archive",
+        parse(from_os_str),
+        help = "Archive the raw trace data to FILE, compressed with Zstandard, so that it can be decoded again later, e.g. with other options. The archive is readable up to the last second even if decoding is cut short."
+    )]
+    archive: Option<PathBuf>,
+
+    #[structopt(
+        long = "--influx",
+        help = "Output data-trace values and per-port counters as InfluxDB line protocol to DEST: a file, \"-\" for stdout, or an http:// write endpoint URL, e.g. \"http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET\". The token of the INFLUX_TOKEN environment variable, if set, is sent for authorization."
+    )]
+    influx: Option<String>,
+
+    #[structopt(
+        long = "--influx-watch",
+        number_of_values = 1,
+        parse(try_from_str = parse_watch),
+        help = "Only output the data values of the given comparator to --influx, tagged with a variable name, e.g. \"1=motor_speed\". May be given multiple times."
+    )]
+    influx_watch: Vec<(u8, String)>,
+
+    #[structopt(
+        long = "--influx-interval",
+        default_value = "1s",
+        parse(try_from_str = humantime::parse_duration),
+        help = "Interval at which per-port counters are output to --influx, and lines are posted to an HTTP endpoint."
+    )]
+    influx_interval: Duration,
+
+    #[structopt(
+        long = "--websocket",
+        value_name = "ADDRESS:PORT",
+        help = "Serve decoded packets as JSON to WebSocket clients at the given address, e.g. 127.0.0.1:8765. Clients subscribe to packet kinds and stimulus ports with the query of the URL, e.g. ws://127.0.0.1:8765/?kinds=instrumentation&ports=0,1."
+    )]
+    websocket: Option<String>,
+
+    #[structopt(
+        long = "--pprof",
+        parse(from_os_str),
+        conflicts_with = "timestamps",
+        help = "Write sampled PC values to FILE as a pprof profile, symbolized if --elf is given."
+    )]
+    pprof: Option<PathBuf>,
+
+    #[structopt(
+        long = "--sample-period",
+        parse(try_from_str = humantime::parse_duration),
+        help = "Period of PC sampling, e.g. \"64us\", with which --pprof also reports the CPU time of each sample."
+    )]
+    sample_period: Option<Duration>,
+
+    #[structopt(
+        long = "--elf",
+        parse(from_os_str),
+        help = "Firmware ELF file, whose debug information and symbols are used to resolve PC values to functions and source lines in text output and --profile, and the addresses of data trace comparators given by --register or --itm-config to variables, struct members and array elements, whose types format the traced values."
+    )]
+    elf: Option<PathBuf>,
+
+    #[structopt(
+        long = "--value-type",
+        value_name = "COMPARATOR=TYPE",
+        number_of_values = 1,
+        parse(try_from_str = parse_value_type),
+        help = "Type of the data values of a comparator, by which they are output instead of by the type of their variable in --elf: u8 to u64, i8 to i64, f32, f64, bool or char, optionally followed by enumeration variants, e.g. \"1=i16\" or \"2=u8{Idle=0,Running=1}\". May be given multiple times."
+    )]
+    value_type: Vec<(u8, VariableType)>,
+
+    #[structopt(
+        long = "--input-format",
+        default_value = "raw",
+        help = "Format of FILE: raw trace data (raw), or a logic-analyzer capture of the SWO pin in NRZ mode, as a sigrok session file (sigrok) or a Saleae Logic CSV export (saleae)."
+    )]
+    input_format: InputFormat,
+
+    #[structopt(
+        long = "--channel",
+        default_value = "0",
+        help = "Channel of the SWO pin in a logic-analyzer capture, by name or 0-based index."
+    )]
+    channel: String,
+
+    #[structopt(
+        long = "--baud",
+        help = "SWO bit rate, in bits per second: of the serial device that FILE is, of a --cmsis-dap or --stlink probe, or of a logic-analyzer capture."
+    )]
+    baud: Option<u32>,
+
+    #[structopt(
+        long = "--auto-baud",
+        conflicts_with = "baud",
+        help = "Detect the SWO bit rate of an NRZ logic-analyzer capture from its edge timing."
+    )]
+    auto_baud: bool,
+
+    #[structopt(
+        long = "--manchester",
+        help = "The SWO pin of a logic-analyzer capture or --cmsis-dap probe is Manchester-encoded, instead of NRZ. The bit rate of a capture is recovered from it."
+    )]
+    manchester: bool,
+
+    #[structopt(
+        long = "--list-devices",
+        help = "List the available serial devices, CMSIS-DAP v2 probes and ST-Links, with their USB VID:PID and descriptions, and exit."
+    )]
+    list_devices: bool,
+
+    #[structopt(
+        long = "--device",
+        conflicts_with = "FILE",
+        help = "Read from the serial device matched by the given USB VID:PID (e.g. 0483:374b) or substring of its path or description, instead of FILE."
+    )]
+    device: Option<String>,
+
+    #[structopt(
+        long = "--tcp",
+        value_name = "HOST:PORT",
+        conflicts_with_all(&["FILE", "device", "jlink", "udp", "unix"]),
+        help = "Read from a TCP trace server, e.g. OpenOCD's TPIU/ITM output or orbuculum's, instead of FILE. The connection is re-established if lost, and decoding resumes at the next synchronization packet."
+    )]
+    tcp: Option<String>,
+
+    #[structopt(
+        long = "--jlink",
+        value_name = "HOST[:PORT]",
+        conflicts_with_all(&["FILE", "device", "udp", "unix"]),
+        help = "Read from the SWO port (by default 2332) of a running J-Link GDB Server, instead of FILE. SWO must be enabled, e.g. with `monitor SWO EnableTarget` from GDB. The connection is re-established if lost, and decoding resumes at the next synchronization packet."
+    )]
+    jlink: Option<String>,
+
+    #[structopt(
+        long = "--cmsis-dap",
+        value_name = "SELECTOR",
+        conflicts_with_all(&["FILE", "device", "tcp", "jlink", "udp", "unix"]),
+        requires = "baud",
+        help = "Capture SWO with a CMSIS-DAP v2 probe over USB, instead of reading FILE. The probe is matched by an optional USB VID:PID or substring of its description, as listed by --list-devices. The target must configure its TPIU for the --baud rate. Decoding resumes at the next synchronization packet after the probe drops data."
+    )]
+    cmsis_dap: Option<Option<String>>,
+
+    #[structopt(
+        long = "--stlink",
+        value_name = "SELECTOR",
+        conflicts_with_all(&["FILE", "device", "tcp", "jlink", "cmsis-dap", "udp", "unix"]),
+        requires = "baud",
+        help = "Capture SWO with an ST-Link over USB, instead of reading FILE. The probe is matched by an optional USB VID:PID or substring of its serial number, as listed by --list-devices. The target must configure its TPIU for NRZ at the --baud rate."
+    )]
+    stlink: Option<Option<String>>,
+
+    #[structopt(
+        long = "--udp",
+        value_name = "ADDRESS:PORT",
+        conflicts_with_all(&["FILE", "device", "unix"]),
+        help = "Read from UDP datagrams of raw trace data received at the given local address, e.g. 0.0.0.0:5000, instead of FILE."
+    )]
+    udp: Option<String>,
+
+    #[structopt(
+        long = "--udp-sequenced",
+        requires = "udp",
+        help = "Each --udp datagram starts with a 32-bit little-endian sequence number. Lost datagrams are then reported, after which decoding resumes at the next synchronization packet, and late ones dropped."
+    )]
+    udp_sequenced: bool,
+
+    #[structopt(
+        long = "--unix",
+        value_name = "PATH",
+        parse(from_os_str),
+        conflicts_with_all(&["FILE", "device"]),
+        help = "Read from the Unix domain socket at PATH, e.g. of a debug bridge, instead of FILE. The connection is re-established if the peer restarts, and decoding resumes at the next synchronization packet."
+    )]
+    unix: Option<PathBuf>,
+
+    #[structopt(
+        long = "--unix-seqpacket",
+        requires = "unix",
+        help = "The --unix socket is of type SOCK_SEQPACKET, instead of SOCK_STREAM."
+    )]
+    unix_seqpacket: bool,
+
+    #[structopt(
+        long = "--follow",
+        requires = "FILE",
+        conflicts_with_all(&["device", "tcp", "jlink", "cmsis-dap", "stlink", "udp", "unix"]),
+        help = "Keep reading FILE as it grows, e.g. as OpenOCD appends to it, like `tail -f`. If FILE is truncated or replaced, e.g. by log rotation, it is read again from its start, and decoding resumes at the next synchronization packet."
+    )]
+    follow: bool,
+
+    #[structopt(
+        name = "FILE",
+        parse(from_os_str),
+        help = "Trace input file, which is decompressed if gzip or Zstandard compressed. Standard input if \"-\" or absent."
+    )]
+    file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() -> Result<()> {
-    let opt = Opt::from_args();
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Writes a converted copy of a capture, instead of decoding it.
+    Convert {
+        #[structopt(
+            long = "--trim-corrupt",
+            help = "Trim any corrupt tail of the capture, e.g. from a power loss: the region from which it no longer decodes consistently."
+        )]
+        trim_corrupt: bool,
+
+        #[structopt(
+            short = "o",
+            long = "--output",
+            parse(from_os_str),
+            help = "File to write the converted copy to."
+        )]
+        output: PathBuf,
+
+        #[structopt(
+            name = "FILE",
+            parse(from_os_str),
+            help = "Capture to convert, which is decompressed if gzip or Zstandard compressed. Standard input if \"-\" or absent."
+        )]
+        file: Option<PathBuf>,
+    },
+
+    /// Attaches to the target through a debug probe, sets up SWO and
+    /// decodes it, instead of reading FILE. Decoding options are given
+    /// before the subcommand.
+    #[cfg(feature = "probe-rs")]
+    Capture {
+        #[structopt(
+            long = "--chip",
+            help = "Target chip, as named by probe-rs, e.g. STM32F411RETx."
+        )]
+        chip: String,
+
+        #[structopt(
+            long = "--probe",
+            value_name = "SELECTOR",
+            help = "The probe to capture with, matched by USB VID:PID or a substring of its serial number, as listed by --list-devices. The single probe if absent."
+        )]
+        probe: Option<String>,
+
+        #[structopt(
+            long = "--trace-clock",
+            help = "Frequency of the TPIU clock of the target, usually the core clock, in Hz. Also the --clock-frequency, unless given."
+        )]
+        trace_clock: u32,
+
+        #[structopt(
+            long = "--baud",
+            help = "SWO bit rate, in bits per second, of which --trace-clock must be a multiple."
+        )]
+        baud: u32,
+
+        #[structopt(
+            long = "--speed",
+            help = "Speed of the debug interface, in kHz. The default of the probe if absent."
+        )]
+        speed: Option<u32>,
+    },
+}
 
-    let file = File::open(&opt.file).context("failed to open file")?;
-    if let Some(freq) = opt.freq {
-        serial::configure(&file, freq)?;
+fn main() -> Result<()> {
+    let mut opt = Opt::from_args();
+    if opt.clock_frequency.is_none() && !opt.emulator {
+        opt.clock_frequency = opt.freq;
+    }
+    #[cfg(feature = "probe-rs")]
+    if let Some(Command::Capture { trace_clock, .. }) = &opt.command {
+        if opt.clock_frequency.is_none() && !opt.emulator {
+            opt.clock_frequency = Some(*trace_clock);
+        }
+    }
+    if opt.timestamps && opt.clock_frequency.is_none() && !opt.emulator {
+        bail!("--timestamps requires --clock-frequency");
     }
 
-    let decoder = Decoder::<File>::new(
+    if let Some(Command::Convert {
+        trim_corrupt,
+        output,
         file,
+    }) = &opt.command
+    {
+        return convert(Source::new(file.clone()), output, *trim_corrupt);
+    }
+
+    if opt.list_devices {
+        for device in serial::devices()? {
+            println!("{}", device);
+        }
+        match cmsis_dap::probes() {
+            Ok(probes) => {
+                for probe in probes {
+                    println!("CMSIS-DAP {}", probe);
+                }
+            }
+            Err(e) => eprintln!("failed to list CMSIS-DAP probes: {}", e),
+        }
+        match stlink::probes() {
+            Ok(probes) => {
+                for probe in probes {
+                    println!("{}", probe);
+                }
+            }
+            Err(e) => eprintln!("failed to list ST-Links: {}", e),
+        }
+        #[cfg(feature = "probe-rs")]
+        for probe in probe::probes() {
+            println!("probe-rs {}", probe);
+        }
+        return Ok(());
+    }
+
+    let mut check = if let (Some(path), Some(remote)) = (&opt.itm_config, &opt.gdb_remote) {
+        let config: TraceConfig =
+            toml::from_str(&fs::read_to_string(path).context("failed to read --itm-config file")?)
+                .context("failed to parse --itm-config file")?;
+        let registers = config.registers().context("invalid --itm-config")?;
+        gdb::program(remote, &registers).context("failed to program the target")?;
+        Some(ConfigCheck::new(config))
+    } else {
+        match target_registers(&opt.register)? {
+            Some(registers) => Some(ConfigCheck::new(
+                TraceConfig::from_registers(&registers).context("invalid --register values")?,
+            )),
+            None => None,
+        }
+    };
+
+    let source = if let Some(source) = capture_source(&opt) {
+        source
+    } else if let Some(selector) = &opt.device {
+        Source::File(serial::find(selector)?.path.into())
+    } else if let Some(address) = &opt.tcp {
+        Source::Tcp(address.clone())
+    } else if let Some(address) = &opt.jlink {
+        Source::JLink(if address.contains(':') {
+            address.clone()
+        } else {
+            format!("{}:2332", address)
+        })
+    } else if let Some(selector) = &opt.cmsis_dap {
+        Source::CmsisDap {
+            selector: selector.clone(),
+            options: cmsis_dap::SwoOptions {
+                baud_rate: opt.baud.context("--cmsis-dap requires --baud")?,
+                mode: if opt.manchester {
+                    cmsis_dap::SwoMode::Manchester
+                } else {
+                    cmsis_dap::SwoMode::Uart
+                },
+            },
+        }
+    } else if let Some(selector) = &opt.stlink {
+        Source::StLink {
+            selector: selector.clone(),
+            baud_rate: opt.baud.context("--stlink requires --baud")?,
+        }
+    } else if let Some(address) = &opt.udp {
+        Source::Udp {
+            address: address.clone(),
+            sequenced: opt.udp_sequenced,
+        }
+    } else if let Some(path) = &opt.unix {
+        Source::Unix {
+            path: path.clone(),
+            seqpacket: opt.unix_seqpacket,
+        }
+    } else if opt.follow {
+        match Source::new(opt.file.clone()) {
+            Source::File(path) => Source::Follow(path),
+            _ => bail!("--follow requires a file, not standard input"),
+        }
+    } else {
+        Source::new(opt.file.clone())
+    };
+
+    let ports = match &opt.ports {
+        Some(path) => ports::port_map(path)?,
+        None => PortMap::new(),
+    };
+
+    let symbolizer = match &opt.elf {
+        Some(path) => {
+            let elf = fs::read(path).context("failed to read ELF file")?;
+            Some(Symbolizer::from_elf(&elf).context("failed to load ELF file")?)
+        }
+        None => None,
+    };
+    // The data trace comparators, by which data trace packets are
+    // symbolized.
+    let comparators = Comparators {
+        addresses: match &check {
+            Some(check) => check
+                .config()
+                .dwt
+                .comparators
+                .iter()
+                .enumerate()
+                .filter_map(|(n, comparator)| Some((n as u8, comparator.as_ref()?.address)))
+                .collect(),
+            None => BTreeMap::new(),
+        },
+        types: opt.value_type.iter().cloned().collect(),
+    };
+
+    let mut out = match &opt.output {
+        Some(path) => Output::create(
+            path.clone(),
+            RotateOptions {
+                size: opt.rotate_size,
+                interval: opt.rotate_interval,
+            },
+        )?,
+        None => Output::stdout(),
+    };
+    let mut table = match opt.format {
+        Format::Human
+        | Format::Debug
+        | Format::Text
+        | Format::Json
+        | Format::Cbor
+        | Format::Msgpack => None,
+        format => {
+            let fields = match &opt.fields {
+                Some(fields) => Field::parse_list(fields)?,
+                None => Field::DEFAULT.to_vec(),
+            };
+            let mut table =
+                Table::new(out.clone(), format, fields, ports.clone()).wall_clock(opt.epoch);
+            table.header()?;
+            Some(table)
+        }
+    };
+
+    let mut records = Records::new(opt.format, out.clone());
+    let mut parquet = match &opt.parquet {
+        Some(path) => {
+            let file = File::create(path).context("failed to create Parquet file")?;
+            Some(ParquetWriter::new(
+                io::BufWriter::new(file),
+                ParquetOptions::default(),
+            )?)
+        }
+        None => None,
+    };
+    let mut sqlite = match &opt.sqlite {
+        Some(path) => {
+            let db = SqliteWriter::open(path).context("failed to open SQLite database")?;
+            Some(match &symbolizer {
+                Some(symbolizer) => db.symbols(symbolizer.symbols().clone()),
+                None => db,
+            })
+        }
+        None => None,
+    };
+
+    let mut monitor = match &opt.influx {
+        Some(dest) => {
+            let lines = LineProtocol::new(
+                ports.clone(),
+                InfluxOptions {
+                    watch: opt.influx_watch.iter().cloned().collect(),
+                },
+            );
+            Some(Monitor::new(lines, Sink::open(dest)?, opt.influx_interval))
+        }
+        None => None,
+    };
+    let mut websocket = match &opt.websocket {
+        Some(address) => Some(websocket::Server::bind(address)?),
+        None => None,
+    };
+
+    let irq_names = match &opt.svd {
+        Some(svd) => svd::interrupts(svd)?,
+        None => BTreeMap::new(),
+    };
+    let mut filter = ExceptionFilter::new();
+    if let Some(exception) = &opt.exception {
+        filter = filter.include(exception, &irq_names)?;
+    }
+    if let Some(exception) = &opt.exclude_exception {
+        filter = filter.exclude(exception, &irq_names)?;
+    }
+
+    let mut human = match opt.format {
+        Format::Human => Some(
+            Human::new(
+                out.clone(),
+                opt.color.enabled(opt.output.is_none()),
+                opt.compact,
+                ports.clone(),
+                &irq_names,
+            )
+            .wall_clock(opt.epoch),
+        ),
+        _ => None,
+    };
+
+    let start_on = match &opt.start_on {
+        Some(condition) => Some(Condition::parse(condition, &irq_names)?),
+        None => None,
+    };
+    let stop_after = match &opt.stop_after {
+        Some(condition) => Some(Condition::parse(condition, &irq_names)?),
+        None => None,
+    };
+    let pre_trigger = opt.pre_trigger.unwrap_or(0);
+    let recorder_options = match &opt.flight_recorder {
+        Some(dir) => Some(RecorderOptions {
+            dir: dir.clone(),
+            max_age: match (opt.recorder_duration, opt.recorder_size) {
+                (None, None) => Some(Duration::from_secs(10)),
+                (duration, _) => duration,
+            },
+            max_bytes: opt.recorder_size.map(|size| size as usize),
+            dump_on: opt
+                .dump_on
+                .iter()
+                .map(|condition| Condition::parse(condition, &irq_names))
+                .collect::<Result<_, _>>()?,
+        }),
+        None => None,
+    };
+
+    let mut alerts = match opt.alert.is_empty() {
+        true => None,
+        false => Some(Alerts::new(opt.alert.clone(), opt.alert_exec.clone())),
+    };
+
+    let mut packet_filter = PacketFilter::new();
+    if let Some(port) = &opt.port {
+        packet_filter = packet_filter.ports(port, &ports)?;
+    }
+    if let Some(only) = &opt.only {
+        packet_filter = packet_filter.only(only)?;
+    }
+    if let Some(exclude) = &opt.exclude {
+        packet_filter = packet_filter.exclude(exclude)?;
+    }
+
+    let mut port_outputs = match &opt.port_output {
+        Some(spec) => Some(PortOutputs::parse(spec, &ports)?),
+        None => None,
+    };
+
+    let discontinuity = Discontinuity::new();
+    let mut input: Box<dyn io::Read> = match opt.input_format {
+        InputFormat::Raw => match (&source, opt.baud) {
+            (Source::CmsisDap { .. } | Source::StLink { .. }, _) => source.open(&discontinuity)?,
+            #[cfg(feature = "probe-rs")]
+            (Source::Probe { .. }, _) => source.open(&discontinuity)?,
+            // A serial device
+            (Source::File(path), Some(baud)) => {
+                Box::new(serial::open(&path.to_string_lossy(), baud)?)
+            }
+            (_, Some(_)) => bail!("--baud requires a serial device, not {}", source),
+            (_, None) => source.open(&discontinuity)?,
+        },
+        format => {
+            let capture = io::Cursor::new(source.read_all()?);
+            let edges = match format {
+                InputFormat::Sigrok => logic::sigrok(capture, &opt.channel),
+                _ => logic::saleae_csv(capture, &opt.channel),
+            }
+            .context("failed to import logic-analyzer capture")?;
+            let bytes = if opt.manchester {
+                manchester::decode(&edges)
+            } else {
+                let baud = match opt.baud {
+                    Some(baud) => baud,
+                    None if opt.auto_baud => {
+                        let baud = nrz::detect_baud(&edges)
+                            .context("failed to detect the bit rate of the capture")?;
+                        eprintln!("detected bit rate: {} Bd", baud);
+                        baud
+                    }
+                    None => {
+                        bail!("--baud or --auto-baud is required for NRZ logic-analyzer captures")
+                    }
+                };
+                let mut decoder = NrzDecoder::new(baud);
+                let mut bytes = vec![];
+                for edge in edges {
+                    decoder.push(edge);
+                }
+                decoder.finish();
+                while let Some(byte) = decoder.pull() {
+                    match byte {
+                        Ok(byte) => bytes.push(byte),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                bytes
+            };
+            Box::new(io::Cursor::new(bytes))
+        }
+    };
+    if let Some(skip) = opt.skip_bytes {
+        let skipped = io::copy(&mut (&mut input).take(skip), &mut io::sink())
+            .context("failed to skip input")?;
+        if skipped < skip {
+            bail!(
+                "the input ends after {} bytes, before --skip-bytes",
+                skipped
+            );
+        }
+        // The input now starts at an arbitrary point.
+        discontinuity.signal();
+    }
+    let input: Box<dyn io::Read> = match opt.max_bytes {
+        Some(max) => Box::new(input.take(max)),
+        None => input,
+    };
+    let input: Box<dyn io::Read> = match &opt.pcapng {
+        Some(path) => {
+            let capture = File::create(path).context("failed to create pcapng file")?;
+            let interface = source.to_string();
+            let writer = PcapngWriter::new(
+                capture,
+                PcapngOptions {
+                    interface,
+                    ..PcapngOptions::default()
+                },
+            )?;
+            Box::new(PcapngTee::new(input, writer))
+        }
+        None => input,
+    };
+
+    let input: Box<dyn io::Read> = match &opt.archive {
+        Some(path) => {
+            let archive = File::create(path).context("failed to create archive")?;
+            Box::new(ZstdTee::new(input, archive, 0).context("failed to create archive")?)
+        }
+        None => input,
+    };
+
+    let input = CountingReader::new(input);
+    let bytes = input.count();
+    let input: Box<dyn io::Read> = Box::new(input);
+    let mut trace_stats = TraceStats::new();
+    let stats_format = opt.stats.then(|| {
+        opt.stats_format
+            .clone()
+            .unwrap_or_else(|| "text".to_string())
+    });
+
+    let decoder = Decoder::<ArrivalReader<Box<dyn io::Read>>>::new(
+        ArrivalReader::new(input),
         DecoderOptions {
             ignore_eof: opt.ignore_eof,
+            recovery: if opt.skip_to_sync {
+                RecoveryPolicy::SkipToSync
+            } else if opt.report_unknown {
+                RecoveryPolicy::ReportUnknown
+            } else {
+                RecoveryPolicy::Continue
+            },
+            arch: if opt.armv8m {
+                ArchVersion::V8M
+            } else {
+                ArchVersion::V7M
+            },
+            track_stimulus_page: opt.track_stimulus_page,
+            max_buffered: None,
+            lenient_sync: opt.emulator,
         },
-    );
+    )
+    .resync_on(discontinuity);
+
+    let schemas = match &opt.schema {
+        Some(path) => ports::schemas(path)?,
+        None => SchemaDecoder::new(),
+    };
+    let mut payloads = Payloads::new(schemas, &ports);
 
     match opt {
         Opt {
             timestamps: true,
             prescaler,
-            freq: Some(freq),
+            clock_frequency,
+            emulator,
             expect_malformed,
+            best_effort,
+            drift,
+            correct_drift,
+            exception_stats,
+            latency,
+            format,
+            epoch,
+            from,
+            to,
+            chrome_trace,
+            count,
+            duration,
+            replay,
+            speed,
             ..
-        } => {
-            for packets in decoder.timestamps(TimestampsConfiguration {
-                clock_frequency: freq,
-                lts_prescaler: match prescaler {
-                    None | Some(1) => LocalTimestampOptions::Enabled,
-                    Some(4) => LocalTimestampOptions::EnabledDiv4,
-                    Some(16) => LocalTimestampOptions::EnabledDiv16,
-                    Some(64) => LocalTimestampOptions::EnabledDiv64,
-                    Some(n) => bail!(
-                        "{} is not a valid prescaler; valid prescalers are: 4, 16, 64.",
-                        n
+        } if clock_frequency.is_some() || emulator => {
+            let clock = epoch.unwrap_or_else(WallClock::now);
+            let mut link = LinkLatency::new();
+            let mut stats = ExceptionStats::new();
+            let mut chrome = ChromeTrace::new(ports.clone());
+            let mut events = vec![];
+            let mut last = None;
+            // Sequence number of the next output packet
+            let mut seq = 0;
+            let timeline = match clock_frequency {
+                Some(clock_frequency) => Timeline::Target(decoder.timestamps(TimestampsConfiguration {
+                    clock_frequency,
+                    lts_prescaler: match (prescaler, &check) {
+                        (None, Some(check)) => match check.config().itm.local_timestamps {
+                            LocalTimestampOptions::Disabled => {
+                                bail!("--timestamps requires local timestamps, which the trace configuration disables")
+                            }
+                            lts_prescaler => lts_prescaler,
+                        },
+                        (prescaler, _) => match prescaler {
+                        None | Some(1) => LocalTimestampOptions::Enabled,
+                        Some(4) => LocalTimestampOptions::EnabledDiv4,
+                        Some(16) => LocalTimestampOptions::EnabledDiv16,
+                        Some(64) => LocalTimestampOptions::EnabledDiv64,
+                        Some(n) => bail!(
+                            "{} is not a valid prescaler; valid prescalers are: 4, 16, 64.",
+                            n
+                        ),
+                        },
+                    },
+                    expect_malformed,
+                    best_effort,
+                    correct_drift,
+                })),
+                None => Timeline::Host(HostTimestamps::new(decoder)),
+            };
+            let mut it = Trigger::new(start_on, stop_after, pre_trigger).over(Window::new(
+                timeline,
+                (
+                    from.map_or(Bound::Unbounded, Bound::Included),
+                    to.map_or(Bound::Unbounded, Bound::Excluded),
+                ),
+            ));
+            let mut recorder = recorder_options.map(Recorder::new);
+            let mut remaining = count;
+            let mut start = None;
+            // Host time at which, and offset of, the first set replayed
+            let mut replayed: Option<(Instant, Duration)> = None;
+            // The next set is not awaited once --count packets have been
+            // output.
+            while let Some(mut packets) = (remaining != Some(0)).then(|| it.next()).flatten() {
+                if let (Some(duration), Ok(packets)) = (duration, &packets) {
+                    let offset = packets.timestamp.offset();
+                    if offset.saturating_sub(*start.get_or_insert(offset)) >= duration {
+                        break;
+                    }
+                }
+                if let (true, Ok(packets)) = (replay, &packets) {
+                    let offset = packets.timestamp.offset();
+                    let (started, first) = *replayed.get_or_insert((Instant::now(), offset));
+                    let due = offset.saturating_sub(first).div_f64(speed.unwrap_or(1.0));
+                    if let Some(wait) = due.checked_sub(started.elapsed()) {
+                        if let Some(records) = &mut records {
+                            records.flush()?;
+                        }
+                        out.flush()?;
+                        std::thread::sleep(wait);
+                    }
+                }
+                if let (true, Ok(packets), Some(arrival)) =
+                    (latency, &packets, it.get_ref().get_ref().last_arrival())
+                {
+                    let sample = link.record(packets.timestamp.offset(), arrival);
+                    eprintln!(
+                        "latency: {:?} (jitter: {:?})",
+                        sample.latency, sample.jitter
+                    );
+                }
+
+                if let (Ok(packets), false) = (&packets, format == Format::Debug) {
+                    if let Some(gap) = &packets.overflow {
+                        eprintln!(
+                            "overflow: trace data lost between {:?} and {:?}",
+                            gap.start, gap.end
+                        );
+                    }
+                }
+
+                // Sequence numbers of the output packets of the set
+                let mut seqs = vec![];
+                if let Ok(packets) = &mut packets {
+                    if packets.packets.contains(&TracePacket::Sync) {
+                        rotate(&out, &mut table, &mut records)?;
+                    }
+                    if let Some(check) = &mut check {
+                        for packet in packets.packets.iter() {
+                            warn_inconsistent(check, packet);
+                        }
+                    }
+                    stats.push_set(packets);
+                    trace_stats.push_set(packets);
+                    packets
+                        .packets
+                        .retain(|packet| filter.retain(packet) && packet_filter.retain(packet));
+                    if let Some(remaining) = &mut remaining {
+                        packets.packets.truncate(*remaining);
+                        *remaining -= packets.packets.len();
+                    }
+                    seqs.extend(seq..seq + packets.packets.len() as u64);
+                    seq += packets.packets.len() as u64;
+                    if let Some(parquet) = &mut parquet {
+                        for (seq, packet) in seqs.iter().zip(packets.packets.iter()) {
+                            parquet.row(*seq, packet, Some(&packets.timestamp))?;
+                        }
+                    }
+                    if let Some(sqlite) = &mut sqlite {
+                        for (seq, packet) in seqs.iter().zip(packets.packets.iter()) {
+                            sqlite.row(*seq, packet, Some(&packets.timestamp))?;
+                        }
+                    }
+                    if let Some(monitor) = &mut monitor {
+                        for packet in packets.packets.iter() {
+                            monitor.push(packet, clock.at(&packets.timestamp))?;
+                        }
+                    }
+                    if let Some(alerts) = &mut alerts {
+                        for packet in packets.packets.iter() {
+                            alerts.push(packet, Some(packets.timestamp.offset()))?;
+                        }
+                    }
+                    if let Some(websocket) = &mut websocket {
+                        for (seq, packet) in seqs.iter().zip(packets.packets.iter()) {
+                            websocket.send(Sequenced {
+                                seq: *seq,
+                                item: packet,
+                            })?;
+                        }
+                    }
+                    if chrome_trace.is_some() {
+                        chrome.push_set(packets);
+                        events.extend(std::iter::from_fn(|| chrome.pull()));
+                        last = Some(packets.timestamp.clone());
+                    }
+                    if let Some(port_outputs) = &mut port_outputs {
+                        port_outputs.route(&mut packets.packets, &mut seqs)?;
+                    }
+                }
+
+                // A set is numbered by its first packet, or if it has none,
+                // by the next packet.
+                let set_seq = seqs.first().copied().unwrap_or(seq);
+                if let (Ok(packets), Some(recorder)) = (&packets, &mut recorder) {
+                    recorder.push(
+                        packets.timestamp.offset(),
+                        Sequenced {
+                            seq: set_seq,
+                            item: packets.clone(),
+                        },
+                    )?;
+                    continue;
+                }
+                if let (Ok(packets), Some(records)) = (&packets, &mut records) {
+                    records.write_at(
+                        &Sequenced {
+                            seq: set_seq,
+                            item: packets,
+                        },
+                        &packets.timestamp,
+                        epoch.as_ref(),
+                    )?;
+                    continue;
+                }
+                if let (Ok(packets), Some(human)) = (&packets, &mut human) {
+                    let time = Some(&packets.timestamp);
+                    for malformed in packets.malformed_packets.iter() {
+                        human.malformed(time, malformed)?;
+                    }
+                    for (seq, packet) in seqs.iter().zip(packets.packets.iter()) {
+                        if payloads.push(packet) {
+                            while let Some(decoded) = payloads.pull() {
+                                human.decoded(time, *seq, &decoded)?;
+                            }
+                            continue;
+                        }
+                        human.packet(
+                            time,
+                            Sequenced {
+                                seq: *seq,
+                                item: packet,
+                            },
+                            &symbolize(symbolizer.as_ref(), &comparators, packet),
+                        )?;
+                    }
+                    continue;
+                }
+
+                match (packets, &mut table) {
+                    (Err(e), _) => return Err(e).context("Decoder error"),
+                    (Ok(packets), None) if format == Format::Text => {
+                        for packet in packets.packets {
+                            match &epoch {
+                                Some(epoch) => writeln!(
+                                    out,
+                                    "{}\t{}{}",
+                                    epoch.rfc3339(&packets.timestamp),
+                                    ports.display(&packet),
+                                    symbolize(symbolizer.as_ref(), &comparators, &packet)
+                                )?,
+                                None => writeln!(
+                                    out,
+                                    "{:?}\t{}{}",
+                                    packets.timestamp.offset(),
+                                    ports.display(&packet),
+                                    symbolize(symbolizer.as_ref(), &comparators, &packet)
+                                )?,
+                            }
+                        }
+                    }
+                    (Ok(mut packets), None) => {
+                        packets.packets.retain(|packet| !payloads.push(packet));
+                        let time = match &epoch {
+                            Some(epoch) => epoch.rfc3339(&packets.timestamp),
+                            None => format!("{:?}", packets.timestamp.offset()),
+                        };
+                        match &epoch {
+                            Some(_) => writeln!(out, "{}\t{:?}", time, packets)?,
+                            None => writeln!(out, "{:?}", packets)?,
+                        }
+                        while let Some(decoded) = payloads.pull() {
+                            let port = ports.label(decoded.port());
+                            let fields = decoded.fields().join("\t");
+                            writeln!(out, "{}\t{}\t{}", time, port, fields)?;
+                        }
+                    }
+                    (Ok(packets), Some(table)) => {
+                        for malformed in packets.malformed_packets {
+                            eprintln!("{}", malformed);
+                        }
+                        for (seq, packet) in seqs.into_iter().zip(packets.packets) {
+                            table.row(seq, &packet, Some(&packets.timestamp))?;
+                        }
+                    }
+                }
+            }
+
+            if let Some(recorder) = &mut recorder {
+                recorder.finish()?;
+            }
+            if let Some(alerts) = &mut alerts {
+                alerts.finish()?;
+            }
+
+            if let Some(path) = &chrome_trace {
+                if let Some(last) = &last {
+                    chrome.finish(last);
+                    events.extend(std::iter::from_fn(|| chrome.pull()));
+                }
+                let file = File::create(path).context("failed to create Chrome trace file")?;
+                let mut writer = io::BufWriter::new(file);
+                serde_json::to_writer(&mut writer, &serde_json::json!({ "traceEvents": events }))
+                    .map_err(io::Error::from)
+                    .and_then(|_| io::Write::flush(&mut writer))
+                    .context("failed to write Chrome trace")?;
+            }
+
+            if exception_stats {
+                eprintln!(
+                    "{:<20} {:>8} {:>14} {:>14} {:>14} {:>14} {:>10} {:>10}",
+                    "exception", "count", "total", "min", "max", "mean", "preempted", "preempting"
+                );
+                for (exception, stats) in stats.iter() {
+                    eprintln!(
+                        "{:<20} {:>8} {:>14} {:>14} {:>14} {:>14} {:>10} {:>10}",
+                        exception.to_string(),
+                        stats.count,
+                        format!("{:?}", stats.total),
+                        format!("{:?}", stats.min),
+                        format!("{:?}", stats.max),
+                        format!("{:?}", stats.mean()),
+                        stats.preempted,
+                        stats.preempting
+                    );
+                }
+            }
+
+            if drift {
+                match it.get_ref().get_ref().clock_drift() {
+                    Some(drift) => eprintln!(
+                        "clock drift: {:.3} ppm ({} global ticks per {} local ticks)",
+                        drift.ppm(),
+                        drift.global_ticks,
+                        drift.local_ticks
                     ),
-                },
-                expect_malformed,
-            }) {
-                match packets {
-                    Err(e) => return Err(e).context("Decoder error"),
-                    Ok(packets) => println!("{:?}", packets),
+                    None => eprintln!("clock drift: not enough global timestamps"),
                 }
             }
+
+            if latency {
+                let summary = link.summary();
+                eprintln!(
+                    "{} samples; latency: max {:?}, mean {:?}; jitter: {:?}",
+                    summary.samples, summary.max_latency, summary.mean_latency, summary.jitter
+                );
+            }
         }
         _ => {
-            let mut log_line: Vec<u8> = Vec::new();
-            for packet in decoder.singles() {
+            // Output moves on to the next piece at the next packet after a
+            // synchronization packet, whether or not that is output.
+            let synced = Cell::new(false);
+            let packets = Trigger::new(start_on, stop_after, pre_trigger).over(decoder.singles());
+            let packets = packets.filter(|packet| {
+                trace_stats.push_result(packet);
+                match packet {
+                    Ok(packet) => {
+                        if *packet == TracePacket::Sync {
+                            synced.set(true);
+                        }
+                        filter.retain(packet) && packet_filter.retain(packet)
+                    }
+                    Err(e @ DecoderError::Resynchronized { .. }) => {
+                        eprintln!("{}", e);
+                        false
+                    }
+                    Err(_) => true,
+                }
+            });
+            let packets = packets.take(opt.count.unwrap_or(usize::MAX));
+            let mut profile = Profile::new();
+            let mut stacks = CollapsedStacks::new();
+            let mut recorder = recorder_options.map(Recorder::new);
+            let started = Instant::now();
+            for Sequenced { seq, item: packet } in Sequence::new(packets) {
+                if synced.replace(false) {
+                    rotate(&out, &mut table, &mut records)?;
+                }
+                if let Ok(packet) = &packet {
+                    if let Some(check) = &mut check {
+                        warn_inconsistent(check, packet);
+                    }
+                    profile.push(packet);
+                    stacks.push(packet);
+                    if let Some(parquet) = &mut parquet {
+                        parquet.row(seq, packet, None)?;
+                    }
+                    if let Some(sqlite) = &mut sqlite {
+                        sqlite.row(seq, packet, None)?;
+                    }
+                    if let Some(monitor) = &mut monitor {
+                        monitor.push(packet, SystemTime::now())?;
+                    }
+                    if let Some(alerts) = &mut alerts {
+                        alerts.push(packet, None)?;
+                    }
+                    if let Some(websocket) = &mut websocket {
+                        websocket.send(Sequenced { seq, item: packet })?;
+                    }
+                    if let Some(port_outputs) = &mut port_outputs {
+                        if port_outputs.push(packet)? {
+                            continue;
+                        }
+                    }
+                }
+                if let Some(table) = &mut table {
+                    table.row(seq, &packet.context("Decoder error")?, None)?;
+                    continue;
+                }
+                if let Some(recorder) = &mut recorder {
+                    let packet = packet.context("Decoder error")?;
+                    recorder.push(started.elapsed(), Sequenced { seq, item: packet })?;
+                    continue;
+                }
+                if let Some(records) = &mut records {
+                    records.write(&Sequenced {
+                        seq,
+                        item: packet.context("Decoder error")?,
+                    })?;
+                    continue;
+                }
+                if let Some(human) = &mut human {
+                    let packet = packet.context("Decoder error")?;
+                    if payloads.push(&packet) {
+                        while let Some(decoded) = payloads.pull() {
+                            human.decoded(None, seq, &decoded)?;
+                        }
+                        continue;
+                    }
+                    human.packet(
+                        None,
+                        Sequenced { seq, item: &packet },
+                        &symbolize(symbolizer.as_ref(), &comparators, &packet),
+                    )?;
+                    continue;
+                }
+
                 match packet {
                     Err(e) => return Err(e).context("Decoder error"),
-                    Ok(TracePacket::Instrumentation { port, payload }) => {
-                        if payload.len() == 1 && payload[0] == 10 {
-                            match str::from_utf8(&log_line) {
-                                Ok(s) => println!("{port}\t{s}"),
-                                Err(e) => eprintln!("{e}"),
-                            }
-                            log_line.clear();
-                        } else {
-                            for c in payload {
-                                log_line.push(c);
-                            }
+                    Ok(packet) if opt.format == Format::Text => writeln!(
+                        out,
+                        "{}{}",
+                        ports.display(&packet),
+                        symbolize(symbolizer.as_ref(), &comparators, &packet)
+                    )?,
+                    Ok(packet) if payloads.push(&packet) => {
+                        while let Some(decoded) = payloads.pull() {
+                            let port = ports.label(decoded.port());
+                            writeln!(out, "{}\t{}", port, decoded.fields().join("\t"))?;
                         }
                     }
-                    Ok(packet) => println!("{:?}", packet),
+                    Ok(TracePacket::Instrumentation { port, payload }) => {
+                        writeln!(out, "{}\t{}", ports.label(port), hex(&payload))?
+                    }
+                    Ok(packet) => writeln!(out, "{:?}", packet)?,
                 }
             }
+
+            if let Some(recorder) = &mut recorder {
+                recorder.finish()?;
+            }
+            if let Some(alerts) = &mut alerts {
+                alerts.finish()?;
+            }
+
+            if opt.profile {
+                report_profile(&profile, symbolizer.as_ref().map(Symbolizer::symbols));
+            }
+            if let Some(path) = &opt.pprof {
+                let pb = pprof::encode(&profile, opt.sample_period, |pc| match &symbolizer {
+                    Some(symbolizer) => symbolizer.frames(pc),
+                    None => vec![],
+                });
+                fs::write(path, pb).context("failed to write pprof profile")?;
+            }
+            if let Some(path) = &opt.collapsed {
+                let file = File::create(path).context("failed to create collapsed stacks file")?;
+                let mut writer = io::BufWriter::new(file);
+                stacks
+                    .write(
+                        &mut writer,
+                        opt.collapsed_exceptions,
+                        |pc| match &symbolizer {
+                            Some(symbolizer) => symbolizer
+                                .frames(pc)
+                                .into_iter()
+                                .rev()
+                                .filter_map(|frame| frame.function)
+                                .collect(),
+                            None => vec![],
+                        },
+                    )
+                    .and_then(|_| io::Write::flush(&mut writer))
+                    .context("failed to write collapsed stacks")?;
+            }
+        }
+    }
+
+    if let Some(records) = &mut records {
+        records.flush()?;
+    }
+    out.flush()?;
+    if let Some(port_outputs) = &mut port_outputs {
+        port_outputs.flush()?;
+    }
+    if let Some(monitor) = &mut monitor {
+        monitor.finish()?;
+    }
+    if let Some(sqlite) = sqlite {
+        sqlite.close().context("failed to write SQLite database")?;
+    }
+    if let Some(parquet) = parquet {
+        io::Write::flush(&mut parquet.close()?).context("failed to write Parquet file")?;
+    }
+    if let Some(format) = stats_format {
+        trace_stats.bytes = bytes.get();
+        report_stats(&trace_stats, &ports, &format)?;
+    }
+
+    Ok(())
+}
+
+/// Reports the `--stats` summary.
+fn report_stats(stats: &TraceStats, ports: &PortMap, format: &str) -> Result<()> {
+    if format == "json" {
+        let mut report = serde_json::to_value(stats)?;
+        report["total_packets"] = stats.total_packets().into();
+        report["bandwidth"] = serde_json::json!(stats.bandwidth());
+        eprintln!("{}", report);
+        return Ok(());
+    }
+    let kinds: Vec<String> = stats
+        .packets
+        .iter()
+        .map(|(kind, count)| format!("{} {}", kind, count))
+        .collect();
+    eprintln!("packets: {} ({})", stats.total_packets(), kinds.join(", "));
+    eprintln!("bytes: {}", stats.bytes);
+    eprintln!("decode errors: {}", stats.errors);
+    eprintln!("overflows: {}", stats.overflows);
+    for (port, bytes) in stats.port_bytes.iter() {
+        eprintln!("port {}: {} bytes", ports.label(*port), bytes);
+    }
+    if let Some(duration) = stats.duration() {
+        eprintln!("time span: {:?}", duration);
+    }
+    if let Some(bandwidth) = stats.bandwidth() {
+        eprintln!("bandwidth: {:.0} B/s", bandwidth);
+    }
+    Ok(())
+}
+
+/// Reports the `--profile` histogram.
+fn report_profile(profile: &Profile, symbols: Option<&SymbolTable>) {
+    let rows: Vec<(String, u64, f64)> = match symbols {
+        Some(symbols) => profile
+            .by_symbol(symbols)
+            .into_iter()
+            .map(|e| {
+                let name = e.key.unwrap_or_else(|| "<unknown>".to_string());
+                (name, e.samples, e.percent)
+            })
+            .collect(),
+        None => profile
+            .by_address()
+            .into_iter()
+            .map(|e| (format!("{:#010x}", e.key), e.samples, e.percent))
+            .collect(),
+    };
+    for (key, samples, percent) in rows {
+        eprintln!("{:>10} {:>6.2}% {}", samples, percent, key);
+    }
+    if profile.sleeping() > 0 {
+        eprintln!(
+            "{:>10} {:>6.2}% <sleeping>",
+            profile.sleeping(),
+            profile.sleeping() as f64 * 100.0 / profile.total() as f64
+        );
+    }
+}
+
+/// What is known of the data trace comparators, by number.
+#[derive(Default)]
+struct Comparators {
+    addresses: BTreeMap<u8, u32>,
+    /// Types of the traced values, as given by `--value-type`.
+    types: BTreeMap<u8, VariableType>,
+}
+
+/// Describes the function frames of the PC value of a packet, if any,
+/// e.g. `\tfoo at src/foo.rs:3 <- main at src/main.rs:12`, or the
+/// variable of a data trace packet by the address of its comparator,
+/// and the value by the type of the variable or the comparator, e.g.
+/// `\tmotor_state.speed = 1200`.
+fn symbolize(
+    symbolizer: Option<&Symbolizer>,
+    comparators: &Comparators,
+    packet: &TracePacket,
+) -> String {
+    let variables = symbolizer.map(Symbolizer::variables);
+    let name = |variable: &VariableRef| match variable.offset {
+        0 => variable.path.clone(),
+        offset => format!("{}+{}", variable.path, offset),
+    };
+    let le = |bytes: &[u8]| bytes.iter().rev().fold(0u64, |acc, b| acc << 8 | *b as u64);
+    let pc = match packet {
+        TracePacket::PCSample { pc: Some(pc) } | TracePacket::DataTracePC { pc, .. } => *pc,
+        TracePacket::DataTraceValue {
+            comparator, value, ..
+        } => {
+            let variable = match (variables, comparators.addresses.get(comparator)) {
+                (Some(variables), Some(address)) => {
+                    variables.lookup_value(*address, value.len() as u32)
+                }
+                _ => None,
+            };
+            let ty = match (comparators.types.get(comparator), &variable) {
+                (Some(ty), _) => Some(ty),
+                (None, Some(variable)) if variable.offset == 0 => Some(variable.ty),
+                _ => None,
+            };
+            let value = match ty {
+                Some(ty) => ty.format_value(value),
+                None => le(value).to_string(),
+            };
+            return match variable {
+                Some(variable) => format!("\t{} = {}", name(&variable), value),
+                None if ty.is_some() => format!("\t{}", value),
+                None => String::new(),
+            };
+        }
+        TracePacket::DataTraceAddress { comparator, data } => {
+            // Unless the full address is traced, the high half is that
+            // of the comparator
+            let address = match (data.len(), comparators.addresses.get(comparator)) {
+                (4, _) => le(data) as u32,
+                (_, Some(address)) => address & !0xffff | le(data) as u32,
+                _ => return String::new(),
+            };
+            return match variables.and_then(|variables| variables.lookup(address)) {
+                Some(variable) => format!("\t{}", name(&variable)),
+                None => String::new(),
+            };
         }
+        _ => return String::new(),
+    };
+    let frames = match symbolizer {
+        Some(symbolizer) => symbolizer.frames(pc),
+        None => return String::new(),
+    };
+    if frames.is_empty() {
+        return String::new();
+    }
+    let frames: Vec<String> = frames.iter().map(ToString::to_string).collect();
+    format!("\t{}", frames.join(" <- "))
+}
+
+/// The source of the `capture` subcommand, if given.
+#[cfg(feature = "probe-rs")]
+fn capture_source(opt: &Opt) -> Option<Source> {
+    match &opt.command {
+        Some(Command::Capture {
+            chip,
+            probe,
+            trace_clock,
+            baud,
+            speed,
+        }) => Some(Source::Probe {
+            selector: probe.clone(),
+            options: probe::CaptureOptions {
+                chip: chip.clone(),
+                trace_clock: *trace_clock,
+                baud_rate: *baud,
+                speed: *speed,
+            },
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "probe-rs"))]
+fn capture_source(_: &Opt) -> Option<Source> {
+    None
+}
+
+/// Parses the `--epoch` option.
+fn parse_epoch(s: &str) -> Result<WallClock> {
+    if s == "now" {
+        return Ok(WallClock::now());
+    }
+    let epoch = humantime::parse_rfc3339_weak(s)
+        .with_context(|| format!("{:?} is not an RFC 3339 time", s))?;
+    Ok(WallClock::new(epoch))
+}
+
+/// Parses a watched comparator, e.g. `1=motor_speed`.
+fn parse_watch(s: &str) -> Result<(u8, String)> {
+    let (comparator, name) = s
+        .split_once('=')
+        .with_context(|| format!("{:?} is not of the form COMPARATOR=NAME", s))?;
+    let comparator = comparator
+        .parse()
+        .with_context(|| format!("{:?} is not a valid comparator", comparator))?;
+    Ok((comparator, name.to_string()))
+}
+
+/// Parses the type of a comparator, e.g. `1=i16`.
+fn parse_value_type(s: &str) -> Result<(u8, VariableType)> {
+    let (comparator, ty) = s
+        .split_once('=')
+        .with_context(|| format!("{:?} is not of the form COMPARATOR=TYPE", s))?;
+    let comparator = comparator
+        .parse()
+        .with_context(|| format!("{:?} is not a valid comparator", comparator))?;
+    Ok((comparator, ty.parse()?))
+}
+
+/// Parses a register value, e.g. `ITM_TCR=0x1000b`.
+fn parse_register(s: &str) -> Result<(String, u32)> {
+    let (name, value) = s
+        .split_once('=')
+        .with_context(|| format!("{:?} is not of the form NAME=VALUE", s))?;
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
     }
+    .with_context(|| format!("{:?} is not a valid register value", value))?;
+    Ok((name.to_uppercase(), value))
+}
 
+/// Collects the --register values, if any.
+fn target_registers(values: &[(String, u32)]) -> Result<Option<Registers>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    let value = |name: &str| {
+        values
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| *value)
+            .with_context(|| {
+                format!(
+                    "--register {}=VALUE is required along with the others",
+                    name
+                )
+            })
+    };
+    let mut registers = Registers {
+        itm_tcr: value("ITM_TCR")?,
+        itm_ter: value("ITM_TER")?,
+        dwt_ctrl: value("DWT_CTRL")?,
+        ..Registers::default()
+    };
+    for (name, value) in values {
+        if matches!(name.as_str(), "ITM_TCR" | "ITM_TER" | "DWT_CTRL") {
+            continue;
+        }
+        let (field, n): (fn(&mut ComparatorRegisters) -> &mut u32, _) =
+            if let Some(n) = name.strip_prefix("DWT_COMP") {
+                (|c| &mut c.comp, n)
+            } else if let Some(n) = name.strip_prefix("DWT_MASK") {
+                (|c| &mut c.mask, n)
+            } else if let Some(n) = name.strip_prefix("DWT_FUNCTION") {
+                (|c| &mut c.function, n)
+            } else {
+                bail!("unknown register {:?}", name);
+            };
+        let n: usize = n
+            .parse()
+            .ok()
+            .filter(|n| *n < 16)
+            .with_context(|| format!("unknown register {:?}", name))?;
+        if registers.dwt_comparators.len() <= n {
+            registers
+                .dwt_comparators
+                .resize(n + 1, ComparatorRegisters::default());
+        }
+        *field(&mut registers.dwt_comparators[n]) = *value;
+    }
+    Ok(Some(registers))
+}
+
+/// Warns of `packet` if the trace configuration rules it out, once per
+/// kind of inconsistency.
+fn warn_inconsistent(check: &mut ConfigCheck, packet: &TracePacket) {
+    if let Some(inconsistency) = check.push(packet) {
+        eprintln!(
+            "warning: the stream contains {}, contrary to the trace configuration",
+            inconsistency
+        );
+    }
+}
+
+/// Moves output on to the next --output file, if the current one is
+/// full, at a synchronization packet.
+fn rotate(
+    out: &Output,
+    table: &mut Option<Table<Output>>,
+    records: &mut Option<Records<Output>>,
+) -> Result<()> {
+    if let Some(records) = records {
+        records.flush()?;
+    }
+    if out.rotate()? {
+        if let Some(table) = table {
+            table.header()?;
+        }
+    }
     Ok(())
 }
+
+/// Parses a replay speed, e.g. `10x`, `0.5x` or `2`.
+fn parse_speed(s: &str) -> Result<f64> {
+    match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => bail!("{:?} is not a valid speed, e.g. 10x", s),
+    }
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix for KiB,
+/// MiB or GiB.
+fn parse_size(s: &str) -> Result<u64> {
+    let (digits, shift) = match s.trim_end_matches(['B', 'i']) {
+        s if s.ends_with(['K', 'k']) => (&s[..s.len() - 1], 10),
+        s if s.ends_with('M') => (&s[..s.len() - 1], 20),
+        s if s.ends_with('G') => (&s[..s.len() - 1], 30),
+        s => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .with_context(|| format!("{:?} is not a valid size, e.g. 256M", s))
+}
+
+/// The timeline of decoded packets: reconstructed from the timestamp
+/// packets of the target, or with --emulator, the host arrival times.
+enum Timeline {
+    Target(Timestamps<ArrivalReader<Box<dyn io::Read>>>),
+    Host(HostTimestamps<Box<dyn io::Read>>),
+}
+
+impl Timeline {
+    fn last_arrival(&self) -> Option<Instant> {
+        match self {
+            Timeline::Target(it) => it.get_ref().last_arrival(),
+            Timeline::Host(it) => it.get_ref().last_arrival(),
+        }
+    }
+
+    fn clock_drift(&self) -> Option<ClockDrift> {
+        match self {
+            Timeline::Target(it) => it.clock_drift(),
+            Timeline::Host(_) => None,
+        }
+    }
+}
+
+impl Iterator for Timeline {
+    type Item = Result<TimestampedTracePackets, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Timeline::Target(it) => it.next(),
+            Timeline::Host(it) => it.next(),
+        }
+    }
+}
+
+/// Formats bytes as a contiguous hexadecimal string.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Writes a copy of the capture of `source` to `output`, with any
+/// corrupt tail trimmed if `trim_corrupt`.
+fn convert(source: Source, output: &Path, trim_corrupt: bool) -> Result<()> {
+    if !trim_corrupt {
+        bail!("convert requires a conversion, e.g. --trim-corrupt");
+    }
+    let capture = source.read_all()?;
+    let trimmed = trim_corrupt_tail(&capture);
+    fs::write(output, &capture[..trimmed.len]).context("failed to write trimmed copy")?;
+    eprintln!(
+        "kept {} of {} bytes ({} packets); dropped {} packets and {} malformed packets",
+        trimmed.len,
+        capture.len(),
+        trimmed.packets,
+        trimmed.dropped_packets,
+        trimmed.dropped_errors
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Opt, structopt::clap::Error> {
+        Opt::from_iter_safe(std::iter::once("itm-decode").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn stats_options() {
+        let opt = parse(&["trace.bin"]).unwrap();
+        assert!(!opt.stats);
+        assert_eq!(opt.stats_format, None);
+
+        let opt = parse(&["--stats", "trace.bin"]).unwrap();
+        assert!(opt.stats);
+        assert_eq!(opt.stats_format, None);
+
+        let opt = parse(&["--stats", "--stats-format", "json", "trace.bin"]).unwrap();
+        assert_eq!(opt.stats_format.as_deref(), Some("json"));
+
+        assert!(parse(&["--stats-format", "json", "trace.bin"]).is_err());
+        assert!(parse(&["--stats", "--stats-format", "yaml", "trace.bin"]).is_err());
+    }
+
+    #[test]
+    fn profiles_without_timestamps() {
+        assert!(parse(&["--profile", "trace.bin"]).is_ok());
+        assert!(parse(&["--timestamps", "--profile", "trace.bin"]).is_err());
+        assert!(parse(&["--collapsed", "out.folded", "trace.bin"]).is_ok());
+        assert!(parse(&["--timestamps", "--collapsed", "out.folded", "trace.bin"]).is_err());
+        assert!(parse(&["--pprof", "out.pb", "trace.bin"]).is_ok());
+        assert!(parse(&["--timestamps", "--pprof", "out.pb", "trace.bin"]).is_err());
+    }
+
+    #[test]
+    fn speeds() {
+        for (speed, factor) in [("10x", 10.0), ("0.5x", 0.5), ("2", 2.0)] {
+            assert_eq!(parse_speed(speed).unwrap(), factor, "{}", speed);
+        }
+        for invalid in ["", "x", "0x", "-1x", "infx", "NaN", "fast"] {
+            assert!(parse_speed(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        for (size, bytes) in [
+            ("0", 0),
+            ("512", 512),
+            ("512B", 512),
+            ("4k", 4 << 10),
+            ("4K", 4 << 10),
+            ("4KiB", 4 << 10),
+            ("256M", 256 << 20),
+            ("256MiB", 256 << 20),
+            ("2G", 2 << 30),
+            ("2GiB", 2 << 30),
+        ] {
+            assert_eq!(parse_size(size).unwrap(), bytes, "{}", size);
+        }
+        for invalid in ["", "M", "-1", "1.5M", "4T", "4m", "99999999999G"] {
+            assert!(parse_size(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn convert_command() {
+        let opt = parse(&["trace.bin"]).unwrap();
+        assert_eq!(opt.file, Some(PathBuf::from("trace.bin")));
+        assert!(opt.command.is_none());
+
+        let opt = parse(&["convert", "--trim-corrupt", "-o", "clean.bin", "trace.bin"]).unwrap();
+        assert_eq!(opt.file, None);
+        match opt.command {
+            Some(Command::Convert {
+                trim_corrupt,
+                output,
+                file,
+            }) => {
+                assert!(trim_corrupt);
+                assert_eq!(output, PathBuf::from("clean.bin"));
+                assert_eq!(file, Some(PathBuf::from("trace.bin")));
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        assert!(parse(&["convert", "--trim-corrupt", "trace.bin"]).is_err());
+    }
+
+    #[cfg(feature = "probe-rs")]
+    #[test]
+    fn capture_command() {
+        let opt = parse(&[
+            "--timestamps",
+            "capture",
+            "--chip",
+            "STM32F411RETx",
+            "--trace-clock",
+            "16000000",
+            "--baud",
+            "2000000",
+        ])
+        .unwrap();
+        assert!(opt.timestamps);
+        match capture_source(&opt) {
+            Some(Source::Probe { selector, options }) => {
+                assert_eq!(selector, None);
+                assert_eq!(
+                    options,
+                    probe::CaptureOptions {
+                        chip: "STM32F411RETx".to_string(),
+                        trace_clock: 16_000_000,
+                        baud_rate: 2_000_000,
+                        speed: None,
+                    }
+                );
+            }
+            source => panic!("unexpected source {:?}", source),
+        }
+
+        assert!(parse(&["capture", "--chip", "STM32F411RETx", "--baud", "2000000"]).is_err());
+        assert!(capture_source(&parse(&["trace.bin"]).unwrap()).is_none());
+    }
+}
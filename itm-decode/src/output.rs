@@ -0,0 +1,329 @@
+//! Destination of decoded output: stdout, or a file that is split into
+//! pieces by size or age.
+//!
+//! Pieces after the first are named after the file, with their number
+//! before the extension, e.g. `capture.json`, `capture.1.json`,
+//! `capture.2.json`. Output only moves on to the next piece at a
+//! synchronization packet, so that each piece can be used on its own.
+//!
+//! The instrumentation payloads of selected stimulus ports can instead
+//! be routed to outputs of their own. See [`PortOutputs`].
+
+use anyhow::{bail, Context, Result};
+use itm::{PortEncoding, PortMap, TracePacket};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// When output moves on to the next piece.
+#[derive(Debug, Default)]
+pub struct RotateOptions {
+    /// Number of bytes after which the piece is full.
+    pub size: Option<u64>,
+
+    /// Time after which the piece is full.
+    pub interval: Option<Duration>,
+}
+
+/// A handle to the output, shared by the writers of all formats.
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<Sink>>);
+
+enum Sink {
+    Stdout(io::Stdout),
+    Files(Pieces),
+}
+
+struct Pieces {
+    path: PathBuf,
+    options: RotateOptions,
+    /// Number of the current piece.
+    index: usize,
+    file: BufWriter<File>,
+    /// Bytes written to the current piece.
+    written: u64,
+    opened: Instant,
+}
+
+/// The path of piece `index` of the output at `path`.
+fn piece_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+impl Pieces {
+    fn full(&self) -> bool {
+        self.options.size.iter().any(|size| self.written >= *size)
+            || self
+                .options
+                .interval
+                .iter()
+                .any(|interval| self.opened.elapsed() >= *interval)
+    }
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self(Rc::new(RefCell::new(Sink::Stdout(io::stdout()))))
+    }
+
+    /// Creates the first piece of output at `path`.
+    pub fn create(path: PathBuf, options: RotateOptions) -> Result<Self> {
+        if options.size == Some(0) || options.interval == Some(Duration::ZERO) {
+            bail!("output pieces must not be empty");
+        }
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self(Rc::new(RefCell::new(Sink::Files(Pieces {
+            path,
+            options,
+            index: 0,
+            file: BufWriter::new(file),
+            written: 0,
+            opened: Instant::now(),
+        })))))
+    }
+
+    /// Moves output on to the next piece if the current one is full.
+    /// To be called at synchronization packets, after the writers of
+    /// `self` have been flushed. Returns whether a new piece was started.
+    pub fn rotate(&self) -> Result<bool> {
+        let mut sink = self.0.borrow_mut();
+        let pieces = match &mut *sink {
+            Sink::Files(pieces) if pieces.full() => pieces,
+            _ => return Ok(false),
+        };
+        pieces.file.flush()?;
+        let path = piece_path(&pieces.path, pieces.index + 1);
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        pieces.index += 1;
+        pieces.file = BufWriter::new(file);
+        pieces.written = 0;
+        pieces.opened = Instant::now();
+        Ok(true)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.0.borrow_mut() {
+            Sink::Stdout(stdout) => stdout.write(buf),
+            Sink::Files(pieces) => {
+                let n = pieces.file.write(buf)?;
+                pieces.written += n as u64;
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.0.borrow_mut() {
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::Files(pieces) => pieces.file.flush(),
+        }
+    }
+}
+
+/// Outputs of the payloads written to individual stimulus ports, e.g.
+/// console text on port 0 and binary telemetry on port 1.
+///
+/// Payloads are written to files as is, i.e. as the data the target
+/// wrote to the port. On the console, those of ports with a binary
+/// [`PortEncoding`] are written as a line of hex digits each.
+pub struct PortOutputs {
+    /// Output of each routed port, by index into `sinks`.
+    ports: BTreeMap<u8, usize>,
+    sinks: Vec<PortSink>,
+    encodings: PortMap,
+}
+
+enum PortSink {
+    Console(io::Stdout),
+    File(PathBuf, BufWriter<File>),
+}
+
+impl PortOutputs {
+    /// Parses a comma-separated list of `PORT=DEST` routes, where PORT
+    /// is a port number or a name in `ports`, and DEST is `console` or
+    /// a file path. Ports routed to the same file share it.
+    pub fn parse(spec: &str, ports: &PortMap) -> Result<Self> {
+        let mut outputs = Self {
+            ports: BTreeMap::new(),
+            sinks: vec![],
+            encodings: ports.clone(),
+        };
+        for route in spec.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            let (port, dest) = match route.split_once('=') {
+                Some((port, dest)) => (port.trim(), dest.trim()),
+                None => bail!("{:?} is not a valid port output; expected PORT=DEST", route),
+            };
+            let port = match port.parse().ok().or_else(|| ports.port(port)) {
+                Some(port) => port,
+                None => bail!("{:?} is not a valid stimulus port", port),
+            };
+            let index = match outputs.sinks.iter().position(|sink| sink.is(dest)) {
+                Some(index) => index,
+                None => {
+                    outputs.sinks.push(PortSink::open(dest)?);
+                    outputs.sinks.len() - 1
+                }
+            };
+            if outputs.ports.insert(port, index).is_some() {
+                bail!("stimulus port {} is routed more than once", port);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Writes the payloads of the routed instrumentation packets among
+    /// `packets` to their outputs, and removes those packets, along
+    /// with their sequence numbers among `seqs`.
+    pub fn route(&mut self, packets: &mut Vec<TracePacket>, seqs: &mut Vec<u64>) -> io::Result<()> {
+        let mut retained = Vec::with_capacity(packets.len());
+        for packet in packets.iter() {
+            retained.push(!self.push(packet)?);
+        }
+        let mut keep = retained.iter();
+        packets.retain(|_| *keep.next().unwrap());
+        let mut keep = retained.iter();
+        seqs.retain(|_| *keep.next().unwrap());
+        Ok(())
+    }
+
+    /// Writes the payload of `packet` to its output, if it is an
+    /// instrumentation packet of a routed port. Returns whether it was.
+    pub fn push(&mut self, packet: &TracePacket) -> io::Result<bool> {
+        let (port, payload) = match packet {
+            TracePacket::Instrumentation { port, payload } => (*port, payload),
+            _ => return Ok(false),
+        };
+        let sink = match self.ports.get(&port) {
+            Some(index) => &mut self.sinks[*index],
+            None => return Ok(false),
+        };
+        match (sink, self.encodings.encoding(port)) {
+            (PortSink::Console(stdout), Some(PortEncoding::Binary | PortEncoding::Cobs)) => {
+                let hex: String = payload.iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(stdout, "{}", hex)?;
+            }
+            (PortSink::Console(stdout), _) => stdout.write_all(payload)?,
+            (PortSink::File(_, file), _) => file.write_all(payload)?,
+        }
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        for sink in self.sinks.iter_mut() {
+            match sink {
+                PortSink::Console(stdout) => stdout.flush()?,
+                PortSink::File(_, file) => file.flush()?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PortSink {
+    fn open(dest: &str) -> Result<Self> {
+        if dest == "console" {
+            return Ok(PortSink::Console(io::stdout()));
+        }
+        let file = File::create(dest).with_context(|| format!("failed to create {}", dest))?;
+        Ok(PortSink::File(dest.into(), BufWriter::new(file)))
+    }
+
+    /// Whether this is the output `dest`.
+    fn is(&self, dest: &str) -> bool {
+        match self {
+            PortSink::Console(_) => dest == "console",
+            PortSink::File(path, _) => path == Path::new(dest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn piece_names() {
+        let pieces = |path: &str| -> Vec<PathBuf> {
+            (0..3)
+                .map(|index| piece_path(Path::new(path), index))
+                .collect()
+        };
+        assert_eq!(
+            pieces("out/capture.json"),
+            [
+                Path::new("out/capture.json"),
+                Path::new("out/capture.1.json"),
+                Path::new("out/capture.2.json")
+            ]
+        );
+        assert_eq!(
+            pieces("capture.tar.gz"),
+            [
+                Path::new("capture.tar.gz"),
+                Path::new("capture.tar.1.gz"),
+                Path::new("capture.tar.2.gz")
+            ]
+        );
+        assert_eq!(
+            pieces("capture"),
+            [
+                Path::new("capture"),
+                Path::new("capture.1"),
+                Path::new("capture.2")
+            ]
+        );
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("itm-decode-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.txt");
+        let mut output = Output::create(
+            path.clone(),
+            RotateOptions {
+                size: Some(4),
+                interval: None,
+            },
+        )
+        .unwrap();
+
+        output.write_all(b"abc").unwrap();
+        // Not full yet
+        assert!(!output.rotate().unwrap());
+        output.write_all(b"de").unwrap();
+        assert!(output.rotate().unwrap());
+        output.write_all(b"f").unwrap();
+        output.flush().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"abcde");
+        assert_eq!(fs::read(dir.join("capture.1.txt")).unwrap(), b"f");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Output::create(
+            path,
+            RotateOptions {
+                size: Some(0),
+                interval: None,
+            },
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Decoding of instrumentation payloads by their port: into records of
+//! its schema, COBS frames, or lines of text. The payloads of ports
+//! with a binary encoding are left as they are.
+
+use itm::cobs::CobsDecoder;
+use itm::schema::{Record, SchemaDecoder, Value};
+use itm::{Line, LineSplitter, LinesOptions, PortEncoding, PortMap, TracePacket};
+
+/// Data decoded from the payloads written to a port.
+#[derive(Debug)]
+pub enum Decoded {
+    /// A record of the schema of the port.
+    Record(Record),
+
+    /// A COBS frame, by its port.
+    Frame(u8, Vec<u8>),
+
+    /// A line of text.
+    Line(Line),
+}
+
+impl Decoded {
+    /// Stimulus port the data was written to.
+    pub fn port(&self) -> u8 {
+        match self {
+            Decoded::Record(record) => record.port,
+            Decoded::Frame(port, _) => *port,
+            Decoded::Line(line) => line.port,
+        }
+    }
+
+    /// Kind of the data: `record`, `frame` or `text`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Decoded::Record(_) => "record",
+            Decoded::Frame(..) => "frame",
+            Decoded::Line(_) => "text",
+        }
+    }
+
+    /// The values of a record, the data of a frame in hex, or the text
+    /// of a line.
+    pub fn fields(&self) -> Vec<String> {
+        match self {
+            Decoded::Record(record) => record.values.iter().map(Value::to_string).collect(),
+            Decoded::Frame(_, data) => vec![data.iter().map(|b| format!("{:02x}", b)).collect()],
+            Decoded::Line(line) => vec![line.text.clone()],
+        }
+    }
+}
+
+/// Decodes the payloads of instrumentation packets by their port.
+/// Packets are [pushed](Self::push) as they are decoded, and decoded
+/// data is [pulled](Self::pull) out.
+pub struct Payloads {
+    schemas: SchemaDecoder,
+    frames: CobsDecoder,
+    lines: LineSplitter,
+    ports: PortMap,
+}
+
+impl Payloads {
+    /// Decodes the payloads of ports registered in `schemas` into
+    /// records, and those of the other ports by their encoding in
+    /// `ports`, which is text if not given.
+    pub fn new(schemas: SchemaDecoder, ports: &PortMap) -> Self {
+        Self {
+            schemas,
+            frames: CobsDecoder::new(
+                ports
+                    .iter()
+                    .filter(|(_, info)| info.encoding == Some(PortEncoding::Cobs))
+                    .map(|(port, _)| port),
+            ),
+            lines: LineSplitter::new(LinesOptions {
+                lossy: false,
+                ..LinesOptions::default()
+            }),
+            ports: ports.clone(),
+        }
+    }
+
+    /// Decodes the payload of `packet`, if it is an instrumentation
+    /// packet of a port without a binary encoding. Returns whether it
+    /// was.
+    pub fn push(&mut self, packet: &TracePacket) -> bool {
+        if self.schemas.push(packet) || self.frames.push(packet) {
+            return true;
+        }
+        match packet {
+            TracePacket::Instrumentation { port, payload }
+                if self.ports.encoding(*port) != Some(PortEncoding::Binary) =>
+            {
+                self.lines.push(*port, payload);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pulls the next decoded data. Malformed frames and lines are
+    /// reported and skipped.
+    pub fn pull(&mut self) -> Option<Decoded> {
+        if let Some(record) = self.schemas.pull() {
+            return Some(Decoded::Record(record));
+        }
+        while let Some(frame) = self.frames.pull() {
+            match frame {
+                Ok((port, data)) => return Some(Decoded::Frame(port, data)),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        while let Some(line) = self.lines.pull() {
+            match line {
+                Ok(line) => return Some(Decoded::Line(line)),
+                Err(e) => eprintln!("dropped line: {}", e),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itm::schema::{FieldType, Schema};
+    use itm::{Endianness, PortInfo};
+
+    fn instrumentation(port: u8, payload: &[u8]) -> TracePacket {
+        TracePacket::Instrumentation {
+            port,
+            payload: itm::Payload::new(payload).unwrap(),
+        }
+    }
+
+    #[test]
+    fn by_port() {
+        let mut ports = PortMap::new();
+        ports.insert(
+            1,
+            PortInfo {
+                encoding: Some(PortEncoding::Cobs),
+                ..PortInfo::named("frames")
+            },
+        );
+        ports.insert(
+            2,
+            PortInfo {
+                encoding: Some(PortEncoding::Binary),
+                ..PortInfo::named("raw")
+            },
+        );
+        let mut schemas = SchemaDecoder::new();
+        schemas.register(
+            3,
+            Schema {
+                fields: vec![FieldType::U8, FieldType::U16],
+                endianness: Endianness::Little,
+            },
+        );
+        let mut payloads = Payloads::new(schemas, &ports);
+
+        assert!(payloads.push(&instrumentation(0, b"hel")));
+        assert!(payloads.pull().is_none());
+        assert!(payloads.push(&instrumentation(0, b"lo\n")));
+        assert!(payloads.push(&instrumentation(1, &[0x02, 0xab, 0x00])));
+        assert!(payloads.push(&instrumentation(3, &[7, 0x34, 0x12])));
+        assert!(!payloads.push(&instrumentation(2, &[0xff])));
+        assert!(!payloads.push(&TracePacket::Sync));
+
+        let decoded: Vec<_> = std::iter::from_fn(|| payloads.pull()).collect();
+        assert_eq!(
+            decoded
+                .iter()
+                .map(|d| (d.port(), d.kind(), d.fields()))
+                .collect::<Vec<_>>(),
+            [
+                (3, "record", vec!["7".to_string(), "4660".to_string()]),
+                (1, "frame", vec!["ab".to_string()]),
+                (0, "text", vec!["hello".to_string()]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Loading of per-port configuration from TOML files.
+//!
+//! A file has a table per port under `ports`, e.g. a schema file:
+//!
+//! ```toml
+//! [ports.3]
+//! fields = ["u32"]
+//!
+//! [ports.5]
+//! fields = ["u16", "u16"]
+//! endianness = "Big"
+//! ```
+//!
+//! or a port map file:
+//!
+//! ```toml
+//! [ports.0]
+//! name = "log"
+//! encoding = "text"
+//! description = "Console output"
+//!
+//! [ports.1]
+//! name = "adc_samples"
+//! encoding = "binary"
+//! ```
+
+use anyhow::{Context, Result};
+use itm::schema::{Schema, SchemaDecoder};
+use itm::{PortInfo, PortMap};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Reads the `ports` table of a file into a map of port number to
+/// configuration.
+fn load<T: DeserializeOwned>(path: &Path, what: &str) -> Result<BTreeMap<u8, T>> {
+    let file = fs::read_to_string(path).with_context(|| format!("failed to read {} file", what))?;
+    let mut tables: BTreeMap<String, BTreeMap<String, T>> =
+        toml::from_str(&file).with_context(|| format!("failed to parse {} file", what))?;
+
+    tables
+        .remove("ports")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(port, config)| {
+            let port = port
+                .parse()
+                .with_context(|| format!("{:?} is not a valid stimulus port", port))?;
+            Ok((port, config))
+        })
+        .collect()
+}
+
+/// Reads a schema file into a decoder with all schemas registered.
+pub fn schemas(path: &Path) -> Result<SchemaDecoder> {
+    let mut decoder = SchemaDecoder::new();
+    for (port, schema) in load::<Schema>(path, "schema")? {
+        decoder.register(port, schema);
+    }
+    Ok(decoder)
+}
+
+/// Reads a port map file.
+pub fn port_map(path: &Path) -> Result<PortMap> {
+    Ok(load::<PortInfo>(path, "port map")?.into_iter().collect())
+}
@@ -0,0 +1,201 @@
+//! Flight recorder mode: the latest packets are kept in memory instead
+//! of being output, and are dumped to a file in a directory when a
+//! `--dump-on` condition is satisfied or, on Unix, when SIGUSR1 is
+//! received. Each dump is a JSON file of one packet (or set of
+//! timestamped packets) per line. A signal is acted on when the next
+//! packet is decoded, or at the end of the input.
+
+use anyhow::{Context, Result};
+use itm::trigger::{Condition, FlightRecorder, Packets};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Whether a dump was requested by a signal since the last dump.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    SIGNALLED.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderOptions {
+    /// Directory that dumps are written to.
+    pub dir: PathBuf,
+    pub max_age: Option<Duration>,
+    pub max_bytes: Option<usize>,
+    pub dump_on: Vec<Condition>,
+}
+
+pub struct Recorder<T> {
+    recorder: FlightRecorder<T>,
+    options: RecorderOptions,
+    dumps: usize,
+}
+
+impl<T: Packets + Serialize> Recorder<T> {
+    /// Starts recording, and on Unix, dumping on SIGUSR1.
+    pub fn new(options: RecorderOptions) -> Self {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(
+                libc::SIGUSR1,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+        Self {
+            recorder: FlightRecorder::new(options.max_age, options.max_bytes),
+            options,
+            dumps: 0,
+        }
+    }
+
+    /// Records `item` at time `at`, and dumps the recording if `item`
+    /// satisfies a condition, or a signal was received since the last
+    /// item.
+    pub fn push(&mut self, at: Duration, item: T) -> Result<()> {
+        let fired = item
+            .packets()
+            .iter()
+            .any(|packet| self.options.dump_on.iter().any(|c| c.matches(packet)));
+        self.recorder.push(at, item);
+        if fired || SIGNALLED.swap(false, Ordering::SeqCst) {
+            self.dump()?;
+        }
+        Ok(())
+    }
+
+    /// Dumps the recording if a signal was received since the last
+    /// item. To be called at the end of the input.
+    pub fn finish(&mut self) -> Result<()> {
+        if SIGNALLED.swap(false, Ordering::SeqCst) {
+            self.dump()?;
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self) -> Result<()> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = self
+            .options
+            .dir
+            .join(format!("itm-dump-{}-{}.json", secs, self.dumps));
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        let items = self.recorder.dump();
+        for item in items.iter() {
+            writeln!(writer, "{}", serde_json::to_string(item)?)?;
+        }
+        writer
+            .flush()
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        self.dumps += 1;
+        eprintln!("dumped {} items to {}", items.len(), path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itm::TracePacket;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    /// 2 bytes encoded
+    fn packet(port: u8) -> TracePacket {
+        TracePacket::Instrumentation {
+            port,
+            payload: [0].into(),
+        }
+    }
+
+    /// The ports of the packets of each dump in `dir`, in order.
+    fn dumps(dir: &std::path::Path) -> Vec<Vec<u8>> {
+        let mut dumps: Vec<(usize, Vec<u8>)> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                let index = name.rsplit('-').next().unwrap().parse().unwrap();
+                let ports = fs::read_to_string(&path)
+                    .unwrap()
+                    .lines()
+                    .map(|line| {
+                        serde_json::from_str::<TracePacket>(line)
+                            .unwrap()
+                            .port()
+                            .unwrap()
+                    })
+                    .collect();
+                (index, ports)
+            })
+            .collect();
+        dumps.sort();
+        dumps.into_iter().map(|(_, ports)| ports).collect()
+    }
+
+    fn open(
+        name: &str,
+        max_age: Option<Duration>,
+        max_bytes: Option<usize>,
+    ) -> Recorder<TracePacket> {
+        let dir = std::env::temp_dir().join(format!(
+            "itm-decode-recorder-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        Recorder::new(RecorderOptions {
+            dir,
+            max_age,
+            max_bytes,
+            dump_on: vec![Condition::parse("Instrumentation port 9", &BTreeMap::new()).unwrap()],
+        })
+    }
+
+    // In a single test, as the signal flag is global
+    #[test]
+    fn windows() {
+        let mut recorder = open("wraparound", None, Some(6));
+        for port in 0..=9 {
+            recorder.push(Duration::ZERO, packet(port)).unwrap();
+        }
+        // Only the latest 6 bytes before the trigger are kept.
+        assert_eq!(dumps(&recorder.options.dir), [vec![7, 8, 9]]);
+        fs::remove_dir_all(&recorder.options.dir).unwrap();
+
+        let ms = Duration::from_millis;
+        let mut recorder = open("windows", Some(ms(10)), None);
+        // The window before the first trigger holds what was pushed
+        // within 10 ms of it.
+        for (at, port) in [(0, 0), (5, 1), (12, 2), (20, 9)] {
+            recorder.push(ms(at), packet(port)).unwrap();
+        }
+        // The window after a trigger starts with it, and holds nothing
+        // from before.
+        for (at, port) in [(21, 3), (22, 4), (23, 9)] {
+            recorder.push(ms(at), packet(port)).unwrap();
+        }
+        // Nothing is dumped at the end, unless signalled.
+        recorder.push(ms(24), packet(5)).unwrap();
+        recorder.finish().unwrap();
+        assert_eq!(dumps(&recorder.options.dir), [vec![2, 9], vec![3, 4, 9]]);
+
+        SIGNALLED.store(true, Ordering::SeqCst);
+        recorder.finish().unwrap();
+        assert_eq!(
+            dumps(&recorder.options.dir),
+            [vec![2, 9], vec![3, 4, 9], vec![5]]
+        );
+        fs::remove_dir_all(&recorder.options.dir).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+//! Minimal extraction of device interrupt names from an SVD file.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Reads all `<interrupt>` definitions of an SVD file into a map of
+/// interrupt name to interrupt number.
+pub fn interrupts(path: &Path) -> Result<BTreeMap<String, u16>> {
+    let svd = fs::read_to_string(path).context("failed to read SVD file")?;
+    let mut interrupts = BTreeMap::new();
+
+    let mut rest = svd.as_str();
+    while let Some(start) = rest.find("<interrupt>") {
+        rest = &rest[start + "<interrupt>".len()..];
+        let end = rest
+            .find("</interrupt>")
+            .context("unterminated <interrupt> in SVD file")?;
+        let interrupt = &rest[..end];
+        rest = &rest[end..];
+
+        let name = element(interrupt, "name").context("<interrupt> without <name> in SVD file")?;
+        let value = element(interrupt, "value")
+            .context("<interrupt> without <value> in SVD file")?
+            .parse()
+            .with_context(|| format!("invalid <value> of interrupt {}", name))?;
+        interrupts.insert(name.to_string(), value);
+    }
+
+    Ok(interrupts)
+}
+
+/// Returns the trimmed text of the first `<tag>` element in `s`.
+fn element<'a>(s: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = s.find(&open)? + open.len();
+    let end = s[start..].find(&close)? + start;
+    Some(s[start..end].trim())
+}
@@ -0,0 +1,455 @@
+//! WebSocket server for browser-based viewers.
+//!
+//! Every decoded packet is sent to the connected clients as a text
+//! message holding the packet and its sequence number as JSON, as
+//! output by `--format json`. A
+//! client subscribes to a subset of the packets with the query of the
+//! URL it connects to, e.g. `ws://localhost:8765/?kinds=instrumentation&ports=0,1`:
+//!
+//! - `kinds`: comma-separated packet kinds, as in the `kind` field, e.g.
+//!   `instrumentation,pc-sample`. Packets of other kinds are not sent.
+//! - `ports`: comma-separated stimulus ports. Instrumentation packets
+//!   written to other ports are not sent.
+//!
+//! Messages from clients are ignored. Each client is served by its own
+//! thread, so that a slow client does not hold up decoding or other
+//! clients; one that falls more than 1024 messages behind is dropped.
+
+use anyhow::{bail, Context, Result};
+use itm::{Sequenced, TracePacket};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long a client may take to complete the handshake, or to accept
+/// a message, before it is dropped.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of messages that may be queued for a client before it is
+/// dropped.
+const CLIENT_QUEUE: usize = 1024;
+
+/// Appended to the key of a handshake request to compute the accept
+/// value of the response (RFC 6455, section 1.3).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The packets a client subscribed to.
+#[derive(Debug, Default)]
+struct Filter {
+    kinds: Option<Vec<String>>,
+    ports: Option<Vec<u8>>,
+}
+
+impl Filter {
+    /// Parses the query of a request target, e.g. `/?ports=0,1`.
+    fn parse(target: &str) -> Result<Self> {
+        let mut filter = Filter::default();
+        let query = match target.split_once('?') {
+            Some((_, query)) => query,
+            None => return Ok(filter),
+        };
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, values) = pair.split_once('=').unwrap_or((pair, ""));
+            let values = values.split(',').filter(|value| !value.is_empty());
+            match key {
+                "kinds" => filter.kinds = Some(values.map(str::to_string).collect()),
+                "ports" => {
+                    filter.ports = Some(
+                        values
+                            .map(|port| {
+                                port.parse()
+                                    .with_context(|| format!("invalid port {:?}", port))
+                            })
+                            .collect::<Result<_>>()?,
+                    )
+                }
+                _ => bail!("unknown query parameter {:?}", key),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, packet: &TracePacket) -> bool {
+        if matches!(&self.kinds, Some(kinds) if !kinds.iter().any(|kind| kind == packet.kind())) {
+            return false;
+        }
+        match (packet, &self.ports) {
+            (TracePacket::Instrumentation { port, .. }, Some(ports)) => ports.contains(port),
+            _ => true,
+        }
+    }
+}
+
+struct Client {
+    peer: String,
+    /// Shut down when the client is dropped, to unblock its writer.
+    stream: TcpStream,
+    filter: Filter,
+    /// Messages to be written to the client by its writer thread.
+    queue: SyncSender<Arc<[u8]>>,
+}
+
+/// Serves decoded packets to WebSocket clients. Clients are accepted,
+/// and messages written to them, in the background.
+pub struct Server {
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl Server {
+    /// Listens for clients at `address`, e.g. `127.0.0.1:8765`.
+    pub fn bind(address: &str) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("failed to listen for WebSocket clients at {}", address))?;
+        let clients = Arc::new(Mutex::new(vec![]));
+        let accepted = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = Arc::clone(&accepted);
+                thread::spawn(move || serve(stream, &clients));
+            }
+        });
+        Ok(Self { clients })
+    }
+
+    /// Queues `packet` for the clients subscribed to it. Clients that
+    /// disconnected, or do not keep up, are dropped.
+    pub fn send(&mut self, packet: Sequenced<&TracePacket>) -> Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients
+            .iter()
+            .all(|client| !client.filter.matches(packet.item))
+        {
+            return Ok(());
+        }
+        let message: Arc<[u8]> = frame(serde_json::to_string(&packet)?.as_bytes()).into();
+        let packet = packet.item;
+        clients.retain(|client| {
+            if !client.filter.matches(packet) {
+                return true;
+            }
+            match client.queue.try_send(Arc::clone(&message)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    eprintln!(
+                        "WebSocket client {}: dropped, as it does not keep up",
+                        client.peer
+                    );
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Completes the handshake of a client, adds it to `clients`, and
+/// writes the messages queued for it until it is dropped.
+fn serve(stream: TcpStream, clients: &Mutex<Vec<Client>>) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "?".to_string(), |peer| peer.to_string());
+    let filter = match handshake(&stream) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("WebSocket client {}: {:#}", peer, e);
+            return;
+        }
+    };
+    let (queue, messages) = mpsc::sync_channel::<Arc<[u8]>>(CLIENT_QUEUE);
+    match stream.try_clone() {
+        Ok(stream) => clients.lock().unwrap().push(Client {
+            peer,
+            stream,
+            filter,
+            queue,
+        }),
+        Err(e) => {
+            eprintln!("WebSocket client {}: {}", peer, e);
+            return;
+        }
+    }
+    for message in messages {
+        if (&stream).write_all(&message).is_err() {
+            break;
+        }
+    }
+}
+
+/// Completes the opening handshake of a client (RFC 6455, section 4.2),
+/// and returns the packets it subscribed to.
+fn handshake(stream: &TcpStream) -> Result<Filter> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let target = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _] => target.to_string(),
+        _ => bail!("not a WebSocket handshake request: {:?}", line.trim_end()),
+    };
+
+    let mut key = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let filter = match (key.as_ref(), Filter::parse(&target)) {
+        (Some(_), Ok(filter)) => filter,
+        (None, _) => {
+            (&*stream).write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            bail!("no Sec-WebSocket-Key in handshake request");
+        }
+        (_, Err(e)) => {
+            (&*stream).write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            return Err(e);
+        }
+    };
+    write!(
+        &*stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept(&key.unwrap())
+    )?;
+    Ok(filter)
+}
+
+/// Accept value of the handshake response to a request with `key`.
+fn accept(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// Frames `payload` as a single unmasked text message.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x81];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len < 1 << 16 => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1 digest of `data`, as required by the handshake.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard, padded base64 encoding of `data`.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha1_vectors() {
+        // RFC 3174, section 7.3
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1_000_000])),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+        assert_eq!(
+            hex(&sha1(
+                &b"0123456701234567012345670123456701234567012345670123456701234567".repeat(10)
+            )),
+            "dea356a2cddd90c7a7ecedc5ebb563934f460452"
+        );
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn base64_vectors() {
+        // RFC 4648, section 10
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(data.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn accept_key() {
+        // RFC 6455, section 1.3
+        assert_eq!(
+            accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames() {
+        // RFC 6455, section 5.7
+        assert_eq!(frame(b"Hello"), [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+        let framed = frame(&[b'x'; 256]);
+        assert_eq!(framed[..4], [0x81, 126, 0x01, 0x00]);
+        assert_eq!(framed.len(), 4 + 256);
+
+        let framed = frame(&[b'x'; 1 << 16]);
+        assert_eq!(framed[..10], [0x81, 127, 0, 0, 0, 0, 0, 0x01, 0x00, 0x00]);
+        assert_eq!(framed.len(), 10 + (1 << 16));
+    }
+
+    #[test]
+    fn filters() {
+        let instrumentation = |port| TracePacket::Instrumentation {
+            port,
+            payload: [0].into(),
+        };
+        let sample = TracePacket::PCSample { pc: None };
+
+        let filter = Filter::parse("/").unwrap();
+        assert!(filter.matches(&instrumentation(3)) && filter.matches(&sample));
+
+        let filter = Filter::parse("/?kinds=instrumentation&ports=0,1").unwrap();
+        assert!(filter.matches(&instrumentation(1)));
+        assert!(!filter.matches(&instrumentation(2)));
+        assert!(!filter.matches(&sample));
+
+        let filter = Filter::parse("/?ports=2&").unwrap();
+        assert!(filter.matches(&instrumentation(2)) && filter.matches(&sample));
+
+        assert!(Filter::parse("/?ports=256").is_err());
+        assert!(Filter::parse("/?ports=x").is_err());
+        assert!(Filter::parse("/?colors=red").is_err());
+    }
+
+    #[test]
+    fn drop_lagging_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        // No writer drains the queue
+        let (queue, _messages) = mpsc::sync_channel(CLIENT_QUEUE);
+        let mut server = Server {
+            clients: Arc::new(Mutex::new(vec![Client {
+                peer: "lagging".to_string(),
+                stream,
+                filter: Filter::parse("/?kinds=sync").unwrap(),
+                queue,
+            }])),
+        };
+
+        let sample = TracePacket::PCSample { pc: None };
+        for seq in 0..=CLIENT_QUEUE as u64 {
+            server.send(Sequenced { seq, item: &sample }).unwrap();
+        }
+        assert_eq!(server.clients.lock().unwrap().len(), 1);
+        for seq in 0..CLIENT_QUEUE as u64 {
+            server
+                .send(Sequenced {
+                    seq,
+                    item: &TracePacket::Sync,
+                })
+                .unwrap();
+        }
+        assert_eq!(server.clients.lock().unwrap().len(), 1);
+        server
+            .send(Sequenced {
+                seq: 0,
+                item: &TracePacket::Sync,
+            })
+            .unwrap();
+        assert!(server.clients.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn human_payloads() {
+    let dir = std::env::temp_dir().join(format!("itm-decode-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("schema.toml"),
+        "[ports.3]\nfields = [\"u16\", \"u16\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("ports.toml"),
+        "[ports.1]\nname = \"frames\"\nencoding = \"cobs\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("trace.bin"),
+        [
+            0x1b, 0x01, 0x00, 0x02, 0x00, // port 3: a record
+            0x09, 0x02, 0x09, 0xab, 0x09, 0x00, // port 1: a COBS frame
+            0x01, b'h', 0x01, b'i', 0x01, b'\n', // port 0: a line
+        ],
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_itm-decode"))
+        .arg("--schema")
+        .arg(dir.join("schema.toml"))
+        .arg("--ports")
+        .arg(dir.join("ports.toml"))
+        .arg("--color")
+        .arg("never")
+        .arg(dir.join("trace.bin"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            "       0 record             3 1 2\n",
+            "       3 frame              frames ab\n",
+            "       6 text               0 hi\n",
+        )
+    );
+}
+
+#[test]
+fn timestamped_records() {
+    let dir = std::env::temp_dir().join(format!("itm-decode-cli-records-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("schema.toml"),
+        "[ports.3]\nfields = [\"u16\", \"u16\"]\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("trace.bin"),
+        [
+            0x1b, 0x01, 0x00, 0x02, 0x00, // port 3: a record
+            0x60, // LTS2, ts = 6
+        ],
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_itm-decode"))
+        .args(["--timestamps", "--clock-frequency", "16000000"])
+        .args(["--format", "debug", "--schema"])
+        .arg(dir.join("schema.toml"))
+        .arg(dir.join("trace.bin"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("packets: []"), "{}", stdout);
+    assert!(stdout.ends_with("\n375ns\t3\t1\t2\n"), "{}", stdout);
+}
+
+#[test]
+fn json_wall_clock() {
+    let dir = std::env::temp_dir().join(format!("itm-decode-cli-json-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("trace.bin"),
+        [
+            0x09, 0x41, // port 1
+            0x60, // LTS2, ts = 6
+        ],
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_itm-decode"))
+        .args(["--timestamps", "--clock-frequency", "16000000"])
+        .args(["--format", "json", "--epoch", "2020-09-13T12:26:40Z"])
+        .arg(dir.join("trace.bin"))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(
+            r#"{"seq":0,"wall_clock":"2020-09-13T12:26:40.000000375Z","timestamp":{"Sync":"#
+        ),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,281 @@
+//! Conversion to the Chrome trace event format.
+//!
+//! [`ChromeTrace`] converts timestamped packets into [`TraceEvent`]s,
+//! which, serialized as JSON, can be opened in `chrome://tracing` or the
+//! Perfetto UI: exception handler executions become duration events,
+//! nested as the handlers preempt each other, and returns from
+//! exceptions and instrumentation messages become instant events.
+//!
+//! ```
+//! use itm::analysis::chrome::{ChromeTrace, Phase};
+//! use itm::{ExceptionAction, ExceptionType, PortMap, Timestamp, TracePacket};
+//! use std::time::Duration;
+//!
+//! let systick = ExceptionType::from_name("SysTick").unwrap();
+//! let mut trace = ChromeTrace::new(PortMap::new());
+//! for (action, us) in [(ExceptionAction::Entered, 10), (ExceptionAction::Exited, 15)] {
+//!     trace.push(
+//!         &TracePacket::ExceptionTrace {
+//!             exception: systick.into(),
+//!             action,
+//!         },
+//!         &Timestamp::Sync(Duration::from_micros(us)),
+//!     );
+//! }
+//! let events: Vec<_> = std::iter::from_fn(|| trace.pull())
+//!     .map(|event| (event.name, event.ph, event.ts))
+//!     .collect();
+//! assert_eq!(
+//!     events,
+//!     [
+//!         ("SysTick".to_string(), Phase::Begin, 10.0),
+//!         ("SysTick".to_string(), Phase::End, 15.0),
+//!     ]
+//! );
+//! ```
+
+use crate::{
+    ExceptionAction, ExceptionType, PortEncoding, PortMap, Timestamp, TimestampedTracePackets,
+    TracePacket,
+};
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// The type of a [`TraceEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Phase {
+    /// Start of a duration event.
+    #[cfg_attr(feature = "serde", serde(rename = "B"))]
+    Begin,
+
+    /// End of the most recently begun duration event.
+    #[cfg_attr(feature = "serde", serde(rename = "E"))]
+    End,
+
+    /// An event without duration.
+    #[cfg_attr(feature = "serde", serde(rename = "i"))]
+    Instant,
+}
+
+/// An event of the Chrome trace event format. The field names are those
+/// of the format.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEvent {
+    /// Name of the event: the exception, or the stimulus port.
+    pub name: String,
+
+    /// Category of the event: `exception` or `instrumentation`.
+    pub cat: String,
+
+    /// Type of the event.
+    pub ph: Phase,
+
+    /// Time of the event in microseconds, relative to trace clock start.
+    pub ts: f64,
+
+    /// Process ID. Always 0.
+    pub pid: u32,
+
+    /// Thread ID. Always 0.
+    pub tid: u32,
+
+    /// Scope of instant events: `t`, for thread.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub s: Option<String>,
+
+    /// Arguments of the event, e.g. the payload of an instrumentation
+    /// message.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub args: BTreeMap<String, String>,
+}
+
+/// Converts packets into [`TraceEvent`]s. Packets are
+/// [pushed](Self::push) with their timestamps, and the resulting events
+/// are [pulled](Self::pull) out in order. See the [module
+/// documentation](self).
+#[derive(Debug, Clone)]
+pub struct ChromeTrace {
+    ports: PortMap,
+    /// Active exception handlers, whose duration events have begun.
+    stack: Vec<ExceptionType>,
+    events: VecDeque<TraceEvent>,
+}
+
+impl ChromeTrace {
+    /// Creates a converter that names instrumentation events after the
+    /// ports of `ports`, and formats their payloads by port encoding:
+    /// payloads of [binary](PortEncoding::Binary) and
+    /// [COBS](PortEncoding::Cobs) ports in hex, and others as text.
+    pub fn new(ports: PortMap) -> Self {
+        Self {
+            ports,
+            stack: vec![],
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Processes a set of packets.
+    pub fn push_set(&mut self, set: &TimestampedTracePackets) {
+        for packet in set.packets.iter() {
+            self.push(packet, &set.timestamp);
+        }
+    }
+
+    /// Processes a packet that was generated at `timestamp`. Packets
+    /// other than [`ExceptionTrace`](TracePacket::ExceptionTrace) and
+    /// [`Instrumentation`](TracePacket::Instrumentation) are ignored.
+    ///
+    /// A handler that is exited, or returned from, while handlers that
+    /// preempted it are still active ends the events of those handlers
+    /// too. Exits of handlers that were entered before the capture
+    /// started are ignored.
+    pub fn push(&mut self, packet: &TracePacket, timestamp: &Timestamp) {
+        let ts = timestamp.offset().as_nanos() as f64 / 1000.0;
+        match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                let exception = ExceptionType::from(*exception);
+                match action {
+                    ExceptionAction::Entered => {
+                        self.stack.push(exception);
+                        self.event(exception.to_string(), "exception", Phase::Begin, ts);
+                    }
+                    ExceptionAction::Exited => {
+                        if let Some(i) = self.stack.iter().rposition(|e| *e == exception) {
+                            self.end(i, ts);
+                        }
+                    }
+                    ExceptionAction::Returned => {
+                        if u16::from(exception) == 0 {
+                            self.end(0, ts);
+                        } else if let Some(i) = self.stack.iter().rposition(|e| *e == exception) {
+                            self.end(i + 1, ts);
+                        }
+                        self.event(
+                            format!("return to {}", exception),
+                            "exception",
+                            Phase::Instant,
+                            ts,
+                        );
+                    }
+                }
+            }
+            TracePacket::Instrumentation { port, payload } => {
+                let payload = match self.ports.encoding(*port) {
+                    Some(PortEncoding::Binary | PortEncoding::Cobs) => {
+                        payload.iter().map(|b| format!("{:02x}", b)).collect()
+                    }
+                    _ => String::from_utf8_lossy(payload).into_owned(),
+                };
+                let name = self.ports.label(*port);
+                self.event(name, "instrumentation", Phase::Instant, ts);
+                let event = self.events.back_mut().unwrap();
+                event.args.insert("port".to_string(), port.to_string());
+                event.args.insert("payload".to_string(), payload);
+            }
+            _ => (),
+        }
+    }
+
+    /// Ends the events of the active handlers at `timestamp`, e.g. when
+    /// the trace stream has ended, so that they are displayed.
+    pub fn finish(&mut self, timestamp: &Timestamp) {
+        self.end(0, timestamp.offset().as_nanos() as f64 / 1000.0);
+    }
+
+    /// Pulls the next event.
+    pub fn pull(&mut self) -> Option<TraceEvent> {
+        self.events.pop_front()
+    }
+
+    /// Ends the events of the handlers on the stack from `depth` up.
+    fn end(&mut self, depth: usize, ts: f64) {
+        while self.stack.len() > depth {
+            let exception = self.stack.pop().unwrap();
+            self.event(exception.to_string(), "exception", Phase::End, ts);
+        }
+    }
+
+    fn event(&mut self, name: String, cat: &str, ph: Phase, ts: f64) {
+        self.events.push_back(TraceEvent {
+            name,
+            cat: cat.to_string(),
+            ph,
+            ts,
+            pid: 0,
+            tid: 0,
+            s: (ph == Phase::Instant).then(|| "t".to_string()),
+            args: BTreeMap::new(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PortInfo;
+    use std::time::Duration;
+
+    #[test]
+    fn events() {
+        let systick = ExceptionType::from_name("SysTick").unwrap();
+        let irq = ExceptionType::try_from(16 + 3).unwrap();
+        let exception = |exception: ExceptionType, action| TracePacket::ExceptionTrace {
+            exception: exception.into(),
+            action,
+        };
+        let mut trace = ChromeTrace::new([(1, PortInfo::named("log"))].into_iter().collect());
+        let packets = [
+            exception(systick, ExceptionAction::Entered),
+            exception(irq, ExceptionAction::Entered),
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: [b'h', b'i'].into(),
+            },
+            // Exit of the IRQ is lost
+            exception(systick, ExceptionAction::Exited),
+            exception(irq, ExceptionAction::Exited),
+            exception(
+                ExceptionType::try_from(0).unwrap(),
+                ExceptionAction::Returned,
+            ),
+            exception(systick, ExceptionAction::Entered),
+        ];
+        for (us, packet) in packets.iter().enumerate() {
+            trace.push(packet, &Timestamp::Sync(Duration::from_micros(us as u64)));
+        }
+        trace.finish(&Timestamp::Sync(Duration::from_micros(10)));
+
+        let events: Vec<_> = std::iter::from_fn(|| trace.pull()).collect();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| (e.name.as_str(), e.ph, e.ts))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("SysTick", Phase::Begin, 0.0),
+                ("IRQ3", Phase::Begin, 1.0),
+                ("log", Phase::Instant, 2.0),
+                ("IRQ3", Phase::End, 3.0),
+                ("SysTick", Phase::End, 3.0),
+                ("return to ThreadMode", Phase::Instant, 5.0),
+                ("SysTick", Phase::Begin, 6.0),
+                ("SysTick", Phase::End, 10.0),
+            ]
+        );
+
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::to_string(&events[2]).unwrap(),
+            r#"{"name":"log","cat":"instrumentation","ph":"i","ts":2.0,"pid":0,"tid":0,"s":"t","args":{"payload":"hi","port":"1"}}"#
+        );
+    }
+}
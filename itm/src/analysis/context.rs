@@ -0,0 +1,196 @@
+//! Execution context tracking.
+//!
+//! [`ContextTracker`] follows the [`ExceptionTrace`](TracePacket::ExceptionTrace)
+//! packets of a capture and maintains the stack of active exception
+//! handlers, so that the context that generated a packet, e.g. which
+//! handler wrote an instrumentation message, is known. [`Contexts`]
+//! attaches this context to each packet of a stream.
+//!
+//! ```
+//! use itm::analysis::context::Contexts;
+//! use itm::{ExceptionAction, ExceptionType, TracePacket};
+//!
+//! let systick = ExceptionType::from_name("SysTick").unwrap();
+//! let packets = [
+//!     TracePacket::ExceptionTrace {
+//!         exception: systick.into(),
+//!         action: ExceptionAction::Entered,
+//!     },
+//!     TracePacket::Instrumentation {
+//!         port: 0,
+//!         payload: [b'x'].into(),
+//!     },
+//! ];
+//! let contexts: Vec<_> = Contexts::new(packets.iter().cloned().map(Ok))
+//!     .map(|packet| packet.unwrap().context.to_string())
+//!     .collect();
+//! assert_eq!(contexts, ["ThreadMode", "SysTick"]);
+//! ```
+
+use crate::{
+    DecoderError, ExceptionAction, ExceptionType, LinesInput, Timestamp, TracePacket, VectActive,
+};
+
+use std::collections::VecDeque;
+
+/// A packet and the context in which it was generated.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InContext {
+    /// The packet itself.
+    pub packet: TracePacket,
+
+    /// The active exception, or thread mode. For
+    /// [`ExceptionTrace`](TracePacket::ExceptionTrace) packets, the
+    /// context before the transition.
+    pub context: ExceptionType,
+
+    /// Timestamp of the packet, if the packets were timestamped.
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Maintains the stack of active exception handlers. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct ContextTracker {
+    stack: Vec<ExceptionType>,
+}
+
+impl ContextTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes a packet. Packets other than
+    /// [`ExceptionTrace`](TracePacket::ExceptionTrace) are ignored.
+    pub fn push(&mut self, packet: &TracePacket) {
+        let (exception, action) = match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                (ExceptionType::from(*exception), action)
+            }
+            _ => return,
+        };
+
+        match action {
+            ExceptionAction::Entered => self.stack.push(exception),
+            ExceptionAction::Exited => {
+                if let Some(i) = self.stack.iter().rposition(|e| *e == exception) {
+                    self.stack.truncate(i);
+                }
+            }
+            ExceptionAction::Returned => match self.stack.iter().rposition(|e| *e == exception) {
+                Some(i) => self.stack.truncate(i + 1),
+                // Returned to thread mode, or to a handler whose entry
+                // was not traced
+                None if u16::from(exception) == 0 => self.stack.clear(),
+                None => self.stack = vec![exception],
+            },
+        }
+    }
+
+    /// The active exception, or thread mode if no exception is active.
+    pub fn current(&self) -> ExceptionType {
+        match self.stack.last() {
+            Some(exception) => *exception,
+            None => VectActive::ThreadMode.into(),
+        }
+    }
+
+    /// The active exception handlers, from the outermost to the
+    /// innermost one.
+    pub fn stack(&self) -> &[ExceptionType] {
+        &self.stack
+    }
+}
+
+/// Iterator adapter that yield each packet [`InContext`], from an
+/// iterator over either [`TracePacket`](TracePacket)s, e.g.
+/// [`Singles`](crate::Singles), or
+/// [`TimestampedTracePackets`](crate::TimestampedTracePackets), e.g.
+/// [`Timestamps`](crate::Timestamps). See also [`ContextTracker`].
+pub struct Contexts<I> {
+    inner: I,
+    tracker: ContextTracker,
+    pending: VecDeque<InContext>,
+}
+
+impl<I, T> Contexts<I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            tracker: ContextTracker::new(),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<I, T> Iterator for Contexts<I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    type Item = Result<InContext, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Some(Ok(packet));
+            }
+            match self.inner.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(item) => {
+                    let (packets, timestamp) = item.into_packets();
+                    for packet in packets {
+                        let context = self.tracker.current();
+                        self.tracker.push(&packet);
+                        self.pending.push_back(InContext {
+                            packet,
+                            context,
+                            timestamp: timestamp.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn nesting() {
+        let irq = |n: u16| ExceptionType::try_from(16 + n).unwrap();
+        let mut tracker = ContextTracker::new();
+        let mut contexts = vec![];
+        for (n, action) in [
+            (3, ExceptionAction::Entered),
+            (5, ExceptionAction::Entered),
+            (5, ExceptionAction::Exited),
+            (3, ExceptionAction::Returned),
+            (3, ExceptionAction::Exited),
+            (0, ExceptionAction::Returned),
+            (7, ExceptionAction::Returned),
+        ] {
+            let exception = if n == 0 {
+                VectActive::ThreadMode
+            } else {
+                irq(n).into()
+            };
+            tracker.push(&TracePacket::ExceptionTrace { exception, action });
+            contexts.push(tracker.current());
+        }
+        let thread = ExceptionType::from(VectActive::ThreadMode);
+        assert_eq!(
+            contexts,
+            [irq(3), irq(5), irq(3), irq(3), thread, thread, irq(7)]
+        );
+        assert_eq!(tracker.stack(), [irq(7)]);
+    }
+}
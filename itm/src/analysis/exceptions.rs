@@ -0,0 +1,483 @@
+//! Exception handler executions and statistics.
+//!
+//! [`HandlerTracker`] follows the [`ExceptionTrace`](TracePacket::ExceptionTrace)
+//! packets of a capture and reconstructs each execution of an exception
+//! handler as a [`HandlerExecution`]: when it started and ended, and
+//! which handlers preempted it. [`ExceptionStats`] accumulates these per
+//! exception: how often its handler ran, how long it took, and how
+//! often it preempted or was preempted by another handler.
+//!
+//! The time of a handler execution is the time between the entry to
+//! and the exit from the handler, minus the time spent in the handlers
+//! that preempted it. It is thus the time the processor actually spent
+//! executing the handler, to the precision of the timestamps.
+//!
+//! ```
+//! use itm::analysis::exceptions::ExceptionStats;
+//! use itm::{ExceptionAction, ExceptionType, Timestamp, TracePacket};
+//! use std::time::Duration;
+//!
+//! let systick = ExceptionType::from_name("SysTick").unwrap();
+//! let mut stats = ExceptionStats::new();
+//! for (action, us) in [(ExceptionAction::Entered, 10), (ExceptionAction::Exited, 25)] {
+//!     stats.push(
+//!         &TracePacket::ExceptionTrace {
+//!             exception: systick.into(),
+//!             action,
+//!         },
+//!         &Timestamp::Sync(Duration::from_micros(us)),
+//!     );
+//! }
+//! let systick = stats.get(systick).unwrap();
+//! assert_eq!((systick.count, systick.total), (1, Duration::from_micros(15)));
+//! ```
+
+use crate::{
+    DecoderError, ExceptionAction, ExceptionType, Timestamp, TimestampedTracePackets, TracePacket,
+};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::time::Duration;
+
+/// [`HandlerTracker`] configuration. By default, the entry latency of
+/// handler executions is not measured.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerOptions {
+    /// Stimulus port to which handlers write a marker when they start,
+    /// e.g. the first statement of each handler. The first
+    /// [`Instrumentation`](TracePacket::Instrumentation) packet on this
+    /// port after a handler is entered marks the end of its entry
+    /// latency.
+    pub marker_port: Option<u8>,
+
+    /// Address ranges of handlers. The first non-sleep
+    /// [`PCSample`](TracePacket::PCSample) within the range of a handler
+    /// after it is entered marks the end of its entry latency. As PC
+    /// samples are periodic, this is an upper bound.
+    pub handler_addresses: BTreeMap<ExceptionType, Range<u32>>,
+}
+
+/// An execution of an exception handler, from entry to exit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HandlerExecution {
+    /// The exception that was handled.
+    pub exception: ExceptionType,
+
+    /// When the handler was entered.
+    pub start: Duration,
+
+    /// When the handler was exited.
+    pub end: Duration,
+
+    /// Time spent executing the handler, i.e. the time between
+    /// [`start`](Self::start) and [`end`](Self::end) minus the time
+    /// spent in preempting handlers.
+    pub time: Duration,
+
+    /// The exceptions that directly preempted the handler, in order.
+    pub preempted_by: Vec<ExceptionType>,
+
+    /// The exception whose handler this one preempted, if any.
+    pub preempting: Option<ExceptionType>,
+
+    /// The time between the handler being entered and it being
+    /// observed to execute, if measured. See
+    /// [`HandlerOptions`](HandlerOptions).
+    pub latency: Option<Duration>,
+}
+
+/// Statistics of the executions of an exception handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HandlerStats {
+    /// Number of completed handler executions.
+    pub count: u64,
+
+    /// Total time spent in the handler.
+    pub total: Duration,
+
+    /// Shortest handler execution.
+    pub min: Duration,
+
+    /// Longest handler execution.
+    pub max: Duration,
+
+    /// Number of handler executions that were preempted by another
+    /// exception.
+    pub preempted: u64,
+
+    /// Number of handler executions that preempted another exception
+    /// handler.
+    pub preempting: u64,
+}
+
+impl HandlerStats {
+    /// Mean handler execution time.
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64)
+    }
+}
+
+/// An exception handler that has been entered but not yet exited.
+#[derive(Debug, Clone)]
+struct Frame {
+    execution: HandlerExecution,
+
+    /// When the handler last started or resumed executing.
+    resumed: Duration,
+}
+
+/// Reconstructs [`HandlerExecution`]s. Packets are
+/// [pushed](Self::push) with their timestamps, and completed executions
+/// are [pulled](Self::pull) out in order of completion. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct HandlerTracker {
+    options: HandlerOptions,
+    stack: Vec<Frame>,
+    complete: VecDeque<HandlerExecution>,
+}
+
+impl HandlerTracker {
+    pub fn new(options: HandlerOptions) -> Self {
+        Self {
+            options,
+            stack: vec![],
+            complete: VecDeque::new(),
+        }
+    }
+
+    /// Processes a packet that was generated at `timestamp`. Packets
+    /// other than [`ExceptionTrace`](TracePacket::ExceptionTrace) are
+    /// ignored, save for those that measure entry latency.
+    pub fn push(&mut self, packet: &TracePacket, timestamp: &Timestamp) {
+        let now = timestamp.offset();
+        let (exception, action) = match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                (ExceptionType::from(*exception), action)
+            }
+            TracePacket::Instrumentation { port, .. } => {
+                if self.options.marker_port == Some(*port) {
+                    self.observe(now);
+                }
+                return;
+            }
+            TracePacket::PCSample { pc: Some(pc) } => {
+                if let Some(top) = self.stack.last() {
+                    let range = self.options.handler_addresses.get(&top.execution.exception);
+                    if matches!(range, Some(range) if range.contains(pc)) {
+                        self.observe(now);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        match action {
+            ExceptionAction::Entered => {
+                let preempting = match self.stack.last_mut() {
+                    Some(top) => {
+                        top.execution.time += now.saturating_sub(top.resumed);
+                        top.execution.preempted_by.push(exception);
+                        Some(top.execution.exception)
+                    }
+                    None => None,
+                };
+                self.stack.push(Frame {
+                    execution: HandlerExecution {
+                        exception,
+                        start: now,
+                        end: now,
+                        time: Duration::ZERO,
+                        preempted_by: vec![],
+                        preempting,
+                        latency: None,
+                    },
+                    resumed: now,
+                });
+            }
+            ExceptionAction::Exited => {
+                // Handlers above the exited one were never seen exiting,
+                // e.g. because packets were lost; discard them.
+                let i = match self
+                    .stack
+                    .iter()
+                    .rposition(|f| f.execution.exception == exception)
+                {
+                    Some(i) => i,
+                    None => return,
+                };
+                let mut frame = self.stack.swap_remove(i);
+                self.stack.truncate(i);
+                frame.execution.time += now.saturating_sub(frame.resumed);
+                frame.execution.end = now;
+                // Thread mode has exception number 0
+                if u16::from(exception) != 0 {
+                    self.complete.push_back(frame.execution);
+                }
+
+                if let Some(top) = self.stack.last_mut() {
+                    top.resumed = now;
+                }
+            }
+            ExceptionAction::Returned => {
+                if let Some(top) = self.stack.last_mut() {
+                    if top.execution.exception == exception {
+                        top.resumed = now;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The executing handler was observed to execute.
+    fn observe(&mut self, now: Duration) {
+        if let Some(top) = self.stack.last_mut() {
+            let execution = &mut top.execution;
+            if execution.latency.is_none() {
+                execution.latency = Some(now.saturating_sub(execution.start));
+            }
+        }
+    }
+
+    /// Pulls the next completed handler execution.
+    pub fn pull(&mut self) -> Option<HandlerExecution> {
+        self.complete.pop_front()
+    }
+}
+
+/// Iterator adapter that yield the [`HandlerExecution`]s of an iterator
+/// over [`TimestampedTracePackets`](TimestampedTracePackets), e.g.
+/// [`Timestamps`](crate::Timestamps). See also [`HandlerTracker`].
+pub struct HandlerExecutions<I> {
+    inner: I,
+    tracker: HandlerTracker,
+}
+
+impl<I> HandlerExecutions<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    pub fn new(inner: I, options: HandlerOptions) -> Self {
+        Self {
+            inner,
+            tracker: HandlerTracker::new(options),
+        }
+    }
+}
+
+impl<I> Iterator for HandlerExecutions<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    type Item = Result<HandlerExecution, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(execution) = self.tracker.pull() {
+                return Some(Ok(execution));
+            }
+            match self.inner.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(set) => {
+                    for packet in set.packets.iter() {
+                        self.tracker.push(packet, &set.timestamp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Accumulates [`HandlerStats`] per exception. Packets are
+/// [pushed](Self::push) with their timestamps. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct ExceptionStats {
+    tracker: HandlerTracker,
+    stats: BTreeMap<ExceptionType, HandlerStats>,
+}
+
+impl ExceptionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accumulates the statistics of all exception trace packets of an
+    /// iterator over timestamped sets, e.g.
+    /// [`Timestamps`](crate::Timestamps). Returns on the first error.
+    pub fn from_sets<I>(sets: I) -> Result<Self, DecoderError>
+    where
+        I: IntoIterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+    {
+        let mut stats = Self::new();
+        for set in sets {
+            stats.push_set(&set?);
+        }
+        Ok(stats)
+    }
+
+    /// Processes all packets of a timestamped set.
+    pub fn push_set(&mut self, set: &TimestampedTracePackets) {
+        for packet in set.packets.iter() {
+            self.push(packet, &set.timestamp);
+        }
+    }
+
+    /// Processes a packet that was generated at `timestamp`. Packets
+    /// other than [`ExceptionTrace`](TracePacket::ExceptionTrace) are
+    /// ignored.
+    pub fn push(&mut self, packet: &TracePacket, timestamp: &Timestamp) {
+        self.tracker.push(packet, timestamp);
+        while let Some(execution) = self.tracker.pull() {
+            self.record(&execution);
+        }
+    }
+
+    /// Accumulates a handler execution, e.g. as reconstructed by a
+    /// [`HandlerTracker`].
+    pub fn record(&mut self, execution: &HandlerExecution) {
+        let time = execution.time;
+        let stats = self
+            .stats
+            .entry(execution.exception)
+            .or_insert_with(|| HandlerStats {
+                count: 0,
+                total: Duration::ZERO,
+                min: time,
+                max: time,
+                preempted: 0,
+                preempting: 0,
+            });
+        stats.count += 1;
+        stats.total += time;
+        stats.min = stats.min.min(time);
+        stats.max = stats.max.max(time);
+        stats.preempted += u64::from(!execution.preempted_by.is_empty());
+        stats.preempting += u64::from(execution.preempting.is_some());
+    }
+
+    /// The statistics of an exception, if its handler completed at
+    /// least once.
+    pub fn get(&self, exception: ExceptionType) -> Option<&HandlerStats> {
+        self.stats.get(&exception)
+    }
+
+    /// Iterates over the statistics of all exceptions whose handler
+    /// completed at least once, in order of exception number.
+    pub fn iter(&self) -> impl Iterator<Item = (ExceptionType, &HandlerStats)> {
+        self.stats
+            .iter()
+            .map(|(exception, stats)| (*exception, stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn preemption() {
+        let irq = |n: u16| ExceptionType::try_from(16 + n).unwrap();
+        let mut stats = ExceptionStats::new();
+        for (n, action, us) in [
+            (3, ExceptionAction::Entered, 0),
+            (5, ExceptionAction::Entered, 10),
+            (5, ExceptionAction::Exited, 14),
+            (3, ExceptionAction::Returned, 15),
+            (3, ExceptionAction::Exited, 20),
+            (5, ExceptionAction::Entered, 30),
+            (5, ExceptionAction::Exited, 32),
+        ] {
+            stats.push(
+                &TracePacket::ExceptionTrace {
+                    exception: irq(n).into(),
+                    action,
+                },
+                &Timestamp::Sync(Duration::from_micros(us)),
+            );
+        }
+
+        assert_eq!(
+            stats.get(irq(3)),
+            Some(&HandlerStats {
+                count: 1,
+                total: Duration::from_micros(15),
+                min: Duration::from_micros(15),
+                max: Duration::from_micros(15),
+                preempted: 1,
+                preempting: 0,
+            })
+        );
+        let irq5 = stats.get(irq(5)).unwrap();
+        assert_eq!(irq5.count, 2);
+        assert_eq!(irq5.mean(), Duration::from_micros(3));
+        assert_eq!(
+            (irq5.min, irq5.max),
+            (Duration::from_micros(2), Duration::from_micros(4))
+        );
+        assert_eq!((irq5.preempted, irq5.preempting), (0, 1));
+        assert_eq!(stats.iter().count(), 2);
+    }
+
+    #[test]
+    fn executions() {
+        let irq = |n: u16| ExceptionType::try_from(16 + n).unwrap();
+        let exception = |n, action| TracePacket::ExceptionTrace {
+            exception: irq(n).into(),
+            action,
+        };
+        let mut tracker = HandlerTracker::new(HandlerOptions {
+            marker_port: Some(7),
+            handler_addresses: [(irq(5), 0x100..0x200)].into_iter().collect(),
+        });
+        for (packet, us) in [
+            (exception(3, ExceptionAction::Entered), 0),
+            (
+                TracePacket::Instrumentation {
+                    port: 7,
+                    payload: [0].into(),
+                },
+                1,
+            ),
+            (exception(5, ExceptionAction::Entered), 10),
+            (TracePacket::PCSample { pc: Some(0x80) }, 11),
+            (TracePacket::PCSample { pc: Some(0x180) }, 12),
+            (exception(5, ExceptionAction::Exited), 14),
+            (exception(3, ExceptionAction::Returned), 15),
+            (exception(3, ExceptionAction::Exited), 20),
+        ] {
+            tracker.push(&packet, &Timestamp::Sync(Duration::from_micros(us)));
+        }
+
+        assert_eq!(
+            tracker.pull(),
+            Some(HandlerExecution {
+                exception: irq(5),
+                start: Duration::from_micros(10),
+                end: Duration::from_micros(14),
+                time: Duration::from_micros(4),
+                preempted_by: vec![],
+                preempting: Some(irq(3)),
+                latency: Some(Duration::from_micros(2)),
+            })
+        );
+        assert_eq!(
+            tracker.pull(),
+            Some(HandlerExecution {
+                exception: irq(3),
+                start: Duration::from_micros(0),
+                end: Duration::from_micros(20),
+                time: Duration::from_micros(15),
+                preempted_by: vec![irq(5)],
+                preempting: None,
+                latency: Some(Duration::from_micros(1)),
+            })
+        );
+        assert_eq!(tracker.pull(), None);
+    }
+}
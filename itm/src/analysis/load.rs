@@ -0,0 +1,238 @@
+//! CPU load estimation from periodic PC samples.
+//!
+//! When periodic PC sampling is enabled, the target emits a
+//! [`PCSample`](TracePacket::PCSample) at a fixed interval, with no PC
+//! value if the processor was sleeping. The ratio of non-sleep samples
+//! to all samples within a period thus estimates the CPU utilization
+//! during that period. [`CpuLoad`] computes this ratio over consecutive
+//! time buckets of a configurable length.
+//!
+//! ```
+//! use itm::analysis::load::CpuLoad;
+//! use itm::{Timestamp, TracePacket};
+//! use std::time::Duration;
+//!
+//! let mut load = CpuLoad::new(Duration::from_millis(1));
+//! for (pc, us) in [(Some(0x100), 100), (None, 200), (None, 300), (Some(0x100), 1100)] {
+//!     load.push(
+//!         &TracePacket::PCSample { pc },
+//!         &Timestamp::Sync(Duration::from_micros(us)),
+//!     );
+//! }
+//! load.finish();
+//! let utilizations: Vec<_> = std::iter::from_fn(|| load.pull())
+//!     .map(|bucket| bucket.utilization())
+//!     .collect();
+//! assert_eq!(utilizations, [Some(1.0 / 3.0), Some(1.0)]);
+//! ```
+
+use crate::{DecoderError, Timestamp, TimestampedTracePackets, TracePacket};
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// The PC samples of a time bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadBucket {
+    /// Start of the bucket, relative to trace clock start.
+    pub start: Duration,
+
+    /// End of the bucket, exclusive.
+    pub end: Duration,
+
+    /// Number of PC samples in the bucket.
+    pub samples: u64,
+
+    /// Number of PC samples in the bucket taken while the processor was
+    /// sleeping.
+    pub sleeping: u64,
+}
+
+impl LoadBucket {
+    /// The estimated CPU utilization during the bucket, from 0 to 1.
+    /// `None` if the bucket has no samples.
+    pub fn utilization(&self) -> Option<f64> {
+        if self.samples == 0 {
+            return None;
+        }
+        Some((self.samples - self.sleeping) as f64 / self.samples as f64)
+    }
+}
+
+/// Computes the CPU utilization over consecutive time buckets. Packets
+/// are [pushed](Self::push) with their timestamps, and completed
+/// buckets are [pulled](Self::pull) out in order. Buckets without
+/// samples between two buckets with samples are yielded as well, so
+/// that the buckets form a regular time series. See the [module
+/// documentation](self).
+#[derive(Debug, Clone)]
+pub struct CpuLoad {
+    bucket: Duration,
+    current: Option<LoadBucket>,
+    complete: VecDeque<LoadBucket>,
+}
+
+impl CpuLoad {
+    /// Computes the utilization over buckets of length `bucket`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket` is zero.
+    pub fn new(bucket: Duration) -> Self {
+        assert!(bucket > Duration::ZERO, "bucket length must be non-zero");
+        Self {
+            bucket,
+            current: None,
+            complete: VecDeque::new(),
+        }
+    }
+
+    /// Processes a packet that was generated at `timestamp`. Packets
+    /// other than [`PCSample`](TracePacket::PCSample) are ignored.
+    pub fn push(&mut self, packet: &TracePacket, timestamp: &Timestamp) {
+        let sleeping = match packet {
+            TracePacket::PCSample { pc } => pc.is_none(),
+            _ => return,
+        };
+        let now = timestamp.offset();
+        let bucket = self.bucket.as_nanos();
+        let start = Duration::from_nanos((now.as_nanos() / bucket * bucket) as u64);
+
+        loop {
+            match &mut self.current {
+                // A timestamp that moves the timeline backwards is
+                // accounted to the current bucket
+                Some(bucket) if bucket.start >= start => {
+                    bucket.samples += 1;
+                    bucket.sleeping += u64::from(sleeping);
+                    return;
+                }
+                Some(bucket) => {
+                    let next = bucket.end;
+                    self.complete.push_back(bucket.clone());
+                    self.current = Some(self.empty(next));
+                }
+                None => self.current = Some(self.empty(start)),
+            }
+        }
+    }
+
+    fn empty(&self, start: Duration) -> LoadBucket {
+        LoadBucket {
+            start,
+            end: start + self.bucket,
+            samples: 0,
+            sleeping: 0,
+        }
+    }
+
+    /// Completes the current bucket, e.g. when the trace stream has
+    /// ended.
+    pub fn finish(&mut self) {
+        if let Some(bucket) = self.current.take() {
+            self.complete.push_back(bucket);
+        }
+    }
+
+    /// Pulls the next completed bucket.
+    pub fn pull(&mut self) -> Option<LoadBucket> {
+        self.complete.pop_front()
+    }
+}
+
+/// Iterator adapter that yield the [`LoadBucket`]s of an iterator over
+/// [`TimestampedTracePackets`](TimestampedTracePackets), e.g.
+/// [`Timestamps`](crate::Timestamps). The last bucket is yielded when
+/// the inner iterator ends. See also [`CpuLoad`].
+pub struct CpuLoadSeries<I> {
+    inner: I,
+    load: CpuLoad,
+    done: bool,
+}
+
+impl<I> CpuLoadSeries<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    /// Computes the utilization over buckets of length `bucket`. See
+    /// [`CpuLoad::new`].
+    pub fn new(inner: I, bucket: Duration) -> Self {
+        Self {
+            inner,
+            load: CpuLoad::new(bucket),
+            done: false,
+        }
+    }
+}
+
+impl<I> Iterator for CpuLoadSeries<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    type Item = Result<LoadBucket, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bucket) = self.load.pull() {
+                return Some(Ok(bucket));
+            }
+            if self.done {
+                return None;
+            }
+            match self.inner.next() {
+                None => {
+                    self.done = true;
+                    self.load.finish();
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(set)) => {
+                    for packet in set.packets.iter() {
+                        self.load.push(packet, &set.timestamp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets() {
+        let mut load = CpuLoad::new(Duration::from_millis(1));
+        for (pc, us) in [
+            (None, 500),
+            (Some(0x100), 600),
+            (Some(0x100), 3100),
+            (None, 3000),
+        ] {
+            load.push(
+                &TracePacket::PCSample { pc },
+                &Timestamp::Sync(Duration::from_micros(us)),
+            );
+        }
+        load.push(
+            &TracePacket::Overflow,
+            &Timestamp::Sync(Duration::from_millis(5)),
+        );
+        load.finish();
+
+        let buckets: Vec<_> = std::iter::from_fn(|| load.pull())
+            .map(|b| (b.start.as_millis(), b.samples, b.sleeping))
+            .collect();
+        assert_eq!(buckets, [(0, 2, 1), (1, 0, 0), (2, 0, 0), (3, 2, 1)]);
+        assert_eq!(
+            LoadBucket {
+                start: Duration::ZERO,
+                end: Duration::from_millis(1),
+                samples: 0,
+                sleeping: 0,
+            }
+            .utilization(),
+            None
+        );
+    }
+}
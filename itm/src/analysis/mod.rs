@@ -0,0 +1,13 @@
+//! Analyses of decoded trace data.
+//!
+//! Most analyses consume [`TimestampedTracePackets`](crate::TimestampedTracePackets),
+//! e.g. as yielded by [`Timestamps`](crate::Timestamps), and summarize
+//! what the target did during the capture.
+
+pub mod chrome;
+pub mod context;
+pub mod exceptions;
+pub mod load;
+pub mod pprof;
+pub mod profile;
+pub mod stats;
@@ -0,0 +1,305 @@
+//! Export of PC sample profiles in the pprof format.
+//!
+//! [`encode`] converts a [`Profile`] into a serialized `profile.proto`
+//! message, as read by `pprof` and Speedscope, so that firmware profiles
+//! can be examined with the same tools as host profiles. The message is
+//! not compressed; `pprof` accepts both plain and gzipped profiles.
+//!
+//! ```
+//! use itm::analysis::{pprof, profile::Profile};
+//! use itm::symbols::SourceFrame;
+//! use itm::TracePacket;
+//! use std::time::Duration;
+//!
+//! let mut profile = Profile::new();
+//! profile.push(&TracePacket::PCSample { pc: Some(0x100) });
+//! let pb = pprof::encode(&profile, Some(Duration::from_micros(100)), |_| {
+//!     vec![SourceFrame {
+//!         function: Some("main".to_string()),
+//!         file: Some("src/main.rs".to_string()),
+//!         line: Some(12),
+//!     }]
+//! });
+//! assert!(!pb.is_empty());
+//! ```
+
+use super::profile::Profile;
+use crate::symbols::SourceFrame;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Serializes `profile` as a pprof `Profile` message.
+///
+/// Every sample has a `samples` value and, if the sampling `period` is
+/// known, a `cpu` value in nanoseconds. `frames` resolves an address to
+/// its function frames, from the innermost inlined function to its
+/// outermost caller, e.g. [`Symbolizer::frames`](crate::symbols::Symbolizer::frames);
+/// addresses for which it returns no frames are exported without
+/// function information. Sleep samples are attributed to a `[sleeping]`
+/// pseudo function.
+pub fn encode<F>(profile: &Profile, period: Option<Duration>, mut frames: F) -> Vec<u8>
+where
+    F: FnMut(u32) -> Vec<SourceFrame>,
+{
+    let mut strings = Strings::default();
+    let mut functions: BTreeMap<(i64, i64), u64> = BTreeMap::new();
+    let mut msg = Message::default();
+
+    let value_type = |strings: &mut Strings, ty, unit| {
+        let mut vt = Message::default();
+        vt.int(1, strings.index(ty));
+        vt.int(2, strings.index(unit));
+        vt
+    };
+    msg.message(1, &value_type(&mut strings, "samples", "count"));
+    if period.is_some() {
+        msg.message(1, &value_type(&mut strings, "cpu", "nanoseconds"));
+    }
+    let period_ns = period.map_or(0, |p| p.as_nanos() as i64);
+
+    let mut locations = vec![];
+    let mut samples = vec![];
+    let sleeping = (profile.sleeping() > 0).then(|| {
+        (
+            None,
+            vec![SourceFrame {
+                function: Some("[sleeping]".to_string()),
+                file: None,
+                line: None,
+            }],
+            profile.sleeping(),
+        )
+    });
+    let entries = profile
+        .by_address()
+        .into_iter()
+        .map(|entry| (Some(entry.key), frames(entry.key), entry.samples))
+        .chain(sleeping);
+    for (i, (address, frames, count)) in entries.enumerate() {
+        let id = i as u64 + 1;
+        let mut location = Message::default();
+        location.uint(1, id);
+        if let Some(address) = address {
+            location.uint(3, u64::from(address));
+        }
+        for frame in frames {
+            let name = strings.index(frame.function.as_deref().unwrap_or("??"));
+            let file = strings.index(frame.file.as_deref().unwrap_or(""));
+            let next = functions.len() as u64 + 1;
+            let function = *functions.entry((name, file)).or_insert(next);
+            let mut line = Message::default();
+            line.uint(1, function);
+            line.int(2, i64::from(frame.line.unwrap_or(0)));
+            location.message(4, &line);
+        }
+        locations.push(location);
+
+        let mut sample = Message::default();
+        sample.packed(1, &[id]);
+        let count = count as i64;
+        if period.is_some() {
+            sample.packed(2, &[count as u64, (count * period_ns) as u64]);
+        } else {
+            sample.packed(2, &[count as u64]);
+        }
+        samples.push(sample);
+    }
+
+    for sample in samples.iter() {
+        msg.message(2, sample);
+    }
+    for location in locations.iter() {
+        msg.message(4, location);
+    }
+    for ((name, file), id) in functions {
+        let mut function = Message::default();
+        function.uint(1, id);
+        function.int(2, name);
+        function.int(3, name);
+        function.int(4, file);
+        msg.message(5, &function);
+    }
+    if period.is_some() {
+        msg.message(11, &value_type(&mut strings, "cpu", "nanoseconds"));
+        msg.int(12, period_ns);
+    }
+    for string in strings.table.iter() {
+        msg.bytes(6, string.as_bytes());
+    }
+    msg.buf
+}
+
+/// The string table of a profile. The first string is always empty.
+struct Strings {
+    table: Vec<String>,
+    indices: BTreeMap<String, i64>,
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        Self {
+            table: vec![String::new()],
+            indices: [(String::new(), 0)].into_iter().collect(),
+        }
+    }
+}
+
+impl Strings {
+    fn index(&mut self, s: &str) -> i64 {
+        if let Some(i) = self.indices.get(s) {
+            return *i;
+        }
+        let i = self.table.len() as i64;
+        self.table.push(s.to_string());
+        self.indices.insert(s.to_string(), i);
+        i
+    }
+}
+
+/// A protobuf message under construction.
+#[derive(Default)]
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire_type));
+    }
+
+    /// Writes a `uint64` field. Zero, the default, is omitted.
+    fn uint(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.key(field, 0);
+            self.varint(v);
+        }
+    }
+
+    /// Writes an `int64` field. Zero, the default, is omitted.
+    fn int(&mut self, field: u32, v: i64) {
+        self.uint(field, v as u64);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn message(&mut self, field: u32, msg: &Message) {
+        self.bytes(field, &msg.buf);
+    }
+
+    /// Writes a packed repeated varint field.
+    fn packed(&mut self, field: u32, vs: &[u64]) {
+        let mut packed = Message::default();
+        for v in vs {
+            packed.varint(*v);
+        }
+        self.bytes(field, &packed.buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TracePacket;
+
+    /// Splits a message into its fields, as varints or bytes.
+    fn fields(mut buf: &[u8]) -> Vec<(u64, Result<u64, &[u8]>)> {
+        fn varint(buf: &mut &[u8]) -> u64 {
+            let mut v = 0;
+            for (i, b) in buf.iter().enumerate() {
+                v |= u64::from(b & 0x7f) << (7 * i);
+                if b & 0x80 == 0 {
+                    *buf = &buf[i + 1..];
+                    return v;
+                }
+            }
+            panic!("truncated varint");
+        }
+
+        let mut fields = vec![];
+        while !buf.is_empty() {
+            let key = varint(&mut buf);
+            match key & 7 {
+                0 => fields.push((key >> 3, Ok(varint(&mut buf)))),
+                2 => {
+                    let len = varint(&mut buf) as usize;
+                    fields.push((key >> 3, Err(&buf[..len])));
+                    buf = &buf[len..];
+                }
+                _ => panic!("unexpected wire type"),
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn profile() {
+        let mut profile = Profile::new();
+        for pc in [Some(0x100), Some(0x100), Some(0x200), None] {
+            profile.push(&TracePacket::PCSample { pc });
+        }
+        let pb = encode(&profile, Some(Duration::from_micros(10)), |pc| {
+            if pc != 0x100 {
+                return vec![];
+            }
+            vec![
+                SourceFrame {
+                    function: Some("inlined".to_string()),
+                    file: Some("src/lib.rs".to_string()),
+                    line: Some(3),
+                },
+                SourceFrame {
+                    function: Some("main".to_string()),
+                    file: Some("src/main.rs".to_string()),
+                    line: Some(12),
+                },
+            ]
+        });
+
+        let fields = fields(&pb);
+        let of = |n| fields.iter().filter(move |(f, _)| *f == n).map(|(_, v)| *v);
+        let strings: Vec<_> = of(6)
+            .map(|s| std::str::from_utf8(s.unwrap_err()).unwrap())
+            .collect();
+        assert_eq!(
+            strings,
+            [
+                "",
+                "samples",
+                "count",
+                "cpu",
+                "nanoseconds",
+                "inlined",
+                "src/lib.rs",
+                "main",
+                "src/main.rs",
+                "[sleeping]"
+            ]
+        );
+        assert_eq!((of(2).count(), of(4).count(), of(5).count()), (3, 3, 3));
+        assert_eq!(of(12).collect::<Vec<_>>(), [Ok(10_000)]);
+
+        // Sample of 0x100: location 1, 2 samples, 20 µs
+        let sample = super::tests::fields(of(2).next().unwrap().unwrap_err());
+        assert_eq!(
+            sample,
+            [(1, Err(&[1][..])), (2, Err(&[2, 0xa0, 0x9c, 0x01][..]))]
+        );
+        // Location 1: two frames, innermost first
+        let location = super::tests::fields(of(4).next().unwrap().unwrap_err());
+        assert_eq!(location.len(), 4);
+        assert_eq!(location[..2], [(1, Ok(1)), (3, Ok(0x100))]);
+    }
+}
@@ -0,0 +1,303 @@
+//! Statistical profiling from sampled program counter values.
+//!
+//! [`Profile`] aggregates the addresses of
+//! [`PCSample`](TracePacket::PCSample) and
+//! [`DataTracePC`](TracePacket::DataTracePC) packets into a histogram,
+//! by address or, given a [`SymbolTable`], by function. With periodic
+//! PC sampling, the share of samples of a function estimates the share
+//! of time the processor spent executing it.
+//!
+//! ```
+//! use itm::analysis::profile::Profile;
+//! use itm::symbols::SymbolTable;
+//! use itm::TracePacket;
+//!
+//! let mut profile = Profile::new();
+//! for pc in [Some(0x100), Some(0x104), Some(0x200), None] {
+//!     profile.push(&TracePacket::PCSample { pc });
+//! }
+//! let symbols: SymbolTable = [("main".to_string(), 0x100..0x180)].into_iter().collect();
+//! let by_symbol = profile.by_symbol(&symbols);
+//! assert_eq!(by_symbol[0].key.as_deref(), Some("main"));
+//! assert_eq!((by_symbol[0].samples, by_symbol[0].percent), (2, 50.0));
+//! assert_eq!(by_symbol[1].key, None);
+//! ```
+//!
+//! [`CollapsedStacks`] instead keeps the samples in the "folded" format
+//! of `flamegraph.pl` and `inferno`, so that they can be visualized as
+//! a flame graph. As PC samples carry no call stack, each sample has a
+//! single frame, or the frames of its inlined functions, optionally
+//! under the active exception as a pseudo parent frame.
+
+use super::context::ContextTracker;
+use crate::symbols::SymbolTable;
+use crate::{DecoderError, ExceptionType, TracePacket};
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io;
+
+/// An entry of a profile histogram.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileEntry<K> {
+    /// What the samples were aggregated by: an address, or a function
+    /// name.
+    pub key: K,
+
+    /// Number of samples.
+    pub samples: u64,
+
+    /// Share of all samples, including sleep samples, in percent.
+    pub percent: f64,
+}
+
+/// A histogram of sampled program counter values. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    addresses: BTreeMap<u32, u64>,
+    sleeping: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aggregates the samples of all packets of an iterator over
+    /// packets, e.g. [`Singles`](crate::Singles). Returns on the first
+    /// error.
+    pub fn from_packets<I>(packets: I) -> Result<Self, DecoderError>
+    where
+        I: IntoIterator<Item = Result<TracePacket, DecoderError>>,
+    {
+        let mut profile = Self::new();
+        for packet in packets {
+            profile.push(&packet?);
+        }
+        Ok(profile)
+    }
+
+    /// Processes a packet. Packets other than
+    /// [`PCSample`](TracePacket::PCSample) and
+    /// [`DataTracePC`](TracePacket::DataTracePC) are ignored.
+    pub fn push(&mut self, packet: &TracePacket) {
+        match packet {
+            TracePacket::PCSample { pc: Some(pc) } | TracePacket::DataTracePC { pc, .. } => {
+                *self.addresses.entry(*pc).or_default() += 1
+            }
+            TracePacket::PCSample { pc: None } => self.sleeping += 1,
+            _ => (),
+        }
+    }
+
+    /// Number of samples, including sleep samples.
+    pub fn total(&self) -> u64 {
+        self.addresses.values().sum::<u64>() + self.sleeping
+    }
+
+    /// Number of samples taken while the processor was sleeping.
+    pub fn sleeping(&self) -> u64 {
+        self.sleeping
+    }
+
+    /// The histogram by address, in order of decreasing sample count.
+    pub fn by_address(&self) -> Vec<ProfileEntry<u32>> {
+        self.entries(self.addresses.iter().map(|(pc, n)| (*pc, *n)))
+    }
+
+    /// The histogram by the function that contains the address, in
+    /// order of decreasing sample count. Samples of addresses outside
+    /// any function are aggregated under `None`.
+    pub fn by_symbol(&self, symbols: &SymbolTable) -> Vec<ProfileEntry<Option<String>>> {
+        let mut functions: BTreeMap<Option<&str>, u64> = BTreeMap::new();
+        for (pc, n) in self.addresses.iter() {
+            *functions.entry(symbols.lookup(*pc)).or_default() += n;
+        }
+        self.entries(
+            functions
+                .into_iter()
+                .map(|(name, n)| (name.map(str::to_string), n)),
+        )
+    }
+
+    fn entries<K, I>(&self, counts: I) -> Vec<ProfileEntry<K>>
+    where
+        I: Iterator<Item = (K, u64)>,
+    {
+        let total = self.total();
+        let mut entries: Vec<_> = counts
+            .map(|(key, samples)| ProfileEntry {
+                key,
+                samples,
+                percent: samples as f64 * 100.0 / total as f64,
+            })
+            .collect();
+        // Stable, so that ties remain ordered by key
+        entries.sort_by_key(|e| Reverse(e.samples));
+        entries
+    }
+}
+
+/// Sampled PC values by the context they were sampled in, for output
+/// in the folded stack format. See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct CollapsedStacks {
+    tracker: ContextTracker,
+    /// Sample counts by context and address, `None` if sleeping.
+    samples: BTreeMap<(ExceptionType, Option<u32>), u64>,
+}
+
+impl CollapsedStacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes a packet. [`ExceptionTrace`](TracePacket::ExceptionTrace)
+    /// packets update the active exception, and the samples of
+    /// [`PCSample`](TracePacket::PCSample) and
+    /// [`DataTracePC`](TracePacket::DataTracePC) packets are recorded.
+    /// Other packets are ignored.
+    pub fn push(&mut self, packet: &TracePacket) {
+        self.tracker.push(packet);
+        let pc = match packet {
+            TracePacket::PCSample { pc } => *pc,
+            TracePacket::DataTracePC { pc, .. } => Some(*pc),
+            _ => return,
+        };
+        *self
+            .samples
+            .entry((self.tracker.current(), pc))
+            .or_default() += 1;
+    }
+
+    /// The folded stacks and their sample counts, e.g.
+    /// `("SysTick;app::tick", 12)`, sorted by stack. `frames` resolves an address to its function frames, from
+    /// the outermost to the innermost one; unresolved addresses, for
+    /// which it returns no frames, are formatted in hex. Sleep samples
+    /// have a `[sleeping]` frame. If `exceptions` is set, the active
+    /// exception, e.g. `SysTick` or `ThreadMode`, is the root frame of
+    /// each stack.
+    pub fn fold<F>(&self, exceptions: bool, mut frames: F) -> Vec<(String, u64)>
+    where
+        F: FnMut(u32) -> Vec<String>,
+    {
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for ((context, pc), n) in self.samples.iter() {
+            let mut stack: Vec<String> = match pc {
+                Some(pc) => frames(*pc),
+                None => vec!["[sleeping]".to_string()],
+            };
+            if stack.is_empty() {
+                stack.push(format!("{:#010x}", pc.unwrap_or_default()));
+            }
+            if exceptions {
+                stack.insert(0, context.to_string());
+            }
+            // Frames are separated by semicolons, which occur in
+            // demangled names of array types, e.g. `[u8; 4]`
+            let stack: Vec<String> = stack.iter().map(|f| f.replace(';', ":")).collect();
+            *stacks.entry(stack.join(";")).or_default() += n;
+        }
+        stacks.into_iter().collect()
+    }
+
+    /// Writes the [folded stacks](Self::fold), one `stack count` per
+    /// line.
+    pub fn write<W, F>(&self, mut writer: W, exceptions: bool, frames: F) -> io::Result<()>
+    where
+        W: io::Write,
+        F: FnMut(u32) -> Vec<String>,
+    {
+        for (stack, n) in self.fold(exceptions, frames) {
+            writeln!(writer, "{} {}", stack, n)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let packets = [
+            TracePacket::PCSample { pc: Some(0x104) },
+            TracePacket::DataTracePC {
+                comparator: 0,
+                pc: 0x200,
+            },
+            TracePacket::PCSample { pc: Some(0x104) },
+            TracePacket::PCSample { pc: None },
+            TracePacket::Overflow,
+        ];
+        let profile = Profile::from_packets(packets.iter().cloned().map(Ok)).unwrap();
+        assert_eq!((profile.total(), profile.sleeping()), (4, 1));
+        assert_eq!(
+            profile.by_address(),
+            [
+                ProfileEntry {
+                    key: 0x104,
+                    samples: 2,
+                    percent: 50.0,
+                },
+                ProfileEntry {
+                    key: 0x200,
+                    samples: 1,
+                    percent: 25.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed() {
+        use crate::ExceptionAction;
+
+        let systick = ExceptionType::from_name("SysTick").unwrap();
+        let packets = [
+            TracePacket::PCSample { pc: Some(0x104) },
+            TracePacket::ExceptionTrace {
+                exception: systick.into(),
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::PCSample { pc: Some(0x200) },
+            TracePacket::PCSample { pc: Some(0x202) },
+            TracePacket::ExceptionTrace {
+                exception: systick.into(),
+                action: ExceptionAction::Exited,
+            },
+            TracePacket::PCSample { pc: None },
+            TracePacket::PCSample { pc: Some(0x300) },
+        ];
+        let mut stacks = CollapsedStacks::new();
+        for packet in packets.iter() {
+            stacks.push(packet);
+        }
+        let symbols: SymbolTable = [
+            ("main".to_string(), 0x100..0x180),
+            ("<[u8; 4]>::tick".to_string(), 0x200..0x210),
+        ]
+        .into_iter()
+        .collect();
+        let frames = |pc| symbols.lookup(pc).map(str::to_string).into_iter().collect();
+
+        let mut out = vec![];
+        stacks.write(&mut out, false, frames).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x00000300 1\n<[u8: 4]>::tick 2\n[sleeping] 1\nmain 1\n"
+        );
+        assert_eq!(
+            stacks.fold(true, frames),
+            [
+                ("SysTick;<[u8: 4]>::tick".to_string(), 2),
+                ("ThreadMode;0x00000300".to_string(), 1),
+                ("ThreadMode;[sleeping]".to_string(), 1),
+                ("ThreadMode;main".to_string(), 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Summary statistics of a capture.
+//!
+//! [`TraceStats`] counts the decoded packets by kind, decode errors,
+//! overflows and the payload bytes written to each stimulus port, and,
+//! for [`TimestampedTracePackets`](crate::TimestampedTracePackets), the
+//! time span that the capture covers. The number of bytes of the trace
+//! stream is counted by wrapping its source in a [`CountingReader`].
+//!
+//! ```
+//! use itm::analysis::stats::{CountingReader, TraceStats};
+//! use itm::{Decoder, DecoderOptions};
+//!
+//! // Instrumentation packets (port 1, 1-byte payload; port 2, 2-byte
+//! // payload), overflow
+//! let stream: &[u8] = &[0b0000_1001, 0x41, 0b0001_0010, 0x42, 0x43, 0b0111_0000];
+//! let reader = CountingReader::new(stream);
+//! let bytes = reader.count();
+//!
+//! let mut stats = TraceStats::new();
+//! for packet in Decoder::new(reader, DecoderOptions::default()).singles() {
+//!     stats.push_result(&packet);
+//! }
+//! stats.bytes = bytes.get();
+//!
+//! assert_eq!(stats.packets["instrumentation"], 2);
+//! assert_eq!(stats.overflows, 1);
+//! assert_eq!(stats.port_bytes[&2], 2);
+//! assert_eq!(stats.bytes, 6);
+//! ```
+
+use crate::{DecoderError, TimestampedTracePackets, TracePacket};
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Counters of a capture. See the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceStats {
+    /// Number of packets by [kind](TracePacket::kind).
+    pub packets: BTreeMap<String, u64>,
+
+    /// Number of bytes of the trace stream. Not counted by `TraceStats`
+    /// itself; see [`CountingReader`].
+    pub bytes: u64,
+
+    /// Number of decode errors, including malformed packets.
+    pub errors: u64,
+
+    /// Number of [`Overflow`](TracePacket::Overflow) packets.
+    pub overflows: u64,
+
+    /// Number of payload bytes of instrumentation packets, by stimulus
+    /// port.
+    pub port_bytes: BTreeMap<u8, u64>,
+
+    /// Offset of the first and the last set of timestamped packets.
+    /// `None` if none were pushed.
+    pub span: Option<(Duration, Duration)>,
+}
+
+impl TraceStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a packet.
+    pub fn push(&mut self, packet: &TracePacket) {
+        *self.packets.entry(packet.kind().to_string()).or_default() += 1;
+        match packet {
+            TracePacket::Overflow => self.overflows += 1,
+            TracePacket::Instrumentation { port, payload } => {
+                *self.port_bytes.entry(*port).or_default() += payload.len() as u64;
+            }
+            _ => (),
+        }
+    }
+
+    /// Counts a packet or a decode error.
+    pub fn push_result(&mut self, result: &Result<TracePacket, DecoderError>) {
+        match result {
+            Ok(packet) => self.push(packet),
+            Err(_) => self.errors += 1,
+        }
+    }
+
+    /// Counts the packets and malformed packets of a set, and extends
+    /// the time span to its offset.
+    pub fn push_set(&mut self, set: &TimestampedTracePackets) {
+        for packet in set.packets.iter() {
+            self.push(packet);
+        }
+        self.errors += set.malformed_packets.len() as u64;
+        let offset = set.timestamp.offset();
+        self.span = Some(match self.span {
+            Some((start, _)) => (start, offset),
+            None => (offset, offset),
+        });
+    }
+
+    /// Total number of packets.
+    pub fn total_packets(&self) -> u64 {
+        self.packets.values().sum()
+    }
+
+    /// Duration of the time span.
+    pub fn duration(&self) -> Option<Duration> {
+        self.span.map(|(start, end)| end.saturating_sub(start))
+    }
+
+    /// Average bandwidth of the trace stream over the time span, in
+    /// bytes per second. `None` if the span is empty.
+    pub fn bandwidth(&self) -> Option<f64> {
+        match self.duration() {
+            Some(duration) if duration > Duration::ZERO => {
+                Some(self.bytes as f64 / duration.as_secs_f64())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A count of bytes read by a [`CountingReader`], shared with it.
+#[derive(Debug, Clone, Default)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    /// Number of bytes read so far.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A [`Read`](Read) wrapper that counts the bytes read. The count can
+/// be read through a [`ByteCount`] after the reader has been handed to
+/// a [`Decoder`](crate::Decoder).
+pub struct CountingReader<R: Read> {
+    reader: R,
+    count: ByteCount,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            count: ByteCount::default(),
+        }
+    }
+
+    /// Returns a handle to the count of bytes read.
+    pub fn count(&self) -> ByteCount {
+        self.count.clone()
+    }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.count.0.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MalformedPacket, Ticks, Timestamp};
+
+    #[test]
+    fn sets() {
+        let set = |secs, packets: Vec<TracePacket>, malformed_packets| TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_secs(secs)),
+            ticks: Ticks {
+                ticks: secs,
+                frequency: 1,
+            },
+            delta: Duration::ZERO,
+            consumed_packets: packets.len(),
+            packets,
+            malformed_packets,
+            overflow: None,
+        };
+
+        let mut stats = TraceStats::new();
+        assert_eq!(stats.bandwidth(), None);
+        stats.push_set(&set(1, vec![TracePacket::Sync], vec![]));
+        stats.push_set(&set(
+            3,
+            vec![TracePacket::Overflow, TracePacket::Overflow],
+            vec![MalformedPacket::InvalidHeader(0b0111_0100)],
+        ));
+        stats.bytes = 100;
+
+        assert_eq!(stats.total_packets(), 3);
+        assert_eq!(stats.packets["overflow"], 2);
+        assert_eq!(stats.overflows, 2);
+        assert_eq!(stats.errors, 1);
+        assert_eq!(stats.duration(), Some(Duration::from_secs(2)));
+        assert_eq!(stats.bandwidth(), Some(50.0));
+    }
+}
@@ -0,0 +1,352 @@
+//! Runtime-agnostic asynchronous decoding.
+//!
+//! [`AsyncDecoder`] wraps any [`futures_io::AsyncRead`] and yields
+//! decoded [`TracePacket`](crate::TracePacket)s as a
+//! [`futures_core::Stream`]. Like the blocking
+//! [`Decoder`](crate::Decoder), it can instead yield
+//! [`TimestampedTracePackets`](crate::TimestampedTracePackets) via
+//! [`AsyncDecoder::timestamps`]. No tasks are spawned and no particular
+//! runtime is required, so the decoder can be driven by smol,
+//! async-std, or any other executor. With the `tokio` feature, tokio
+//! readers are adapted via [`TokioCompat`].
+//!
+//! ```
+//! # futures::executor::block_on(async {
+//! use futures::StreamExt;
+//! use itm::async_decoder::AsyncDecoder;
+//! use itm::DecoderOptions;
+//!
+//! // or anything else that implements futures_io::AsyncRead
+//! let stream: &[u8] = &[
+//!     // ...
+//! ];
+//! let mut decoder = AsyncDecoder::new(stream, DecoderOptions::default());
+//! while let Some(packet) = decoder.next().await {
+//!     // ...
+//! }
+//! # });
+//! ```
+
+use super::iter::Timestamper;
+use super::{
+    ClockDrift, DecoderError, DecoderErrorInt, DecoderOptions, PacketDecoder,
+    TimestampedTracePackets, TimestampsConfiguration, TracePacket,
+};
+
+use futures_core::Stream;
+use futures_io::AsyncRead;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Stream that yield [`TracePacket`](crate::TracePacket)s decoded from
+/// an [`AsyncRead`](futures_io::AsyncRead).
+pub struct AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    reader: R,
+    decoder: PacketDecoder,
+    eof: bool,
+}
+
+impl<R> AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Creates a decoder that reads from `reader`.
+    /// [`DecoderOptions::ignore_eof`] has no effect: an
+    /// [`AsyncRead`](futures_io::AsyncRead) returns
+    /// [`Poll::Pending`] while no data is available, and the stream
+    /// ends at the EOF of the reader.
+    pub fn new(reader: R, options: DecoderOptions) -> Self {
+        Self {
+            reader,
+            decoder: PacketDecoder::new(DecoderOptions {
+                max_buffered: None,
+                ..options
+            }),
+            eof: false,
+        }
+    }
+
+    /// Yields [`TimestampedTracePackets`](crate::TimestampedTracePackets)
+    /// instead, as [`Decoder::timestamps`](crate::Decoder::timestamps)
+    /// does.
+    pub fn timestamps(self, options: TimestampsConfiguration) -> AsyncTimestamps<R> {
+        AsyncTimestamps {
+            decoder: self,
+            timestamper: Timestamper::new(options),
+        }
+    }
+
+    /// Returns a reference to the underlying
+    /// [`AsyncRead`](futures_io::AsyncRead).
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying
+    /// [`AsyncRead`](futures_io::AsyncRead).
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+impl<R> Stream for AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<TracePacket, DecoderError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_single(cx).map(|packet| match packet {
+            Ok(packet) => Some(Ok(packet)),
+            Err(e) => e.into_public().map(Err),
+        })
+    }
+}
+
+impl<R> AsyncDecoder<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Polls the next packet, reading from the reader as needed.
+    /// [`Eof`](DecoderErrorInt::Eof) denotes the EOF of the reader.
+    fn poll_single(&mut self, cx: &mut Context<'_>) -> Poll<Result<TracePacket, DecoderErrorInt>> {
+        let mut buffer: [u8; 256] = [0; 256];
+
+        loop {
+            match self.decoder.next_single() {
+                Err(DecoderErrorInt::Eof) if !self.eof => {
+                    match Pin::new(&mut self.reader).poll_read(cx, &mut buffer) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok(0)) => self.eof = true,
+                        Poll::Ready(Ok(n)) => {
+                            self.decoder.feed(&buffer[..n]);
+                        }
+                        Poll::Ready(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                    }
+                }
+                packet => return Poll::Ready(packet),
+            }
+        }
+    }
+}
+
+/// Stream that yield
+/// [`TimestampedTracePackets`](crate::TimestampedTracePackets). See
+/// [`AsyncDecoder::timestamps`].
+pub struct AsyncTimestamps<R>
+where
+    R: AsyncRead + Unpin,
+{
+    decoder: AsyncDecoder<R>,
+    timestamper: Timestamper,
+}
+
+impl<R> AsyncTimestamps<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Returns a reference to the underlying
+    /// [`AsyncRead`](futures_io::AsyncRead).
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+
+    /// Returns the latest estimate of the drift between the local and
+    /// global timestamp clocks. See
+    /// [`Timestamps::clock_drift`](crate::Timestamps::clock_drift).
+    pub fn clock_drift(&self) -> Option<ClockDrift> {
+        self.timestamper.clock_drift()
+    }
+}
+
+impl<R> Stream for AsyncTimestamps<R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = Result<TimestampedTracePackets, DecoderError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let packet = match this.decoder.poll_single(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(packet) => packet,
+            };
+            match this.timestamper.push(packet) {
+                Ok(None) => (),
+                Ok(Some(set)) => return Poll::Ready(Some(Ok(set))),
+                Err(e) => return Poll::Ready(e.into_public().map(Err)),
+            }
+        }
+    }
+}
+
+/// Adapts a [`tokio::io::AsyncRead`] into a
+/// [`futures_io::AsyncRead`] for use with [`AsyncDecoder`].
+#[cfg(feature = "tokio")]
+pub struct TokioCompat<R> {
+    inner: R,
+}
+
+#[cfg(feature = "tokio")]
+impl<R> TokioCompat<R>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R> AsyncRead for TokioCompat<R>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    /// An [`AsyncRead`] that yields one byte per poll, returning
+    /// [`Poll::Pending`] in between, so that packets are split across
+    /// reads.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        pending: bool,
+    }
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            match self.data.split_first() {
+                None => Poll::Ready(Ok(0)),
+                Some((b, rest)) => {
+                    buf[0] = *b;
+                    self.data = rest;
+                    Poll::Ready(Ok(1))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn trickled_packets() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Instrumentation, 4-byte payload
+            0b0000_1011, 0x01, 0x02, 0x03, 0x04,
+            // Overflow
+            0b0111_0000,
+            // LTS1 (truncated)
+            0b1100_0000, 0b1100_1001,
+        ];
+        let decoder = AsyncDecoder::new(
+            Trickle {
+                data: stream,
+                pending: false,
+            },
+            DecoderOptions::default(),
+        );
+        let packets: Vec<TracePacket> = block_on(decoder.map(|p| p.unwrap()).collect());
+
+        assert_eq!(
+            packets,
+            [
+                TracePacket::Instrumentation {
+                    port: 1,
+                    payload: [1, 2, 3, 4].into(),
+                },
+                TracePacket::Overflow,
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Overflow
+            0b0111_0000,
+            // LTS2, ts = 2
+            0b0010_0000,
+            // Overflow
+            0b0111_0000,
+            // LTS1, ts = 3, UnknownDelay (split across reads)
+            0b1101_0000, 0b0000_0011,
+        ];
+        let decoder = AsyncDecoder::new(
+            Trickle {
+                data: stream,
+                pending: false,
+            },
+            DecoderOptions::default(),
+        )
+        .timestamps(TimestampsConfiguration {
+            clock_frequency: 1_000,
+            lts_prescaler: crate::LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+            best_effort: false,
+            correct_drift: false,
+        });
+        let sets: Vec<TimestampedTracePackets> = block_on(decoder.map(|s| s.unwrap()).collect());
+
+        assert_eq!(
+            sets.iter().map(|s| s.timestamp.clone()).collect::<Vec<_>>(),
+            [
+                crate::Timestamp::Sync(std::time::Duration::from_millis(2)),
+                crate::Timestamp::UnknownDelay {
+                    prev: std::time::Duration::from_millis(2),
+                    curr: std::time::Duration::from_millis(5),
+                },
+            ]
+        );
+        assert!(sets
+            .iter()
+            .all(|s| s.packets == [TracePacket::Overflow] && s.consumed_packets == 2));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_reader() {
+        let stream: &[u8] = &[0b0111_0000, 0b0111_0000];
+        let decoder = AsyncDecoder::new(TokioCompat::new(stream), DecoderOptions::default());
+        let packets: Vec<TracePacket> = block_on(decoder.map(|p| p.unwrap()).collect());
+        assert_eq!(packets, [TracePacket::Overflow, TracePacket::Overflow]);
+    }
+}
@@ -0,0 +1,131 @@
+//! CBOR sequence output of decoded packets.
+//!
+//! [`CborWriter`] writes serializable items, e.g. [`TracePacket`]s or
+//! [`TimestampedTracePackets`], as a CBOR sequence ([RFC
+//! 8742](https://www.rfc-editor.org/rfc/rfc8742)): consecutive CBOR data
+//! items without framing. The items have the same structure as their
+//! `serde` representation in other formats, e.g. JSON, but are
+//! considerably more compact.
+//!
+//! ```
+//! use itm::cbor::{read, CborWriter};
+//! use itm::TracePacket;
+//!
+//! let packets = [TracePacket::Sync, TracePacket::PCSample { pc: Some(0x100) }];
+//! let mut cbor = CborWriter::new(vec![]);
+//! for packet in packets.iter() {
+//!     cbor.write(packet).unwrap();
+//! }
+//! let bytes = cbor.into_inner();
+//! let decoded: Vec<TracePacket> = read(&bytes[..]).collect::<Result<_, _>>().unwrap();
+//! assert_eq!(decoded, packets);
+//! ```
+//!
+//! [`TracePacket`]: crate::TracePacket
+//! [`TimestampedTracePackets`]: crate::TimestampedTracePackets
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::io;
+use std::marker::PhantomData;
+
+pub use ciborium::de::Error as DecodeError;
+pub use ciborium::ser::Error as EncodeError;
+
+/// Writes items as a CBOR sequence. See the [module
+/// documentation](self).
+pub struct CborWriter<W: io::Write> {
+    out: W,
+}
+
+impl<W: io::Write> CborWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Writes an item.
+    pub fn write<T: Serialize>(&mut self, item: &T) -> Result<(), EncodeError<io::Error>> {
+        ciborium::ser::into_writer(item, &mut self.out)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Unwraps the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads the items of a CBOR sequence, as written by [`CborWriter`],
+/// until the end of `input`.
+pub fn read<T, R>(input: R) -> CborReader<T, R>
+where
+    T: DeserializeOwned,
+    R: io::BufRead,
+{
+    CborReader {
+        input,
+        _item: PhantomData,
+    }
+}
+
+/// Iterator over the items of a CBOR sequence. See [`read`].
+pub struct CborReader<T, R> {
+    input: R,
+    _item: PhantomData<T>,
+}
+
+impl<T, R> Iterator for CborReader<T, R>
+where
+    T: DeserializeOwned,
+    R: io::BufRead,
+{
+    type Item = Result<T, DecodeError<io::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.input.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(ciborium::de::from_reader(&mut self.input)),
+            Err(e) => Some(Err(DecodeError::Io(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Ticks, Timestamp, TimestampedTracePackets, TracePacket};
+    use std::time::Duration;
+
+    #[test]
+    fn timestamped() {
+        let set = TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_micros(5)),
+            ticks: Ticks {
+                ticks: 80,
+                frequency: 16_000_000,
+            },
+            delta: Duration::from_micros(5),
+            packets: vec![TracePacket::Instrumentation {
+                port: 1,
+                payload: [0xde, 0xad].into(),
+            }],
+            malformed_packets: vec![],
+            consumed_packets: 2,
+            overflow: None,
+        };
+        let mut cbor = CborWriter::new(vec![]);
+        cbor.write(&set).unwrap();
+        cbor.write(&set).unwrap();
+        let bytes = cbor.into_inner();
+
+        let decoded: Vec<TimestampedTracePackets> =
+            read(&bytes[..]).collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded, [set.clone(), set]);
+        assert!(read::<TracePacket, _>(&bytes[..3]).next().unwrap().is_err());
+    }
+}
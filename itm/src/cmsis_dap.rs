@@ -0,0 +1,525 @@
+//! SWO capture from CMSIS-DAP v2 debug probes.
+//!
+//! CMSIS-DAP v2 probes, e.g. DAPLink and many low-cost debuggers, are
+//! driven over their USB bulk endpoints, via libusb, so that no GDB
+//! server is needed to capture SWO. The probe is [found](find) by USB
+//! VID:PID or description, and [opened](open) for capture, after which
+//! the captured stream is read from the returned [`SwoCapture`]:
+//!
+//! ```no_run
+//! use itm::cmsis_dap::{self, SwoMode, SwoOptions};
+//! use itm::{Decoder, DecoderOptions};
+//!
+//! let probe = cmsis_dap::find(None)?;
+//! let capture = cmsis_dap::open(
+//!     &probe,
+//!     &SwoOptions {
+//!         baud_rate: 2_000_000,
+//!         mode: SwoMode::Uart,
+//!     },
+//! )?;
+//! for packet in Decoder::new(capture, DecoderOptions::default()).singles() {
+//!     println!("{:?}", packet);
+//! }
+//! # Ok::<(), cmsis_dap::CmsisDapError>(())
+//! ```
+//!
+//! Only the probe is configured: the TPIU and ITM of the target must be
+//! set up for the same encoding and bit rate, by the firmware or a
+//! debugger.
+
+use crate::Discontinuity;
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use rusb::{Context, Direction, TransferType, UsbContext};
+use thiserror::Error;
+
+/// How long a command may take to be answered.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a single read of the SWO endpoint waits for data before
+/// the trace status is checked.
+const STREAM_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Delay between polls of a probe that had no SWO data.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Command IDs.
+const DAP_INFO: u8 = 0x00;
+const DAP_SWO_TRANSPORT: u8 = 0x17;
+const DAP_SWO_MODE: u8 = 0x18;
+const DAP_SWO_BAUDRATE: u8 = 0x19;
+const DAP_SWO_CONTROL: u8 = 0x1a;
+const DAP_SWO_STATUS: u8 = 0x1b;
+const DAP_SWO_DATA: u8 = 0x1c;
+
+/// DAP_Info IDs.
+const INFO_CAPABILITIES: u8 = 0xf0;
+const INFO_PACKET_SIZE: u8 = 0xff;
+
+/// Bits of the capabilities byte.
+const CAPABILITY_SWO_UART: u8 = 1 << 2;
+const CAPABILITY_SWO_MANCHESTER: u8 = 1 << 3;
+const CAPABILITY_SWO_STREAMING: u8 = 1 << 6;
+
+/// Bits of the trace status byte that flag lost data: a stream error
+/// and a buffer overrun.
+const TRACE_STATUS_LOSS: u8 = 0xc0;
+
+/// Interface class of the bulk interface of a CMSIS-DAP v2 probe.
+const VENDOR_SPECIFIC_CLASS: u8 = 0xff;
+
+/// Maximum deviation, in percent, of the rate the probe captures at
+/// from the requested rate.
+const BAUD_RATE_TOLERANCE: u64 = 1;
+
+/// Possible errors on [`probes`], [`find`] and [`open`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CmsisDapError {
+    #[error("USB error: {0}")]
+    Usb(#[from] rusb::Error),
+
+    #[error("No CMSIS-DAP v2 probe matches {0:?}")]
+    NoSuchProbe(String),
+
+    #[error("Several CMSIS-DAP v2 probes match {selector:?}: {}", matches.join(", "))]
+    AmbiguousProbe {
+        selector: String,
+        matches: Vec<String>,
+    },
+
+    #[error("The probe does not support SWO capture in {0:?} mode")]
+    Unsupported(SwoMode),
+
+    #[error("Baud rate {requested} is not achievable by the probe; it would capture at {actual}")]
+    BaudRate { requested: u32, actual: u32 },
+
+    #[error("The probe failed command {0:#04x}")]
+    Failed(u8),
+
+    #[error("Malformed response to command {0:#04x}")]
+    MalformedResponse(u8),
+}
+
+/// Encoding of the SWO pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwoMode {
+    /// NRZ, as by a UART.
+    Uart,
+    Manchester,
+}
+
+/// How the probe captures SWO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwoOptions {
+    /// Bit rate of the SWO pin, in bits per second.
+    pub baud_rate: u32,
+
+    pub mode: SwoMode,
+}
+
+/// A CMSIS-DAP v2 probe connected to the system.
+#[derive(Debug, Clone)]
+pub struct ProbeInfo {
+    /// Vendor ID.
+    pub vid: u16,
+
+    /// Product ID.
+    pub pid: u16,
+
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+
+    device: rusb::Device<Context>,
+    interface: u8,
+    /// Bulk endpoints: command out, response in and, if any, SWO in.
+    endpoints: Vec<u8>,
+}
+
+impl ProbeInfo {
+    /// Whether the probe is selected by `selector`: a `VID:PID` pair in
+    /// hexadecimal, e.g. `c251:f002`, or a case-insensitive substring of
+    /// the product or serial number.
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Some((vid, pid)) = selector.split_once(':') {
+            if let (Ok(vid), Ok(pid)) = (u16::from_str_radix(vid, 16), u16::from_str_radix(pid, 16))
+            {
+                return self.vid == vid && self.pid == pid;
+            }
+        }
+        let selector = selector.to_lowercase();
+        [&self.product, &self.serial_number]
+            .into_iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(&selector))
+    }
+}
+
+impl std::fmt::Display for ProbeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bus {:03} device {:03} {:04x}:{:04x}",
+            self.device.bus_number(),
+            self.device.address(),
+            self.vid,
+            self.pid
+        )?;
+        if let Some(product) = &self.product {
+            write!(f, " {}", product)?;
+        }
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, " (serial number {})", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the CMSIS-DAP v2 probes connected to the system. Probes that
+/// cannot be opened, e.g. for lack of permissions, are not listed.
+pub fn probes() -> Result<Vec<ProbeInfo>, CmsisDapError> {
+    Ok(Context::new()?
+        .devices()?
+        .iter()
+        .filter_map(probe)
+        .collect())
+}
+
+/// The probe of `device`, if it is a CMSIS-DAP v2 probe, identified by
+/// the "CMSIS-DAP" in the description of its bulk interface.
+fn probe(device: rusb::Device<Context>) -> Option<ProbeInfo> {
+    let descriptor = device.device_descriptor().ok()?;
+    let config = device.active_config_descriptor().ok()?;
+    let handle = device.open().ok()?;
+    let language = *handle.read_languages(COMMAND_TIMEOUT).ok()?.first()?;
+
+    let (interface, endpoints) = config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .filter(|interface| interface.class_code() == VENDOR_SPECIFIC_CLASS)
+        .filter(|interface| {
+            matches!(
+                handle.read_interface_string(language, interface, COMMAND_TIMEOUT),
+                Ok(description) if description.contains("CMSIS-DAP")
+            )
+        })
+        .map(|interface| {
+            let endpoints: Vec<_> = interface
+                .endpoint_descriptors()
+                .filter(|endpoint| endpoint.transfer_type() == TransferType::Bulk)
+                .map(|endpoint| (endpoint.address(), endpoint.direction()))
+                .collect();
+            (interface.interface_number(), endpoints)
+        })
+        .find(|(_, endpoints)| {
+            matches!(endpoints[..], [(_, Direction::Out), (_, Direction::In), ..])
+        })?;
+    let endpoints = endpoints
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, direction))| *i < 2 || *direction == Direction::In)
+        .map(|(_, (address, _))| *address)
+        .take(3)
+        .collect();
+
+    Some(ProbeInfo {
+        vid: descriptor.vendor_id(),
+        pid: descriptor.product_id(),
+        product: handle
+            .read_product_string(language, &descriptor, COMMAND_TIMEOUT)
+            .ok(),
+        serial_number: handle
+            .read_serial_number_string(language, &descriptor, COMMAND_TIMEOUT)
+            .ok(),
+        device,
+        interface,
+        endpoints,
+    })
+}
+
+/// Selects the single probe of `probes` that [matches](ProbeInfo::matches)
+/// `selector`, or the single probe if there is no selector.
+pub fn select(probes: Vec<ProbeInfo>, selector: Option<&str>) -> Result<ProbeInfo, CmsisDapError> {
+    let mut matches: Vec<ProbeInfo> = probes
+        .into_iter()
+        .filter(|probe| selector.iter().all(|selector| probe.matches(selector)))
+        .collect();
+    let selector = selector.unwrap_or("").to_string();
+    match matches.len() {
+        0 => Err(CmsisDapError::NoSuchProbe(selector)),
+        1 => Ok(matches.remove(0)),
+        _ => Err(CmsisDapError::AmbiguousProbe {
+            selector,
+            matches: matches.iter().map(ProbeInfo::to_string).collect(),
+        }),
+    }
+}
+
+/// Finds the single probe connected to the system that
+/// [matches](ProbeInfo::matches) `selector`, or the single probe if
+/// there is no selector.
+pub fn find(selector: Option<&str>) -> Result<ProbeInfo, CmsisDapError> {
+    select(probes()?, selector)
+}
+
+/// Checks that `response` is the successful response to `command`.
+fn check_status(command: u8, response: &[u8]) -> Result<(), CmsisDapError> {
+    match response {
+        [id, 0x00, ..] if *id == command => Ok(()),
+        [id, _, ..] if *id == command => Err(CmsisDapError::Failed(command)),
+        _ => Err(CmsisDapError::MalformedResponse(command)),
+    }
+}
+
+/// Parses the value of a DAP_Info `response`.
+fn parse_info(response: &[u8]) -> Result<&[u8], CmsisDapError> {
+    match response {
+        [DAP_INFO, len, value @ ..] if value.len() >= usize::from(*len) => {
+            Ok(&value[..usize::from(*len)])
+        }
+        _ => Err(CmsisDapError::MalformedResponse(DAP_INFO)),
+    }
+}
+
+/// Parses a DAP_SWO_Baudrate `response` into the rate the probe
+/// captures at, which is 0 if the requested rate is not supported.
+fn parse_baud_rate(response: &[u8]) -> Result<u32, CmsisDapError> {
+    match response {
+        [DAP_SWO_BAUDRATE, a, b, c, d, ..] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => Err(CmsisDapError::MalformedResponse(DAP_SWO_BAUDRATE)),
+    }
+}
+
+/// Parses a DAP_SWO_Data `response` into its trace status and data.
+fn parse_swo_data(response: &[u8]) -> Result<(u8, &[u8]), CmsisDapError> {
+    match response {
+        [DAP_SWO_DATA, status, lo, hi, data @ ..] => {
+            let count = usize::from(u16::from_le_bytes([*lo, *hi]));
+            if count > data.len() {
+                return Err(CmsisDapError::MalformedResponse(DAP_SWO_DATA));
+            }
+            Ok((*status, &data[..count]))
+        }
+        _ => Err(CmsisDapError::MalformedResponse(DAP_SWO_DATA)),
+    }
+}
+
+fn check_baud_rate(requested: u32, actual: u32) -> Result<(), CmsisDapError> {
+    let deviation = u64::from(requested.max(actual) - requested.min(actual)) * 100;
+    if actual == 0 || deviation > u64::from(requested) * BAUD_RATE_TOLERANCE {
+        return Err(CmsisDapError::BaudRate { requested, actual });
+    }
+    Ok(())
+}
+
+/// An ongoing SWO capture. Reads block until SWO data is available.
+/// The capture is stopped on drop.
+pub struct SwoCapture {
+    handle: rusb::DeviceHandle<Context>,
+    interface: u8,
+    command: u8,
+    response: u8,
+    /// The SWO endpoint, if data is streamed, instead of polled with
+    /// DAP_SWO_Data.
+    swo: Option<u8>,
+    /// Size of a command or response.
+    packet_size: usize,
+    buffer: Vec<u8>,
+    /// Range of `buffer` yet to be read.
+    pending: std::ops::Range<usize>,
+    overruns: Option<Discontinuity>,
+}
+
+impl std::fmt::Debug for SwoCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwoCapture")
+            .field("interface", &self.interface)
+            .field("streaming", &self.swo.is_some())
+            .finish()
+    }
+}
+
+/// Opens `probe` and starts capturing SWO as per `options`. The
+/// captured data is streamed over the SWO endpoint of the probe, if it
+/// has one, or else polled for.
+pub fn open(probe: &ProbeInfo, options: &SwoOptions) -> Result<SwoCapture, CmsisDapError> {
+    let handle = probe.device.open()?;
+    handle.claim_interface(probe.interface)?;
+    let swo = probe.endpoints.get(2).copied();
+    let mut capture = SwoCapture {
+        handle,
+        interface: probe.interface,
+        command: probe.endpoints[0],
+        response: probe.endpoints[1],
+        swo: None,
+        packet_size: 64,
+        buffer: vec![],
+        pending: 0..0,
+        overruns: None,
+    };
+
+    let info = capture.transact(&[DAP_INFO, INFO_PACKET_SIZE])?;
+    if let [lo, hi] = parse_info(&info)? {
+        capture.packet_size = usize::from(u16::from_le_bytes([*lo, *hi]));
+    }
+    let info = capture.transact(&[DAP_INFO, INFO_CAPABILITIES])?;
+    let capabilities = parse_info(&info)?.first().copied().unwrap_or(0);
+    let (mode, capability) = match options.mode {
+        SwoMode::Uart => (1, CAPABILITY_SWO_UART),
+        SwoMode::Manchester => (2, CAPABILITY_SWO_MANCHESTER),
+    };
+    if capabilities & capability == 0 {
+        return Err(CmsisDapError::Unsupported(options.mode));
+    }
+    if capabilities & CAPABILITY_SWO_STREAMING != 0 {
+        capture.swo = swo;
+    }
+    capture.buffer = vec![0; capture.packet_size.max(512)];
+
+    capture.command_status(&[DAP_SWO_CONTROL, 0])?;
+    let transport = if capture.swo.is_some() { 2 } else { 1 };
+    capture.command_status(&[DAP_SWO_TRANSPORT, transport])?;
+    capture.command_status(&[DAP_SWO_MODE, mode])?;
+    let mut request = vec![DAP_SWO_BAUDRATE];
+    request.extend_from_slice(&options.baud_rate.to_le_bytes());
+    let actual = parse_baud_rate(&capture.transact(&request)?)?;
+    check_baud_rate(options.baud_rate, actual)?;
+    capture.command_status(&[DAP_SWO_CONTROL, 1])?;
+
+    Ok(capture)
+}
+
+impl SwoCapture {
+    /// Signals `discontinuity` whenever the probe reports that captured
+    /// data was lost, e.g. by a buffer overrun, so that a
+    /// [`Decoder`](crate::Decoder) that [resyncs on](crate::Decoder::resync_on)
+    /// it does not decode across the gap.
+    pub fn signal_overruns(mut self, discontinuity: Discontinuity) -> Self {
+        self.overruns = Some(discontinuity);
+        self
+    }
+
+    /// Sends `request` and returns the response to it.
+    fn transact(&self, request: &[u8]) -> Result<Vec<u8>, CmsisDapError> {
+        self.handle
+            .write_bulk(self.command, request, COMMAND_TIMEOUT)?;
+        let mut response = vec![0; self.packet_size.max(64)];
+        let n = self
+            .handle
+            .read_bulk(self.response, &mut response, COMMAND_TIMEOUT)?;
+        response.truncate(n);
+        if response.first() != Some(&request[0]) {
+            return Err(CmsisDapError::MalformedResponse(request[0]));
+        }
+        Ok(response)
+    }
+
+    /// Sends `request`, whose response is a status.
+    fn command_status(&self, request: &[u8]) -> Result<(), CmsisDapError> {
+        check_status(request[0], &self.transact(request)?)
+    }
+
+    /// Signals a discontinuity if the trace `status` reports lost data.
+    fn check_trace_status(&self, status: u8) {
+        if status & TRACE_STATUS_LOSS != 0 {
+            if let Some(overruns) = &self.overruns {
+                overruns.signal();
+            }
+        }
+    }
+
+    /// Fills the buffer with the next SWO data.
+    fn fill(&mut self) -> Result<(), CmsisDapError> {
+        if let Some(swo) = self.swo {
+            match self.handle.read_bulk(swo, &mut self.buffer, STREAM_TIMEOUT) {
+                Ok(n) => self.pending = 0..n,
+                Err(rusb::Error::Timeout) => {
+                    let response = self.transact(&[DAP_SWO_STATUS])?;
+                    match response.get(1) {
+                        Some(status) => self.check_trace_status(*status),
+                        None => return Err(CmsisDapError::MalformedResponse(DAP_SWO_STATUS)),
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+            return Ok(());
+        }
+
+        // The response header takes 4 bytes of the packet.
+        let max = (self.packet_size.saturating_sub(4) as u16).to_le_bytes();
+        let response = self.transact(&[DAP_SWO_DATA, max[0], max[1]])?;
+        let (status, data) = parse_swo_data(&response)?;
+        self.check_trace_status(status);
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.pending = 0..data.len();
+        if data.is_empty() {
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+impl Read for SwoCapture {
+    #[allow(clippy::io_other_error)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            self.fill()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        let n = buf.len().min(self.pending.len());
+        let start = self.pending.start;
+        buf[..n].copy_from_slice(&self.buffer[start..start + n]);
+        self.pending.start += n;
+        Ok(n)
+    }
+}
+
+impl Drop for SwoCapture {
+    fn drop(&mut self) {
+        let _ = self.command_status(&[DAP_SWO_CONTROL, 0]);
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        assert!(check_status(DAP_SWO_MODE, &[DAP_SWO_MODE, 0x00]).is_ok());
+        assert!(matches!(
+            check_status(DAP_SWO_MODE, &[DAP_SWO_MODE, 0xff]),
+            Err(CmsisDapError::Failed(DAP_SWO_MODE))
+        ));
+        assert!(matches!(
+            check_status(DAP_SWO_MODE, &[DAP_SWO_CONTROL, 0x00]),
+            Err(CmsisDapError::MalformedResponse(DAP_SWO_MODE))
+        ));
+
+        assert_eq!(
+            parse_info(&[DAP_INFO, 2, 0x00, 0x02, 0]).unwrap(),
+            [0x00, 0x02]
+        );
+        assert!(parse_info(&[DAP_INFO, 4, 0x00]).is_err());
+
+        assert_eq!(
+            parse_baud_rate(&[DAP_SWO_BAUDRATE, 0x80, 0x84, 0x1e, 0x00]).unwrap(),
+            2_000_000
+        );
+        assert!(check_baud_rate(2_000_000, 2_000_000).is_ok());
+        assert!(check_baud_rate(2_000_000, 0).is_err());
+        assert!(check_baud_rate(2_000_000, 2_100_000).is_err());
+
+        let (status, data) = parse_swo_data(&[DAP_SWO_DATA, 0x81, 2, 0, 0x01, 0x68, 0xff]).unwrap();
+        assert_eq!(
+            (status & TRACE_STATUS_LOSS, data),
+            (0x80, &[0x01, 0x68][..])
+        );
+        assert!(parse_swo_data(&[DAP_SWO_DATA, 0x01, 4, 0, 0x01]).is_err());
+    }
+}
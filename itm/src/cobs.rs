@@ -0,0 +1,273 @@
+//! Reassembly of COBS-framed messages written to stimulus ports.
+//!
+//! Firmware commonly sends binary messages, e.g. serialized with
+//! [postcard](https://docs.rs/postcard), as
+//! [COBS](https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing)
+//! frames terminated by a zero byte. Such a frame is written to a
+//! stimulus port in 1-4 byte chunks, and thus spans many
+//! [`Instrumentation`](TracePacket::Instrumentation) packets. A
+//! [`CobsDecoder`] concatenates the payloads of each port, splits them
+//! on frame boundaries, and decodes the frames.
+//!
+//! ```
+//! use itm::cobs::CobsDecoder;
+//! use itm::TracePacket;
+//!
+//! let mut decoder = CobsDecoder::new([1]);
+//! for payload in [[0x03, 0x11, 0x22, 0x02], [0x33, 0x00, 0x00, 0x00]] {
+//!     decoder.push(&TracePacket::Instrumentation {
+//!         port: 1,
+//!         payload: payload.into(),
+//!     });
+//! }
+//! assert_eq!(decoder.pull(), Some(Ok((1, vec![0x11, 0x22, 0x00, 0x33]))));
+//! assert_eq!(decoder.pull(), None);
+//! ```
+
+use super::TracePacket;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use super::{DecoderError, ItmFrame, TimestampedTracePackets};
+
+/// A frame could not be decoded, e.g. because data was lost.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+#[cfg_attr(
+    feature = "std",
+    error("Malformed COBS frame of {} bytes on port {port}", data.len())
+)]
+pub struct MalformedFrame {
+    /// Stimulus port the frame was written to.
+    pub port: u8,
+
+    /// The encoded frame, without its terminating zero byte.
+    pub data: Vec<u8>,
+}
+
+/// Decodes a single COBS-encoded frame, without its terminating zero
+/// byte. Returns `None` if the frame is malformed.
+pub fn decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(frame.len());
+    let mut rest = frame;
+    while let Some((&code, tail)) = rest.split_first() {
+        let len = usize::from(code).checked_sub(1)?;
+        if len > tail.len() {
+            return None;
+        }
+        data.extend_from_slice(&tail[..len]);
+        rest = &tail[len..];
+        if code != 0xff && !rest.is_empty() {
+            data.push(0);
+        }
+    }
+    Some(data)
+}
+
+/// Reassembles and decodes the COBS frames written to a set of
+/// stimulus ports. Packets are [pushed](Self::push) as they are
+/// decoded, and decoded frames are [pulled](Self::pull) out. See the
+/// [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct CobsDecoder {
+    ports: BTreeMap<u8, Vec<u8>>,
+    complete: VecDeque<(u8, Vec<u8>)>,
+}
+
+impl CobsDecoder {
+    /// Reassembles the frames written to `ports`.
+    pub fn new<P: IntoIterator<Item = u8>>(ports: P) -> Self {
+        Self {
+            ports: ports.into_iter().map(|port| (port, Vec::new())).collect(),
+            complete: VecDeque::new(),
+        }
+    }
+
+    /// Appends the payload of the packet to the frame of its port, if
+    /// it is an [`Instrumentation`](TracePacket::Instrumentation)
+    /// packet on one of the ports. Returns whether the packet was
+    /// consumed.
+    pub fn push(&mut self, packet: &TracePacket) -> bool {
+        let (port, payload) = match packet {
+            TracePacket::Instrumentation { port, payload } => (*port, payload),
+            _ => return false,
+        };
+        let frame = match self.ports.get_mut(&port) {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        for &b in payload.iter() {
+            match b {
+                // Consecutive delimiters delimit no frame
+                0 if frame.is_empty() => (),
+                0 => self.complete.push_back((port, core::mem::take(frame))),
+                b => frame.push(b),
+            }
+        }
+        true
+    }
+
+    /// Discards the partial frames of all ports, e.g. after an
+    /// [`Overflow`](TracePacket::Overflow), so that decoding resumes at
+    /// the next frame boundary.
+    pub fn reset(&mut self) {
+        for frame in self.ports.values_mut() {
+            frame.clear();
+        }
+    }
+
+    /// Pulls the port and content of the next complete frame, in the
+    /// order of completion.
+    pub fn pull(&mut self) -> Option<Result<(u8, Vec<u8>), MalformedFrame>> {
+        let (port, frame) = self.complete.pop_front()?;
+        Some(match decode(&frame) {
+            Some(data) => Ok((port, data)),
+            None => Err(MalformedFrame { port, data: frame }),
+        })
+    }
+}
+
+/// Set of errors that can occur while reassembling frames.
+#[cfg(feature = "std")]
+#[derive(Debug, thiserror::Error)]
+pub enum FramesError {
+    /// The packets could not be decoded.
+    #[error(transparent)]
+    Decoder(#[from] DecoderError),
+
+    /// A frame could not be decoded.
+    #[error(transparent)]
+    Malformed(#[from] MalformedFrame),
+}
+
+/// Iterator adapter that yield the [`ItmFrame`](ItmFrame)s written to
+/// a set of stimulus ports, from an iterator over
+/// [`TimestampedTracePackets`](TimestampedTracePackets), e.g.
+/// [`Timestamps`](crate::Timestamps). Partial frames are discarded on
+/// [`Overflow`](TracePacket::Overflow). See also
+/// [`CobsDecoder`](CobsDecoder).
+#[cfg(feature = "std")]
+pub struct CobsFrames<I> {
+    inner: I,
+    decoder: CobsDecoder,
+    pending: VecDeque<Result<ItmFrame, FramesError>>,
+}
+
+#[cfg(feature = "std")]
+impl<I> CobsFrames<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    /// Reassembles the frames written to `ports`.
+    pub fn new<P: IntoIterator<Item = u8>>(inner: I, ports: P) -> Self {
+        Self {
+            inner,
+            decoder: CobsDecoder::new(ports),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I> Iterator for CobsFrames<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    type Item = Result<ItmFrame, FramesError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.pending.pop_front() {
+                return Some(frame);
+            }
+
+            let set = match self.inner.next()? {
+                Err(e) => return Some(Err(e.into())),
+                Ok(set) => set,
+            };
+            for packet in set.packets.iter() {
+                if *packet == TracePacket::Overflow {
+                    self.decoder.reset();
+                }
+                self.decoder.push(packet);
+            }
+            while let Some(frame) = self.decoder.pull() {
+                self.pending.push_back(
+                    frame
+                        .map(|(port, data)| ItmFrame {
+                            port,
+                            data,
+                            timestamp: set.timestamp.clone(),
+                        })
+                        .map_err(FramesError::from),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn decode_frames() {
+        let cases: [(&[u8], Option<&[u8]>); 7] = [
+            (&[0x01], Some(&[])),
+            (&[0x01, 0x01], Some(&[0x00])),
+            (
+                &[0x03, 0x11, 0x22, 0x02, 0x33],
+                Some(&[0x11, 0x22, 0x00, 0x33]),
+            ),
+            (&[0x02, 0x11, 0x01, 0x01], Some(&[0x11, 0x00, 0x00])),
+            (&[0x04, 0x11], None),
+            (&[0x00], None),
+            (&[], Some(&[])),
+        ];
+        for (frame, data) in cases.iter() {
+            assert_eq!(decode(frame).as_deref(), *data);
+        }
+
+        let mut long = vec![0xff];
+        long.extend(1..=254);
+        long.extend([0x02, 0x42]);
+        let mut data: Vec<u8> = (1..=254).collect();
+        data.push(0x42);
+        assert_eq!(decode(&long), Some(data));
+    }
+
+    #[test]
+    fn reassemble() {
+        let mut decoder = CobsDecoder::new([1, 2]);
+        let packets = [
+            (1, [0x02, 0x11].into()),
+            (2, [0x00, 0x04, 0x11].into()),
+            (1, [0x00].into()),
+            (2, [0x00].into()),
+            (0, [0x00].into()),
+        ];
+        let consumed: Vec<bool> = packets
+            .iter()
+            .map(|(port, payload)| {
+                decoder.push(&TracePacket::Instrumentation {
+                    port: *port,
+                    payload: *payload,
+                })
+            })
+            .collect();
+        assert_eq!(consumed, [true, true, true, true, false]);
+        assert_eq!(decoder.pull(), Some(Ok((1, vec![0x11]))));
+        assert_eq!(
+            decoder.pull(),
+            Some(Err(MalformedFrame {
+                port: 2,
+                data: vec![0x04, 0x11]
+            }))
+        );
+        assert_eq!(decoder.pull(), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! Compression of archived trace data.
+//!
+//! Raw captures compress well, and are thus often archived as gzip
+//! (`.gz`) or Zstandard (`.zst`) files. [`from_compressed_reader`]
+//! recognizes such data by its magic number and decompresses it while
+//! it is read, so that archives can be decoded without first being
+//! decompressed to disk. Other data is read as is. Conversely,
+//! [`ZstdTee`] archives the data of a live session, compressed, as it
+//! is decoded.
+//!
+//! ```
+//! use itm::compression::from_compressed_reader;
+//! use itm::{Decoder, DecoderOptions, TracePacket};
+//! use std::io::Write;
+//!
+//! // Instrumentation packet (port 1, 1-byte payload), gzip compressed
+//! let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+//! encoder.write_all(&[0b0000_1001, 0x41]).unwrap();
+//! let archive = encoder.finish().unwrap();
+//!
+//! let reader = from_compressed_reader(archive.as_slice()).unwrap();
+//! let packets: Vec<_> = Decoder::new(reader, DecoderOptions::default())
+//!     .singles()
+//!     .collect::<Result<_, _>>()
+//!     .unwrap();
+//! assert_eq!(
+//!     packets,
+//!     [TracePacket::Instrumentation {
+//!         port: 1,
+//!         payload: [0x41].into(),
+//!     }]
+//! );
+//! ```
+
+use flate2::read::MultiGzDecoder;
+use std::io::{self, BufReader, Chain, Cursor, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Magic number of a gzip member.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Magic number of a Zstandard frame.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression format of trace data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Uncompressed.
+    None,
+
+    /// gzip, as written by `gzip`.
+    Gzip,
+
+    /// Zstandard, as written by `zstd`.
+    Zstd,
+}
+
+impl Compression {
+    /// Recognizes the format of data that starts with `header`, the
+    /// first four bytes or, if shorter, all of the data.
+    pub fn detect(header: &[u8]) -> Self {
+        if header.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// The data read by a [`Decompressed`], with the header that was read
+/// to detect its format put back in front.
+type Rewound<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// A [`Read`](Read) of trace data that decompresses it if compressed.
+/// See [`from_compressed_reader`].
+pub enum Decompressed<R: Read> {
+    None(Rewound<R>),
+    Gzip(MultiGzDecoder<Rewound<R>>),
+    Zstd(zstd::Decoder<'static, BufReader<Rewound<R>>>),
+}
+
+impl<R: Read> Decompressed<R> {
+    /// The compression format of the data.
+    pub fn compression(&self) -> Compression {
+        match self {
+            Decompressed::None(_) => Compression::None,
+            Decompressed::Gzip(_) => Compression::Gzip,
+            Decompressed::Zstd(_) => Compression::Zstd,
+        }
+    }
+}
+
+impl<R: Read> Read for Decompressed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decompressed::None(reader) => reader.read(buf),
+            Decompressed::Gzip(reader) => reader.read(buf),
+            Decompressed::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Wraps `reader` so that gzip or Zstandard compressed data is
+/// decompressed as it is read, and other data is read as is. Reads
+/// the first four bytes of `reader` to detect the format. Concatenated
+/// gzip members and Zstandard frames are read in sequence.
+pub fn from_compressed_reader<R: Read>(mut reader: R) -> io::Result<Decompressed<R>> {
+    let mut header = vec![0; ZSTD_MAGIC.len()];
+    let mut len = 0;
+    while len < header.len() {
+        match reader.read(&mut header[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    header.truncate(len);
+
+    let compression = Compression::detect(&header);
+    let reader = Cursor::new(header).chain(reader);
+    Ok(match compression {
+        Compression::None => Decompressed::None(reader),
+        Compression::Gzip => Decompressed::Gzip(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Decompressed::Zstd(zstd::Decoder::new(reader)?),
+    })
+}
+
+/// Time after which a [`ZstdTee`] completes the current Zstandard
+/// frame.
+const FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A [`Read`] wrapper that writes the data read to a Zstandard
+/// compressed archive, e.g. to decode a live session again later with
+/// other options.
+///
+/// The archive is a sequence of frames, each of which is completed
+/// after a second, so that an archive of a session that is cut short
+/// can be read up to the last completed frame. The last frame is
+/// completed when the `ZstdTee` is [finished](Self::finish) or dropped.
+pub struct ZstdTee<R: Read, W: Write> {
+    reader: R,
+    level: i32,
+    /// The encoder of the current frame. Only `None` while a frame is
+    /// being completed.
+    encoder: Option<zstd::Encoder<'static, W>>,
+    /// When the current frame was started.
+    started: Instant,
+}
+
+impl<R: Read, W: Write> ZstdTee<R, W> {
+    /// Archives the data read from `reader` to `writer`, compressed at
+    /// `level`: 1-22, or 0 for the default level.
+    pub fn new(reader: R, writer: W, level: i32) -> io::Result<Self> {
+        Ok(Self {
+            reader,
+            level,
+            encoder: Some(zstd::Encoder::new(writer, level)?),
+            started: Instant::now(),
+        })
+    }
+
+    /// Returns a reference to the underlying [`Read`].
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Completes the current frame and flushes the archive, and returns
+    /// the writer of the archive.
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut writer = self.encoder.take().unwrap().finish()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Completes the current frame, and starts the next.
+    fn next_frame(&mut self) -> io::Result<()> {
+        let mut writer = self.encoder.take().unwrap().finish()?;
+        writer.flush()?;
+        self.encoder = Some(zstd::Encoder::new(writer, self.level)?);
+        self.started = Instant::now();
+        Ok(())
+    }
+}
+
+impl<R: Read, W: Write> Read for ZstdTee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n > 0 {
+            self.encoder.as_mut().unwrap().write_all(&buf[..n])?;
+            if self.started.elapsed() >= FRAME_INTERVAL {
+                self.next_frame()?;
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R: Read, W: Write> Drop for ZstdTee<R, W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            let _ = encoder.finish().and_then(|mut writer| writer.flush());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Sync
+            0, 0, 0, 0, 0, 0b1000_0000,
+            // Instrumentation, port 1, 1-byte payload
+            0b0000_1001, 0x41,
+        ];
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(stream).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(stream, 0).unwrap();
+
+        for (data, compression) in [
+            (stream.to_vec(), Compression::None),
+            (gzip, Compression::Gzip),
+            (zstd, Compression::Zstd),
+            // Shorter than a magic number
+            (vec![0b0111_0000], Compression::None),
+        ] {
+            let mut reader = from_compressed_reader(data.as_slice()).unwrap();
+            assert_eq!(reader.compression(), compression);
+            let mut decompressed = vec![];
+            reader.read_to_end(&mut decompressed).unwrap();
+            match compression {
+                Compression::None => assert_eq!(decompressed, data),
+                _ => assert_eq!(decompressed, stream),
+            }
+        }
+    }
+
+    #[test]
+    fn tee() {
+        let stream: Vec<u8> = (0..=255).collect();
+        let mut tee = ZstdTee::new(stream.as_slice(), vec![], 0).unwrap();
+        let mut read = vec![];
+        tee.read_to_end(&mut read).unwrap();
+        // A completed frame, followed by one that is not
+        tee.next_frame().unwrap();
+        tee.encoder.as_mut().unwrap().write_all(&[0]).unwrap();
+        tee.encoder.as_mut().unwrap().flush().unwrap();
+        let archive = tee.encoder.as_ref().unwrap().get_ref().clone();
+
+        assert_eq!(read, stream);
+        let mut decompressed = vec![];
+        let result = from_compressed_reader(archive.as_slice())
+            .unwrap()
+            .read_to_end(&mut decompressed);
+        assert!(result.is_err());
+        assert!(decompressed.starts_with(&stream));
+
+        let archive = ZstdTee::new(stream.as_slice(), vec![], 0)
+            .and_then(|mut tee| {
+                tee.read_to_end(&mut vec![])?;
+                tee.finish()
+            })
+            .unwrap();
+        assert_eq!(zstd::decode_all(archive.as_slice()).unwrap(), stream);
+    }
+}
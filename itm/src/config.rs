@@ -0,0 +1,895 @@
+//! Configuration of the trace hardware of the target.
+//!
+//! A [`TraceConfig`] describes what the target emits: which stimulus
+//! ports are enabled, local and global timestamps, PC sampling,
+//! exception trace, the DWT comparators, and the SWO output of the
+//! TPIU. The [`registers`](TraceConfig::registers) of a configuration
+//! are the values to write to the target to apply it, and a
+//! configuration is [parsed](TraceConfig::from_registers) from values
+//! read back from the target, so that what the target was configured
+//! with and what the decoder expects can be compared: a
+//! [`ConfigCheck`] reports the decoded packets that the configuration
+//! rules out.
+//!
+//! ```
+//! use itm::config::{LocalTimestampOptions, SwoProtocol, TpiuConfig, TraceConfig};
+//!
+//! let mut config = TraceConfig::default();
+//! config.itm.ports = 0b11;
+//! config.itm.local_timestamps = LocalTimestampOptions::EnabledDiv4;
+//! config.dwt.pc_sampling = Some(1024);
+//! config.tpiu = Some(TpiuConfig::new(SwoProtocol::Nrz, 64_000_000, 2_000_000).unwrap());
+//!
+//! let registers = config.registers().unwrap();
+//! assert_eq!(registers.itm_ter, 0b11);
+//! assert_eq!(registers.tpiu_acpr, Some(31));
+//! assert_eq!(TraceConfig::from_registers(&registers).unwrap(), config);
+//! ```
+//!
+//! (Appendix C1.7, C1.8 and C1.10)
+
+use super::TracePacket;
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use cortex_m::peripheral::itm::{
+    GlobalTimestampOptions, LocalTimestampOptions, TimestampClkSrc,
+};
+
+/// Addresses of the trace registers.
+pub mod address {
+    /// Debug Exception and Monitor Control Register, whose `TRCENA` bit
+    /// must be set for the ITM and DWT to be accessible.
+    pub const DEMCR: u32 = 0xe000_edfc;
+
+    /// Trace Enable Register of stimulus ports 0 to 31.
+    pub const ITM_TER0: u32 = 0xe000_0e00;
+
+    /// Trace Control Register.
+    pub const ITM_TCR: u32 = 0xe000_0e80;
+
+    /// Lock Access Register, which [`LAR_KEY`] unlocks.
+    pub const ITM_LAR: u32 = 0xe000_0fb0;
+
+    /// Control Register.
+    pub const DWT_CTRL: u32 = 0xe000_1000;
+
+    /// Comparator register of comparator 0. The registers of comparator
+    /// `n` are at an offset of `16 * n`: `DWT_COMPn`, `DWT_MASKn` at 4
+    /// and `DWT_FUNCTIONn` at 8.
+    pub const DWT_COMP0: u32 = 0xe000_1020;
+
+    /// Asynchronous Clock Prescaler Register.
+    pub const TPIU_ACPR: u32 = 0xe004_0010;
+
+    /// Selected Pin Protocol Register.
+    pub const TPIU_SPPR: u32 = 0xe004_00f0;
+
+    /// Value that unlocks writes to the registers of a CoreSight
+    /// component.
+    pub const LAR_KEY: u32 = 0xc5ac_ce55;
+
+    /// The `TRCENA` bit of [`DEMCR`].
+    pub const DEMCR_TRCENA: u32 = 1 << 24;
+}
+
+/// Number of cycles between PC samples for each `CYCTAP` setting.
+const CYCTAP_CYCLES: [u32; 2] = [64, 1024];
+
+/// Maximum value of the `POSTPRESET` field of `DWT_CTRL`, plus one.
+const POSTPRESET_COUNT: u32 = 16;
+
+/// Number of cycles between synchronization packets for each non-zero
+/// `SYNCTAP` setting.
+const SYNCTAP_CYCLES: [u32; 3] = [1 << 24, 1 << 26, 1 << 28];
+
+/// A configuration that cannot be represented in the registers, or
+/// registers that do not describe a supported configuration.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ConfigError {
+    /// The PC sampling period, in cycles, is not 64 or 1024 times 1 to
+    /// 16.
+    #[cfg_attr(
+        feature = "std",
+        error("PC sampling period of {0} cycles is not 64 or 1024 times 1 to 16")
+    )]
+    PcSamplingPeriod(u32),
+
+    /// The synchronization packet period, in cycles, is not 2^24, 2^26
+    /// or 2^28.
+    #[cfg_attr(
+        feature = "std",
+        error("Synchronization packet period of {0} cycles is not 2^24, 2^26 or 2^28")
+    )]
+    SyncPeriod(u32),
+
+    /// The trace bus ID does not fit in 7 bits.
+    #[cfg_attr(feature = "std", error("Trace bus ID {0} does not fit in 7 bits"))]
+    TraceBusId(u8),
+
+    /// The mask of a DWT comparator exceeds 31 bits.
+    #[cfg_attr(
+        feature = "std",
+        error("Mask of {0} bits of a comparator exceeds 31 bits")
+    )]
+    ComparatorMask(u8),
+
+    /// The trace clock cannot be divided down to the SWO baud rate.
+    #[cfg_attr(
+        feature = "std",
+        error("Trace clock of {trace_clock} Hz cannot be divided down to {baud_rate} Bd")
+    )]
+    BaudRate { trace_clock: u32, baud_rate: u32 },
+
+    /// `TPIU_SPPR` selects the parallel trace port, or is reserved.
+    #[cfg_attr(
+        feature = "std",
+        error("TPIU_SPPR value {0:#x} does not select an SWO protocol")
+    )]
+    PinProtocol(u32),
+}
+
+/// Configuration of the ITM.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ItmConfig {
+    /// Whether the ITM is enabled at all.
+    pub enabled: bool,
+
+    /// Enabled stimulus ports 0 to 31, one bit per port.
+    pub ports: u32,
+
+    /// ID of the ITM on the trace bus, which must be non-zero and
+    /// unique if the TPIU formatter is used.
+    pub trace_bus_id: u8,
+
+    pub local_timestamps: LocalTimestampOptions,
+    pub global_timestamps: GlobalTimestampOptions,
+
+    /// Clock of the local timestamp counter.
+    pub timestamp_clock: TimestampClkSrc,
+
+    /// Whether synchronization packets are emitted, at the period of
+    /// [`DwtConfig::sync_period`].
+    pub sync_packets: bool,
+
+    /// Whether packets of the DWT are forwarded, which PC sampling,
+    /// exception trace and data trace require.
+    pub forward_dwt: bool,
+}
+
+/// What a DWT comparator emits on a match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ComparatorFunction {
+    /// A [`DataTracePC`](crate::TracePacket::DataTracePC) packet.
+    Pc,
+
+    /// A [`DataTraceValue`](crate::TracePacket::DataTraceValue) packet.
+    Value,
+
+    /// Both [`DataTracePC`](crate::TracePacket::DataTracePC) and
+    /// [`DataTraceValue`](crate::TracePacket::DataTraceValue) packets.
+    PcAndValue,
+
+    /// A [`DataTraceAddress`](crate::TracePacket::DataTraceAddress)
+    /// packet.
+    Address,
+
+    /// Both [`DataTraceAddress`](crate::TracePacket::DataTraceAddress)
+    /// and [`DataTraceValue`](crate::TracePacket::DataTraceValue)
+    /// packets.
+    AddressAndValue,
+}
+
+/// A DWT comparator that emits data trace packets on reads and writes
+/// of an address range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comparator {
+    pub address: u32,
+
+    /// Number of low bits of the address that are ignored when
+    /// matching, e.g. 2 to match any byte of a word.
+    pub ignored_bits: u8,
+
+    pub function: ComparatorFunction,
+}
+
+/// Configuration of the DWT.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DwtConfig {
+    /// Whether the cycle counter is enabled for its own sake. It is
+    /// enabled regardless if [`pc_sampling`](Self::pc_sampling) or
+    /// [`sync_period`](Self::sync_period) is set, as it drives both.
+    pub cycle_counter: bool,
+
+    /// Period of PC sampling, in cycles: 64 or 1024 times 1 to 16.
+    pub pc_sampling: Option<u32>,
+
+    /// Period of synchronization packets, in cycles: 2^24, 2^26 or
+    /// 2^28.
+    pub sync_period: Option<u32>,
+
+    /// Whether exception entries, exits and returns are traced.
+    pub exception_trace: bool,
+
+    /// Comparators by number. Disabled comparators, and comparators
+    /// configured for anything but data trace, e.g. as watchpoints, are
+    /// `None`.
+    pub comparators: Vec<Option<Comparator>>,
+}
+
+/// Protocol of the SWO pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum SwoProtocol {
+    Manchester,
+
+    /// NRZ, as by a UART.
+    Nrz,
+}
+
+/// Configuration of the SWO output of the TPIU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TpiuConfig {
+    pub protocol: SwoProtocol,
+
+    /// Divisor of the trace clock that yields the SWO baud rate.
+    pub prescaler: u32,
+}
+
+impl TpiuConfig {
+    /// The configuration that outputs at `baud_rate` from a trace clock
+    /// of `trace_clock` Hz, which must be a multiple of `baud_rate`.
+    pub fn new(
+        protocol: SwoProtocol,
+        trace_clock: u32,
+        baud_rate: u32,
+    ) -> Result<Self, ConfigError> {
+        let error = ConfigError::BaudRate {
+            trace_clock,
+            baud_rate,
+        };
+        if baud_rate == 0 {
+            return Err(error);
+        }
+        match trace_clock / baud_rate {
+            prescaler @ 1..=0x1_0000 if prescaler * baud_rate == trace_clock => Ok(Self {
+                protocol,
+                prescaler,
+            }),
+            _ => Err(error),
+        }
+    }
+
+    /// The SWO baud rate, given a trace clock of `trace_clock` Hz.
+    pub fn baud_rate(&self, trace_clock: u32) -> u32 {
+        trace_clock / self.prescaler
+    }
+}
+
+/// Configuration of the trace hardware of the target.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub itm: ItmConfig,
+
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dwt: DwtConfig,
+
+    /// The SWO output, if it is configured along with the ITM and DWT.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tpiu: Option<TpiuConfig>,
+}
+
+impl Default for ItmConfig {
+    /// The ITM enabled with stimulus port 0, without timestamps or
+    /// synchronization packets, and forwarding DWT packets.
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ports: 1,
+            trace_bus_id: 1,
+            local_timestamps: LocalTimestampOptions::Disabled,
+            global_timestamps: GlobalTimestampOptions::Disabled,
+            timestamp_clock: TimestampClkSrc::SystemClock,
+            sync_packets: false,
+            forward_dwt: true,
+        }
+    }
+}
+
+/// Values of the registers of a DWT comparator.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComparatorRegisters {
+    pub comp: u32,
+    pub mask: u32,
+    pub function: u32,
+}
+
+/// Values of the trace registers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub itm_tcr: u32,
+    pub itm_ter: u32,
+    pub dwt_ctrl: u32,
+
+    /// Registers of the comparators, by number.
+    pub dwt_comparators: Vec<ComparatorRegisters>,
+
+    /// `TPIU_SPPR` and `TPIU_ACPR`, if the TPIU is configured.
+    pub tpiu_sppr: Option<u32>,
+    pub tpiu_acpr: Option<u32>,
+}
+
+mod tcr {
+    pub const ITMENA: u32 = 1 << 0;
+    pub const TSENA: u32 = 1 << 1;
+    pub const SYNCENA: u32 = 1 << 2;
+    pub const TXENA: u32 = 1 << 3;
+    pub const SWOENA: u32 = 1 << 4;
+    pub const TSPRESCALE_SHIFT: u32 = 8;
+    pub const GTSFREQ_SHIFT: u32 = 10;
+    pub const TRACEBUSID_SHIFT: u32 = 16;
+}
+
+mod ctrl {
+    pub const CYCCNTENA: u32 = 1 << 0;
+    pub const POSTPRESET_SHIFT: u32 = 1;
+    pub const POSTINIT_SHIFT: u32 = 5;
+    pub const CYCTAP: u32 = 1 << 9;
+    pub const SYNCTAP_SHIFT: u32 = 10;
+    pub const PCSAMPLENA: u32 = 1 << 12;
+    pub const EXCTRCENA: u32 = 1 << 16;
+}
+
+/// The `EMITRANGE` bit of `DWT_FUNCTIONn`.
+const EMITRANGE: u32 = 1 << 5;
+
+impl ComparatorFunction {
+    /// The `FUNCTION` and `EMITRANGE` fields of `DWT_FUNCTIONn`.
+    fn bits(self) -> u32 {
+        match self {
+            Self::Pc => 0b0001,
+            Self::Value => 0b0010,
+            Self::PcAndValue => 0b0011,
+            Self::Address => EMITRANGE | 0b0001,
+            Self::AddressAndValue => EMITRANGE | 0b0010,
+        }
+    }
+
+    fn from_bits(function: u32) -> Option<Self> {
+        Some(match function & (EMITRANGE | 0b1111) {
+            0b0001 => Self::Pc,
+            0b0010 => Self::Value,
+            0b0011 => Self::PcAndValue,
+            f if f == EMITRANGE | 0b0001 => Self::Address,
+            f if f == EMITRANGE | 0b0010 || f == EMITRANGE | 0b0011 => Self::AddressAndValue,
+            _ => return None,
+        })
+    }
+}
+
+impl TraceConfig {
+    /// The register values that apply the configuration.
+    pub fn registers(&self) -> Result<Registers, ConfigError> {
+        let itm = &self.itm;
+        if itm.trace_bus_id > 0x7f {
+            return Err(ConfigError::TraceBusId(itm.trace_bus_id));
+        }
+        let mut itm_tcr = u32::from(itm.trace_bus_id) << tcr::TRACEBUSID_SHIFT;
+        itm_tcr |= match itm.local_timestamps {
+            LocalTimestampOptions::Disabled => 0,
+            LocalTimestampOptions::Enabled => tcr::TSENA,
+            LocalTimestampOptions::EnabledDiv4 => tcr::TSENA | 1 << tcr::TSPRESCALE_SHIFT,
+            LocalTimestampOptions::EnabledDiv16 => tcr::TSENA | 2 << tcr::TSPRESCALE_SHIFT,
+            LocalTimestampOptions::EnabledDiv64 => tcr::TSENA | 3 << tcr::TSPRESCALE_SHIFT,
+        };
+        itm_tcr |= match itm.global_timestamps {
+            GlobalTimestampOptions::Disabled => 0,
+            GlobalTimestampOptions::Every128Cycles => 1,
+            GlobalTimestampOptions::Every8192Cycles => 2,
+            GlobalTimestampOptions::EveryPacket => 3,
+        } << tcr::GTSFREQ_SHIFT;
+        for (set, bit) in [
+            (itm.enabled, tcr::ITMENA),
+            (itm.sync_packets, tcr::SYNCENA),
+            (itm.forward_dwt, tcr::TXENA),
+            (
+                itm.timestamp_clock == TimestampClkSrc::AsyncTPIU,
+                tcr::SWOENA,
+            ),
+        ] {
+            if set {
+                itm_tcr |= bit;
+            }
+        }
+
+        let dwt = &self.dwt;
+        let mut dwt_ctrl = 0;
+        if let Some(period) = dwt.pc_sampling {
+            let (cyctap, postpreset) = CYCTAP_CYCLES
+                .iter()
+                .enumerate()
+                .find(|(_, cycles)| {
+                    period % *cycles == 0 && (1..=POSTPRESET_COUNT).contains(&(period / *cycles))
+                })
+                .map(|(cyctap, cycles)| (cyctap, period / cycles - 1))
+                .ok_or(ConfigError::PcSamplingPeriod(period))?;
+            if cyctap == 1 {
+                dwt_ctrl |= ctrl::CYCTAP;
+            }
+            dwt_ctrl |= postpreset << ctrl::POSTPRESET_SHIFT
+                | postpreset << ctrl::POSTINIT_SHIFT
+                | ctrl::PCSAMPLENA;
+        }
+        if let Some(period) = dwt.sync_period {
+            let synctap = SYNCTAP_CYCLES
+                .iter()
+                .position(|cycles| *cycles == period)
+                .ok_or(ConfigError::SyncPeriod(period))?;
+            dwt_ctrl |= (synctap as u32 + 1) << ctrl::SYNCTAP_SHIFT;
+        }
+        if dwt.cycle_counter || dwt.pc_sampling.is_some() || dwt.sync_period.is_some() {
+            dwt_ctrl |= ctrl::CYCCNTENA;
+        }
+        if dwt.exception_trace {
+            dwt_ctrl |= ctrl::EXCTRCENA;
+        }
+
+        let dwt_comparators = dwt
+            .comparators
+            .iter()
+            .map(|comparator| match comparator {
+                None => Ok(ComparatorRegisters::default()),
+                Some(comparator) if comparator.ignored_bits > 31 => {
+                    Err(ConfigError::ComparatorMask(comparator.ignored_bits))
+                }
+                Some(comparator) => Ok(ComparatorRegisters {
+                    comp: comparator.address,
+                    mask: u32::from(comparator.ignored_bits),
+                    function: comparator.function.bits(),
+                }),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Registers {
+            itm_tcr,
+            itm_ter: itm.ports,
+            dwt_ctrl,
+            dwt_comparators,
+            tpiu_sppr: self.tpiu.as_ref().map(|tpiu| match tpiu.protocol {
+                SwoProtocol::Manchester => 1,
+                SwoProtocol::Nrz => 2,
+            }),
+            tpiu_acpr: self.tpiu.as_ref().map(|tpiu| tpiu.prescaler - 1),
+        })
+    }
+
+    /// The configuration described by register values, e.g. as read
+    /// back from the target. Fields of the registers that are not part
+    /// of a [`TraceConfig`] are ignored.
+    pub fn from_registers(registers: &Registers) -> Result<Self, ConfigError> {
+        let tcr = registers.itm_tcr;
+        let itm = ItmConfig {
+            enabled: tcr & tcr::ITMENA != 0,
+            ports: registers.itm_ter,
+            trace_bus_id: (tcr >> tcr::TRACEBUSID_SHIFT & 0x7f) as u8,
+            local_timestamps: match (tcr & tcr::TSENA != 0, tcr >> tcr::TSPRESCALE_SHIFT & 0b11) {
+                (false, _) => LocalTimestampOptions::Disabled,
+                (true, 0) => LocalTimestampOptions::Enabled,
+                (true, 1) => LocalTimestampOptions::EnabledDiv4,
+                (true, 2) => LocalTimestampOptions::EnabledDiv16,
+                (true, _) => LocalTimestampOptions::EnabledDiv64,
+            },
+            global_timestamps: match tcr >> tcr::GTSFREQ_SHIFT & 0b11 {
+                0 => GlobalTimestampOptions::Disabled,
+                1 => GlobalTimestampOptions::Every128Cycles,
+                2 => GlobalTimestampOptions::Every8192Cycles,
+                _ => GlobalTimestampOptions::EveryPacket,
+            },
+            timestamp_clock: if tcr & tcr::SWOENA != 0 {
+                TimestampClkSrc::AsyncTPIU
+            } else {
+                TimestampClkSrc::SystemClock
+            },
+            sync_packets: tcr & tcr::SYNCENA != 0,
+            forward_dwt: tcr & tcr::TXENA != 0,
+        };
+
+        let ctrl = registers.dwt_ctrl;
+        let cycles = CYCTAP_CYCLES[usize::from(ctrl & ctrl::CYCTAP != 0)];
+        let mut dwt = DwtConfig {
+            cycle_counter: false,
+            pc_sampling: if ctrl & ctrl::PCSAMPLENA != 0 {
+                Some(cycles * ((ctrl >> ctrl::POSTPRESET_SHIFT & 0xf) + 1))
+            } else {
+                None
+            },
+            sync_period: match ctrl >> ctrl::SYNCTAP_SHIFT & 0b11 {
+                0 => None,
+                synctap => Some(SYNCTAP_CYCLES[synctap as usize - 1]),
+            },
+            exception_trace: ctrl & ctrl::EXCTRCENA != 0,
+            comparators: registers
+                .dwt_comparators
+                .iter()
+                .map(|registers| {
+                    ComparatorFunction::from_bits(registers.function).map(|function| Comparator {
+                        address: registers.comp,
+                        ignored_bits: (registers.mask & 0x1f) as u8,
+                        function,
+                    })
+                })
+                .collect(),
+        };
+        dwt.cycle_counter =
+            ctrl & ctrl::CYCCNTENA != 0 && dwt.pc_sampling.is_none() && dwt.sync_period.is_none();
+
+        let tpiu = match (registers.tpiu_sppr, registers.tpiu_acpr) {
+            (Some(sppr), Some(acpr)) => Some(TpiuConfig {
+                protocol: match sppr & 0b11 {
+                    1 => SwoProtocol::Manchester,
+                    2 => SwoProtocol::Nrz,
+                    _ => return Err(ConfigError::PinProtocol(sppr)),
+                },
+                prescaler: (acpr & 0xffff) + 1,
+            }),
+            _ => None,
+        };
+
+        Ok(Self { itm, dwt, tpiu })
+    }
+}
+
+impl Registers {
+    /// The writes, as `(address, value)` pairs in order, that apply the
+    /// register values to the target: the ITM is unlocked and disabled
+    /// while the DWT and TPIU are configured, and enabled last. The
+    /// `TRCENA` bit of [`DEMCR`](address::DEMCR) must be set first,
+    /// which is left to the caller so that the other bits of the
+    /// register are preserved.
+    pub fn writes(&self) -> Vec<(u32, u32)> {
+        let mut writes = vec![
+            (address::ITM_LAR, address::LAR_KEY),
+            (address::ITM_TCR, 0),
+            (address::DWT_CTRL, self.dwt_ctrl),
+        ];
+        for (n, comparator) in self.dwt_comparators.iter().enumerate() {
+            let base = address::DWT_COMP0 + 16 * n as u32;
+            writes.extend_from_slice(&[
+                (base, comparator.comp),
+                (base + 4, comparator.mask),
+                (base + 8, comparator.function),
+            ]);
+        }
+        if let (Some(sppr), Some(acpr)) = (self.tpiu_sppr, self.tpiu_acpr) {
+            writes.push((address::TPIU_SPPR, sppr));
+            writes.push((address::TPIU_ACPR, acpr));
+        }
+        writes.push((address::ITM_TER0, self.itm_ter));
+        writes.push((address::ITM_TCR, self.itm_tcr));
+        writes
+    }
+}
+
+/// Why a packet is inconsistent with a [`TraceConfig`], i.e. could not
+/// have been emitted by a target configured with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Inconsistency {
+    /// The ITM is disabled.
+    ItmDisabled,
+
+    /// The stimulus port of an instrumentation packet is disabled.
+    PortDisabled(u8),
+
+    /// Local timestamps are disabled.
+    LocalTimestamps,
+
+    /// Global timestamps are disabled.
+    GlobalTimestamps,
+
+    /// Packets of the DWT are not forwarded.
+    DwtNotForwarded,
+
+    /// PC sampling is disabled.
+    PcSampling,
+
+    /// Exception trace is disabled.
+    ExceptionTrace,
+
+    /// The comparator of a data trace packet is not configured to emit
+    /// it.
+    DataTrace(u8),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ItmDisabled => write!(f, "packets while the ITM is disabled"),
+            Self::PortDisabled(port) => {
+                write!(
+                    f,
+                    "instrumentation packets of disabled stimulus port {}",
+                    port
+                )
+            }
+            Self::LocalTimestamps => write!(f, "local timestamps while they are disabled"),
+            Self::GlobalTimestamps => write!(f, "global timestamps while they are disabled"),
+            Self::DwtNotForwarded => write!(f, "DWT packets while they are not forwarded"),
+            Self::PcSampling => write!(f, "PC samples while PC sampling is disabled"),
+            Self::ExceptionTrace => write!(f, "exception trace while it is disabled"),
+            Self::DataTrace(comparator) => write!(
+                f,
+                "data trace packets that comparator {} is not configured to emit",
+                comparator
+            ),
+        }
+    }
+}
+
+impl TraceConfig {
+    /// Checks that `packet` could have been emitted by a target
+    /// configured as described. Packets of features that a
+    /// [`TraceConfig`] does not describe, e.g. event counters, pass.
+    pub fn check(&self, packet: &TracePacket) -> Result<(), Inconsistency> {
+        use TracePacket as P;
+
+        // Packets of the DWT remain.
+        let dwt = match packet {
+            P::Sync | P::Overflow | P::Extension { .. } | P::Unknown { .. } => return Ok(()),
+            _ if !self.itm.enabled => return Err(Inconsistency::ItmDisabled),
+            P::Instrumentation { port, .. } => {
+                if *port < 32 && self.itm.ports & 1 << port == 0 {
+                    return Err(Inconsistency::PortDisabled(*port));
+                }
+                return Ok(());
+            }
+            P::LocalTimestamp1 { .. } | P::LocalTimestamp2 { .. } => {
+                if self.itm.local_timestamps == LocalTimestampOptions::Disabled {
+                    return Err(Inconsistency::LocalTimestamps);
+                }
+                return Ok(());
+            }
+            P::GlobalTimestamp1 { .. } | P::GlobalTimestamp2 { .. } => {
+                if self.itm.global_timestamps == GlobalTimestampOptions::Disabled {
+                    return Err(Inconsistency::GlobalTimestamps);
+                }
+                return Ok(());
+            }
+            P::PCSample { .. } if self.dwt.pc_sampling.is_none() => Err(Inconsistency::PcSampling),
+            P::ExceptionTrace { .. } if !self.dwt.exception_trace => {
+                Err(Inconsistency::ExceptionTrace)
+            }
+            P::DataTracePC { comparator, .. }
+            | P::DataTraceAddress { comparator, .. }
+            | P::DataTraceValue { comparator, .. } => {
+                use ComparatorFunction as F;
+
+                let function = self
+                    .dwt
+                    .comparators
+                    .get(usize::from(*comparator))
+                    .and_then(|comparator| comparator.as_ref())
+                    .map(|comparator| comparator.function);
+                let emitted = match (packet, function) {
+                    (_, None) => false,
+                    (P::DataTracePC { .. }, Some(function)) => {
+                        matches!(function, F::Pc | F::PcAndValue)
+                    }
+                    (P::DataTraceAddress { .. }, Some(function)) => {
+                        matches!(function, F::Address | F::AddressAndValue)
+                    }
+                    (_, Some(function)) => {
+                        matches!(function, F::Value | F::PcAndValue | F::AddressAndValue)
+                    }
+                };
+                if emitted {
+                    Ok(())
+                } else {
+                    Err(Inconsistency::DataTrace(*comparator))
+                }
+            }
+            _ => Ok(()),
+        };
+        if !self.itm.forward_dwt {
+            return Err(Inconsistency::DwtNotForwarded);
+        }
+        dwt
+    }
+}
+
+/// Checks a stream of packets against a [`TraceConfig`], reporting
+/// each kind of [`Inconsistency`] once, e.g. to warn that the decoder
+/// and the target disagree on the configuration.
+///
+/// ```
+/// use itm::config::{ConfigCheck, Inconsistency, TraceConfig};
+/// use itm::TracePacket;
+///
+/// let mut check = ConfigCheck::new(TraceConfig::default());
+/// let sample = TracePacket::PCSample { pc: Some(0x0800_0100) };
+/// assert_eq!(check.push(&sample), Some(Inconsistency::PcSampling));
+/// assert_eq!(check.push(&sample), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConfigCheck {
+    config: TraceConfig,
+    reported: BTreeSet<Inconsistency>,
+}
+
+impl ConfigCheck {
+    pub fn new(config: TraceConfig) -> Self {
+        Self {
+            config,
+            reported: BTreeSet::new(),
+        }
+    }
+
+    /// Checks `packet`, and returns why it is inconsistent with the
+    /// configuration, unless that has been returned before.
+    pub fn push(&mut self, packet: &TracePacket) -> Option<Inconsistency> {
+        match self.config.check(packet) {
+            Err(inconsistency) if self.reported.insert(inconsistency) => Some(inconsistency),
+            _ => None,
+        }
+    }
+
+    /// The configuration checked against.
+    pub fn config(&self) -> &TraceConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers() {
+        let config = TraceConfig {
+            itm: ItmConfig {
+                ports: 0x8000_0001,
+                local_timestamps: LocalTimestampOptions::EnabledDiv16,
+                global_timestamps: GlobalTimestampOptions::Every8192Cycles,
+                timestamp_clock: TimestampClkSrc::AsyncTPIU,
+                sync_packets: true,
+                ..ItmConfig::default()
+            },
+            dwt: DwtConfig {
+                pc_sampling: Some(16 * 1024),
+                sync_period: Some(1 << 26),
+                exception_trace: true,
+                comparators: vec![
+                    None,
+                    Some(Comparator {
+                        address: 0x2000_0100,
+                        ignored_bits: 2,
+                        function: ComparatorFunction::AddressAndValue,
+                    }),
+                ],
+                ..DwtConfig::default()
+            },
+            tpiu: Some(TpiuConfig::new(SwoProtocol::Manchester, 72_000_000, 1_000_000).unwrap()),
+        };
+        let registers = config.registers().unwrap();
+        assert_eq!(
+            registers,
+            Registers {
+                itm_tcr: 0x0001_0a1f,
+                itm_ter: 0x8000_0001,
+                dwt_ctrl: 0x0001_1bff,
+                dwt_comparators: vec![
+                    ComparatorRegisters::default(),
+                    ComparatorRegisters {
+                        comp: 0x2000_0100,
+                        mask: 2,
+                        function: 0x22,
+                    },
+                ],
+                tpiu_sppr: Some(1),
+                tpiu_acpr: Some(71),
+            }
+        );
+        assert_eq!(TraceConfig::from_registers(&registers).unwrap(), config);
+
+        let writes = registers.writes();
+        assert_eq!(writes[0], (address::ITM_LAR, address::LAR_KEY));
+        assert_eq!(writes[6], (0xe000_1030, 0x2000_0100));
+        assert_eq!(writes.last(), Some(&(address::ITM_TCR, 0x0001_0a1f)));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut config = TraceConfig::default();
+        config.dwt.pc_sampling = Some(100);
+        assert_eq!(config.registers(), Err(ConfigError::PcSamplingPeriod(100)));
+        config.dwt.pc_sampling = Some(17 * 64);
+        assert_eq!(config.registers(), Err(ConfigError::PcSamplingPeriod(1088)));
+        config.dwt.pc_sampling = Some(2048);
+        assert!(config.registers().is_ok());
+
+        config.dwt.sync_period = Some(1000);
+        assert_eq!(config.registers(), Err(ConfigError::SyncPeriod(1000)));
+
+        assert!(TpiuConfig::new(SwoProtocol::Nrz, 64_000_000, 3_000_000).is_err());
+        assert!(TpiuConfig::new(SwoProtocol::Nrz, 64_000_000, 0).is_err());
+
+        let registers = Registers {
+            tpiu_sppr: Some(0),
+            tpiu_acpr: Some(0),
+            ..Registers::default()
+        };
+        assert_eq!(
+            TraceConfig::from_registers(&registers),
+            Err(ConfigError::PinProtocol(0))
+        );
+    }
+
+    #[test]
+    fn check() {
+        use crate::{MemoryAccessType, Payload};
+
+        let mut config = TraceConfig::default();
+        config.itm.ports = 0b10;
+        config.dwt.comparators = vec![
+            None,
+            Some(Comparator {
+                address: 0x2000_0000,
+                ignored_bits: 0,
+                function: ComparatorFunction::PcAndValue,
+            }),
+        ];
+
+        let instrumentation = |port| TracePacket::Instrumentation {
+            port,
+            payload: Payload::from([0x41]),
+        };
+        let value = |comparator| TracePacket::DataTraceValue {
+            comparator,
+            access_type: MemoryAccessType::Write,
+            value: Payload::from([0x01]),
+        };
+        assert_eq!(config.check(&instrumentation(1)), Ok(()));
+        assert_eq!(
+            config.check(&instrumentation(0)),
+            Err(Inconsistency::PortDisabled(0))
+        );
+        assert_eq!(config.check(&value(1)), Ok(()));
+        assert_eq!(config.check(&value(0)), Err(Inconsistency::DataTrace(0)));
+        assert_eq!(
+            config.check(&TracePacket::DataTraceAddress {
+                comparator: 1,
+                data: Payload::from([0x00, 0x01]),
+            }),
+            Err(Inconsistency::DataTrace(1))
+        );
+        assert_eq!(
+            config.check(&TracePacket::LocalTimestamp2 { ts: 1 }),
+            Err(Inconsistency::LocalTimestamps)
+        );
+        assert_eq!(config.check(&TracePacket::Sync), Ok(()));
+
+        config.itm.forward_dwt = false;
+        assert_eq!(config.check(&value(1)), Err(Inconsistency::DwtNotForwarded));
+        config.itm.enabled = false;
+        assert_eq!(
+            config.check(&instrumentation(1)),
+            Err(Inconsistency::ItmDisabled)
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Event counter wraps as a set of flags.
+
+use super::TracePacket;
+
+bitflags::bitflags! {
+    /// The set of DWT event counters that wrapped, as reported by an
+    /// [`EventCounterWrap`](TracePacket::EventCounterWrap) packet. The
+    /// bits are those of the packet payload. (Appendix D4.3.1)
+    ///
+    /// ```
+    /// use itm::{DwtCounterWrap, TracePacket};
+    ///
+    /// let packet = TracePacket::from(DwtCounterWrap::CYC | DwtCounterWrap::SLEEP);
+    /// let wraps = packet.counter_wrap().unwrap();
+    /// assert!(wraps.contains(DwtCounterWrap::CYC | DwtCounterWrap::SLEEP));
+    /// assert!(!wraps.contains(DwtCounterWrap::LSU));
+    /// ```
+    ///
+    /// With the `serde` feature, the flags are serialized as the
+    /// payload byte.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct DwtCounterWrap: u8 {
+        /// POSTCNT wrap (see Appendix C1, p. 732).
+        const CYC = 1 << 5;
+        /// FOLDCNT wrap (see Appendix C1, p. 734).
+        const FOLD = 1 << 4;
+        /// LSUCNT wrap (see Appendix C1, p. 734).
+        const LSU = 1 << 3;
+        /// SLEEPCNT wrap (see Appendix C1, p. 734).
+        const SLEEP = 1 << 2;
+        /// EXCCNT wrap (see Appendix C1, p. 734).
+        const EXC = 1 << 1;
+        /// CPICNT wrap (see Appendix C1, p. 734).
+        const CPI = 1 << 0;
+    }
+}
+
+impl From<DwtCounterWrap> for TracePacket {
+    fn from(wraps: DwtCounterWrap) -> Self {
+        TracePacket::EventCounterWrap {
+            cyc: wraps.contains(DwtCounterWrap::CYC),
+            fold: wraps.contains(DwtCounterWrap::FOLD),
+            lsu: wraps.contains(DwtCounterWrap::LSU),
+            sleep: wraps.contains(DwtCounterWrap::SLEEP),
+            exc: wraps.contains(DwtCounterWrap::EXC),
+            cpi: wraps.contains(DwtCounterWrap::CPI),
+        }
+    }
+}
+
+impl TracePacket {
+    /// The wrapped counters of an
+    /// [`EventCounterWrap`](TracePacket::EventCounterWrap) packet.
+    pub fn counter_wrap(&self) -> Option<DwtCounterWrap> {
+        match self {
+            TracePacket::EventCounterWrap {
+                cyc,
+                fold,
+                lsu,
+                sleep,
+                exc,
+                cpi,
+            } => {
+                let mut wraps = DwtCounterWrap::empty();
+                wraps.set(DwtCounterWrap::CYC, *cyc);
+                wraps.set(DwtCounterWrap::FOLD, *fold);
+                wraps.set(DwtCounterWrap::LSU, *lsu);
+                wraps.set(DwtCounterWrap::SLEEP, *sleep);
+                wraps.set(DwtCounterWrap::EXC, *exc);
+                wraps.set(DwtCounterWrap::CPI, *cpi);
+                Some(wraps)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for DwtCounterWrap {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(self.bits())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DwtCounterWrap {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bits = u8::deserialize(d)?;
+        DwtCounterWrap::from_bits(bits).ok_or_else(|| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(bits.into()),
+                &"event counter wrap flags",
+            )
+        })
+    }
+}
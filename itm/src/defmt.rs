@@ -0,0 +1,277 @@
+//! Decoding of [defmt](https://defmt.ferrous-systems.com) log frames
+//! written to a stimulus port.
+//!
+//! Firmware that uses defmt over ITM writes its encoded log frames to
+//! a single stimulus port. [`DefmtBridge`] feeds the payloads of that
+//! port to [`defmt_decoder`], which requires the
+//! [`Table`](Table) of format strings from the ELF of the firmware.
+//! [`Defmt`] yields the decoded frames interleaved with all other
+//! packets, so that they can be correlated with e.g. exception traces
+//! and timestamps.
+//!
+//! ```no_run
+//! use itm::defmt::{self, Defmt, DefmtEvent};
+//! use itm::{Decoder, DecoderOptions};
+//!
+//! let elf = std::fs::read("firmware.elf").unwrap();
+//! let table = defmt::load_table(&elf).unwrap();
+//! let decoder = Decoder::new(std::io::stdin(), DecoderOptions::default());
+//! for item in Defmt::new(decoder.singles(), &table, 0) {
+//!     match item.unwrap().event {
+//!         DefmtEvent::Frame(frame) => println!("{}", frame.text),
+//!         DefmtEvent::Packet(packet) => println!("{}", packet),
+//!     }
+//! }
+//! ```
+//!
+//! Requires the `defmt` feature.
+
+use super::{DecoderError, LinesInput, Timestamp, TracePacket};
+
+use defmt_decoder::{DecodeError, StreamDecoder};
+use std::collections::VecDeque;
+
+pub use defmt_decoder::Table;
+
+/// Set of errors that can occur while decoding defmt frames.
+#[derive(Debug, thiserror::Error)]
+pub enum DefmtError {
+    /// The ELF could not be parsed.
+    #[error("Failed to parse ELF: {0}")]
+    Elf(Box<dyn std::error::Error + Send + Sync>),
+
+    /// The ELF does not contain defmt data.
+    #[error("ELF does not contain defmt data")]
+    NoDefmtData,
+
+    /// The payloads of the port could not be decoded as defmt frames.
+    #[error("Malformed defmt frame on port {0}")]
+    Malformed(u8),
+
+    /// The packets could not be decoded.
+    #[error(transparent)]
+    Decoder(#[from] DecoderError),
+}
+
+/// Reads the defmt [`Table`](Table) from the ELF of the firmware.
+pub fn load_table(elf: &[u8]) -> Result<Table, DefmtError> {
+    Table::parse(elf)
+        .map_err(|e| DefmtError::Elf(e.into()))?
+        .ok_or(DefmtError::NoDefmtData)
+}
+
+/// A decoded defmt log frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefmtFrame {
+    /// Index of the format string in the [`Table`](Table).
+    pub index: u64,
+
+    /// The formatted log message.
+    pub message: String,
+
+    /// The log message with the defmt timestamp and level, if any, as
+    /// printed by `defmt-print`.
+    pub text: String,
+}
+
+/// Feeds the payloads of a stimulus port to a defmt stream decoder.
+/// Packets are [pushed](Self::push) as they are decoded, and complete
+/// frames are [pulled](Self::pull) out.
+pub struct DefmtBridge<'t> {
+    port: u8,
+    decoder: Box<dyn StreamDecoder + 't>,
+    /// Whether the stream decoder skips malformed frames, rather than
+    /// failing on them over and over.
+    recoverable: bool,
+    failed: bool,
+}
+
+impl<'t> DefmtBridge<'t> {
+    /// Decodes the frames written to `port`.
+    pub fn new(table: &'t Table, port: u8) -> Self {
+        Self {
+            port,
+            decoder: table.new_stream_decoder(),
+            recoverable: table.encoding().can_recover(),
+            failed: false,
+        }
+    }
+
+    /// The port the frames are read from.
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// Feeds the payload of the packet to the stream decoder if it is
+    /// an [`Instrumentation`](TracePacket::Instrumentation) packet on
+    /// the port. Returns whether the packet was consumed.
+    pub fn push(&mut self, packet: &TracePacket) -> bool {
+        match packet {
+            TracePacket::Instrumentation { port, payload } if *port == self.port => {
+                self.decoder.received(payload);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Pulls the next complete frame. Depending on the defmt encoding,
+    /// the stream decoder may not recover after a
+    /// [`Malformed`](DefmtError::Malformed) frame, in which case no
+    /// frames are pulled after it.
+    pub fn pull(&mut self) -> Option<Result<DefmtFrame, DefmtError>> {
+        if self.failed {
+            return None;
+        }
+        match self.decoder.decode() {
+            Ok(frame) => Some(Ok(DefmtFrame {
+                index: frame.index(),
+                message: frame.display_message().to_string(),
+                text: frame.display(false).to_string(),
+            })),
+            Err(DecodeError::UnexpectedEof) => None,
+            Err(DecodeError::Malformed) => {
+                self.failed = !self.recoverable;
+                Some(Err(DefmtError::Malformed(self.port)))
+            }
+        }
+    }
+}
+
+/// Either a defmt frame or a packet that does not carry one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefmtEvent {
+    /// A frame decoded from the payloads of the defmt port.
+    Frame(DefmtFrame),
+
+    /// Any packet other than an
+    /// [`Instrumentation`](TracePacket::Instrumentation) packet on the
+    /// defmt port.
+    Packet(TracePacket),
+}
+
+/// An item yielded by [`Defmt`](Defmt).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefmtItem {
+    pub event: DefmtEvent,
+
+    /// Timestamp of the packet that completed the frame, or of the
+    /// packet itself, if the packets were timestamped.
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Iterator adapter that yield [`DefmtItem`](DefmtItem)s from an
+/// iterator over either [`TracePacket`](TracePacket)s, e.g.
+/// [`Singles`](crate::Singles), or
+/// [`TimestampedTracePackets`](crate::TimestampedTracePackets), e.g.
+/// [`Timestamps`](crate::Timestamps). See also
+/// [`DefmtBridge`](DefmtBridge).
+pub struct Defmt<'t, I> {
+    inner: I,
+    bridge: DefmtBridge<'t>,
+    pending: VecDeque<Result<DefmtItem, DefmtError>>,
+}
+
+impl<'t, I, T> Defmt<'t, I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    /// Decodes the frames written to `port`.
+    pub fn new(inner: I, table: &'t Table, port: u8) -> Self {
+        Self {
+            inner,
+            bridge: DefmtBridge::new(table, port),
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<'t, I, T> Iterator for Defmt<'t, I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    type Item = Result<DefmtItem, DefmtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+
+            let (packets, timestamp) = match self.inner.next()? {
+                Err(e) => return Some(Err(e.into())),
+                Ok(item) => item.into_packets(),
+            };
+            for packet in packets {
+                if !self.bridge.push(&packet) {
+                    self.pending.push_back(Ok(DefmtItem {
+                        event: DefmtEvent::Packet(packet),
+                        timestamp: timestamp.clone(),
+                    }));
+                    continue;
+                }
+                while let Some(frame) = self.bridge.pull() {
+                    self.pending.push_back(frame.map(|frame| DefmtItem {
+                        event: DefmtEvent::Frame(frame),
+                        timestamp: timestamp.clone(),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Payload;
+
+    /// A stream decoder of the raw encoding, which fails on the same
+    /// malformed frame for as long as it is not reset.
+    struct Corrupt;
+
+    impl StreamDecoder for Corrupt {
+        fn received(&mut self, _: &[u8]) {}
+
+        fn decode(&mut self) -> Result<defmt_decoder::Frame<'_>, DecodeError> {
+            Err(DecodeError::Malformed)
+        }
+    }
+
+    #[test]
+    fn unrecoverable() {
+        let packets = vec![
+            Ok(TracePacket::Instrumentation {
+                port: 0,
+                payload: Payload::from([0xff, 0xff]),
+            }),
+            Ok(TracePacket::Sync),
+            Ok(TracePacket::Instrumentation {
+                port: 0,
+                payload: Payload::from([0x00]),
+            }),
+        ];
+        let defmt = Defmt {
+            inner: packets.into_iter(),
+            bridge: DefmtBridge {
+                port: 0,
+                decoder: Box::new(Corrupt),
+                recoverable: false,
+                failed: false,
+            },
+            pending: VecDeque::new(),
+        };
+        let items: Vec<_> = defmt.collect();
+        assert_eq!(items.len(), 2);
+        assert!(matches!(items[0], Err(DefmtError::Malformed(0))));
+        assert!(matches!(
+            items[1],
+            Ok(DefmtItem {
+                event: DefmtEvent::Packet(TracePacket::Sync),
+                ..
+            })
+        ));
+    }
+}
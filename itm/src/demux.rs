@@ -0,0 +1,118 @@
+//! Demultiplexing of instrumentation packets by stimulus port.
+//!
+//! Different stimulus ports usually carry unrelated data, e.g. console
+//! output on port 0 and binary telemetry on port 1. A [`Demux`] routes
+//! the payloads written to each port to a separate channel, so that
+//! they can be consumed by different components, possibly on different
+//! threads.
+//!
+//! ```
+//! use itm::demux::Demux;
+//!
+//! let mut demux = Demux::new();
+//! let console = demux.port(0);
+//! let rest = demux.rest();
+//!
+//! // Instrumentation packets on port 0 and port 1, overflow
+//! let stream = [0b0000_0001, b'h', 0b0000_1001, 0x01, 0b0111_0000];
+//! demux.run(itm::decode_all(&stream)).unwrap();
+//!
+//! assert_eq!(console.try_iter().collect::<Vec<_>>(), [[b'h'].into()]);
+//! assert_eq!(rest.try_iter().count(), 2);
+//! ```
+
+use super::{DecoderError, Payload, TracePacket};
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Routes instrumentation payloads to a channel per stimulus port. See
+/// the [module documentation](self).
+#[derive(Default)]
+pub struct Demux {
+    ports: BTreeMap<u8, Sender<Payload>>,
+    rest: Option<Sender<TracePacket>>,
+}
+
+impl Demux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a receiver of the payloads written to `port`, in order.
+    /// Replaces any previously returned receiver of the port.
+    pub fn port(&mut self, port: u8) -> Receiver<Payload> {
+        let (tx, rx) = channel();
+        self.ports.insert(port, tx);
+        rx
+    }
+
+    /// Returns a receiver of all packets that are not routed to a port
+    /// receiver. Replaces any previously returned receiver. Without
+    /// one, such packets are dropped.
+    pub fn rest(&mut self) -> Receiver<TracePacket> {
+        let (tx, rx) = channel();
+        self.rest = Some(tx);
+        rx
+    }
+
+    /// Routes a packet. Packets whose receiver has been dropped are
+    /// dropped.
+    pub fn push(&mut self, packet: TracePacket) {
+        if let TracePacket::Instrumentation { port, payload } = &packet {
+            if let Some(tx) = self.ports.get(port) {
+                let _ = tx.send(*payload);
+                return;
+            }
+        }
+        if let Some(tx) = &self.rest {
+            let _ = tx.send(packet);
+        }
+    }
+
+    /// Routes all packets of the given stream, e.g.
+    /// [`Decoder::singles`](crate::Decoder::singles) or
+    /// [`decode_all`](crate::decode_all), until it ends or yields an
+    /// error. The receivers are disconnected when done.
+    pub fn run<I>(mut self, packets: I) -> Result<(), DecoderError>
+    where
+        I: IntoIterator<Item = Result<TracePacket, DecoderError>>,
+    {
+        for packet in packets {
+            self.push(packet?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn demux() {
+        let mut demux = Demux::new();
+        let console = demux.port(0);
+        let telemetry = demux.port(1);
+
+        #[rustfmt::skip]
+        let stream = [
+            // Port 0, 2-byte payload
+            0b0000_0010, b'h', b'i',
+            // Port 1, 4-byte payload
+            0b0000_1011, 0x01, 0x02, 0x03, 0x04,
+            // Port 2, 1-byte payload
+            0b0001_0001, 0xff,
+            // Port 0, 1-byte payload
+            0b0000_0001, b'\n',
+        ];
+        let decoder = thread::spawn(move || demux.run(crate::decode_all(&stream)));
+
+        let console: Vec<Payload> = console.iter().collect();
+        let telemetry: Vec<Payload> = telemetry.iter().collect();
+        decoder.join().unwrap().unwrap();
+        assert_eq!(console, [(*b"hi").into(), [b'\n'].into()]);
+        assert_eq!(telemetry, [[1, 2, 3, 4].into()]);
+    }
+}
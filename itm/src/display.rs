@@ -0,0 +1,220 @@
+//! Human-readable representation of [`TracePacket`]s.
+
+use super::{ExceptionAction, ExceptionType, MemoryAccessType, TimestampDataRelation, TracePacket};
+
+use core::fmt;
+
+/// Writes bytes as space-separated hexadecimal numbers.
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            f.write_str(" ")?;
+        }
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+/// Writes a little-endian value as a hexadecimal number.
+fn write_le(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    f.write_str("0x")?;
+    for b in bytes.iter().rev() {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+/// Writes a one-line description of the packet, e.g. `Exception SysTick
+/// entered` or `Instrumentation port 1: 41 42`. Multi-byte values are
+/// written as hexadecimal numbers.
+impl fmt::Display for TracePacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TracePacket::Sync => f.write_str("Synchronization"),
+            TracePacket::Overflow => f.write_str("Overflow"),
+            TracePacket::LocalTimestamp1 { ts, data_relation } => write!(
+                f,
+                "Local timestamp {} ({})",
+                ts,
+                match data_relation {
+                    TimestampDataRelation::Sync => "synchronous",
+                    TimestampDataRelation::UnknownDelay => "timestamp delayed",
+                    TimestampDataRelation::AssocEventDelay => "packet delayed",
+                    TimestampDataRelation::UnknownAssocEventDelay => {
+                        "timestamp and packet delayed"
+                    }
+                }
+            ),
+            TracePacket::LocalTimestamp2 { ts } => write!(f, "Local timestamp {}", ts),
+            TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+                write!(f, "Global timestamp bits[25:0] {}", ts)?;
+                if *wrap {
+                    f.write_str(", high-order bits changed")?;
+                }
+                if *clkch {
+                    f.write_str(", clock changed")?;
+                }
+                Ok(())
+            }
+            TracePacket::GlobalTimestamp2 { ts } => {
+                write!(f, "Global timestamp bits[63:26] {}", ts)
+            }
+            TracePacket::Extension { page } => write!(f, "Stimulus port page {}", page),
+            TracePacket::Instrumentation { port, payload } => {
+                write!(f, "Instrumentation port {}: ", port)?;
+                write_hex(f, payload)
+            }
+            TracePacket::EventCounterWrap { .. } => {
+                f.write_str("Event counter wrap:")?;
+                for (name, _) in self.counter_wrap().unwrap().iter_names() {
+                    write!(f, " {}", name)?;
+                }
+                Ok(())
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                write!(
+                    f,
+                    "Exception {} {}",
+                    ExceptionType::from(*exception),
+                    match action {
+                        ExceptionAction::Entered => "entered",
+                        ExceptionAction::Exited => "exited",
+                        ExceptionAction::Returned => "returned to",
+                    }
+                )
+            }
+            TracePacket::PCSample { pc: None } => f.write_str("PC sample: sleeping"),
+            TracePacket::PCSample { pc: Some(pc) } => write!(f, "PC sample {:#010x}", pc),
+            TracePacket::DataTracePC { comparator, pc } => {
+                write!(f, "Comparator {} matched PC {:#010x}", comparator, pc)
+            }
+            TracePacket::DataTraceAddress { comparator, data } => {
+                write!(f, "Comparator {} matched address ", comparator)?;
+                write_le(f, data)
+            }
+            TracePacket::DataTraceMatch { comparator } => {
+                write!(f, "Comparator {} matched", comparator)
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => {
+                write!(
+                    f,
+                    "Comparator {} matched {} of ",
+                    comparator,
+                    match access_type {
+                        MemoryAccessType::Read => "read",
+                        MemoryAccessType::Write => "write",
+                    }
+                )?;
+                write_le(f, value)
+            }
+            TracePacket::Unknown { header, payload } => {
+                write!(f, "Unknown packet: {:02x}", header)?;
+                for b in payload {
+                    write!(f, " {:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TracePacket {
+    /// Short description of the packet kind, with a reference to its
+    /// specification in the ARMv7-M Architecture Reference Manual.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TracePacket::Sync => "Synchronization packet (Appendix D4.2.1)",
+            TracePacket::Overflow => "Overflow packet (Appendix D4.2.3)",
+            TracePacket::LocalTimestamp1 { .. } => {
+                "Local timestamp packet, format 1 (Appendix D4.2.4)"
+            }
+            TracePacket::LocalTimestamp2 { .. } => {
+                "Local timestamp packet, format 2 (Appendix D4.2.4)"
+            }
+            TracePacket::GlobalTimestamp1 { .. } => {
+                "Global timestamp packet, format 1 (Appendix D4.2.5)"
+            }
+            TracePacket::GlobalTimestamp2 { .. } => {
+                "Global timestamp packet, format 2 (Appendix D4.2.5)"
+            }
+            TracePacket::Extension { .. } => "Extension packet (Appendix D4.2.6)",
+            TracePacket::Instrumentation { .. } => "Instrumentation packet (Appendix D4.2.8)",
+            TracePacket::EventCounterWrap { .. } => "Event counter packet (Appendix D4.3.1)",
+            TracePacket::ExceptionTrace { .. } => "Exception trace packet (Appendix D4.3.2)",
+            TracePacket::PCSample { .. } => "Periodic PC sample packet (Appendix D4.3.3)",
+            TracePacket::DataTracePC { .. } => "Data trace PC value packet (Appendix D4.3.4)",
+            TracePacket::DataTraceAddress { .. } => "Data trace address packet (Appendix D4.3.4)",
+            TracePacket::DataTraceMatch { .. } => "Data trace match packet (ARMv8-M only)",
+            TracePacket::DataTraceValue { .. } => "Data trace data value packet (Appendix D4.3.4)",
+            TracePacket::Unknown { .. } => "Malformed packet",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectActive;
+    use alloc::string::ToString;
+
+    #[test]
+    fn display() {
+        let cases = [
+            (
+                TracePacket::ExceptionTrace {
+                    exception: VectActive::from(15).unwrap(),
+                    action: ExceptionAction::Entered,
+                },
+                "Exception SysTick entered",
+            ),
+            (
+                TracePacket::ExceptionTrace {
+                    exception: VectActive::from(16 + 11).unwrap(),
+                    action: ExceptionAction::Returned,
+                },
+                "Exception IRQ11 returned to",
+            ),
+            (
+                TracePacket::Instrumentation {
+                    port: 1,
+                    payload: [0x41, 0x42].into(),
+                },
+                "Instrumentation port 1: 41 42",
+            ),
+            (
+                TracePacket::DataTraceValue {
+                    comparator: 2,
+                    access_type: MemoryAccessType::Write,
+                    value: [0xef, 0xbe].into(),
+                },
+                "Comparator 2 matched write of 0xbeef",
+            ),
+            (
+                TracePacket::EventCounterWrap {
+                    cyc: true,
+                    fold: false,
+                    lsu: false,
+                    sleep: true,
+                    exc: false,
+                    cpi: false,
+                },
+                "Event counter wrap: CYC SLEEP",
+            ),
+            (
+                TracePacket::GlobalTimestamp1 {
+                    ts: 42,
+                    wrap: true,
+                    clkch: false,
+                },
+                "Global timestamp bits[25:0] 42, high-order bits changed",
+            ),
+        ];
+        for (packet, s) in cases.iter() {
+            assert_eq!(packet.to_string(), *s);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Decoding of trace data from emulators, e.g. QEMU's ARMv7-M ITM
+//! emulation or Renode.
+//!
+//! Emulated trace differs from that of hardware in that its timing
+//! bears no relation to that of a real target: local and global
+//! timestamp packets are missing or meaningless. [`HostTimestamps`]
+//! instead timestamps packets with the host time at which they
+//! arrived, relative to the arrival of the first data, so that traces
+//! of emulated test runs can be placed on a timeline nonetheless.
+//!
+//! Emulators may also emit synchronization packets that are shorter
+//! than the architecture requires, which the decoder accepts if
+//! [`lenient_sync`](crate::DecoderOptions::lenient_sync) is set.
+
+use crate::latency::ArrivalReader;
+use crate::{
+    Decoder, DecoderError, DecoderErrorInt, OverflowGap, Ticks, Timestamp, TimestampedTracePackets,
+    TracePacket, Window,
+};
+use std::io::Read;
+use std::ops::RangeBounds;
+use std::time::{Duration, Instant};
+
+/// Frequency of the [`Ticks`](Ticks) of host timestamps, which count
+/// nanoseconds.
+const NANOS_PER_SEC: u32 = 1_000_000_000;
+
+/// Iterator that yields each decoded packet in a
+/// [`TimestampedTracePackets`](TimestampedTracePackets) of its own, with
+/// a [`Sync`](Timestamp::Sync) timestamp of the host time at which the
+/// data that completed it arrived. See the [module
+/// documentation](self).
+///
+/// Timestamp packets are dropped, but counted as
+/// [consumed](TimestampedTracePackets::consumed_packets) by the next
+/// set. Malformed packets do not end iteration, but are yielded as the
+/// [`malformed_packets`](TimestampedTracePackets::malformed_packets) of
+/// a set without packets. An [`Overflow`](TracePacket::Overflow) is
+/// reported as a gap between the arrival of the previous set and its
+/// own.
+///
+/// ```
+/// use itm::emulator::HostTimestamps;
+/// use itm::latency::ArrivalReader;
+/// use itm::{Decoder, DecoderOptions, TracePacket};
+///
+/// // LTS1 packet (1 tick), instrumentation packet (port 1, 1-byte payload)
+/// let stream: &[u8] = &[0b1100_0000, 1, 0b0000_1001, 0x41];
+/// let decoder = Decoder::new(ArrivalReader::new(stream), DecoderOptions::default());
+/// let sets: Vec<_> = HostTimestamps::new(decoder)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+///
+/// assert_eq!(sets.len(), 1);
+/// assert_eq!(
+///     sets[0].packets,
+///     [TracePacket::Instrumentation {
+///         port: 1,
+///         payload: [0x41].into(),
+///     }]
+/// );
+/// assert_eq!(sets[0].consumed_packets, 2);
+/// ```
+pub struct HostTimestamps<R>
+where
+    R: Read,
+{
+    decoder: Decoder<ArrivalReader<R>>,
+
+    /// Host time at which the first data arrived.
+    epoch: Option<Instant>,
+
+    /// Offset of the previously yielded set.
+    previous: Duration,
+
+    /// Packets consumed since the previously yielded set.
+    consumed_packets: usize,
+}
+
+impl<R> HostTimestamps<R>
+where
+    R: Read,
+{
+    pub fn new(decoder: Decoder<ArrivalReader<R>>) -> Self {
+        Self {
+            decoder,
+            epoch: None,
+            previous: Duration::ZERO,
+            consumed_packets: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying [`ArrivalReader`].
+    pub fn get_ref(&self) -> &ArrivalReader<R> {
+        self.decoder.get_ref()
+    }
+
+    /// Restricts the yielded sets to those whose
+    /// [offset](Timestamp::offset) is within `range`. See
+    /// [`Window`](Window).
+    pub fn window<B: RangeBounds<Duration>>(self, range: B) -> Window<Self> {
+        Window::new(self, range)
+    }
+
+    /// Offset of the host time at which the latest data arrived from
+    /// that of the first data.
+    fn arrival(&mut self) -> Duration {
+        let arrival = self
+            .decoder
+            .get_ref()
+            .last_arrival()
+            .unwrap_or_else(Instant::now);
+        arrival.saturating_duration_since(*self.epoch.get_or_insert(arrival))
+    }
+
+    fn set(&mut self, packet: Option<TracePacket>) -> TimestampedTracePackets {
+        // Data may arrive in chunks, so later data need not have
+        // arrived later.
+        let offset = self.arrival().max(self.previous);
+        let set = TimestampedTracePackets {
+            timestamp: Timestamp::Sync(offset),
+            ticks: Ticks {
+                ticks: offset.as_nanos() as u64,
+                frequency: NANOS_PER_SEC,
+            },
+            delta: offset - self.previous,
+            overflow: match packet {
+                Some(TracePacket::Overflow) => Some(OverflowGap {
+                    start: self.previous,
+                    end: offset,
+                }),
+                _ => None,
+            },
+            packets: packet.into_iter().collect(),
+            malformed_packets: vec![],
+            consumed_packets: std::mem::take(&mut self.consumed_packets),
+        };
+        self.previous = offset;
+        set
+    }
+}
+
+impl<R> Iterator for HostTimestamps<R>
+where
+    R: Read,
+{
+    type Item = Result<TimestampedTracePackets, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let packet = self.decoder.next_single();
+            if let Ok(_) | Err(DecoderErrorInt::MalformedPacket(_)) = packet {
+                self.consumed_packets += 1;
+            }
+            match packet {
+                Ok(
+                    TracePacket::LocalTimestamp1 { .. }
+                    | TracePacket::LocalTimestamp2 { .. }
+                    | TracePacket::GlobalTimestamp1 { .. }
+                    | TracePacket::GlobalTimestamp2 { .. },
+                ) => continue,
+                Ok(packet) => return Some(Ok(self.set(Some(packet)))),
+                Err(DecoderErrorInt::MalformedPacket(malformed)) => {
+                    let mut set = self.set(None);
+                    set.malformed_packets.push(malformed);
+                    return Some(Ok(set));
+                }
+                Err(e) => return e.into_public().map(Err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecoderOptions, MalformedPacket};
+
+    #[test]
+    fn lenient() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Sync of 31 zeros
+            0, 0, 0, 0b1000_0000,
+            // Invalid hardware source packet
+            0b1111_1111,
+            // GTS1
+            0b1001_0100, 0b0000_0001,
+            // Overflow
+            0b0111_0000,
+        ];
+        let decoder = Decoder::new(
+            ArrivalReader::new(stream),
+            DecoderOptions {
+                lenient_sync: true,
+                ..Default::default()
+            },
+        );
+        let sets: Vec<_> = HostTimestamps::new(decoder)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[0].packets, [TracePacket::Sync]);
+        assert!(matches!(
+            sets[1].malformed_packets[..],
+            [MalformedPacket::InvalidHardwareDisc { disc_id: 31, .. }]
+        ));
+        assert_eq!(sets[2].packets, [TracePacket::Overflow]);
+        assert_eq!(sets[2].consumed_packets, 2);
+        assert!(sets[2].overflow.is_some());
+        assert!(sets
+            .windows(2)
+            .all(|sets| sets[0].timestamp.offset() <= sets[1].timestamp.offset()));
+    }
+}
@@ -0,0 +1,280 @@
+//! Encoding of [`TracePacket`](TracePacket)s into the ITM/DWT packet
+//! protocol.
+
+use super::{
+    exception_number, ExceptionAction, MemoryAccessType, TimestampDataRelation, TracePacket,
+};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A packet that cannot be represented in the ITM/DWT packet protocol.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum EncodeError {
+    /// A field value does not fit in its encoding.
+    #[cfg_attr(feature = "std", error("{field} value {value} cannot be encoded"))]
+    OutOfRange {
+        /// Name of the field.
+        field: &'static str,
+
+        /// The value of the field.
+        value: u64,
+    },
+
+    /// The payload size is invalid for the packet.
+    #[cfg_attr(feature = "std", error("Payload size {0} is invalid for this packet"))]
+    InvalidPayloadSize(usize),
+}
+
+fn check(field: &'static str, value: u64, max: u64) -> Result<(), EncodeError> {
+    if value > max {
+        return Err(EncodeError::OutOfRange { field, value });
+    }
+    Ok(())
+}
+
+/// Encodes a source packet payload size. (Appendix D4.2.8, Table D4-4)
+fn encode_ss(size: usize) -> Result<u8, EncodeError> {
+    match size {
+        1 => Ok(0b01),
+        2 => Ok(0b10),
+        4 => Ok(0b11),
+        _ => Err(EncodeError::InvalidPayloadSize(size)),
+    }
+}
+
+/// Encodes `ts` as a payload of `len` bytes with continuation bits set
+/// on all but the last byte.
+fn encode_timestamp(ts: u64, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            let b = ((ts >> (7 * i)) & 0x7f) as u8;
+            if i + 1 < len {
+                b | 0x80
+            } else {
+                b
+            }
+        })
+        .collect()
+}
+
+fn hardware_source(disc_id: u8, payload: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    let mut bytes = vec![(disc_id << 3) | 0b100 | encode_ss(payload.len())?];
+    bytes.extend_from_slice(payload);
+    Ok(bytes)
+}
+
+impl TracePacket {
+    /// Encodes the packet into the exact byte sequence that
+    /// [`Decoder`](crate::Decoder) decodes it from. Variable-length
+    /// timestamps are encoded in as few bytes as possible, except for
+    /// [`GlobalTimestamp1`](TracePacket::GlobalTimestamp1), which is
+    /// always encoded in full.
+    ///
+    /// Packets that only exist on ARMv8-M are encoded as such, and are
+    /// only decoded with [`ArchVersion::V8M`](crate::ArchVersion::V8M).
+    /// An [`Unknown`](TracePacket::Unknown) packet is encoded as its
+    /// bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodeError> {
+        match self {
+            // 47 zeros followed by a one
+            TracePacket::Sync => Ok(vec![0, 0, 0, 0, 0, 0b1000_0000]),
+            TracePacket::Overflow => Ok(vec![0b0111_0000]),
+            TracePacket::LocalTimestamp1 { ts, data_relation } => {
+                check("LocalTimestamp1 ts", (*ts).into(), (1 << 28) - 1)?;
+                let len = (1..4).find(|n| *ts >> (7 * n) == 0).unwrap_or(4);
+                let tc = match data_relation {
+                    TimestampDataRelation::Sync => 0b00,
+                    TimestampDataRelation::UnknownDelay => 0b01,
+                    TimestampDataRelation::AssocEventDelay => 0b10,
+                    TimestampDataRelation::UnknownAssocEventDelay => 0b11,
+                };
+                let mut bytes = vec![0b1100_0000 | (tc << 4)];
+                bytes.extend(encode_timestamp((*ts).into(), len));
+                Ok(bytes)
+            }
+            TracePacket::LocalTimestamp2 { ts } => {
+                if !(1..=6).contains(ts) {
+                    return Err(EncodeError::OutOfRange {
+                        field: "LocalTimestamp2 ts",
+                        value: (*ts).into(),
+                    });
+                }
+                Ok(vec![ts << 4])
+            }
+            TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+                check("GlobalTimestamp1 ts", *ts, (1 << 26) - 1)?;
+                let mut bytes = vec![0b1001_0100];
+                bytes.extend(encode_timestamp(*ts, 4));
+                bytes[4] |= ((*wrap as u8) << 6) | ((*clkch as u8) << 5);
+                Ok(bytes)
+            }
+            TracePacket::GlobalTimestamp2 { ts } => {
+                check("GlobalTimestamp2 ts", *ts, (1 << 38) - 1)?;
+                // bits[47:26] or bits[63:26]
+                let len = if *ts >> 22 == 0 { 4 } else { 6 };
+                let mut bytes = vec![0b1011_0100];
+                bytes.extend(encode_timestamp(*ts, len));
+                Ok(bytes)
+            }
+            TracePacket::Extension { page } if *page <= 0b111 => Ok(vec![(page << 4) | 0b1000]),
+            TracePacket::Extension { page } => {
+                // ARMv8-M: page number continues into the payload
+                let mut bytes = vec![0b1000_1000 | ((page & 0b111) << 4)];
+                bytes.extend(encode_timestamp((page >> 3).into(), 1));
+                Ok(bytes)
+            }
+            TracePacket::Instrumentation { port, payload } => {
+                check("Instrumentation port", (*port).into(), 31)?;
+                let mut bytes = vec![(port << 3) | encode_ss(payload.len())?];
+                bytes.extend_from_slice(payload);
+                Ok(bytes)
+            }
+            TracePacket::EventCounterWrap { .. } => {
+                hardware_source(0, &[self.counter_wrap().unwrap().bits()])
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                let number = exception_number(exception);
+                let function = match action {
+                    ExceptionAction::Entered => 0b01,
+                    ExceptionAction::Exited => 0b10,
+                    ExceptionAction::Returned => 0b11,
+                };
+                hardware_source(
+                    1,
+                    &[number as u8, (function << 4) | ((number >> 8) as u8 & 1)],
+                )
+            }
+            TracePacket::PCSample { pc: None } => hardware_source(2, &[0]),
+            TracePacket::PCSample { pc: Some(pc) } => hardware_source(2, &pc.to_le_bytes()),
+            TracePacket::DataTracePC { comparator, pc } => {
+                check("DataTracePC comparator", (*comparator).into(), 3)?;
+                hardware_source(0b0_1000 | (comparator << 1), &pc.to_le_bytes())
+            }
+            TracePacket::DataTraceAddress { comparator, data } => {
+                check("DataTraceAddress comparator", (*comparator).into(), 3)?;
+                if data.len() != 2 && data.len() != 4 {
+                    return Err(EncodeError::InvalidPayloadSize(data.len()));
+                }
+                hardware_source(0b0_1001 | (comparator << 1), data)
+            }
+            TracePacket::DataTraceMatch { comparator } => {
+                check("DataTraceMatch comparator", (*comparator).into(), 3)?;
+                hardware_source(0b0_1000 | (comparator << 1), &[1])
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => {
+                check("DataTraceValue comparator", (*comparator).into(), 3)?;
+                let d = match access_type {
+                    MemoryAccessType::Read => 0,
+                    MemoryAccessType::Write => 1,
+                };
+                hardware_source(0b1_0000 | (comparator << 1) | d, value)
+            }
+            TracePacket::Unknown { header, payload } => {
+                let mut bytes = vec![*header];
+                bytes.extend_from_slice(payload);
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecoderOptions, PacketDecoder, VectActive};
+
+    #[test]
+    fn round_trip() {
+        let packets = [
+            TracePacket::Sync,
+            TracePacket::Overflow,
+            TracePacket::LocalTimestamp1 {
+                ts: 0,
+                data_relation: TimestampDataRelation::Sync,
+            },
+            TracePacket::LocalTimestamp1 {
+                ts: (1 << 28) - 1,
+                data_relation: TimestampDataRelation::UnknownAssocEventDelay,
+            },
+            TracePacket::LocalTimestamp2 { ts: 6 },
+            TracePacket::GlobalTimestamp1 {
+                ts: 0b00000_0000100_0100000_0000000,
+                wrap: true,
+                clkch: false,
+            },
+            TracePacket::GlobalTimestamp2 {
+                ts: 0b1_0010001_1110100_0111101,
+            },
+            TracePacket::GlobalTimestamp2 {
+                ts: 0b111_1110100_0000001_0010001_1110100_0111101,
+            },
+            TracePacket::Extension { page: 5 },
+            TracePacket::Instrumentation {
+                port: 31,
+                payload: [1, 2].into(),
+            },
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: true,
+                sleep: false,
+                exc: true,
+                cpi: false,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::from(16 + 300).unwrap(),
+                action: ExceptionAction::Returned,
+            },
+            TracePacket::PCSample { pc: None },
+            TracePacket::PCSample {
+                pc: Some(0x0800_1234),
+            },
+            TracePacket::DataTracePC {
+                comparator: 2,
+                pc: 0x0800_1234,
+            },
+            TracePacket::DataTraceAddress {
+                comparator: 1,
+                data: [0xbe, 0xef].into(),
+            },
+            TracePacket::DataTraceValue {
+                comparator: 3,
+                access_type: MemoryAccessType::Write,
+                value: [0xde, 0xad, 0xbe, 0xef].into(),
+            },
+        ];
+
+        let stream: Vec<u8> = packets.iter().flat_map(|p| p.encode().unwrap()).collect();
+        let mut decoder = PacketDecoder::new(DecoderOptions::default());
+        decoder.feed(&stream);
+        for packet in packets.iter() {
+            assert_eq!(decoder.pull().unwrap().as_ref(), Some(packet));
+        }
+        assert_eq!(decoder.pull().unwrap(), None);
+    }
+
+    #[test]
+    fn unencodable() {
+        assert_eq!(
+            TracePacket::LocalTimestamp2 { ts: 7 }.encode(),
+            Err(EncodeError::OutOfRange {
+                field: "LocalTimestamp2 ts",
+                value: 7
+            })
+        );
+        assert_eq!(
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: [1, 2, 3].into()
+            }
+            .encode(),
+            Err(EncodeError::InvalidPayloadSize(3))
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! High-level events layered above [`TracePacket`](TracePacket)s.
+//!
+//! Applications are seldom interested in the raw protocol packets
+//! themselves, but rather in what the target meant to convey: a line of
+//! text written to a stimulus port, an update of a watched variable, or
+//! the time spent in an exception handler. [`Events`](Events)
+//! reassembles such [`ItmEvent`](ItmEvent)s from a stream of
+//! [`TimestampedTracePackets`](TimestampedTracePackets).
+
+use super::cobs::CobsDecoder;
+use super::{
+    DecoderError, ExceptionAction, LineSplitter, LinesOptions, MemoryAccessType, Payload,
+    Timestamp, TimestampedTracePackets, TracePacket, VectActive,
+};
+
+use std::collections::VecDeque;
+
+/// A semantically meaningful event reconstructed from one or more
+/// [`TracePacket`](TracePacket)s.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ItmEvent {
+    /// A complete line of text written to a stimulus port.
+    Text(ItmText),
+
+    /// A complete binary frame written to a stimulus port.
+    Frame(ItmFrame),
+
+    /// A DWT comparator traced a data value.
+    VariableUpdate(VariableUpdate),
+
+    /// The processor entered and later exited an exception handler.
+    ExceptionSpan(ExceptionSpan),
+}
+
+/// A line of text written to a stimulus port, without its terminating
+/// newline.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItmText {
+    /// Stimulus port the text was written to.
+    pub port: u8,
+
+    /// The text itself. Invalid UTF-8 sequences are replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER`.
+    pub text: String,
+
+    /// Timestamp of the packet that completed the line.
+    pub timestamp: Timestamp,
+}
+
+/// A binary frame written to a stimulus port, as reassembled by e.g.
+/// [`CobsFrames`](crate::cobs::CobsFrames).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItmFrame {
+    /// Stimulus port the frame was written to.
+    pub port: u8,
+
+    /// Frame content, without any framing bytes.
+    pub data: Vec<u8>,
+
+    /// Timestamp of the packet that completed the frame.
+    pub timestamp: Timestamp,
+}
+
+/// A data value traced by a DWT comparator.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VariableUpdate {
+    /// The comparator that traced the value.
+    pub comparator: u8,
+
+    /// Whether the value was read or written.
+    pub access_type: MemoryAccessType,
+
+    /// The data value. MSB, BE.
+    pub value: Payload,
+
+    /// Timestamp of the access.
+    pub timestamp: Timestamp,
+}
+
+/// The execution of an exception handler, from entry to exit.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExceptionSpan {
+    /// The exception that was handled.
+    pub exception: VectActive,
+
+    /// When the exception was entered.
+    pub entered: Timestamp,
+
+    /// When the exception was exited.
+    pub exited: Timestamp,
+}
+
+/// Iterator adapter that yield [`ItmEvent`](ItmEvent)s from an iterator
+/// over [`TimestampedTracePackets`](TimestampedTracePackets), e.g.
+/// [`Timestamps`](super::Timestamps).
+///
+/// The following events are generated:
+/// - [`Text`](ItmEvent::Text) for each newline-terminated sequence of
+///   [`Instrumentation`](TracePacket::Instrumentation) payloads on a
+///   port, as split by a [`LineSplitter`](LineSplitter);
+/// - [`Frame`](ItmEvent::Frame) for each COBS frame written to one of
+///   the [`frames`](Self::frames) ports, as reassembled by a
+///   [`CobsDecoder`](CobsDecoder). Malformed frames are discarded;
+/// - [`VariableUpdate`](ItmEvent::VariableUpdate) for each
+///   [`DataTraceValue`](TracePacket::DataTraceValue);
+/// - [`ExceptionSpan`](ItmEvent::ExceptionSpan) for each
+///   [`ExceptionTrace`](TracePacket::ExceptionTrace) exit that matches a
+///   previous entry.
+pub struct Events<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    inner: I,
+    lines: LineSplitter,
+    frames: CobsDecoder,
+    entered: Vec<(VectActive, Timestamp)>,
+    pending: VecDeque<ItmEvent>,
+}
+
+impl<I> Events<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    /// Generates events from `inner`. All stimulus ports carry text,
+    /// unless set otherwise with [`frames`](Self::frames).
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            lines: LineSplitter::new(LinesOptions::default()),
+            frames: CobsDecoder::new(std::iter::empty()),
+            entered: vec![],
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Reassembles COBS frames, instead of lines of text, from the
+    /// payloads written to `ports`.
+    pub fn frames<P: IntoIterator<Item = u8>>(mut self, ports: P) -> Self {
+        self.frames = CobsDecoder::new(ports);
+        self
+    }
+
+    fn process(&mut self, packet: TracePacket, timestamp: &Timestamp) {
+        if self.frames.push(&packet) {
+            while let Some(frame) = self.frames.pull() {
+                if let Ok((port, data)) = frame {
+                    self.pending.push_back(ItmEvent::Frame(ItmFrame {
+                        port,
+                        data,
+                        timestamp: timestamp.clone(),
+                    }));
+                }
+            }
+            return;
+        }
+
+        match packet {
+            TracePacket::Instrumentation { port, payload } => {
+                self.lines.push(port, &payload);
+                while let Some(Ok(line)) = self.lines.pull() {
+                    self.pending.push_back(ItmEvent::Text(ItmText {
+                        port: line.port,
+                        text: line.text,
+                        timestamp: timestamp.clone(),
+                    }));
+                }
+            }
+            TracePacket::Overflow => self.frames.reset(),
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => self
+                .pending
+                .push_back(ItmEvent::VariableUpdate(VariableUpdate {
+                    comparator,
+                    access_type,
+                    value,
+                    timestamp: timestamp.clone(),
+                })),
+            TracePacket::ExceptionTrace {
+                exception,
+                action: ExceptionAction::Entered,
+            } => self.entered.push((exception, timestamp.clone())),
+            TracePacket::ExceptionTrace {
+                exception,
+                action: ExceptionAction::Exited,
+            } => {
+                if let Some(i) = self.entered.iter().rposition(|(e, _)| *e == exception) {
+                    let (exception, entered) = self.entered.remove(i);
+                    self.pending
+                        .push_back(ItmEvent::ExceptionSpan(ExceptionSpan {
+                            exception,
+                            entered,
+                            exited: timestamp.clone(),
+                        }));
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<I> Iterator for Events<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    type Item = Result<ItmEvent, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            match self.inner.next()? {
+                Err(e) => return Some(Err(e)),
+                Ok(set) => {
+                    for packet in set.packets {
+                        self.process(packet, &set.timestamp);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ticks;
+    use std::time::Duration;
+
+    fn set(ts: u64, packets: Vec<TracePacket>) -> Result<TimestampedTracePackets, DecoderError> {
+        Ok(TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_nanos(ts)),
+            packets,
+            malformed_packets: vec![],
+            ticks: Ticks {
+                ticks: 0,
+                frequency: 1,
+            },
+            consumed_packets: 0,
+            overflow: None,
+            delta: Duration::ZERO,
+        })
+    }
+
+    #[test]
+    fn events() {
+        let irq = VectActive::Interrupt { irqn: 3 };
+        let sets = vec![
+            set(
+                1,
+                vec![
+                    TracePacket::Instrumentation {
+                        port: 0,
+                        payload: (*b"he").into(),
+                    },
+                    TracePacket::ExceptionTrace {
+                        exception: irq,
+                        action: ExceptionAction::Entered,
+                    },
+                    // A partial frame that is discarded on overflow
+                    TracePacket::Instrumentation {
+                        port: 1,
+                        payload: [0x03, 0x11].into(),
+                    },
+                    TracePacket::Overflow,
+                    TracePacket::Instrumentation {
+                        port: 1,
+                        payload: [0x03, 0x11, 0x22, 0x02].into(),
+                    },
+                ],
+            ),
+            set(
+                2,
+                vec![
+                    TracePacket::Instrumentation {
+                        port: 0,
+                        payload: (*b"y\nyo").into(),
+                    },
+                    TracePacket::ExceptionTrace {
+                        exception: irq,
+                        action: ExceptionAction::Exited,
+                    },
+                    TracePacket::DataTraceValue {
+                        comparator: 1,
+                        access_type: MemoryAccessType::Write,
+                        value: [0x2a].into(),
+                    },
+                ],
+            ),
+            set(
+                3,
+                vec![
+                    // An unmatched exit
+                    TracePacket::ExceptionTrace {
+                        exception: irq,
+                        action: ExceptionAction::Exited,
+                    },
+                    TracePacket::Instrumentation {
+                        port: 1,
+                        payload: [0x33, 0x00, 0x04, 0x00].into(),
+                    },
+                ],
+            ),
+        ];
+
+        let events: Vec<ItmEvent> = Events::new(sets.into_iter())
+            .frames([1])
+            .map(|e| e.unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ItmEvent::Text(ItmText {
+                    port: 0,
+                    text: "hey".to_string(),
+                    timestamp: Timestamp::Sync(Duration::from_nanos(2)),
+                }),
+                ItmEvent::ExceptionSpan(ExceptionSpan {
+                    exception: irq,
+                    entered: Timestamp::Sync(Duration::from_nanos(1)),
+                    exited: Timestamp::Sync(Duration::from_nanos(2)),
+                }),
+                ItmEvent::VariableUpdate(VariableUpdate {
+                    comparator: 1,
+                    access_type: MemoryAccessType::Write,
+                    value: [0x2a].into(),
+                    timestamp: Timestamp::Sync(Duration::from_nanos(2)),
+                }),
+                ItmEvent::Frame(ItmFrame {
+                    port: 1,
+                    data: vec![0x11, 0x22, 0x00, 0x33],
+                    timestamp: Timestamp::Sync(Duration::from_nanos(3)),
+                }),
+            ]
+        );
+    }
+}
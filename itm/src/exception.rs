@@ -0,0 +1,128 @@
+//! Exception numbers and names.
+
+use super::{exception_number, VectActive};
+
+use core::convert::TryFrom;
+use core::fmt;
+
+/// CMSIS names of the exceptions that are not external interrupts, by
+/// exception number. (Table B1-4)
+const NAMES: [(u16, &str); 11] = [
+    (0, "ThreadMode"),
+    (2, "NonMaskableInt"),
+    (3, "HardFault"),
+    (4, "MemoryManagement"),
+    (5, "BusFault"),
+    (6, "UsageFault"),
+    (7, "SecureFault"),
+    (11, "SVCall"),
+    (12, "DebugMonitor"),
+    (14, "PendSV"),
+    (15, "SysTick"),
+];
+
+/// An exception, or thread mode, identified by its exception number.
+/// Converts from and into exception numbers and
+/// [`VectActive`](VectActive), and displays as its CMSIS name, e.g.
+/// `SysTick` or, for external interrupt 17, `IRQ17`.
+///
+/// ```
+/// use itm::ExceptionType;
+/// use std::convert::TryFrom;
+///
+/// let systick = ExceptionType::try_from(15).unwrap();
+/// assert_eq!(systick.to_string(), "SysTick");
+/// assert_eq!(ExceptionType::try_from(16 + 17).unwrap().to_string(), "IRQ17");
+/// assert_eq!(u16::from(systick), 15);
+/// assert!(ExceptionType::try_from(1).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExceptionType(u16);
+
+impl ExceptionType {
+    /// The external interrupt number, if the exception is an external
+    /// interrupt.
+    pub fn irqn(&self) -> Option<u16> {
+        self.0.checked_sub(16)
+    }
+
+    /// Resolves an exception name as displayed, i.e. a CMSIS exception
+    /// name, or `IRQ<n>` for external interrupt `n`.
+    pub fn from_name(name: &str) -> Option<ExceptionType> {
+        if let Some(irqn) = name.strip_prefix("IRQ") {
+            let irqn: u16 = irqn.parse().ok()?;
+            return ExceptionType::try_from(irqn.checked_add(16)?).ok();
+        }
+        NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(number, _)| ExceptionType(*number))
+    }
+}
+
+/// The number is not that of an exception. (Table B1-4)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+#[cfg_attr(feature = "std", error("{0} is not a valid exception number"))]
+pub struct InvalidExceptionNumber(pub u16);
+
+impl TryFrom<u16> for ExceptionType {
+    type Error = InvalidExceptionNumber;
+
+    fn try_from(number: u16) -> Result<Self, Self::Error> {
+        match VectActive::from(number) {
+            Some(_) => Ok(ExceptionType(number)),
+            None => Err(InvalidExceptionNumber(number)),
+        }
+    }
+}
+
+impl From<ExceptionType> for u16 {
+    fn from(exception: ExceptionType) -> u16 {
+        exception.0
+    }
+}
+
+impl From<VectActive> for ExceptionType {
+    fn from(exception: VectActive) -> Self {
+        ExceptionType(exception_number(&exception))
+    }
+}
+
+impl From<ExceptionType> for VectActive {
+    fn from(exception: ExceptionType) -> Self {
+        VectActive::from(exception.0).unwrap()
+    }
+}
+
+impl fmt::Display for ExceptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.irqn() {
+            Some(irqn) => write!(f, "IRQ{}", irqn),
+            None => f.write_str(NAMES.iter().find(|(n, _)| *n == self.0).unwrap().1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn names() {
+        for number in 0..512 {
+            let exception = match ExceptionType::try_from(number) {
+                Ok(exception) => exception,
+                Err(_) => continue,
+            };
+            let name = exception.to_string();
+            assert_eq!(ExceptionType::from_name(&name), Some(exception));
+            let vect_active: VectActive = exception.into();
+            assert_eq!(ExceptionType::from(vect_active), exception);
+        }
+        assert_eq!(ExceptionType::from_name("IRQ496"), None);
+        assert_eq!(ExceptionType::from_name("Reset"), None);
+    }
+}
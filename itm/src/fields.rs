@@ -0,0 +1,419 @@
+//! Named fields of decoded packets, for tabular output.
+//!
+//! Every output backend that presents packets as rows (e.g. CSV, or
+//! the aligned columns of `itm-decode`) should extract its columns via
+//! [`Field`](Field), so that column names and formatting stay
+//! consistent across formats. [`CsvWriter`] is such a backend.
+
+use super::wall_clock::WallClock;
+use super::{ExceptionType, PortMap, Timestamp, TracePacket};
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// A column of tabular packet output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// Sequence number of the packet in the output. See
+    /// [`Sequenced`](crate::Sequenced).
+    Seq,
+
+    /// Timestamp offset in seconds, relative to trace clock start.
+    /// Empty if the packet is not timestamped.
+    Time,
+
+    /// Timestamp quality. Empty if the packet is not timestamped.
+    Quality,
+
+    /// The packet kind, as returned by [`TracePacket::kind`].
+    Kind,
+
+    /// The stimulus port of an instrumentation packet.
+    Port,
+
+    /// The DWT comparator of a data trace packet.
+    Comparator,
+
+    /// The packet value: a payload, a timestamp, a PC value, etc.
+    Value,
+
+    /// The payload of an instrumentation packet, or the data of a data
+    /// trace packet, in hex.
+    PayloadHex,
+
+    /// The payload of an instrumentation packet as text. Invalid UTF-8
+    /// is replaced.
+    PayloadText,
+}
+
+/// A field name that could not be parsed into a [`Field`](Field).
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Unknown field {0:?}; valid fields are: seq, time (or ts), quality, kind, port, comparator, value, payload_hex, payload_text")]
+pub struct UnknownField(pub String);
+
+impl Field {
+    /// All fields.
+    pub const ALL: [Field; 9] = [
+        Field::Seq,
+        Field::Time,
+        Field::Quality,
+        Field::Kind,
+        Field::Port,
+        Field::Comparator,
+        Field::Value,
+        Field::PayloadHex,
+        Field::PayloadText,
+    ];
+
+    /// The fields that are output by default, in their default order.
+    pub const DEFAULT: [Field; 7] = [
+        Field::Seq,
+        Field::Time,
+        Field::Quality,
+        Field::Kind,
+        Field::Port,
+        Field::Comparator,
+        Field::Value,
+    ];
+
+    /// Name of the field, as used in column headers.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Seq => "seq",
+            Field::Time => "time",
+            Field::Quality => "quality",
+            Field::Kind => "kind",
+            Field::Port => "port",
+            Field::Comparator => "comparator",
+            Field::Value => "value",
+            Field::PayloadHex => "payload_hex",
+            Field::PayloadText => "payload_text",
+        }
+    }
+
+    /// Suggested column width for aligned output.
+    pub fn width(&self) -> usize {
+        match self {
+            Field::Seq => 8,
+            Field::Time => 14,
+            Field::Quality => 25,
+            Field::Kind => 18,
+            Field::Port => 4,
+            Field::Comparator => 10,
+            Field::Value | Field::PayloadHex | Field::PayloadText => 0,
+        }
+    }
+
+    /// Parses a comma-separated list of field names, e.g.
+    /// `"time,kind,port,value"`. `ts` is accepted for `time`.
+    pub fn parse_list(s: &str) -> Result<Vec<Field>, UnknownField> {
+        s.split(',').map(|f| f.trim().parse()).collect()
+    }
+
+    /// Extracts the value of this field from a packet, its sequence
+    /// number, and its (optional) timestamp.
+    pub fn extract(&self, seq: u64, packet: &TracePacket, timestamp: Option<&Timestamp>) -> String {
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|b| format!("{:02x}", b)).collect()
+        }
+
+        match self {
+            Field::Seq => seq.to_string(),
+            Field::Time => timestamp
+                .map(|ts| {
+                    let offset = ts.offset();
+                    format!("{}.{:09}", offset.as_secs(), offset.subsec_nanos())
+                })
+                .unwrap_or_default(),
+            Field::Quality => timestamp
+                .map(|ts| {
+                    match ts {
+                        Timestamp::Sync(_) => "sync",
+                        Timestamp::UnknownDelay { .. } => "unknown-delay",
+                        Timestamp::AssocEventDelay(_) => "assoc-event-delay",
+                        Timestamp::UnknownAssocEventDelay { .. } => "unknown-assoc-event-delay",
+                        Timestamp::Gap { .. } => "gap",
+                    }
+                    .to_string()
+                })
+                .unwrap_or_default(),
+            Field::Kind => packet.kind().to_string(),
+            Field::Port => match packet {
+                TracePacket::Instrumentation { port, .. } => port.to_string(),
+                _ => String::new(),
+            },
+            Field::Comparator => match packet {
+                TracePacket::DataTracePC { comparator, .. }
+                | TracePacket::DataTraceAddress { comparator, .. }
+                | TracePacket::DataTraceMatch { comparator }
+                | TracePacket::DataTraceValue { comparator, .. } => comparator.to_string(),
+                _ => String::new(),
+            },
+            Field::Value => match packet {
+                TracePacket::Sync | TracePacket::Overflow | TracePacket::DataTraceMatch { .. } => {
+                    String::new()
+                }
+                TracePacket::LocalTimestamp1 { ts, .. } => ts.to_string(),
+                TracePacket::LocalTimestamp2 { ts } => ts.to_string(),
+                TracePacket::GlobalTimestamp1 { ts, .. } | TracePacket::GlobalTimestamp2 { ts } => {
+                    ts.to_string()
+                }
+                TracePacket::Extension { page } => page.to_string(),
+                TracePacket::Instrumentation { payload, .. } => hex(payload),
+                TracePacket::EventCounterWrap { .. } => packet
+                    .counter_wrap()
+                    .unwrap()
+                    .iter_names()
+                    .map(|(name, _)| name.to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join("|"),
+                TracePacket::ExceptionTrace { exception, action } => {
+                    format!("{} {:?}", ExceptionType::from(*exception), action)
+                }
+                TracePacket::PCSample { pc: None } => "sleep".to_string(),
+                TracePacket::PCSample { pc: Some(pc) } | TracePacket::DataTracePC { pc, .. } => {
+                    format!("{:#010x}", pc)
+                }
+                TracePacket::DataTraceAddress { data, .. } => hex(data),
+                TracePacket::DataTraceValue { value, .. } => hex(value),
+                TracePacket::Unknown { header, payload } => {
+                    format!("{:02x}{}", header, hex(payload))
+                }
+            },
+            Field::PayloadHex => match packet {
+                TracePacket::Instrumentation { payload, .. } => hex(payload),
+                TracePacket::DataTraceAddress { data, .. } => hex(data),
+                TracePacket::DataTraceValue { value, .. } => hex(value),
+                _ => String::new(),
+            },
+            Field::PayloadText => match packet {
+                TracePacket::Instrumentation { payload, .. } => {
+                    String::from_utf8_lossy(payload).into_owned()
+                }
+                _ => String::new(),
+            },
+        }
+    }
+
+    /// Like [`extract`](Self::extract), but with the stimulus port of an
+    /// instrumentation packet presented by its name in `ports`, if any.
+    pub fn extract_named(
+        &self,
+        seq: u64,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+        ports: &PortMap,
+    ) -> String {
+        match (self, packet.port()) {
+            (Field::Port, Some(port)) => ports.label(port),
+            _ => self.extract(seq, packet, timestamp),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Field {
+    type Err = UnknownField;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "ts" {
+            return Ok(Field::Time);
+        }
+        Field::ALL
+            .iter()
+            .find(|f| f.name() == s)
+            .copied()
+            .ok_or_else(|| UnknownField(s.to_string()))
+    }
+}
+
+impl TracePacket {
+    /// Short, kebab-case name of the packet kind, e.g.
+    /// `"instrumentation"` or `"exception-trace"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TracePacket::Sync => "sync",
+            TracePacket::Overflow => "overflow",
+            TracePacket::LocalTimestamp1 { .. } => "local-timestamp-1",
+            TracePacket::LocalTimestamp2 { .. } => "local-timestamp-2",
+            TracePacket::GlobalTimestamp1 { .. } => "global-timestamp-1",
+            TracePacket::GlobalTimestamp2 { .. } => "global-timestamp-2",
+            TracePacket::Extension { .. } => "extension",
+            TracePacket::Instrumentation { .. } => "instrumentation",
+            TracePacket::EventCounterWrap { .. } => "event-counter-wrap",
+            TracePacket::ExceptionTrace { .. } => "exception-trace",
+            TracePacket::PCSample { .. } => "pc-sample",
+            TracePacket::DataTracePC { .. } => "data-trace-pc",
+            TracePacket::DataTraceAddress { .. } => "data-trace-address",
+            TracePacket::DataTraceMatch { .. } => "data-trace-match",
+            TracePacket::DataTraceValue { .. } => "data-trace-value",
+            TracePacket::Unknown { .. } => "unknown",
+        }
+    }
+}
+
+/// Writes packets as comma-separated values, one row of the selected
+/// [`Field`]s per packet. Values that contain commas, quotes or newlines
+/// are quoted.
+///
+/// ```
+/// use itm::{CsvWriter, Field, TracePacket};
+///
+/// let mut csv = CsvWriter::new(vec![], vec![Field::Kind, Field::PayloadText]);
+/// csv.header().unwrap();
+/// let packet = TracePacket::Instrumentation {
+///     port: 0,
+///     payload: [b'a', b',', b'b'].into(),
+/// };
+/// csv.row(0, &packet, None).unwrap();
+/// assert_eq!(
+///     String::from_utf8(csv.into_inner()).unwrap(),
+///     "kind,payload_text\ninstrumentation,\"a,b\"\n"
+/// );
+/// ```
+pub struct CsvWriter<W: io::Write> {
+    out: W,
+    fields: Vec<Field>,
+    ports: PortMap,
+    wall_clock: Option<WallClock>,
+}
+
+impl<W: io::Write> CsvWriter<W> {
+    pub fn new(out: W, fields: Vec<Field>) -> Self {
+        Self {
+            out,
+            fields,
+            ports: PortMap::new(),
+            wall_clock: None,
+        }
+    }
+
+    /// Outputs stimulus ports by their names in `ports`. See
+    /// [`Field::extract_named`].
+    pub fn ports(mut self, ports: PortMap) -> Self {
+        self.ports = ports;
+        self
+    }
+
+    /// Outputs the time column as wall-clock times.
+    pub fn wall_clock(mut self, wall_clock: Option<WallClock>) -> Self {
+        self.wall_clock = wall_clock;
+        self
+    }
+
+    /// The selected fields.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Writes the header row of field names.
+    pub fn header(&mut self) -> io::Result<()> {
+        let names: Vec<&str> = self.fields.iter().map(Field::name).collect();
+        self.write_record(&names)
+    }
+
+    /// Writes the row of a packet.
+    pub fn row(
+        &mut self,
+        seq: u64,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> io::Result<()> {
+        let values: Vec<String> = self
+            .fields
+            .iter()
+            .map(|f| match (f, &self.wall_clock, timestamp) {
+                (Field::Time, Some(wall_clock), Some(ts)) => wall_clock.rfc3339(ts),
+                _ => f.extract_named(seq, packet, timestamp, &self.ports),
+            })
+            .collect();
+        self.write_record(&values)
+    }
+
+    /// Writes a row of arbitrary values.
+    pub fn write_record<S: AsRef<str>>(&mut self, values: &[S]) -> io::Result<()> {
+        let mut first = true;
+        for value in values {
+            if !first {
+                self.out.write_all(b",")?;
+            }
+            first = false;
+            let value = value.as_ref();
+            if value.contains(&[',', '"', '\n', '\r'][..]) {
+                write!(self.out, "\"{}\"", value.replace('"', "\"\""))?;
+            } else {
+                self.out.write_all(value.as_bytes())?;
+            }
+        }
+        self.out.write_all(b"\n")
+    }
+
+    /// Unwraps the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PortInfo;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_list() {
+        assert_eq!(
+            Field::parse_list("time,kind, port,value").unwrap(),
+            [Field::Time, Field::Kind, Field::Port, Field::Value]
+        );
+        assert_eq!(
+            Field::parse_list("ts,payload_hex").unwrap(),
+            [Field::Time, Field::PayloadHex]
+        );
+        assert_eq!(
+            Field::parse_list("time,payload"),
+            Err(UnknownField("payload".to_string()))
+        );
+    }
+
+    #[test]
+    fn extract() {
+        let packet = TracePacket::Instrumentation {
+            port: 3,
+            payload: [0xde, 0xad].into(),
+        };
+        let ts = Timestamp::Sync(Duration::from_micros(1_500_250));
+
+        assert_eq!(
+            Field::DEFAULT
+                .iter()
+                .map(|f| f.extract(42, &packet, Some(&ts)))
+                .collect::<Vec<_>>(),
+            [
+                "42",
+                "1.500250000",
+                "sync",
+                "instrumentation",
+                "3",
+                "",
+                "dead"
+            ]
+        );
+        assert_eq!(Field::Time.extract(42, &packet, None), "");
+        assert_eq!(Field::PayloadHex.extract(42, &packet, None), "dead");
+        assert_eq!(Field::PayloadText.extract(42, &packet, None), "\u{7ad}");
+
+        let ports = [(3, PortInfo::named("adc"))].into_iter().collect();
+        assert_eq!(Field::Port.extract_named(42, &packet, None, &ports), "adc");
+        assert_eq!(
+            Field::Value.extract_named(42, &packet, None, &ports),
+            "dead"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Selection of decoded packets.
+
+use super::{exception_number, ExceptionType, PortMap, TracePacket, VectActive};
+
+use std::collections::BTreeMap;
+
+/// An exception name that could not be resolved.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Unknown exception {0:?}; expected e.g. SysTick, HardFault, IRQ11, ExternalInterrupt(11), or a device interrupt name")]
+pub struct UnknownException(pub String);
+
+/// A packet kind or stimulus port that could not be resolved.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum UnknownSelector {
+    #[error("Unknown packet kind {0:?}; expected e.g. instrumentation, exception-trace, pc-sample or timestamp")]
+    Kind(String),
+
+    #[error("Unknown stimulus port {0:?}; expected a port number 0-255 or a port name")]
+    Port(String),
+}
+
+/// Names of all packet kinds, as returned by [`TracePacket::kind`].
+pub(crate) const KINDS: &[&str] = &[
+    "sync",
+    "overflow",
+    "local-timestamp-1",
+    "local-timestamp-2",
+    "global-timestamp-1",
+    "global-timestamp-2",
+    "extension",
+    "instrumentation",
+    "event-counter-wrap",
+    "exception-trace",
+    "pc-sample",
+    "data-trace-pc",
+    "data-trace-address",
+    "data-trace-match",
+    "data-trace-value",
+    "unknown",
+];
+
+/// Selects [`ExceptionTrace`](TracePacket::ExceptionTrace) packets by
+/// exception. Other packets are always retained.
+///
+/// Exceptions are named as displayed by [`ExceptionType`] (e.g.
+/// `SysTick`, `HardFault`, `ThreadMode`, `IRQ11`), as
+/// `ExternalInterrupt(n)` for external interrupt `n`, or by any name
+/// in a user-supplied map of device interrupt names to interrupt
+/// numbers (e.g. as read from an SVD file).
+#[derive(Debug, Clone, Default)]
+pub struct ExceptionFilter {
+    /// Exception numbers to retain. If empty, all exceptions not in
+    /// `exclude` are retained.
+    include: Vec<u16>,
+
+    /// Exception numbers to drop.
+    exclude: Vec<u16>,
+}
+
+impl ExceptionFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retains only the given comma-separated list of exceptions, in
+    /// addition to any previously included exceptions.
+    pub fn include(
+        mut self,
+        list: &str,
+        irq_names: &BTreeMap<String, u16>,
+    ) -> Result<Self, UnknownException> {
+        self.include.extend(parse_list(list, irq_names)?);
+        Ok(self)
+    }
+
+    /// Drops the given comma-separated list of exceptions.
+    pub fn exclude(
+        mut self,
+        list: &str,
+        irq_names: &BTreeMap<String, u16>,
+    ) -> Result<Self, UnknownException> {
+        self.exclude.extend(parse_list(list, irq_names)?);
+        Ok(self)
+    }
+
+    /// Whether the given exception is selected by this filter.
+    pub fn matches(&self, exception: &VectActive) -> bool {
+        let number = exception_number(exception);
+        (self.include.is_empty() || self.include.contains(&number))
+            && !self.exclude.contains(&number)
+    }
+
+    /// Whether the given packet should be retained.
+    pub fn retain(&self, packet: &TracePacket) -> bool {
+        match packet {
+            TracePacket::ExceptionTrace { exception, .. } => self.matches(exception),
+            _ => true,
+        }
+    }
+}
+
+/// Selects packets by [kind](TracePacket::kind), and
+/// [`Instrumentation`](TracePacket::Instrumentation) packets by
+/// stimulus port.
+///
+/// Kinds are named as returned by [`TracePacket::kind`], e.g.
+/// `instrumentation` or `pc-sample`; `timestamp` names all local and
+/// global timestamp kinds. Ports are given by number, or by any name
+/// in a [`PortMap`].
+///
+/// ```
+/// use itm::{PacketFilter, PortMap, TracePacket};
+///
+/// let filter = PacketFilter::new()
+///     .exclude("pc-sample,timestamp")
+///     .unwrap()
+///     .ports("1", &PortMap::new())
+///     .unwrap();
+/// assert!(!filter.retain(&TracePacket::PCSample { pc: None }));
+/// assert!(!filter.retain(&TracePacket::Instrumentation {
+///     port: 0,
+///     payload: [0x41].into(),
+/// }));
+/// assert!(filter.retain(&TracePacket::Overflow));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PacketFilter {
+    /// Kinds to retain. If empty, all kinds not in `exclude` are
+    /// retained.
+    only: Vec<&'static str>,
+
+    /// Kinds to drop.
+    exclude: Vec<&'static str>,
+
+    /// Stimulus ports to retain instrumentation packets of. If empty,
+    /// those of all ports are retained.
+    ports: Vec<u8>,
+}
+
+impl PacketFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retains only packets of the given comma-separated list of kinds,
+    /// in addition to any previously retained kinds.
+    pub fn only(mut self, list: &str) -> Result<Self, UnknownSelector> {
+        self.only.extend(parse_kinds(list)?);
+        Ok(self)
+    }
+
+    /// Drops packets of the given comma-separated list of kinds.
+    pub fn exclude(mut self, list: &str) -> Result<Self, UnknownSelector> {
+        self.exclude.extend(parse_kinds(list)?);
+        Ok(self)
+    }
+
+    /// Retains only instrumentation packets written to the given
+    /// comma-separated list of stimulus ports, in addition to any
+    /// previously retained ports.
+    pub fn ports(mut self, list: &str, ports: &PortMap) -> Result<Self, UnknownSelector> {
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let port = name
+                .parse()
+                .ok()
+                .or_else(|| ports.port(name))
+                .ok_or_else(|| UnknownSelector::Port(name.into()))?;
+            self.ports.push(port);
+        }
+        Ok(self)
+    }
+
+    /// Whether the given packet should be retained.
+    pub fn retain(&self, packet: &TracePacket) -> bool {
+        let kind = packet.kind();
+        (self.only.is_empty() || self.only.contains(&kind))
+            && !self.exclude.contains(&kind)
+            && match packet.port() {
+                Some(port) => self.ports.is_empty() || self.ports.contains(&port),
+                None => true,
+            }
+    }
+}
+
+fn parse_kinds(list: &str) -> Result<Vec<&'static str>, UnknownSelector> {
+    let mut kinds = vec![];
+    for name in list
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        if name == "timestamp" {
+            kinds.extend(KINDS.iter().filter(|kind| kind.contains("-timestamp-")));
+            continue;
+        }
+        match KINDS.iter().find(|kind| **kind == name) {
+            Some(kind) => kinds.push(*kind),
+            None => return Err(UnknownSelector::Kind(name.into())),
+        }
+    }
+    Ok(kinds)
+}
+
+fn parse_list(list: &str, irq_names: &BTreeMap<String, u16>) -> Result<Vec<u16>, UnknownException> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| parse_exception(name, irq_names).ok_or_else(|| UnknownException(name.into())))
+        .collect()
+}
+
+/// Resolves an exception name into an exception number.
+pub(crate) fn parse_exception(name: &str, irq_names: &BTreeMap<String, u16>) -> Option<u16> {
+    if let Some(irqn) = irq_names.get(name) {
+        return irqn.checked_add(16);
+    }
+    if let Some(irqn) = name
+        .strip_prefix("ExternalInterrupt(")
+        .and_then(|s| s.strip_suffix(')'))
+    {
+        return irqn.trim().parse::<u16>().ok()?.checked_add(16);
+    }
+
+    ExceptionType::from_name(name).map(u16::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExceptionAction;
+
+    fn trace(number: u16) -> TracePacket {
+        TracePacket::ExceptionTrace {
+            exception: VectActive::from(number).unwrap(),
+            action: ExceptionAction::Entered,
+        }
+    }
+
+    #[test]
+    fn exception_filter() {
+        let mut irq_names = BTreeMap::new();
+        irq_names.insert("USART1".to_string(), 37);
+
+        let filter = ExceptionFilter::new()
+            .include("SysTick, ExternalInterrupt(11),USART1", &irq_names)
+            .unwrap();
+        assert!(filter.retain(&trace(15)));
+        assert!(filter.retain(&trace(16 + 11)));
+        assert!(filter.retain(&trace(16 + 37)));
+        assert!(!filter.retain(&trace(3)));
+        assert!(filter.retain(&TracePacket::Overflow));
+
+        let filter = ExceptionFilter::new()
+            .exclude("SysTick", &irq_names)
+            .unwrap();
+        assert!(!filter.retain(&trace(15)));
+        assert!(filter.retain(&trace(3)));
+
+        assert_eq!(
+            ExceptionFilter::new().include("SysTock", &irq_names).err(),
+            Some(UnknownException("SysTock".to_string()))
+        );
+        irq_names.insert("BOGUS".to_string(), 65530);
+        for name in ["ExternalInterrupt(65535)", "BOGUS"] {
+            assert_eq!(
+                ExceptionFilter::new().include(name, &irq_names).err(),
+                Some(UnknownException(name.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn packet_filter() {
+        let mut ports = PortMap::new();
+        ports.insert(2, crate::PortInfo::named("log"));
+        let instrumentation = |port| TracePacket::Instrumentation {
+            port,
+            payload: [0].into(),
+        };
+
+        let filter = PacketFilter::new()
+            .only("instrumentation, exception-trace")
+            .unwrap()
+            .ports("0,log", &ports)
+            .unwrap();
+        assert!(filter.retain(&instrumentation(0)));
+        assert!(filter.retain(&instrumentation(2)));
+        assert!(!filter.retain(&instrumentation(1)));
+        assert!(filter.retain(&trace(15)));
+        assert!(!filter.retain(&TracePacket::Overflow));
+
+        let filter = PacketFilter::new().exclude("timestamp").unwrap();
+        assert!(!filter.retain(&TracePacket::LocalTimestamp2 { ts: 1 }));
+        assert!(!filter.retain(&TracePacket::GlobalTimestamp1 {
+            ts: 1,
+            wrap: false,
+            clkch: false,
+        }));
+        assert!(filter.retain(&instrumentation(1)));
+
+        assert_eq!(
+            PacketFilter::new().only("pc-samples").err(),
+            Some(UnknownSelector::Kind("pc-samples".to_string()))
+        );
+        assert_eq!(
+            PacketFilter::new().ports("256", &ports).err(),
+            Some(UnknownSelector::Port("256".to_string()))
+        );
+    }
+}
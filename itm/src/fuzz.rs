@@ -0,0 +1,106 @@
+//! [`Arbitrary`] implementations, for fuzzing with e.g.
+//! [cargo-fuzz](https://github.com/rust-fuzz/cargo-fuzz).
+
+use super::{Payload, TracePacket, VectActive};
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+fn payload(u: &mut Unstructured<'_>, sizes: &[usize]) -> Result<Payload> {
+    let len = *u.choose(sizes)?;
+    let mut bytes = [0; 4];
+    u.fill_buffer(&mut bytes[..len])?;
+    Ok(Payload::new(&bytes[..len]).unwrap())
+}
+
+impl<'a> Arbitrary<'a> for Payload {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        payload(u, &[0, 1, 2, 3, 4])
+    }
+}
+
+/// Generates only packets that can be [encoded](TracePacket::encode)
+/// and are decoded back into the same packet with the default
+/// [`DecoderOptions`](crate::DecoderOptions). That is, no packets that
+/// only exist on ARMv8-M, and no [`Unknown`](TracePacket::Unknown)
+/// packets.
+impl<'a> Arbitrary<'a> for TracePacket {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(0..=14)? {
+            0 => TracePacket::Sync,
+            1 => TracePacket::Overflow,
+            2 => TracePacket::LocalTimestamp1 {
+                ts: u.int_in_range(0..=(1 << 28) - 1)?,
+                data_relation: u.arbitrary()?,
+            },
+            3 => TracePacket::LocalTimestamp2 {
+                ts: u.int_in_range(1..=6)?,
+            },
+            4 => TracePacket::GlobalTimestamp1 {
+                ts: u.int_in_range(0..=(1 << 26) - 1)?,
+                wrap: u.arbitrary()?,
+                clkch: u.arbitrary()?,
+            },
+            5 => TracePacket::GlobalTimestamp2 {
+                ts: u.int_in_range(0..=(1 << 38) - 1)?,
+            },
+            6 => TracePacket::Extension {
+                page: u.int_in_range(0..=7)?,
+            },
+            7 => TracePacket::Instrumentation {
+                port: u.int_in_range(0..=31)?,
+                payload: payload(u, &[1, 2, 4])?,
+            },
+            8 => TracePacket::EventCounterWrap {
+                cyc: u.arbitrary()?,
+                fold: u.arbitrary()?,
+                lsu: u.arbitrary()?,
+                sleep: u.arbitrary()?,
+                exc: u.arbitrary()?,
+                cpi: u.arbitrary()?,
+            },
+            9 => TracePacket::ExceptionTrace {
+                // Reserved exception numbers are replaced by thread mode
+                exception: VectActive::from(u.int_in_range(0..=511)?)
+                    .unwrap_or(VectActive::ThreadMode),
+                action: u.arbitrary()?,
+            },
+            10 => TracePacket::PCSample { pc: u.arbitrary()? },
+            11 => TracePacket::DataTracePC {
+                comparator: u.int_in_range(0..=3)?,
+                pc: u.arbitrary()?,
+            },
+            12 => TracePacket::DataTraceAddress {
+                comparator: u.int_in_range(0..=3)?,
+                data: payload(u, &[2])?,
+            },
+            _ => TracePacket::DataTraceValue {
+                comparator: u.int_in_range(0..=3)?,
+                access_type: u.arbitrary()?,
+                value: payload(u, &[1, 2, 4])?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecoderOptions, PacketDecoder};
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut u = Unstructured::new(&data);
+        let packets: Vec<TracePacket> = (0..128).map(|_| u.arbitrary().unwrap()).collect();
+
+        let mut decoder = PacketDecoder::new(DecoderOptions::default());
+        for packet in &packets {
+            decoder.feed(&packet.encode().unwrap());
+        }
+        let (decoded, error) = decoder.pull_all();
+        assert!(error.is_none());
+        assert_eq!(decoded, packets);
+    }
+}
@@ -0,0 +1,233 @@
+//! InfluxDB line protocol output for live monitoring.
+//!
+//! [`LineProtocol`] converts packets into points of the [InfluxDB line
+//! protocol](https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/),
+//! so that a live trace stream can feed e.g. Grafana dashboards via
+//! InfluxDB or Telegraf. The points are:
+//!
+//! - `itm_data`: one point per traced data value, i.e. per
+//!   [`DataTraceValue`](TracePacket::DataTraceValue) packet, tagged with
+//!   the `comparator`, the `variable` name, if the comparator is watched
+//!   by name, and the `access` (`read` or `write`), with the field
+//!   `value`, an unsigned integer.
+//! - `itm_port`: one point per stimulus port when the
+//!   [counters](LineProtocol::counters) are requested, tagged with the
+//!   `port` and its `name`, if any, with the fields `packets` and `bytes`:
+//!   the number of instrumentation packets, and of payload bytes, written
+//!   to the port since the start of the capture.
+//!
+//! Points are timestamped in nanoseconds since the Unix epoch, or not at
+//! all, in which case the server timestamps them on arrival.
+//!
+//! ```
+//! use itm::influx::{InfluxOptions, LineProtocol};
+//! use itm::{MemoryAccessType, PortMap, TracePacket};
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let mut influx = LineProtocol::new(PortMap::new(), InfluxOptions::default());
+//! let time = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+//! influx.push(
+//!     &TracePacket::DataTraceValue {
+//!         comparator: 1,
+//!         access_type: MemoryAccessType::Write,
+//!         value: [0xb0, 0x04].into(),
+//!     },
+//!     Some(time),
+//! );
+//! assert_eq!(
+//!     influx.pull().unwrap(),
+//!     "itm_data,comparator=1,access=write value=1200i 1600000000000000000"
+//! );
+//! ```
+
+use crate::{Endianness, MemoryAccessType, PortMap, TracePacket};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Options of a [`LineProtocol`] converter.
+#[derive(Debug, Clone, Default)]
+pub struct InfluxOptions {
+    /// Names of the watched comparators. If empty, the values of all
+    /// comparators are output; otherwise only those of the watched
+    /// comparators, tagged with their names.
+    pub watch: BTreeMap<u8, String>,
+}
+
+/// Counters of a stimulus port.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PortCounters {
+    /// Number of instrumentation packets written to the port.
+    pub packets: u64,
+
+    /// Number of payload bytes written to the port.
+    pub bytes: u64,
+}
+
+/// Converts packets into InfluxDB line protocol points. Packets are
+/// [pushed](Self::push) with their (wall-clock) time, and the resulting
+/// lines, without terminating newlines, are [pulled](Self::pull) out in
+/// order. See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct LineProtocol {
+    ports: PortMap,
+    options: InfluxOptions,
+    counters: BTreeMap<u8, PortCounters>,
+    lines: VecDeque<String>,
+}
+
+impl LineProtocol {
+    /// Creates a converter that tags `itm_port` points with the names
+    /// of the ports of `ports`.
+    pub fn new(ports: PortMap, options: InfluxOptions) -> Self {
+        Self {
+            ports,
+            options,
+            counters: BTreeMap::new(),
+            lines: VecDeque::new(),
+        }
+    }
+
+    /// Processes a packet that was generated at `time`, if known.
+    /// Instrumentation packets are counted, and data values of watched
+    /// comparators output. Other packets are ignored.
+    pub fn push(&mut self, packet: &TracePacket, time: Option<SystemTime>) {
+        match packet {
+            TracePacket::Instrumentation { port, payload } => {
+                let counters = self.counters.entry(*port).or_default();
+                counters.packets += 1;
+                counters.bytes += payload.len() as u64;
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => {
+                let variable = self.options.watch.get(comparator);
+                if variable.is_none() && !self.options.watch.is_empty() {
+                    return;
+                }
+                let value = match value.len() {
+                    1 => u32::from(value[0]),
+                    2 => u32::from(value.as_u16(Endianness::Little).unwrap()),
+                    _ => value.as_u32(Endianness::Little).unwrap_or(0),
+                };
+                let mut line = format!("itm_data,comparator={}", comparator);
+                if let Some(variable) = variable {
+                    line += &format!(",variable={}", escape(variable));
+                }
+                line += match access_type {
+                    MemoryAccessType::Read => ",access=read",
+                    MemoryAccessType::Write => ",access=write",
+                };
+                line += &format!(" value={}i", value);
+                self.line(line, time);
+            }
+            _ => (),
+        }
+    }
+
+    /// Outputs the counters of all ports written to so far, as of
+    /// `time`. Call e.g. periodically.
+    pub fn counters(&mut self, time: Option<SystemTime>) {
+        let lines: Vec<String> = self
+            .counters
+            .iter()
+            .map(|(port, counters)| {
+                let mut line = format!("itm_port,port={}", port);
+                if let Some(name) = self.ports.name(*port) {
+                    line += &format!(",name={}", escape(name));
+                }
+                line + &format!(" packets={}i,bytes={}i", counters.packets, counters.bytes)
+            })
+            .collect();
+        for line in lines {
+            self.line(line, time);
+        }
+    }
+
+    /// The counters of a port.
+    pub fn port(&self, port: u8) -> PortCounters {
+        self.counters.get(&port).copied().unwrap_or_default()
+    }
+
+    /// Pulls the next line.
+    pub fn pull(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+
+    fn line(&mut self, mut line: String, time: Option<SystemTime>) {
+        if let Some(time) = time {
+            let ns = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            line += &format!(" {}", ns.as_nanos());
+        }
+        self.lines.push_back(line);
+    }
+}
+
+/// Escapes a tag value.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if let ',' | '=' | ' ' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PortInfo;
+
+    #[test]
+    fn points() {
+        let ports = [(2, PortInfo::named("motor log"))].into_iter().collect();
+        let mut influx = LineProtocol::new(
+            ports,
+            InfluxOptions {
+                watch: [(0, "speed".to_string())].into_iter().collect(),
+            },
+        );
+        let value = |comparator, value: &[u8]| TracePacket::DataTraceValue {
+            comparator,
+            access_type: MemoryAccessType::Read,
+            value: value.try_into().unwrap(),
+        };
+        let packets = [
+            value(0, &[0x01, 0x00, 0x01, 0x00]),
+            // Not watched
+            value(1, &[0x01]),
+            TracePacket::Instrumentation {
+                port: 2,
+                payload: [b'o', b'k'].into(),
+            },
+            TracePacket::Instrumentation {
+                port: 2,
+                payload: [b'\n'].into(),
+            },
+        ];
+        for packet in packets.iter() {
+            influx.push(packet, None);
+        }
+        influx.counters(Some(UNIX_EPOCH + std::time::Duration::from_nanos(5)));
+
+        let lines: Vec<String> = std::iter::from_fn(|| influx.pull()).collect();
+        assert_eq!(
+            lines,
+            [
+                "itm_data,comparator=0,variable=speed,access=read value=65537i",
+                r"itm_port,port=2,name=motor\ log packets=2i,bytes=3i 5",
+            ]
+        );
+        assert_eq!(
+            influx.port(2),
+            PortCounters {
+                packets: 2,
+                bytes: 3
+            }
+        );
+    }
+}
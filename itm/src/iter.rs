@@ -3,6 +3,7 @@ use super::{
 };
 
 use std::io::Read;
+use std::ops::{Bound, RangeBounds};
 use std::time::Duration;
 
 pub use cortex_m::peripheral::itm::LocalTimestampOptions;
@@ -22,6 +23,11 @@ where
     pub(super) fn new(decoder: Decoder<R>) -> Self {
         Self { decoder }
     }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
 }
 
 impl<R> Iterator for Singles<R>
@@ -34,14 +40,63 @@ where
         let trace = self.decoder.next_single();
 
         match trace {
-            Err(DecoderErrorInt::Eof) => None,
-            Err(DecoderErrorInt::Io(io)) => Some(Err(DecoderError::Io(io))),
-            Err(DecoderErrorInt::MalformedPacket(m)) => Some(Err(DecoderError::MalformedPacket(m))),
+            Err(e) => e.into_public().map(Err),
             Ok(trace) => Some(Ok(trace)),
         }
     }
 }
 
+/// Iterator that yield [`TracePacket`](TracePacket)s from a
+/// non-blocking [`Read`](Read). See [`Decoder::non_blocking`].
+pub struct NonBlocking<R>
+where
+    R: Read,
+{
+    decoder: Decoder<R>,
+}
+
+/// An item yielded by [`NonBlocking`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Polled {
+    /// A decoded packet.
+    Packet(TracePacket),
+
+    /// The [`Read`](Read) would block. Iteration may resume once more
+    /// data is available, e.g. when the source is readable again.
+    NeedMoreData,
+}
+
+impl<R> NonBlocking<R>
+where
+    R: Read,
+{
+    pub(super) fn new(decoder: Decoder<R>) -> Self {
+        Self { decoder }
+    }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+}
+
+impl<R> Iterator for NonBlocking<R>
+where
+    R: Read,
+{
+    type Item = Result<Polled, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.decoder.next_single() {
+            Ok(packet) => Some(Ok(Polled::Packet(packet))),
+            Err(DecoderErrorInt::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                Some(Ok(Polled::NeedMoreData))
+            }
+            Err(e) => e.into_public().map(Err),
+        }
+    }
+}
+
 /// [`Timestamps`](Timestamps) configuration.
 #[derive(Clone)]
 pub struct TimestampsConfiguration {
@@ -58,6 +113,23 @@ pub struct TimestampsConfiguration {
     /// [`TimestampedTracePackets::malformed_packets`](TimestampedTracePackets::malformed_packets)
     /// instead of returning it as an `Result::Err`.
     pub expect_malformed: bool,
+
+    /// When set, a malformed packet does not end the set being
+    /// collected with an error. Instead, the packets decoded so far are
+    /// yielded with a [`Gap`](Timestamp::Gap) timestamp, and the
+    /// [`MalformedPacket`](MalformedPacket) is pushed to
+    /// [`TimestampedTracePackets::malformed_packets`](TimestampedTracePackets::malformed_packets).
+    /// Because the malformed data may have contained timestamps, the
+    /// timeline is additionally kept monotonic: a global timestamp
+    /// never moves it backwards. Takes precedence over
+    /// [`expect_malformed`](Self::expect_malformed).
+    pub best_effort: bool,
+
+    /// When set, scales local timestamps by the latest
+    /// [`ClockDrift`](ClockDrift) estimate, so that they advance at the
+    /// rate of the global timestamp clock. Has no effect until two
+    /// global timestamps have been decoded.
+    pub correct_drift: bool,
 }
 
 /// A set of timestamped [`TracePacket`](TracePacket)s.
@@ -68,6 +140,17 @@ pub struct TimestampedTracePackets {
     /// [`malformed_packets`](Self::malformed_packets).
     pub timestamp: Timestamp,
 
+    /// Exact trace clock cycle count of [`timestamp`](Self::timestamp).
+    /// For timestamps of unknown delay, that of the current (upper
+    /// bound) timestamp.
+    pub ticks: Ticks,
+
+    /// Time elapsed since the previously yielded set, i.e. the
+    /// difference between their [`ticks`](Self::ticks), rounded up to
+    /// the nanosecond. Zero if a global timestamp moved the timeline
+    /// backwards.
+    pub delta: Duration,
+
     /// Packets that the target generated during
     /// [`timestamp`](Self::timestamp).
     pub packets: Vec<TracePacket>,
@@ -79,6 +162,67 @@ pub struct TimestampedTracePackets {
     /// The number of [`TracePacket`](TracePacket)s consumed to generate
     /// this structure.
     pub consumed_packets: usize,
+
+    /// Set if [`packets`](Self::packets) contain an
+    /// [`Overflow`](TracePacket::Overflow), i.e. if trace data was
+    /// lost.
+    pub overflow: Option<OverflowGap>,
+}
+
+/// The ratio between the rates of the global and local timestamp
+/// clocks, as estimated from the local timestamp ticks that elapsed
+/// between two global timestamps. The ratio differs from one on parts
+/// where the local timestamp clock, e.g. `TRACECLKIN`, differs from the
+/// global timestamp clock. See [`Timestamps::clock_drift`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockDrift {
+    /// Local timestamp clock cycles elapsed, after prescaling.
+    pub local_ticks: u64,
+
+    /// Global timestamp clock cycles elapsed.
+    pub global_ticks: u64,
+}
+
+impl ClockDrift {
+    /// Global timestamp clock cycles per local timestamp clock cycle.
+    pub fn ratio(&self) -> f64 {
+        self.global_ticks as f64 / self.local_ticks as f64
+    }
+
+    /// Drift of the local timestamp clock relative to the global
+    /// timestamp clock, in parts per million. Positive if the local
+    /// clock is slow.
+    pub fn ppm(&self) -> f64 {
+        (self.ratio() - 1.0) * 1e6
+    }
+
+    /// Converts a number of local timestamp clock cycles to global
+    /// timestamp clock cycles, rounding down.
+    pub fn correct(&self, local_ticks: u64) -> u64 {
+        (u128::from(local_ticks) * u128::from(self.global_ticks) / u128::from(self.local_ticks))
+            as u64
+    }
+}
+
+/// A region of the timeline during which trace data was lost to an
+/// [`Overflow`](TracePacket::Overflow).
+///
+/// The lost packets were generated between the last timestamp before
+/// the overflow and the timestamp that follows it, rounded up to the
+/// resolution of local timestamps, i.e. one
+/// [prescaler](TimestampsConfiguration::lts_prescaler) period. If local
+/// timestamp packets were lost as well, the timestamps that follow lag
+/// behind by the time they accounted for, until the next global
+/// timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OverflowGap {
+    /// Lower bound of the lost region.
+    pub start: Duration,
+
+    /// Upper bound of the lost region.
+    pub end: Duration,
 }
 
 /// Timestamp relative to trace clock start with quality
@@ -125,18 +269,104 @@ pub enum Timestamp {
         /// The current timestamp.
         curr: Duration,
     },
+    /// Malformed data was encountered, which may have contained
+    /// timestamps. The packets were generated at or some time after the
+    /// previous timestamp provided here. See
+    /// [`TimestampsConfiguration::best_effort`](TimestampsConfiguration::best_effort).
+    Gap {
+        /// The previous timestamp.
+        prev: Duration,
+    },
+}
+
+impl Timestamp {
+    /// Returns the offset from trace clock start. For timestamps of
+    /// unknown delay, the current (upper bound) timestamp is returned.
+    /// For [`Gap`](Timestamp::Gap)s, the previous (lower bound)
+    /// timestamp is returned.
+    pub fn offset(&self) -> Duration {
+        match self {
+            Timestamp::Sync(curr)
+            | Timestamp::AssocEventDelay(curr)
+            | Timestamp::UnknownDelay { curr, .. }
+            | Timestamp::UnknownAssocEventDelay { curr, .. }
+            | Timestamp::Gap { prev: curr } => *curr,
+        }
+    }
+}
+
+/// Timestamp as an exact number of trace clock cycles since trace
+/// clock start, as reconstructed from global and local timestamp
+/// packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ticks {
+    /// Number of trace clock cycles.
+    pub ticks: u64,
+
+    /// Frequency of the trace clock, in Hz. See
+    /// [`TimestampsConfiguration::clock_frequency`](TimestampsConfiguration::clock_frequency).
+    pub frequency: u32,
+}
+
+impl Ticks {
+    /// Returns the offset from trace clock start, rounded up to the
+    /// nanosecond so as to not report an event before it occurred on
+    /// hardware. Computed with integer arithmetic, and thus exact for
+    /// any tick count.
+    pub fn duration(&self) -> Duration {
+        let frequency = u64::from(self.frequency);
+        let secs = self.ticks / frequency;
+        let rem = u128::from(self.ticks % frequency);
+        // `div_ceil` requires Rust 1.73
+        #[allow(clippy::manual_div_ceil)]
+        let nanos = (rem * 1_000_000_000 + u128::from(frequency) - 1) / u128::from(frequency);
+        Duration::new(secs, nanos as u32)
+    }
 }
 
 /// Iterator that yield [`TimestampedTracePackets`](TimestampedTracePackets).
+///
+/// A set is yielded for each local timestamp packet, of either format:
+/// [`LocalTimestamp1`](TracePacket::LocalTimestamp1), or the
+/// single-byte [`LocalTimestamp2`](TracePacket::LocalTimestamp2) that
+/// the target emits instead when the timestamp value is 1-6 and
+/// synchronous to the associated data. Both advance the timeline by
+/// their value in prescaled timestamp clock ticks, so an LTS2 is as
+/// precise as an LTS1: to one prescaler period, i.e. one to 64 trace
+/// clock cycles depending on
+/// [`lts_prescaler`](TimestampsConfiguration::lts_prescaler). A set
+/// closed by an LTS2 is always [`Sync`](Timestamp::Sync).
 pub struct Timestamps<R>
 where
     R: Read,
 {
     decoder: Decoder<R>,
+    timestamper: Timestamper,
+}
+
+/// Groups decoded packets by the local timestamp that follows them.
+/// Shared by the blocking and asynchronous decoders.
+pub(crate) struct Timestamper {
     options: TimestampsConfiguration,
-    current_offset: Duration,
+    current_ticks: u64,
     gts: Gts,
-    prev_lts: Duration,
+    prev_ticks: u64,
+
+    /// Uncorrected local timestamp ticks since trace clock start.
+    local_ticks: u64,
+    /// Global and local ticks at the global timestamp from which
+    /// [`drift`](Self::drift) is estimated.
+    drift_anchor: Option<(u64, u64)>,
+    drift: Option<ClockDrift>,
+
+    /// Ticks of the previously yielded set.
+    yielded_ticks: u64,
+
+    /// The set being collected until the next local timestamp.
+    packets: Vec<TracePacket>,
+    malformed_packets: Vec<MalformedPacket>,
+    consumed_packets: usize,
 }
 
 #[cfg_attr(test, derive(Clone, Debug))]
@@ -197,144 +427,284 @@ where
     R: Read,
 {
     pub(super) fn new(decoder: Decoder<R>, options: TimestampsConfiguration) -> Self {
+        Self {
+            decoder,
+            timestamper: Timestamper::new(options),
+        }
+    }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        self.decoder.get_ref()
+    }
+
+    /// Restricts the yielded sets to those whose
+    /// [offset](Timestamp::offset) is within `range`. See
+    /// [`Window`](Window).
+    pub fn window<B: RangeBounds<Duration>>(self, range: B) -> Window<Self> {
+        Window::new(self, range)
+    }
+
+    /// Returns the latest estimate of the drift between the local and
+    /// global timestamp clocks, if two global timestamps have been
+    /// decoded. The estimate spans from the first global timestamp, or
+    /// the first after an [`Overflow`](TracePacket::Overflow) or a
+    /// clock change, to the latest one. See also
+    /// [`TimestampsConfiguration::correct_drift`](TimestampsConfiguration::correct_drift).
+    pub fn clock_drift(&self) -> Option<ClockDrift> {
+        self.timestamper.clock_drift()
+    }
+
+    fn next_timestamped(&mut self) -> Result<TimestampedTracePackets, DecoderErrorInt> {
+        loop {
+            if let Some(set) = self.timestamper.push(self.decoder.next_single())? {
+                return Ok(set);
+            }
+        }
+    }
+}
+
+impl Timestamper {
+    /// The region of the timeline lost to an overflow in the set being
+    /// collected, if any, given the ticks of the timestamp that closes
+    /// the set.
+    fn overflow_gap(&self, end: u64) -> Option<OverflowGap> {
+        if !self.packets.contains(&TracePacket::Overflow) {
+            return None;
+        }
+        let ticks = |ticks| Ticks {
+            ticks,
+            frequency: self.options.clock_frequency,
+        };
+        Some(OverflowGap {
+            start: ticks(self.prev_ticks).duration(),
+            end: ticks(end + prescale(self.options.lts_prescaler)).duration(),
+        })
+    }
+
+    /// Re-estimates the drift between the local and global timestamp
+    /// clocks upon a global timestamp.
+    fn update_drift(&mut self, gts: u64) {
+        match self.drift_anchor {
+            Some((global, local)) if gts > global && self.local_ticks > local => {
+                self.drift = Some(ClockDrift {
+                    local_ticks: self.local_ticks - local,
+                    global_ticks: gts - global,
+                });
+            }
+            Some(_) => (),
+            None => self.drift_anchor = Some((gts, self.local_ticks)),
+        }
+    }
+
+    /// The time elapsed since the previously yielded set, given the
+    /// ticks of the set to be yielded.
+    fn delta(&mut self, ticks: u64) -> Duration {
+        let delta = Ticks {
+            ticks: ticks.saturating_sub(self.yielded_ticks),
+            frequency: self.options.clock_frequency,
+        };
+        self.yielded_ticks = ticks;
+        delta.duration()
+    }
+
+    /// The latest estimate of the drift between the local and global
+    /// timestamp clocks.
+    pub fn clock_drift(&self) -> Option<ClockDrift> {
+        self.drift
+    }
+
+    pub fn new(options: TimestampsConfiguration) -> Self {
         if options.lts_prescaler == LocalTimestampOptions::Disabled {
             unimplemented!("Generating approximate absolute timestamps from global timestamps alone is not yet supported");
         }
 
         Self {
-            current_offset: Duration::from_nanos(0),
-            decoder,
+            current_ticks: 0,
             options,
             gts: Gts {
                 lower: None,
                 upper: None,
             },
-            // NOTE: required because GTS resets current_offset. GTS
+            // NOTE: required because GTS resets current_ticks. GTS
             // -> LTS, would yield incorrect prev timestamp if this
             // field, upon which only local timestamps are applied, is
             // not used.
-            prev_lts: Duration::from_nanos(0),
+            prev_ticks: 0,
+            local_ticks: 0,
+            drift_anchor: None,
+            drift: None,
+            yielded_ticks: 0,
+            packets: vec![],
+            malformed_packets: vec![],
+            consumed_packets: 0,
         }
     }
 
-    fn next_timestamped(
+    /// Processes the next decoded packet. Returns the collected set of
+    /// packets once a local timestamp is decoded. An EOF condition is
+    /// passed through; the set is resumed on the next call.
+    pub fn push(
         &mut self,
-        options: TimestampsConfiguration,
-    ) -> Result<TimestampedTracePackets, DecoderErrorInt> {
-        use std::ops::Add;
-
-        let mut packets: Vec<TracePacket> = vec![];
-        let mut malformed_packets: Vec<MalformedPacket> = vec![];
-        let mut consumed_packets: usize = 0;
-
+        packet: Result<TracePacket, DecoderErrorInt>,
+    ) -> Result<Option<TimestampedTracePackets>, DecoderErrorInt> {
         fn apply_lts(
-            prev_offset: &mut Duration,
-            lts: u64,
+            prev_ticks: &mut u64,
+            delta: u64,
             data_relation: TimestampDataRelation,
-            current_offset: &mut Duration,
+            current_ticks: &mut u64,
             options: &TimestampsConfiguration,
-        ) -> Timestamp {
-            let offset = calc_offset(lts, Some(options.lts_prescaler), options.clock_frequency);
-            *current_offset = current_offset.add(offset);
+        ) -> (Timestamp, Ticks) {
+            *current_ticks += delta;
+            let ticks = |ticks| Ticks {
+                ticks,
+                frequency: options.clock_frequency,
+            };
+            let prev = ticks(*prev_ticks).duration();
+            let curr = ticks(*current_ticks).duration();
 
             let lts = match data_relation {
-                TimestampDataRelation::Sync => Timestamp::Sync(*current_offset),
-                TimestampDataRelation::UnknownDelay => Timestamp::UnknownDelay {
-                    prev: *prev_offset,
-                    curr: *current_offset,
-                },
-                TimestampDataRelation::AssocEventDelay => {
-                    Timestamp::AssocEventDelay(*current_offset)
-                }
+                TimestampDataRelation::Sync => Timestamp::Sync(curr),
+                TimestampDataRelation::UnknownDelay => Timestamp::UnknownDelay { prev, curr },
+                TimestampDataRelation::AssocEventDelay => Timestamp::AssocEventDelay(curr),
                 TimestampDataRelation::UnknownAssocEventDelay => {
-                    Timestamp::UnknownAssocEventDelay {
-                        prev: *prev_offset,
-                        curr: *current_offset,
-                    }
+                    Timestamp::UnknownAssocEventDelay { prev, curr }
                 }
             };
-            *prev_offset = *current_offset;
-            lts
+            *prev_ticks = *current_ticks;
+            (lts, ticks(*current_ticks))
         }
 
-        fn apply_gts(gts: &Gts, current_offset: &mut Duration, options: &TimestampsConfiguration) {
-            if let Some(gts) = gts.merge() {
-                let offset = calc_offset(gts, None, options.clock_frequency);
-                *current_offset = offset;
-            }
+        fn apply_gts(
+            gts: &Gts,
+            current_ticks: &mut u64,
+            options: &TimestampsConfiguration,
+        ) -> Option<u64> {
+            let gts = gts.merge()?;
+            *current_ticks = if options.best_effort {
+                gts.max(*current_ticks)
+            } else {
+                gts
+            };
+            Some(gts)
         }
 
-        loop {
-            consumed_packets += 1;
-            match self.decoder.next_single() {
-                Err(DecoderErrorInt::MalformedPacket(m)) if options.expect_malformed => {
-                    malformed_packets.push(m);
+        if let Err(DecoderErrorInt::Eof) = packet {
+            return Err(DecoderErrorInt::Eof);
+        }
+
+        self.consumed_packets += 1;
+        let (ts, data_relation): (u64, _) = match packet {
+            Err(DecoderErrorInt::MalformedPacket(m))
+            | Err(DecoderErrorInt::Resynchronized { cause: m, .. })
+                if self.options.best_effort =>
+            {
+                self.malformed_packets.push(m);
+                let ticks = Ticks {
+                    ticks: self.current_ticks,
+                    frequency: self.options.clock_frequency,
+                };
+                let overflow = self.overflow_gap(self.current_ticks);
+                self.prev_ticks = self.current_ticks;
+                return Ok(Some(TimestampedTracePackets {
+                    overflow,
+                    delta: self.delta(ticks.ticks),
+                    timestamp: Timestamp::Gap {
+                        prev: ticks.duration(),
+                    },
+                    ticks,
+                    packets: std::mem::take(&mut self.packets),
+                    malformed_packets: std::mem::take(&mut self.malformed_packets),
+                    consumed_packets: std::mem::take(&mut self.consumed_packets),
+                }));
+            }
+            Err(DecoderErrorInt::MalformedPacket(m))
+            | Err(DecoderErrorInt::Resynchronized { cause: m, .. })
+                if self.options.expect_malformed =>
+            {
+                self.malformed_packets.push(m);
+                return Ok(None);
+            }
+            Err(e) => {
+                self.consumed_packets = 0;
+                self.packets.clear();
+                self.malformed_packets.clear();
+                return Err(e);
+            }
+
+            // A local timestamp: packets received up to this point
+            // relate to this local timestamp. Return these.
+            Ok(TracePacket::LocalTimestamp1 { ts, data_relation }) => (ts.into(), data_relation),
+            Ok(TracePacket::LocalTimestamp2 { ts }) => (ts.into(), TimestampDataRelation::Sync),
+
+            // A global timestamp: store until we have both the
+            // upper (GTS2) and lower (GTS1) bits.
+            Ok(TracePacket::GlobalTimestamp1 { ts, wrap, clkch }) => {
+                self.gts.replace_lower(ts);
+
+                if wrap {
+                    // upper bits have changed; GTS2 incoming
+                    self.gts.upper = None;
+                } else if clkch {
+                    // system has asserted clock change input; full GTS incoming
+                    //
+                    // A clock change signal that the system
+                    // asserts if there is a change in the ratio
+                    // between the global timestamp clock
+                    // frequency and the processor clock
+                    // frequency. Implementation and use of the
+                    // clock change signal is optional and
+                    // deprecated.
+                    self.gts.reset();
+                    // the clock ratio may have changed
+                    self.drift_anchor = None;
+                } else if let Some(gts) =
+                    apply_gts(&self.gts, &mut self.current_ticks, &self.options)
+                {
+                    self.update_drift(gts);
                 }
-                Err(e) => return Err(e),
-                Ok(packet) => match packet {
-                    // A local timestamp: packets received up to this point
-                    // relate to this local timestamp. Return these.
-                    TracePacket::LocalTimestamp1 { ts, data_relation } => {
-                        return Ok(TimestampedTracePackets {
-                            timestamp: apply_lts(
-                                &mut self.prev_lts,
-                                ts.into(),
-                                data_relation,
-                                &mut self.current_offset,
-                                &self.options,
-                            ),
-                            packets,
-                            malformed_packets,
-                            consumed_packets,
-                        });
-                    }
-                    TracePacket::LocalTimestamp2 { ts } => {
-                        return Ok(TimestampedTracePackets {
-                            timestamp: apply_lts(
-                                &mut self.prev_lts,
-                                ts.into(),
-                                TimestampDataRelation::Sync,
-                                &mut self.current_offset,
-                                &self.options,
-                            ),
-                            packets,
-                            malformed_packets,
-                            consumed_packets,
-                        });
-                    }
-
-                    // A global timestamp: store until we have both the
-                    // upper (GTS2) and lower (GTS1) bits.
-                    TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
-                        self.gts.replace_lower(ts);
-
-                        if wrap {
-                            // upper bits have changed; GTS2 incoming
-                            self.gts.upper = None;
-                        } else if clkch {
-                            // system has asserted clock change input; full GTS incoming
-                            //
-                            // A clock change signal that the system
-                            // asserts if there is a change in the ratio
-                            // between the global timestamp clock
-                            // frequency and the processor clock
-                            // frequency. Implementation and use of the
-                            // clock change signal is optional and
-                            // deprecated.
-                            self.gts.reset();
-                        } else {
-                            apply_gts(&self.gts, &mut self.current_offset, &options);
-                        }
-                    }
-                    TracePacket::GlobalTimestamp2 { ts } => {
-                        self.gts.upper = Some(ts);
-                        apply_gts(&self.gts, &mut self.current_offset, &options);
-                    }
-
-                    packet => packets.push(packet),
-                },
+                return Ok(None);
             }
+            Ok(TracePacket::GlobalTimestamp2 { ts }) => {
+                self.gts.upper = Some(ts);
+                if let Some(gts) = apply_gts(&self.gts, &mut self.current_ticks, &self.options) {
+                    self.update_drift(gts);
+                }
+                return Ok(None);
+            }
+
+            Ok(packet) => {
+                if packet == TracePacket::Overflow {
+                    // local timestamps may have been lost
+                    self.drift_anchor = None;
+                }
+                self.packets.push(packet);
+                return Ok(None);
+            }
+        };
+
+        let mut delta = ts * prescale(self.options.lts_prescaler);
+        self.local_ticks += delta;
+        if let (true, Some(drift)) = (self.options.correct_drift, self.drift) {
+            delta = drift.correct(delta);
         }
+        let overflow = self.overflow_gap(self.current_ticks + delta);
+        let (timestamp, ticks) = apply_lts(
+            &mut self.prev_ticks,
+            delta,
+            data_relation,
+            &mut self.current_ticks,
+            &self.options,
+        );
+        Ok(Some(TimestampedTracePackets {
+            timestamp,
+            delta: self.delta(ticks.ticks),
+            ticks,
+            overflow,
+            packets: std::mem::take(&mut self.packets),
+            malformed_packets: std::mem::take(&mut self.malformed_packets),
+            consumed_packets: std::mem::take(&mut self.consumed_packets),
+        }))
     }
 }
 
@@ -345,31 +715,105 @@ where
     type Item = Result<TimestampedTracePackets, DecoderError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let trace = self.next_timestamped(self.options.clone());
+        let trace = self.next_timestamped();
 
         match trace {
-            Err(DecoderErrorInt::Eof) => None,
-            Err(DecoderErrorInt::Io(io)) => Some(Err(DecoderError::Io(io))),
-            Err(DecoderErrorInt::MalformedPacket(m)) => Some(Err(DecoderError::MalformedPacket(m))),
+            Err(e) => e.into_public().map(Err),
             Ok(trace) => Some(Ok(trace)),
         }
     }
 }
 
-fn calc_offset(ts: u64, prescaler: Option<LocalTimestampOptions>, freq: u32) -> Duration {
-    let prescale = match prescaler {
-        None | Some(LocalTimestampOptions::Enabled) => 1,
-        Some(LocalTimestampOptions::EnabledDiv4) => 4,
-        Some(LocalTimestampOptions::EnabledDiv16) => 16,
-        Some(LocalTimestampOptions::EnabledDiv64) => 64,
-        Some(LocalTimestampOptions::Disabled) => unreachable!(), // checked in `Timestamps::new`
-    };
-    let ticks = ts * prescale;
-    let seconds = ticks as f64 / freq as f64;
-
-    // NOTE(ceil) we rount up so as to not report an event before it
-    // occurs on hardware.
-    Duration::from_nanos((seconds * 1e9).ceil() as u64)
+/// Iterator adapter that yield the
+/// [`TimestampedTracePackets`](TimestampedTracePackets) whose
+/// [offset](Timestamp::offset) is within a time window, from an
+/// iterator over timestamped sets, e.g. [`Timestamps`](Timestamps).
+///
+/// Sets before the window are decoded, so that the timeline is
+/// correct, but dropped. Iteration ends at the first set after the
+/// window, so that the rest of the trace need not be decoded. Errors
+/// are passed through.
+///
+/// ```
+/// use itm::{Decoder, DecoderOptions, LocalTimestampOptions, TimestampsConfiguration};
+/// use std::time::Duration;
+///
+/// // LTS1 packets (sync, 1, 2, and 3 ticks)
+/// let stream: &[u8] = &[0b1100_0000, 1, 0b1100_0000, 2, 0b1100_0000, 3];
+/// let offsets: Vec<_> = Decoder::new(stream, DecoderOptions::default())
+///     .timestamps(TimestampsConfiguration {
+///         clock_frequency: 1_000_000,
+///         lts_prescaler: LocalTimestampOptions::Enabled,
+///         expect_malformed: false,
+///         best_effort: false,
+///         correct_drift: false,
+///     })
+///     .window(Duration::from_micros(2)..Duration::from_micros(6))
+///     .map(|set| set.unwrap().timestamp.offset())
+///     .collect();
+/// assert_eq!(offsets, [Duration::from_micros(3)]);
+/// ```
+pub struct Window<I> {
+    inner: I,
+    start: Bound<Duration>,
+    end: Bound<Duration>,
+    done: bool,
+}
+
+impl<I> Window<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    pub fn new<B: RangeBounds<Duration>>(inner: I, range: B) -> Self {
+        Self {
+            inner,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            done: false,
+        }
+    }
+
+    /// Returns a reference to the underlying iterator.
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<I> Iterator for Window<I>
+where
+    I: Iterator<Item = Result<TimestampedTracePackets, DecoderError>>,
+{
+    type Item = Result<TimestampedTracePackets, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let set = match self.inner.next()? {
+                Ok(set) => set,
+                Err(e) => return Some(Err(e)),
+            };
+            let offset = set.timestamp.offset();
+            self.done = match self.end {
+                Bound::Included(end) => offset > end,
+                Bound::Excluded(end) => offset >= end,
+                Bound::Unbounded => false,
+            };
+            if !self.done && (self.start, self.end).contains(&offset) {
+                return Some(Ok(set));
+            }
+        }
+        None
+    }
+}
+
+/// The number of trace clock cycles per local timestamp tick.
+fn prescale(prescaler: LocalTimestampOptions) -> u64 {
+    match prescaler {
+        LocalTimestampOptions::Enabled => 1,
+        LocalTimestampOptions::EnabledDiv4 => 4,
+        LocalTimestampOptions::EnabledDiv16 => 16,
+        LocalTimestampOptions::EnabledDiv64 => 64,
+        LocalTimestampOptions::Disabled => unreachable!(), // checked in `Timestamper::new`
+    }
 }
 
 #[cfg(test)]
@@ -424,10 +868,23 @@ mod timestamp_utils {
 
     #[test]
     fn offset() {
-        assert_eq!(
-            calc_offset(1000, Some(LocalTimestampOptions::EnabledDiv4), 16_000_000),
-            Duration::from_micros(250),
-        );
+        let ticks = Ticks {
+            ticks: 1000 * prescale(LocalTimestampOptions::EnabledDiv4),
+            frequency: 16_000_000,
+        };
+        assert_eq!(ticks.duration(), Duration::from_micros(250));
+
+        // Rounded up, without precision loss for large tick counts
+        let ticks = Ticks {
+            ticks: u64::MAX,
+            frequency: 3,
+        };
+        assert_eq!(ticks.duration(), Duration::new(u64::MAX / 3, 0));
+        let ticks = Ticks {
+            ticks: 3 * 1_000_000_007 + 1,
+            frequency: 1_000_000_007,
+        };
+        assert_eq!(ticks.duration(), Duration::new(3, 1));
     }
 }
 
@@ -439,7 +896,7 @@ mod timestamps {
     const FREQ: u32 = 16_000_000;
 
     /// Check whether timestamps are correctly generated by effectively
-    /// comparing `Timestamps::next_timestamps` and [outer_calc_offset].
+    /// comparing `Timestamps::next_timestamps` and precalculated offsets.
     #[test]
     fn check_timestamps() {
         #[rustfmt::skip]
@@ -521,11 +978,13 @@ mod timestamps {
             0b0110_0000,
         ];
 
-        let decoder = Decoder::new(stream.clone(), DecoderOptions { ignore_eof: false });
+        let decoder = Decoder::new(stream.clone(), DecoderOptions::default());
         let mut it = decoder.timestamps(TimestampsConfiguration {
             clock_frequency: FREQ,
             lts_prescaler: LocalTimestampOptions::Enabled,
             expect_malformed: false,
+            best_effort: false,
+            correct_drift: false,
         });
 
         for set in [
@@ -537,35 +996,68 @@ mod timestamps {
                 ]
                 .into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::from_nanos(10026857009420563),
                 timestamp: Timestamp::Sync(Duration::from_nanos(10026857009420563)),
                 consumed_packets: 6,
+                ticks: Ticks {
+                    ticks: 160429712150729,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [TracePacket::PCSample { pc: None }].into(),
                 malformed_packets: [].into(),
-                timestamp: Timestamp::Sync(Duration::from_nanos(10026857009433126)),
+                overflow: None,
+                delta: Duration::from_nanos(12563),
+                timestamp: Timestamp::Sync(Duration::from_nanos(10026857009433125)),
                 consumed_packets: 2,
+                ticks: Ticks {
+                    ticks: 160429712150930,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [TracePacket::Overflow].into(),
                 malformed_packets: [].into(),
-                timestamp: Timestamp::Sync(Duration::from_nanos(10026857009445689)),
+                overflow: Some(OverflowGap {
+                    start: Duration::from_nanos(10026857009433125),
+                    end: Duration::from_nanos(10026857009445750),
+                }),
+                delta: Duration::from_nanos(12563),
+                timestamp: Timestamp::Sync(Duration::from_nanos(10026857009445688)),
                 consumed_packets: 2,
+                ticks: Ticks {
+                    ticks: 160429712151131,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [].into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::ZERO,
                 timestamp: Timestamp::UnknownAssocEventDelay {
-                    prev: Duration::from_nanos(10026857009445689),
+                    prev: Duration::from_nanos(10026857009445688),
                     curr: Duration::from_nanos(10026857009420563),
                 },
                 consumed_packets: 3,
+                ticks: Ticks {
+                    ticks: 160429712150729,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [].into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::from_nanos(375),
                 timestamp: Timestamp::Sync(Duration::from_nanos(10026857009420938)),
                 consumed_packets: 1,
+                ticks: Ticks {
+                    ticks: 160429712150735,
+                    frequency: FREQ,
+                },
             },
         ]
         .iter()
@@ -574,6 +1066,168 @@ mod timestamps {
         }
     }
 
+    /// LTS2 packets advance the timeline like LTS1 packets, and are
+    /// always synchronous.
+    #[test]
+    fn lts2() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // PC sample (sleeping)
+            0b0001_0101,
+            0b0000_0000,
+
+            // LTS1 (unknown delay, 100)
+            0b1101_0000,
+            0b0110_0100,
+
+            // PC sample (sleeping)
+            0b0001_0101,
+            0b0000_0000,
+
+            // LTS2 (3)
+            0b0011_0000,
+        ];
+
+        let decoder = Decoder::new(stream, DecoderOptions::default());
+        let mut it = decoder.timestamps(TimestampsConfiguration {
+            clock_frequency: 4_000_000,
+            lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+            expect_malformed: false,
+            best_effort: false,
+            correct_drift: false,
+        });
+
+        let set = it.next().unwrap().unwrap();
+        assert_eq!(
+            set.timestamp,
+            Timestamp::UnknownDelay {
+                prev: Duration::from_micros(0),
+                curr: Duration::from_micros(100),
+            }
+        );
+        assert_eq!(set.ticks.ticks, 400);
+
+        let set = it.next().unwrap().unwrap();
+        assert_eq!(set.packets, [TracePacket::PCSample { pc: None }]);
+        assert_eq!(set.timestamp, Timestamp::Sync(Duration::from_micros(103)));
+        assert_eq!(set.ticks.ticks, 412);
+    }
+
+    /// Test that malformed data ends the set being collected with a
+    /// [`Timestamp::Gap`] in best-effort mode.
+    #[test]
+    fn best_effort() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // PC sample (sleeping)
+            0b0001_0101,
+            0b0000_0000,
+
+            // LTS1 (unknown delay, 100)
+            0b1101_0000,
+            0b0110_0100,
+
+            // PC sample (sleeping)
+            0b0001_0101,
+            0b0000_0000,
+
+            // Hardware source packet of invalid discriminator
+            0b1111_1111,
+
+            // PC sample (sleeping)
+            0b0001_0101,
+            0b0000_0000,
+
+            // LTS1 (sync, 50)
+            0b1100_0000,
+            0b0011_0010,
+        ];
+
+        let decoder = Decoder::new(stream, DecoderOptions::default());
+        let mut it = decoder.timestamps(TimestampsConfiguration {
+            clock_frequency: 1_000_000,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+            best_effort: true,
+            correct_drift: false,
+        });
+
+        let set = it.next().unwrap().unwrap();
+        assert_eq!(set.timestamp.offset(), Duration::from_micros(100));
+
+        let set = it.next().unwrap().unwrap();
+        assert_eq!(
+            set.timestamp,
+            Timestamp::Gap {
+                prev: Duration::from_micros(100)
+            }
+        );
+        assert_eq!(set.packets, [TracePacket::PCSample { pc: None }]);
+        assert!(matches!(
+            set.malformed_packets[..],
+            [MalformedPacket::InvalidHardwareDisc { disc_id: 31, .. }]
+        ));
+
+        let set = it.next().unwrap().unwrap();
+        assert_eq!(set.packets, [TracePacket::PCSample { pc: None }]);
+        assert_eq!(set.timestamp, Timestamp::Sync(Duration::from_micros(150)));
+        assert!(it.next().is_none());
+    }
+
+    /// Test that the drift between the local and global timestamp
+    /// clocks is estimated, and corrected for if requested.
+    #[test]
+    fn clock_drift() {
+        let stream: Vec<u8> = [
+            TracePacket::GlobalTimestamp1 {
+                ts: 1000,
+                wrap: false,
+                clkch: false,
+            },
+            TracePacket::GlobalTimestamp2 { ts: 0 },
+            TracePacket::LocalTimestamp1 {
+                ts: 200,
+                data_relation: TimestampDataRelation::Sync,
+            },
+            TracePacket::GlobalTimestamp1 {
+                ts: 1210,
+                wrap: false,
+                clkch: false,
+            },
+            TracePacket::LocalTimestamp1 {
+                ts: 100,
+                data_relation: TimestampDataRelation::Sync,
+            },
+        ]
+        .iter()
+        .flat_map(|packet| packet.encode().unwrap())
+        .collect();
+
+        for (correct_drift, ticks) in [(false, 1310), (true, 1315)] {
+            let decoder = Decoder::new(stream.as_slice(), DecoderOptions::default());
+            let mut it = decoder.timestamps(TimestampsConfiguration {
+                clock_frequency: 1_000_000,
+                lts_prescaler: LocalTimestampOptions::Enabled,
+                expect_malformed: false,
+                best_effort: false,
+                correct_drift,
+            });
+
+            assert_eq!(it.next().unwrap().unwrap().ticks.ticks, 1200);
+            assert_eq!(it.clock_drift(), None);
+            assert_eq!(it.next().unwrap().unwrap().ticks.ticks, ticks);
+            let drift = it.clock_drift().unwrap();
+            assert_eq!(
+                drift,
+                ClockDrift {
+                    local_ticks: 200,
+                    global_ticks: 210,
+                }
+            );
+            assert!((drift.ppm() - 50_000.0).abs() < 1e-6);
+        }
+    }
+
     /// Test cases where a GTS2 applied to two GTS1; 64-bit GTS2; and
     /// compares timestamps to precalculated [Duration] offsets.
     #[test]
@@ -614,31 +1268,51 @@ mod timestamps {
             // previous GTS1
         ];
 
-        let decoder = Decoder::new(stream.clone(), DecoderOptions { ignore_eof: false });
+        let decoder = Decoder::new(stream.clone(), DecoderOptions::default());
         let mut it = decoder.timestamps(TimestampsConfiguration {
             clock_frequency: FREQ,
             lts_prescaler: LocalTimestampOptions::Enabled,
             expect_malformed: false,
+            best_effort: false,
+            correct_drift: false,
         });
 
         for set in [
             TimestampedTracePackets {
                 packets: [].into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::from_nanos(375),
                 timestamp: Timestamp::Sync(Duration::from_nanos(375)),
                 consumed_packets: 1,
+                ticks: Ticks {
+                    ticks: 6,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [].into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::from_nanos(4194304063),
                 timestamp: Timestamp::Sync(Duration::from_nanos(4194304438)),
                 consumed_packets: 3,
+                ticks: Ticks {
+                    ticks: 67108871,
+                    frequency: FREQ,
+                },
             },
             TimestampedTracePackets {
                 packets: [].into(),
                 malformed_packets: [].into(),
+                overflow: None,
+                delta: Duration::from_nanos(7875),
                 timestamp: Timestamp::Sync(Duration::from_nanos(4194312313)),
                 consumed_packets: 2,
+                ticks: Ticks {
+                    ticks: 67108997,
+                    frequency: FREQ,
+                },
             },
         ]
         .iter()
@@ -0,0 +1,204 @@
+//! SWO link latency and jitter estimation.
+//!
+//! The target and the host do not share a clock, so the absolute
+//! transport latency of trace data cannot be observed. What can be
+//! observed is how the difference between the host arrival time of
+//! trace data and its reconstructed target
+//! [`Timestamp`](crate::Timestamp) changes over time. [`LinkLatency`]
+//! tracks this difference relative to the lowest difference yet seen
+//! (i.e. the fastest observed transport), and estimates the jitter of
+//! the link as per RFC 3550, Section 6.4.1.
+//!
+//! Host arrival times are recorded by wrapping the trace source in an
+//! [`ArrivalReader`].
+//!
+//! Note that a target clock that deviates from the configured
+//! [`clock_frequency`](crate::TimestampsConfiguration::clock_frequency)
+//! will show up as a latency that steadily grows or shrinks.
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// A [`Read`](Read) wrapper that records the host time at which the
+/// latest chunk of data arrived.
+pub struct ArrivalReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    last_arrival: Option<Instant>,
+}
+
+impl<R> ArrivalReader<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            last_arrival: None,
+        }
+    }
+
+    /// Host time at which the latest non-empty read returned. `None` if
+    /// no data has been read yet.
+    pub fn last_arrival(&self) -> Option<Instant> {
+        self.last_arrival
+    }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+}
+
+impl<R> Read for ArrivalReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n > 0 {
+            self.last_arrival = Some(Instant::now());
+        }
+        Ok(n)
+    }
+}
+
+/// A latency estimate of a single set of trace data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySample {
+    /// Target timestamp of the trace data.
+    pub target: Duration,
+
+    /// Transport latency relative to the lowest observed latency.
+    pub latency: Duration,
+
+    /// Current jitter estimate of the link.
+    pub jitter: Duration,
+}
+
+/// Summary of all samples recorded by a [`LinkLatency`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    /// Number of recorded samples.
+    pub samples: usize,
+
+    /// Largest relative latency observed.
+    pub max_latency: Duration,
+
+    /// Mean relative latency.
+    pub mean_latency: Duration,
+
+    /// Final jitter estimate.
+    pub jitter: Duration,
+}
+
+/// Estimates the SWO transport latency and jitter from pairs of host
+/// arrival times and target timestamps. See the [module
+/// documentation](self).
+#[derive(Default)]
+pub struct LinkLatency {
+    /// Host time of the first sample. Arrival times are expressed as
+    /// offsets from this instant.
+    epoch: Option<Instant>,
+
+    /// Lowest observed difference between host arrival offset and
+    /// target timestamp, in nanoseconds.
+    base: Option<i128>,
+
+    /// Difference of the previous sample, in nanoseconds.
+    prev: Option<i128>,
+
+    /// Jitter estimate, in nanoseconds.
+    jitter: f64,
+
+    samples: usize,
+    latency_sum: u128,
+    max_latency: Duration,
+}
+
+impl LinkLatency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that trace data with the target timestamp `target`
+    /// arrived at the host at `arrival`.
+    pub fn record(&mut self, target: Duration, arrival: Instant) -> LatencySample {
+        let epoch = *self.epoch.get_or_insert(arrival);
+        let host = arrival.saturating_duration_since(epoch);
+        let diff = host.as_nanos() as i128 - target.as_nanos() as i128;
+
+        // A new lowest difference moves the baseline; previously
+        // recorded latencies are not revisited.
+        let base = *self.base.get_or_insert(diff);
+        let base = if diff < base {
+            self.base = Some(diff);
+            diff
+        } else {
+            base
+        };
+
+        // RFC 3550, Section 6.4.1: J += (|D| - J) / 16
+        if let Some(prev) = self.prev {
+            let d = (diff - prev).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.prev = Some(diff);
+
+        let latency = Duration::from_nanos((diff - base) as u64);
+        self.samples += 1;
+        self.latency_sum += latency.as_nanos();
+        self.max_latency = self.max_latency.max(latency);
+
+        LatencySample {
+            target,
+            latency,
+            jitter: Duration::from_nanos(self.jitter as u64),
+        }
+    }
+
+    /// Summarizes all recorded samples.
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            samples: self.samples,
+            max_latency: self.max_latency,
+            mean_latency: if self.samples == 0 {
+                Duration::from_nanos(0)
+            } else {
+                Duration::from_nanos((self.latency_sum / self.samples as u128) as u64)
+            },
+            jitter: Duration::from_nanos(self.jitter as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency() {
+        let epoch = Instant::now();
+        let ms = Duration::from_millis;
+        let mut link = LinkLatency::new();
+
+        // constant 5 ms transport latency...
+        for i in 0..4 {
+            let sample = link.record(ms(i * 10), epoch + ms(i * 10 + 5));
+            assert_eq!(sample.latency, ms(0));
+            assert_eq!(sample.jitter, ms(0));
+        }
+
+        // ...after which a single packet is delayed by another 16 ms.
+        let sample = link.record(ms(40), epoch + ms(40 + 5 + 16));
+        assert_eq!(sample.latency, ms(16));
+        assert_eq!(sample.jitter, ms(1));
+
+        let summary = link.summary();
+        assert_eq!(summary.samples, 5);
+        assert_eq!(summary.max_latency, ms(16));
+        assert_eq!(summary.mean_latency, Duration::from_micros(3200));
+    }
+}
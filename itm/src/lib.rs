@@ -18,6 +18,10 @@
 //! [a timestamp relative to target reset of when the packets where
 //! generated target-side](TimestampedTracePackets::timestamp).
 //!
+//! On top of these, [`Events`](Events) reassembles higher-level
+//! [`ItmEvent`](ItmEvent)s, such as lines of text written to a stimulus
+//! port or the execution span of an exception handler.
+//!
 //! Usage is simple:
 //! ```
 //! use itm::{Decoder, DecoderOptions};
@@ -26,26 +30,177 @@
 //! let stream: &[u8] = &[
 //!     // ...
 //! ];
-//! let mut decoder = Decoder::<&[u8]>::new(stream, DecoderOptions { ignore_eof: false });
+//! let mut decoder = Decoder::<&[u8]>::new(stream, DecoderOptions::default());
 //! for packet in decoder.singles() {
 //!     // ...
 //! }
 //! ```
+//!
+//! ## `no_std`
+//!
+//! All of the above require the `std` feature, which is enabled by
+//! default. Without it, the crate only requires `alloc`, and packets
+//! are decoded with the sans-I/O [`PacketDecoder`](PacketDecoder),
+//! which [`Decoder`] is built upon: trace data is
+//! [fed](PacketDecoder::feed) to it as it becomes available, and
+//! decoded packets are [pulled](PacketDecoder::pull) from it.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
 #[deny(rustdoc::broken_intra_doc_links)]
+#[cfg(feature = "std")]
 mod iter;
+#[cfg(feature = "std")]
 pub use iter::{
-    LocalTimestampOptions, Singles, Timestamp, TimestampedTracePackets, Timestamps,
-    TimestampsConfiguration,
+    ClockDrift, LocalTimestampOptions, NonBlocking, OverflowGap, Polled, Singles, Ticks, Timestamp,
+    TimestampedTracePackets, Timestamps, TimestampsConfiguration, Window,
 };
 
-#[cfg(feature = "serial")]
+#[cfg(feature = "std")]
+mod events;
+#[cfg(feature = "std")]
+pub use events::{Events, ExceptionSpan, ItmEvent, ItmFrame, ItmText, VariableUpdate};
+
+#[cfg(feature = "std")]
+mod lines;
+#[cfg(feature = "std")]
+pub use lines::{Line, LineSplitter, Lines, LinesError, LinesInput, LinesOptions};
+
+mod encode;
+pub use encode::EncodeError;
+
+mod display;
+
+mod counter;
+pub use counter::DwtCounterWrap;
+
+pub mod v2;
+
+mod exception;
+pub use exception::{ExceptionType, InvalidExceptionNumber};
+
+#[cfg(feature = "std")]
+mod fields;
+#[cfg(feature = "std")]
+pub use fields::{CsvWriter, Field, UnknownField};
+
+#[cfg(feature = "std")]
+mod filter;
+#[cfg(feature = "std")]
+pub use filter::{ExceptionFilter, PacketFilter, UnknownException, UnknownSelector};
+
+#[cfg(feature = "std")]
+pub mod latency;
+
+#[cfg(feature = "std")]
+pub mod emulator;
+
+#[cfg(feature = "std")]
+pub mod trigger;
+
+#[cfg(feature = "std")]
+pub mod watch;
+
+#[cfg(feature = "std")]
+pub mod loss;
+
+#[cfg(feature = "std")]
+pub mod repair;
+
+#[cfg(feature = "std")]
+pub mod demux;
+
+#[cfg(feature = "std")]
+pub mod wall_clock;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+
+#[cfg(feature = "std")]
+pub mod symbols;
+
+#[cfg(feature = "std")]
+pub mod influx;
+
+#[cfg(feature = "std")]
+pub mod pcapng;
+
+#[cfg(feature = "std")]
+pub mod logic;
+
+#[cfg(feature = "std")]
+pub mod nrz;
+
+#[cfg(feature = "std")]
+pub mod manchester;
+
+pub mod schema;
+
+pub mod config;
+
+pub mod cobs;
+
+mod ports;
+pub use ports::{PortEncoding, PortInfo, PortMap};
+
+mod sequence;
+pub use sequence::{Sequence, Sequenced};
+
+mod snapshot;
+pub use snapshot::DecoderSnapshot;
+
+mod payload;
+pub use payload::{Endianness, Payload, PayloadTooLong};
+
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+
+#[cfg(feature = "proptest")]
+pub mod strategy;
+
+#[cfg(any(feature = "serial", feature = "serialport"))]
 pub mod serial;
 
-use std::convert::TryInto;
+#[cfg(feature = "cmsis-dap")]
+pub mod cmsis_dap;
+
+#[cfg(feature = "stlink")]
+pub mod stlink;
+
+#[cfg(feature = "probe-rs")]
+pub mod probe;
+
+#[cfg(feature = "async")]
+pub mod async_decoder;
+
+#[cfg(feature = "defmt")]
+pub mod defmt;
+
+#[cfg(feature = "cbor")]
+pub mod cbor;
+
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[cfg(feature = "compression")]
+pub mod compression;
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io::Read;
 
 use bitmatch::bitmatch;
-use bitvec::prelude::*;
 pub use cortex_m::peripheral::scb::VectActive;
 
 /// The set of valid packet types that can be decoded.
@@ -116,6 +271,9 @@ pub enum TracePacket {
     /// identified source (one of two possible, theoretically). On
     /// ARMv7-M this packet is only used to denote on which ITM stimulus
     /// port a payload was written. (Appendix D4.2.6)
+    ///
+    /// On ARMv8-M, the page number may continue into a payload, which
+    /// allows for up to 32 stimulus port pages.
     Extension {
         /// Source port page number.
         page: u8,
@@ -128,7 +286,7 @@ pub enum TracePacket {
         port: u8,
 
         /// Instrumentation data written to the stimulus port. MSB, BE.
-        payload: Vec<u8>,
+        payload: Payload,
     },
 
     /// One or more event counters have wrapped. (Appendix D4.3.1)
@@ -175,8 +333,17 @@ pub enum TracePacket {
         /// The comparator number that generated the data.
         comparator: u8,
 
-        /// Data address content; bits\[15:0\]. MSB, BE.
-        data: Vec<u8>,
+        /// Data address content; bits\[15:0\], or on ARMv8-M
+        /// optionally the full address, bits\[31:0\]. MSB, BE.
+        data: Payload,
+    },
+
+    /// A DWT comparator matched. Only generated on ARMv8-M, by a
+    /// comparator configured to emit a match packet instead of the
+    /// matched PC, address, or value.
+    DataTraceMatch {
+        /// The comparator number that matched.
+        comparator: u8,
     },
 
     /// A data trace packet with a value. (Appendix D4.3.4)
@@ -188,13 +355,27 @@ pub enum TracePacket {
         access_type: MemoryAccessType,
 
         /// The data value. MSB, BE.
-        value: Vec<u8>,
+        value: Payload,
+    },
+
+    /// A malformed packet, reported as is. Only decoded with
+    /// [`RecoveryPolicy::ReportUnknown`].
+    Unknown {
+        /// The first byte of the packet. A malformed
+        /// [`Sync`](TracePacket::Sync) packet need not be byte-aligned,
+        /// so this byte may hold bits of the preceding packet.
+        header: u8,
+
+        /// The bytes of the packet after the header that were read
+        /// before it was found to be malformed.
+        payload: Vec<u8>,
     },
 }
 
 /// Denotes the action taken by the processor by a given exception. (Table D4-6)
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ExceptionAction {
     /// Exception was entered.
     Entered,
@@ -209,6 +390,7 @@ pub enum ExceptionAction {
 /// Denotes the type of memory access.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum MemoryAccessType {
     /// Memory was read.
     Read,
@@ -222,6 +404,7 @@ pub enum MemoryAccessType {
 /// (Appendix D4.2.4)
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum TimestampDataRelation {
     /// The local timestamp value is synchronous to the corresponding
     /// ITM or DWT data. The value in the TS field is the timestamp
@@ -255,16 +438,17 @@ pub enum TimestampDataRelation {
 }
 
 /// Set of malformed [`TracePacket`](TracePacket)s that can occur during decode.
-#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MalformedPacket {
     /// Header is invalid and cannot be decoded.
-    #[error("Header is invalid and cannot be decoded: {}", format!("{:#b}", .0))]
+    #[cfg_attr(feature = "std", error("Header is invalid and cannot be decoded: {}", format!("{:#b}", .0)))]
     InvalidHeader(u8),
 
     /// The type discriminator ID in the hardware source packet header
     /// is invalid or the associated payload is of wrong size.
-    #[error("Hardware source packet type discriminator ID ({disc_id}) or payload length ({}) is invalid", .payload.len())]
+    #[cfg_attr(feature = "std", error("Hardware source packet type discriminator ID ({disc_id}) or payload length ({}) is invalid", .payload.len()))]
     InvalidHardwarePacket {
         /// The discriminator ID. Potentially invalid.
         disc_id: u8,
@@ -275,7 +459,10 @@ pub enum MalformedPacket {
 
     /// The type discriminator ID in the hardware source packet header
     /// is invalid.
-    #[error("Hardware source packet discriminator ID is invalid: {disc_id}")]
+    #[cfg_attr(
+        feature = "std",
+        error("Hardware source packet discriminator ID is invalid: {disc_id}")
+    )]
     InvalidHardwareDisc {
         /// The discriminator ID. Potentially invalid.
         disc_id: u8,
@@ -286,7 +473,10 @@ pub enum MalformedPacket {
 
     /// An exception trace packet refers to an invalid action or an
     /// invalid exception number.
-    #[error("IRQ number {exception} and/or action {function} is invalid")]
+    #[cfg_attr(
+        feature = "std",
+        error("IRQ number {exception} and/or action {function} is invalid")
+    )]
     InvalidExceptionTrace {
         /// The exception number.
         exception: u16,
@@ -297,7 +487,7 @@ pub enum MalformedPacket {
     },
 
     /// The payload length of a PCSample packet is invalid.
-    #[error("Payload length of PC sample is invalid: {}", .payload.len())]
+    #[cfg_attr(feature = "std", error("Payload length of PC sample is invalid: {}", .payload.len()))]
     InvalidPCSampleSize {
         /// The payload constituting the PC value, of invalid size. MSB, BE.
         payload: Vec<u8>,
@@ -305,24 +495,46 @@ pub enum MalformedPacket {
 
     /// The GlobalTimestamp2 packet does not contain a 48-bit or 64-bit
     /// timestamp.
-    #[error("GlobalTimestamp2 packet does not contain a 48-bit or 64-bit timestamp")]
+    #[cfg_attr(
+        feature = "std",
+        error("GlobalTimestamp2 packet does not contain a 48-bit or 64-bit timestamp")
+    )]
     InvalidGTS2Size {
         /// The payload constituting the timestamp, of invalid size. MSB, BE.
         payload: Vec<u8>,
     },
 
+    /// The page number of an Extension packet does not fit in a byte,
+    /// or, if the stimulus port page is
+    /// [tracked](DecoderOptions::track_stimulus_page), selects ports
+    /// above 255.
+    #[cfg_attr(
+        feature = "std",
+        error("Extension packet page number is out of range: {page}")
+    )]
+    InvalidExtension {
+        /// The page number.
+        page: u32,
+    },
+
     /// The number of zeroes in the Synchronization packet is less than
     /// 47.
-    #[error(
-        "The number of zeroes in the Synchronization packet is less than expected: {0} < {}",
-        SYNC_MIN_ZEROS
+    #[cfg_attr(
+        feature = "std",
+        error(
+            "The number of zeroes in the Synchronization packet is less than expected: {0} < {}",
+            SYNC_MIN_ZEROS
+        )
     )]
     InvalidSync(usize),
 
     /// A source packet (from software or hardware) contains an invalid
     /// expected payload size.
-    #[error(
-        "A source packet (from software or hardware) contains an invalid expected payload size"
+    #[cfg_attr(
+        feature = "std",
+        error(
+            "A source packet (from software or hardware) contains an invalid expected payload size"
+        )
     )]
     InvalidSourcePayload {
         /// The header which contains the invalid payload size.
@@ -331,10 +543,28 @@ pub enum MalformedPacket {
         /// The invalid payload size. See (Appendix D4.2.8, Table D4-4).
         size: u8,
     },
+
+    /// The payload of a timestamp or Extension packet continues beyond
+    /// the maximum length of the packet.
+    #[cfg_attr(
+        feature = "std",
+        error("Packet payload continues beyond its maximum length of {max_len} bytes")
+    )]
+    InvalidPayloadLength {
+        /// The maximum length of the payload.
+        max_len: usize,
+
+        /// The payload up to the maximum length, each byte of which has
+        /// its continuation bit set.
+        payload: Vec<u8>,
+    },
 }
 
 const SYNC_MIN_ZEROS: usize = 47;
 
+/// Size of the largest packet: a header and a 6-byte payload.
+const MAX_PACKET_SIZE: usize = 7;
+
 /// The decoder's possible states. The default decoder state is `Header`
 /// and will always return there after a maximum of two steps. (E.g. if
 /// the current state is `Syncing` or `HardwareSource`, the next state
@@ -358,6 +588,11 @@ enum PacketStub {
         data_relation: TimestampDataRelation,
     },
 
+    /// Next bytes will be assumed to be part of an ARMv8-M Extension
+    /// packet, until the MSB is cleared. `page` holds the lower bits of
+    /// the page number.
+    Extension { page: u8 },
+
     /// Next bytes will be assumed to be part of a GlobalTimestamp1
     /// packet, until the MSB is set.
     GlobalTimestamp1,
@@ -373,175 +608,717 @@ enum HeaderVariant {
 }
 
 /// [`Decoder`](Decoder) configuration.
+#[derive(Default)]
 pub struct DecoderOptions {
     /// Whether to keep reading after a (temporary) EOF condition. If
     /// set iteration is done over [`Singles`](Singles) or
     /// [`Timestamps`](Timestamps), [`next`](Iterator::next) will never
     /// return unless the EOF condition is eventually resolved.
     pub ignore_eof: bool,
+
+    /// How to recover from a malformed packet.
+    pub recovery: RecoveryPolicy,
+
+    /// Architecture of the target, which determines the set of valid
+    /// packet encodings.
+    pub arch: ArchVersion,
+
+    /// Maximum number of bytes that a [`PacketDecoder`] buffers. If
+    /// set, [`feed`](PacketDecoder::feed) accepts no more data than
+    /// that, so that callers can apply backpressure. The limit must
+    /// leave room for the largest packet, so a limit below
+    /// 7 bytes is raised to that. A
+    /// [`Decoder`] or an `AsyncDecoder` only reads as much data as it
+    /// needs to decode the next packet, and ignores the limit.
+    pub max_buffered: Option<usize>,
+
+    /// Whether to track the stimulus port page selected by
+    /// [`Extension`](TracePacket::Extension) packets. If set, the
+    /// [`port`](TracePacket::Instrumentation::port) of an
+    /// instrumentation packet is the effective stimulus port number
+    /// 0-255 instead of the port number within the current page. The
+    /// page is reset to 0 by a [`Sync`](TracePacket::Sync) packet.
+    pub track_stimulus_page: bool,
+
+    /// Whether to accept a [`Sync`](TracePacket::Sync) packet of fewer
+    /// than the 47 zero bits that the architecture requires, instead of
+    /// reporting it as an
+    /// [`InvalidSync`](MalformedPacket::InvalidSync). Some emulators,
+    /// e.g. QEMU and Renode, emit such short synchronization packets.
+    /// Skipping to the next synchronization packet (see
+    /// [`RecoveryPolicy::SkipToSync`]) still requires 47 zero bits.
+    pub lenient_sync: bool,
+}
+
+/// Architecture version of the target that generated the trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArchVersion {
+    /// ARMv7-M, e.g. Cortex-M3, -M4, and -M7.
+    V7M,
+
+    /// ARMv8-M, e.g. Cortex-M23, -M33, and -M55. Additionally decodes
+    /// Extension packets with a payload,
+    /// [`DataTraceMatch`](TracePacket::DataTraceMatch) packets, and
+    /// [`DataTraceAddress`](TracePacket::DataTraceAddress) packets
+    /// that carry the full address.
+    V8M,
+}
+
+// `#[default]` requires Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for ArchVersion {
+    fn default() -> Self {
+        ArchVersion::V7M
+    }
 }
 
-#[derive(Debug, thiserror::Error)]
+/// How the [`Decoder`](Decoder) recovers from a
+/// [`MalformedPacket`](MalformedPacket).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RecoveryPolicy {
+    /// Report the malformed packet and continue decoding at the byte
+    /// after it. The decoder may be out of alignment with the packet
+    /// stream afterwards and decode garbage until the next
+    /// synchronization.
+    Continue,
+
+    /// Drop all data until the next [`Sync`](TracePacket::Sync)
+    /// packet, and report the malformed packet together with the
+    /// number of dropped bytes as
+    /// [`DecoderError::Resynchronized`](DecoderError::Resynchronized).
+    /// Decoding then resumes with the `Sync` packet.
+    SkipToSync,
+
+    /// Like [`Continue`](Self::Continue), but report the malformed
+    /// packet as a [`TracePacket::Unknown`] packet instead of an error,
+    /// so that the packet stream keeps flowing. Useful for links that
+    /// occasionally produce garbage bytes.
+    ReportUnknown,
+}
+
+// `#[default]` requires Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        RecoveryPolicy::Continue
+    }
+}
+
+#[derive(Debug)]
 enum DecoderErrorInt {
-    #[error("Buffer failed to read from source: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("EOF encountered")]
+    /// The source failed to read.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+
+    /// More data is needed to decode the next packet.
     Eof,
-    #[error("untars")]
-    MalformedPacket(#[from] MalformedPacket),
+
+    MalformedPacket(MalformedPacket),
+
+    Resynchronized {
+        cause: MalformedPacket,
+        skipped: usize,
+    },
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for DecoderErrorInt {
+    fn from(e: std::io::Error) -> Self {
+        DecoderErrorInt::Io(e)
+    }
+}
+
+impl From<MalformedPacket> for DecoderErrorInt {
+    fn from(m: MalformedPacket) -> Self {
+        DecoderErrorInt::MalformedPacket(m)
+    }
 }
 
 /// Set of errors that can occur during decode.
-#[derive(Debug, thiserror::Error)]
+///
+/// With the `serde` feature, an [`Io`](DecoderError::Io) error is
+/// serialized as its message only, and deserializes into an error of
+/// kind [`Other`](std::io::ErrorKind::Other).
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DecoderError {
+    #[cfg(feature = "std")]
     #[error("I/O error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("A malformed packet was encountered: {0}")]
-    MalformedPacket(#[from] MalformedPacket),
+    Io(
+        #[from]
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        std::io::Error,
+    ),
+    #[cfg_attr(feature = "std", error("A malformed packet was encountered: {0}"))]
+    MalformedPacket(#[cfg_attr(feature = "std", from)] MalformedPacket),
+
+    /// A malformed packet was encountered, after which `skipped`
+    /// bytes were dropped until the next [`Sync`](TracePacket::Sync)
+    /// packet. See [`RecoveryPolicy::SkipToSync`].
+    #[cfg_attr(feature = "std", error("A malformed packet was encountered: {cause}; skipped {skipped} bytes to the next synchronization packet"))]
+    Resynchronized {
+        /// The malformed packet.
+        cause: MalformedPacket,
+
+        /// Number of dropped bytes, rounded up.
+        skipped: usize,
+    },
 }
 
-struct Buffer<R>
-where
-    R: Read,
-{
-    reader: R,
-    buffer: BitVec,
-    ignore_eof: bool,
+/// (De)serialization of [`std::io::Error`] by its message.
+#[cfg(all(feature = "std", feature = "serde"))]
+mod io_error {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::io;
+
+    pub fn serialize<S: Serializer>(e: &io::Error, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(e)
+    }
+
+    // `io::Error::other` requires Rust 1.74
+    #[allow(clippy::io_other_error)]
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<io::Error, D::Error> {
+        let msg = String::deserialize(d)?;
+        Ok(io::Error::new(io::ErrorKind::Other, msg))
+    }
 }
 
-impl<R> Buffer<R>
-where
-    R: Read,
-{
-    pub fn new(reader: R, ignore_eof: bool) -> Buffer<R> {
-        Buffer {
-            reader,
-            ignore_eof,
-            buffer: BitVec::new(),
+impl DecoderError {
+    /// A suggestion on how to recover from the error, for display to
+    /// the user.
+    pub fn suggested_recovery(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "std")]
+            DecoderError::Io(_) => "check the connection to the trace source and reopen it",
+            DecoderError::MalformedPacket(_) => {
+                "check that the trace configuration (e.g. baud rate, architecture version) matches the target, or decode with `RecoveryPolicy::SkipToSync` to realign on the next synchronization packet"
+            }
+            DecoderError::Resynchronized { .. } => {
+                "none required; decoding resumed at the next synchronization packet"
+            }
         }
     }
+}
 
-    /// Tries to read up to 32 bytes from [Self::reader]. Continuously retries if [ignore_eof] is set.
-    fn buffer_some(&mut self) -> Result<(), DecoderErrorInt> {
-        // `Read::read` reportedly reads in 32-byte chunks. Source:
-        // <https://github.com/rust-embedded/itm/blob/3e4251b42aa2e4b05ae372c47c7b835b8acae6dc/src/lib.rs#L42>.
-        let mut buffer: [u8; 32] = [0; 32];
-        loop {
-            match self.reader.read(&mut buffer) {
-                Ok(0) => {
-                    if self.ignore_eof {
-                        continue;
-                    }
-                    return Err(DecoderErrorInt::Eof);
-                }
-                Ok(n) => {
-                    let mut bv = BitVec::<_, LocalBits>::from_vec(buffer[0..n].to_vec());
-                    bv.reverse();
-                    bv.append(&mut self.buffer);
-                    self.buffer.append(&mut bv);
+/// The state of a [`PacketDecoder`] with respect to the packet stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecoderState {
+    /// Decoding packets.
+    Decoding,
 
-                    return Ok(());
-                }
-                Err(e) => {
-                    // XXX any other errors we should retry on?
-                    if e.kind() == std::io::ErrorKind::Interrupted {
-                        continue;
-                    }
-                    return Err(e.into());
-                }
+    /// Within a [`Sync`](TracePacket::Sync) packet.
+    Synchronizing,
+
+    /// Dropping data until the next [`Sync`](TracePacket::Sync) packet.
+    /// See [`RecoveryPolicy::SkipToSync`].
+    Skipping,
+}
+
+impl fmt::Display for DecoderState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DecoderState::Decoding => "decoding",
+            DecoderState::Synchronizing => "synchronizing",
+            DecoderState::Skipping => "skipping to the next synchronization packet",
+        })
+    }
+}
+
+/// A [`DecoderError`] together with the context it occurred in. See
+/// [`PacketDecoder::pull_with_context`].
+#[derive(Debug)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+#[cfg_attr(
+    feature = "std",
+    error("{error} (at byte {offset}, while {state}); suggested recovery: {}", .error.suggested_recovery())
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContextualError {
+    /// The error.
+    pub error: DecoderError,
+
+    /// Offset in bytes from the start of the stream of the byte that
+    /// holds the first bit of the failed packet.
+    pub offset: u64,
+
+    /// The state the decoder was in when it encountered the error.
+    pub state: DecoderState,
+}
+
+impl From<DecoderError> for DecoderErrorInt {
+    fn from(e: DecoderError) -> Self {
+        match e {
+            #[cfg(feature = "std")]
+            DecoderError::Io(io) => DecoderErrorInt::Io(io),
+            DecoderError::MalformedPacket(m) => DecoderErrorInt::MalformedPacket(m),
+            DecoderError::Resynchronized { cause, skipped } => {
+                DecoderErrorInt::Resynchronized { cause, skipped }
             }
         }
     }
+}
 
-    /// Pops a single bit from the buffer. Tries to buffer first if
-    /// the buffer is empty.
-    pub fn pop_bit(&mut self) -> Result<bool, DecoderErrorInt> {
-        loop {
-            match self.buffer.pop() {
-                None => {
-                    self.buffer_some()?;
-                    continue;
-                }
-                Some(bit) => return Ok(bit),
+impl DecoderErrorInt {
+    /// Copies a decoding error into its public counterpart.
+    ///
+    /// # Panics
+    ///
+    /// If the error is an EOF condition or an I/O error, neither of
+    /// which a [`PacketDecoder`] stores.
+    fn clone_public(&self) -> DecoderError {
+        match self {
+            DecoderErrorInt::MalformedPacket(m) => DecoderError::MalformedPacket(m.clone()),
+            DecoderErrorInt::Resynchronized { cause, skipped } => DecoderError::Resynchronized {
+                cause: cause.clone(),
+                skipped: *skipped,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Converts the error into its public counterpart. An EOF condition
+    /// is not an error and converts into `None`.
+    fn into_public(self) -> Option<DecoderError> {
+        match self {
+            DecoderErrorInt::Eof => None,
+            #[cfg(feature = "std")]
+            DecoderErrorInt::Io(io) => Some(DecoderError::Io(io)),
+            DecoderErrorInt::MalformedPacket(m) => Some(DecoderError::MalformedPacket(m)),
+            DecoderErrorInt::Resynchronized { cause, skipped } => {
+                Some(DecoderError::Resynchronized { cause, skipped })
             }
         }
     }
+}
+
+/// Bytes of the trace stream that are yet to be decoded. The stream is
+/// read through a bit cursor, so that it can be realigned on a
+/// [`Sync`](TracePacket::Sync) packet that does not start on a byte
+/// boundary, but is otherwise read a byte at a time.
+struct Buffer {
+    /// Fed bytes, starting with the byte that holds the cursor at the
+    /// last [commit](Self::commit).
+    bytes: VecDeque<u8>,
+
+    /// Read cursor, in bits from the start of `bytes`. Bits are read
+    /// LSB first.
+    cursor: usize,
+
+    /// The cursor at the last commit. The cursor is reset to this
+    /// position on a [rewind](Self::rewind).
+    mark: usize,
+
+    /// Number of bits dropped from the front of `bytes` so far.
+    dropped: u64,
+
+    /// Bytes committed since the recording was started. See
+    /// [`PacketDecoder::pull_annotated`].
+    recording: Option<Recording>,
+
+    /// Scratch space for [`pop_payload`](Self::pop_payload), reused
+    /// between packets.
+    payload: Vec<u8>,
+}
+
+struct Recording {
+    /// Bit offset at which the recording was started.
+    start: u64,
+
+    /// Bytes dropped from the buffer since.
+    bytes: Vec<u8>,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer {
+            bytes: VecDeque::new(),
+            cursor: 0,
+            mark: 0,
+            dropped: 0,
+            recording: None,
+            payload: vec![],
+        }
+    }
 
-    /// Pops a single byte from the buffer. Tries to buffer if more data
-    /// is needed.
-    pub fn pop_byte(&mut self) -> Result<u8, DecoderErrorInt> {
-        let mut b: u8 = 0;
-        for i in 0..8 {
-            b |= (self.pop_bit()? as u8) << i;
+    /// Number of bits read from the stream so far.
+    pub fn consumed(&self) -> u64 {
+        self.dropped + self.cursor as u64
+    }
+
+    /// Forgets all bits read since the last commit. Call when a packet
+    /// has been completely decoded.
+    pub fn commit(&mut self) {
+        let whole = self.cursor / 8;
+        if let Some(recording) = self.recording.as_mut() {
+            recording.bytes.extend(self.bytes.range(..whole));
+        }
+        self.bytes.drain(..whole);
+        self.dropped += whole as u64 * 8;
+        self.cursor %= 8;
+        self.mark = self.cursor;
+    }
+
+    /// Starts recording the committed bytes, unless already recording.
+    pub fn record(&mut self) {
+        if self.recording.is_none() {
+            self.recording = Some(Recording {
+                start: self.consumed(),
+                bytes: vec![],
+            });
+        }
+    }
+
+    /// Returns the start offset in bytes and the committed bytes of the
+    /// recording, including a partially committed byte, and restarts it.
+    pub fn take_recording(&mut self) -> (u64, Vec<u8>) {
+        let recording = self.recording.take().unwrap_or(Recording {
+            start: self.consumed(),
+            bytes: vec![],
+        });
+        let mut bytes = recording.bytes;
+        if self.cursor > 0 {
+            bytes.push(self.bytes[0]);
+        }
+        self.record();
+        (recording.start / 8, bytes)
+    }
+
+    /// Returns the bytes that hold the bits read since the last commit.
+    pub fn uncommitted(&self) -> Vec<u8> {
+        self.bytes
+            .range(..(self.cursor + 7) >> 3)
+            .copied()
+            .collect()
+    }
+
+    /// Returns all bits read since the last commit to the buffer, so
+    /// that a partially decoded packet can be decoded anew once more
+    /// data is available.
+    pub fn rewind(&mut self) {
+        self.cursor = self.mark;
+    }
+
+    /// Drops all bits that are yet to be read, as if they were read.
+    pub fn drop_all(&mut self) {
+        self.dropped += self.bytes.len() as u64 * 8;
+        self.bytes.clear();
+        self.cursor = 0;
+        self.mark = 0;
+    }
+
+    /// Appends bytes to the end of the stream.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    /// Number of bytes that are yet to be read in full.
+    pub fn len(&self) -> usize {
+        self.bytes.len() - self.cursor / 8
+    }
+
+    /// Number of bits that are yet to be read.
+    fn available(&self) -> usize {
+        self.bytes.len() * 8 - self.cursor
+    }
+
+    /// Pops a single bit from the buffer. Fails with
+    /// [`Eof`](DecoderErrorInt::Eof) if the buffer is empty.
+    pub fn pop_bit(&mut self) -> Result<bool, DecoderErrorInt> {
+        if self.available() == 0 {
+            return Err(DecoderErrorInt::Eof);
         }
+        let bit = (self.bytes[self.cursor / 8] >> (self.cursor % 8)) & 1;
+        self.cursor += 1;
+        Ok(bit != 0)
+    }
 
+    /// Pops a single byte from the buffer.
+    pub fn pop_byte(&mut self) -> Result<u8, DecoderErrorInt> {
+        if self.available() < 8 {
+            return Err(DecoderErrorInt::Eof);
+        }
+        let (i, shift) = (self.cursor / 8, self.cursor % 8);
+        let b = if shift == 0 {
+            self.bytes[i]
+        } else {
+            (self.bytes[i] >> shift) | (self.bytes[i + 1] << (8 - shift))
+        };
+        self.cursor += 8;
         Ok(b)
     }
 
-    /// Pops `cnt` bytes from the buffer. Tries to buffer if more data
-    /// is needed.
-    pub fn pop_bytes(&mut self, cnt: usize) -> Result<Vec<u8>, DecoderErrorInt> {
-        let mut bytes = vec![];
-        for _ in 0..cnt {
-            bytes.push(self.pop_byte()?);
+    /// Pops `cnt` bytes, at most 4, from the buffer.
+    pub fn pop_bytes(&mut self, cnt: usize) -> Result<Payload, DecoderErrorInt> {
+        let mut bytes = [0; 4];
+        for b in &mut bytes[..cnt] {
+            *b = self.pop_byte()?;
         }
 
-        Ok(bytes)
+        Ok(Payload::new(&bytes[..cnt]).unwrap())
     }
 
     /// Pops bytes from the incoming buffer until the continuation-bit
     /// is not set. All [TracePacket]s with a defined payload follow
-    /// this payload schema. (c.f. e.g. Appendix D4, Fig. D4-4)
+    /// this payload schema. (c.f. e.g. Appendix D4, Fig. D4-4) A
+    /// payload that continues beyond `max_len` bytes is malformed.
     #[bitmatch]
-    pub fn pop_payload(&mut self) -> Result<Vec<u8>, DecoderErrorInt> {
-        let mut payload = vec![];
+    pub fn pop_payload(&mut self, max_len: usize) -> Result<&[u8], DecoderErrorInt> {
+        self.payload.clear();
         loop {
             let b = self.pop_byte()?;
-            payload.push(b);
+            self.payload.push(b);
 
             #[bitmatch]
             let "c???_????" = b;
             if c == 0 {
                 break;
             }
+            if self.payload.len() == max_len {
+                return Err(MalformedPacket::InvalidPayloadLength {
+                    max_len,
+                    payload: self.payload.clone(),
+                }
+                .into());
+            }
         }
 
-        Ok(payload)
+        Ok(&self.payload)
     }
 }
 
-/// ITM/DWT packet protocol decoder.
+/// Sans-I/O ITM/DWT packet protocol decoder. Trace data is
+/// [fed](Self::feed) to the decoder as it becomes available, and
+/// decoded packets are [pulled](Self::pull) from it. Unlike
+/// [`Decoder`](Decoder), this decoder does not require the `std`
+/// feature.
+///
+/// ```
+/// use itm::{DecoderOptions, PacketDecoder, TracePacket};
+///
+/// let mut decoder = PacketDecoder::new(DecoderOptions::default());
+/// decoder.feed(&[0b0000_1001]);
+/// assert_eq!(decoder.pull().unwrap(), None); // payload is missing
+/// decoder.feed(&[0x41]);
+/// assert_eq!(
+///     decoder.pull().unwrap(),
+///     Some(TracePacket::Instrumentation {
+///         port: 1,
+///         payload: [0x41].into(),
+///     })
+/// );
+/// ```
+pub struct PacketDecoder {
+    /// Intermediate buffer to store the fed trace byte stream.
+    buffer: Buffer,
+
+    /// Whether the decoder is in a state of synchronization.
+    sync: Option<usize>,
+
+    recovery: RecoveryPolicy,
+
+    arch: ArchVersion,
+
+    /// The current stimulus port page, if tracked. See
+    /// [`DecoderOptions::track_stimulus_page`].
+    stimulus_page: Option<u8>,
+
+    /// Progress of skipping to the next synchronization packet after a
+    /// malformed packet. See [`RecoveryPolicy::SkipToSync`].
+    skip: Option<Skip>,
+
+    /// Whether the synchronization packet that ended a
+    /// [skip](Self::skip) is yet to be returned.
+    resynchronized: bool,
+
+    /// The result of a [peek](Self::peek), to be returned next.
+    peeked: Option<Result<TracePacket, DecoderErrorInt>>,
+
+    /// See [`DecoderOptions::max_buffered`].
+    max_buffered: Option<usize>,
+
+    /// See [`DecoderOptions::lenient_sync`].
+    lenient_sync: bool,
+}
+
+/// An item together with where it was decoded from. See
+/// [`PacketDecoder::pull_annotated`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Annotated<T> {
+    /// Offset in bytes from the start of the stream of the byte that
+    /// holds the first bit of the item.
+    pub offset: u64,
+
+    /// The bytes the item was decoded from. A
+    /// [`Sync`](TracePacket::Sync) packet need not be byte-aligned, so
+    /// the first and last byte may hold bits of adjacent packets.
+    pub bytes: Vec<u8>,
+
+    /// The decoded item.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub item: T,
+}
+
+/// Decodes a complete capture held in memory with the default
+/// [`DecoderOptions`]. An incomplete packet at the end of the capture
+/// is ignored.
+///
+/// ```
+/// use itm::TracePacket;
+///
+/// // Overflow, instrumentation packet on port 1
+/// let packets: Vec<_> = itm::decode_all(&[0b0111_0000, 0b0000_1001, 0x41])
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(
+///     packets,
+///     [
+///         TracePacket::Overflow,
+///         TracePacket::Instrumentation {
+///             port: 1,
+///             payload: [0x41].into(),
+///         }
+///     ]
+/// );
+/// ```
+pub fn decode_all(bytes: &[u8]) -> impl Iterator<Item = Result<TracePacket, DecoderError>> {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    decoder.feed(bytes);
+    core::iter::from_fn(move || decoder.pull().transpose())
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Skip {
+    /// The malformed packet that caused the skip, if any. See
+    /// [`PacketDecoder::resync`].
+    cause: Option<MalformedPacket>,
+
+    /// Number of bits dropped so far.
+    bits: usize,
+
+    /// Number of consecutive zero bits most recently dropped.
+    zeros: usize,
+}
+
+/// ITM/DWT packet protocol decoder that reads the trace byte stream
+/// from a [`Read`](Read).
+#[cfg(feature = "std")]
 pub struct Decoder<R>
 where
     R: Read,
 {
-    /// Intermediate buffer to store the trace byte stream read from the
-    /// given [Read] instance.
-    buffer: Buffer<R>,
+    reader: R,
+    decoder: PacketDecoder,
+    ignore_eof: bool,
+    discontinuity: Option<Discontinuity>,
+}
 
-    /// Whether the decoder is in a state of synchronization.
-    sync: Option<usize>,
+/// A signal of a discontinuity of a trace stream, e.g. a re-established
+/// trace connection, shared between the [`Read`](Read) that produces the
+/// stream and the [`Decoder`](Decoder) of it. See
+/// [`Decoder::resync_on`].
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct Discontinuity(std::sync::Arc<core::sync::atomic::AtomicBool>);
+
+#[cfg(feature = "std")]
+impl Discontinuity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Signals a discontinuity. Call before the first read of the data
+    /// after the discontinuity returns.
+    pub fn signal(&self) {
+        self.0.store(true, core::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Whether a discontinuity was signalled since the last call.
+    fn take(&self) -> bool {
+        self.0.swap(false, core::sync::atomic::Ordering::SeqCst)
+    }
 }
 
+#[cfg(feature = "std")]
 impl<R> Decoder<R>
 where
     R: Read,
 {
     pub fn new(reader: R, options: DecoderOptions) -> Decoder<R> {
         Decoder {
-            buffer: Buffer::new(reader, options.ignore_eof),
-            sync: None,
+            reader,
+            ignore_eof: options.ignore_eof,
+            discontinuity: None,
+            decoder: PacketDecoder::new(DecoderOptions {
+                max_buffered: None,
+                ..options
+            }),
         }
     }
 
     /// Returns a reference to the underlying [`Read`](Read).
     pub fn get_ref(&self) -> &R {
-        &self.buffer.reader
+        &self.reader
     }
 
     /// Returns a mutable reference to the underlying [`Read`](Read).
     pub fn get_mut(&mut self) -> &mut R {
-        &mut self.buffer.reader
+        &mut self.reader
+    }
+
+    /// [Resyncs](PacketDecoder::resync) the decoder whenever the
+    /// [`Read`](Read) signals a `discontinuity`, before the data read
+    /// after it is decoded.
+    ///
+    /// ```
+    /// use itm::{Decoder, DecoderOptions, Discontinuity, TracePacket};
+    /// use std::io::Read;
+    ///
+    /// // Yields the first half of an instrumentation packet, then, after
+    /// // a reconnection, a synchronization and an overflow packet.
+    /// struct Connection(Vec<&'static [u8]>, Discontinuity);
+    ///
+    /// impl Read for Connection {
+    ///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    ///         if self.0.len() == 1 {
+    ///             self.1.signal();
+    ///         }
+    ///         match self.0.pop() {
+    ///             Some(data) => {
+    ///                 buf[..data.len()].copy_from_slice(data);
+    ///                 Ok(data.len())
+    ///             }
+    ///             None => Ok(0),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let discontinuity = Discontinuity::new();
+    /// let connection = Connection(
+    ///     vec![&[0, 0, 0, 0, 0, 0x80, 0x70], &[0x0b, 0x41]],
+    ///     discontinuity.clone(),
+    /// );
+    /// let packets: Vec<_> = Decoder::new(connection, DecoderOptions::default())
+    ///     .resync_on(discontinuity)
+    ///     .singles()
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    /// assert_eq!(packets, [TracePacket::Sync, TracePacket::Overflow]);
+    /// ```
+    pub fn resync_on(mut self, discontinuity: Discontinuity) -> Self {
+        self.discontinuity = Some(discontinuity);
+        self
+    }
+
+    /// Number of bits of the stream that make up all packets decoded so
+    /// far.
+    pub(crate) fn bit_offset(&self) -> u64 {
+        self.decoder.bit_offset()
     }
 
     /// Returns an iterator over [`TracePacket`](TracePacket)s. Consumes
@@ -550,6 +1327,17 @@ where
         Singles::new(self)
     }
 
+    /// Returns an iterator over [`TracePacket`](TracePacket)s for a
+    /// non-blocking [`Read`](Read). Where [`singles`](Self::singles)
+    /// would yield an [`Io`](DecoderError::Io) error of kind
+    /// [`WouldBlock`](std::io::ErrorKind::WouldBlock), this iterator
+    /// yields [`Polled::NeedMoreData`] instead, and can be resumed
+    /// without losing any partially decoded packet. Consumes the
+    /// [`Decoder`](Decoder).
+    pub fn non_blocking(self) -> NonBlocking<R> {
+        NonBlocking::new(self)
+    }
+
     /// Returns an iterator over
     /// [`TimestampedTracePackets`](TimestampedTracePackets). Consumes
     /// the [`Decoder`](Decoder).
@@ -563,14 +1351,334 @@ where
         Timestamps::new(self, options)
     }
 
-    /// Returns the next [TracePacket] in the stream.
+    /// Returns an iterator over [`TracePacket`](TracePacket)s
+    /// interleaved with detected transport losses. Consumes the
+    /// [`Decoder`](Decoder). See [`loss`].
+    pub fn detect_loss(self, options: loss::LossDetectionConfiguration) -> loss::LossDetector<R> {
+        loss::LossDetector::new(self, options)
+    }
+
+    /// Returns the next [TracePacket] in the stream, reading more data
+    /// from the [`Read`](Read) as needed.
     fn next_single(&mut self) -> Result<TracePacket, DecoderErrorInt> {
+        loop {
+            match self.decoder.next_single() {
+                Err(DecoderErrorInt::Eof) => self.buffer_some()?,
+                packet => return packet,
+            }
+        }
+    }
+
+    /// Tries to read up to 32 bytes from [Self::reader]. Continuously retries if [ignore_eof] is set.
+    fn buffer_some(&mut self) -> Result<(), DecoderErrorInt> {
+        // `Read::read` reportedly reads in 32-byte chunks. Source:
+        // <https://github.com/rust-embedded/itm/blob/3e4251b42aa2e4b05ae372c47c7b835b8acae6dc/src/lib.rs#L42>.
+        let mut buffer: [u8; 32] = [0; 32];
+        loop {
+            match self.reader.read(&mut buffer) {
+                Ok(0) => {
+                    if self.ignore_eof {
+                        continue;
+                    }
+                    return Err(DecoderErrorInt::Eof);
+                }
+                Ok(n) => {
+                    if matches!(&self.discontinuity, Some(d) if d.take()) {
+                        self.decoder.resync();
+                    }
+                    self.decoder.feed(&buffer[0..n]);
+                    return Ok(());
+                }
+                Err(e) => {
+                    // XXX any other errors we should retry on?
+                    if e.kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+}
+
+impl PacketDecoder {
+    pub fn new(options: DecoderOptions) -> PacketDecoder {
+        PacketDecoder {
+            buffer: Buffer::new(),
+            sync: None,
+            recovery: options.recovery,
+            arch: options.arch,
+            stimulus_page: if options.track_stimulus_page {
+                Some(0)
+            } else {
+                None
+            },
+            skip: None,
+            resynchronized: false,
+            peeked: None,
+            max_buffered: options.max_buffered.map(|max| max.max(MAX_PACKET_SIZE)),
+            lenient_sync: options.lenient_sync,
+        }
+    }
+
+    /// Appends trace data to the end of the stream. Returns the number
+    /// of bytes accepted, which is less than `data.len()` if the
+    /// [limit](DecoderOptions::max_buffered) on buffered data would
+    /// be exceeded. The remaining bytes must be fed again after
+    /// packets have been [pulled](Self::pull).
+    pub fn feed(&mut self, data: &[u8]) -> usize {
+        let n = match self.max_buffered {
+            Some(max) => data.len().min(max.saturating_sub(self.buffer.len())),
+            None => data.len(),
+        };
+        self.buffer.push_bytes(&data[..n]);
+        n
+    }
+
+    /// Decodes the next packet. Returns `Ok(None)` if more data must be
+    /// [fed](Self::feed) first; a partially decoded packet is then
+    /// resumed on the next call.
+    pub fn pull(&mut self) -> Result<Option<TracePacket>, DecoderError> {
+        self.buffer.recording = None;
+        match self.next_single() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(e) => match e.into_public() {
+                None => Ok(None),
+                Some(e) => Err(e),
+            },
+        }
+    }
+
+    /// Decodes all packets of the data fed so far, up to the first
+    /// error. The decoded packets are returned together with the error,
+    /// if any; decoding continues after the error on the next call.
+    pub fn pull_all(&mut self) -> (Vec<TracePacket>, Option<DecoderError>) {
+        self.buffer.recording = None;
+        let mut packets = vec![];
+        loop {
+            match self.next_single() {
+                Ok(packet) => packets.push(packet),
+                Err(e) => return (packets, e.into_public()),
+            }
+        }
+    }
+
+    /// Decodes the next packet without consuming it: it is returned
+    /// again by the next call to [`pull`](Self::pull) or
+    /// [`pull_annotated`](Self::pull_annotated). Returns `Ok(None)` if
+    /// more data must be [fed](Self::feed) first.
+    pub fn peek(&mut self) -> Result<Option<&TracePacket>, DecoderError> {
+        if self.peeked.is_none() {
+            // Keep the bytes of the packet in case it is pulled
+            // annotated.
+            self.buffer.record();
+            match self.next_single() {
+                Err(DecoderErrorInt::Eof) => return Ok(None),
+                packet => self.peeked = Some(packet),
+            }
+        }
+
+        match self.peeked.as_ref().unwrap() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(e) => Err(e.clone_public()),
+        }
+    }
+
+    /// Like [`pull`](Self::pull), but annotates the decoded packet, or
+    /// the error, with its position in the stream and the bytes it was
+    /// decoded from. Returns `None` if more data must be
+    /// [fed](Self::feed) first.
+    ///
+    /// For a [`Resynchronized`](DecoderError::Resynchronized) error,
+    /// the bytes include the skipped data.
+    pub fn pull_annotated(&mut self) -> Option<Annotated<Result<TracePacket, DecoderError>>> {
+        self.buffer.record();
+        let item = match self.next_single() {
+            Ok(packet) => Ok(packet),
+            Err(e) => Err(e.into_public()?),
+        };
+        let (offset, bytes) = self.buffer.take_recording();
+        Some(Annotated {
+            offset,
+            bytes,
+            item,
+        })
+    }
+
+    /// Like [`pull`](Self::pull), but reports an error together with
+    /// where and in which state of the decoder it occurred.
+    pub fn pull_with_context(&mut self) -> Result<Option<TracePacket>, ContextualError> {
+        let state = self.state();
+        match self.pull_annotated() {
+            None => Ok(None),
+            Some(Annotated {
+                item: Ok(packet), ..
+            }) => Ok(Some(packet)),
+            Some(Annotated {
+                offset,
+                item: Err(error),
+                ..
+            }) => Err(ContextualError {
+                error,
+                offset,
+                state,
+            }),
+        }
+    }
+
+    /// The current state of the decoder.
+    pub fn state(&self) -> DecoderState {
+        if self.skip.is_some() {
+            DecoderState::Skipping
+        } else if self.sync.is_some() {
+            DecoderState::Synchronizing
+        } else {
+            DecoderState::Decoding
+        }
+    }
+
+    /// Handles a discontinuity of the stream, e.g. a re-established
+    /// trace connection, after which the stream may resume in the
+    /// middle of a packet: drops the data fed so far that is yet to be
+    /// decoded, including a partially decoded packet, and skips the data
+    /// fed hereafter up to the next [`Sync`](TracePacket::Sync) packet,
+    /// which is then decoded as usual. Unlike after a malformed packet
+    /// (see [`RecoveryPolicy::SkipToSync`]), no error is reported.
+    pub fn resync(&mut self) {
+        self.buffer.drop_all();
+        self.sync = None;
+        self.peeked = None;
+        self.resynchronized = false;
+        self.skip = Some(Skip {
+            cause: None,
+            bits: 0,
+            zeros: 0,
+        });
+    }
+
+    /// Number of bits of the stream that make up all packets decoded so
+    /// far.
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn bit_offset(&self) -> u64 {
+        self.buffer.consumed()
+    }
+
+    /// Returns the next [TracePacket] in the stream. If the stream
+    /// ends midway through a packet, the partially decoded packet is
+    /// rewound so that a later call can resume decoding it in full.
+    fn next_single(&mut self) -> Result<TracePacket, DecoderErrorInt> {
+        if let Some(packet) = self.peeked.take() {
+            return packet;
+        }
+
+        let packet = self.next_untracked()?;
+        let page = match self.stimulus_page.as_mut() {
+            Some(page) => page,
+            None => return Ok(packet),
+        };
+
+        match packet {
+            TracePacket::Sync => *page = 0,
+            TracePacket::Extension { page: p } => *page = p,
+            TracePacket::Instrumentation { port, payload } => {
+                return Ok(TracePacket::Instrumentation {
+                    port: (*page << 5) | port,
+                    payload,
+                });
+            }
+            _ => (),
+        }
+        Ok(packet)
+    }
+
+    /// Returns the next [TracePacket] in the stream without regard to
+    /// the stimulus port page.
+    fn next_untracked(&mut self) -> Result<TracePacket, DecoderErrorInt> {
+        if self.resynchronized {
+            self.resynchronized = false;
+            return Ok(TracePacket::Sync);
+        }
+        if self.skip.is_some() {
+            return self.skip_to_sync();
+        }
+
+        let sync = self.sync;
+        let packet = self.decode_single();
+        match packet {
+            // Zeros of a synchronization packet are counted, so that a
+            // long run of zeros is not buffered in full.
+            Err(DecoderErrorInt::Eof) if self.sync.is_some() => self.buffer.commit(),
+            Err(DecoderErrorInt::Eof) => {
+                self.buffer.rewind();
+                self.sync = sync;
+            }
+            Err(DecoderErrorInt::MalformedPacket(cause))
+                if self.recovery == RecoveryPolicy::SkipToSync =>
+            {
+                self.buffer.commit();
+                self.sync = None;
+                self.skip = Some(Skip {
+                    cause: Some(cause),
+                    bits: 0,
+                    zeros: 0,
+                });
+                return self.skip_to_sync();
+            }
+            Err(DecoderErrorInt::MalformedPacket(_))
+                if self.recovery == RecoveryPolicy::ReportUnknown =>
+            {
+                let bytes = self.buffer.uncommitted();
+                self.buffer.commit();
+                self.sync = None;
+                return Ok(TracePacket::Unknown {
+                    header: bytes[0],
+                    payload: bytes[1..].to_vec(),
+                });
+            }
+            Ok(_) | Err(_) => self.buffer.commit(),
+        }
+
+        packet
+    }
+
+    /// Drops bits until a synchronization packet has been popped. The
+    /// dropped bits are not rewound if the stream ends first; skipping
+    /// continues on the next call instead.
+    fn skip_to_sync(&mut self) -> Result<TracePacket, DecoderErrorInt> {
+        loop {
+            let bit = self.buffer.pop_bit();
+            self.buffer.commit();
+            let skip = self.skip.as_mut().unwrap();
+
+            match bit? {
+                false => skip.zeros += 1,
+                true if skip.zeros >= SYNC_MIN_ZEROS => {
+                    let skip = self.skip.take().unwrap();
+                    let cause = match skip.cause {
+                        Some(cause) => cause,
+                        // A resync is not an error.
+                        None => return Ok(TracePacket::Sync),
+                    };
+                    self.resynchronized = true;
+                    let skipped = skip.bits - skip.zeros;
+                    return Err(DecoderErrorInt::Resynchronized {
+                        cause,
+                        skipped: (skipped + 7) >> 3,
+                    });
+                }
+                true => skip.zeros = 0,
+            }
+            skip.bits += 1;
+        }
+    }
+
+    fn decode_single(&mut self) -> Result<TracePacket, DecoderErrorInt> {
         if self.sync.is_some() {
             return self.handle_sync();
         }
         assert!(self.sync.is_none());
 
-        match decode_header(self.buffer.pop_byte()?)? {
+        match decode_header(self.buffer.pop_byte()?, self.arch)? {
             HeaderVariant::Packet(p) => Ok(p),
             HeaderVariant::Stub(s) => self.process_stub(&s),
         }
@@ -580,21 +1688,12 @@ where
     /// realigns the incoming bitstream for further processing, which
     /// broke alignment on target-generated overflow packet.
     fn handle_sync(&mut self) -> Result<TracePacket, DecoderErrorInt> {
-        let zeros = self.sync.unwrap();
-        match (self.buffer.pop_bit()?, zeros) {
-            (true, zeros) if zeros >= SYNC_MIN_ZEROS => {
-                self.sync = None;
-                Ok(TracePacket::Sync)
-            }
-            (true, zeros) if zeros < SYNC_MIN_ZEROS => {
-                self.sync = None;
-                Err(MalformedPacket::InvalidSync(zeros).into())
-            }
-            (false, _) => {
-                *self.sync.as_mut().unwrap() += 1;
-                self.handle_sync()
-            }
-            (true, _) => unreachable!(),
+        while !self.buffer.pop_bit()? {
+            *self.sync.as_mut().unwrap() += 1;
+        }
+        match self.sync.take().unwrap() {
+            zeros if zeros >= SYNC_MIN_ZEROS || self.lenient_sync => Ok(TracePacket::Sync),
+            zeros => Err(MalformedPacket::InvalidSync(zeros).into()),
         }
     }
 
@@ -611,18 +1710,32 @@ where
                 expected_size,
             } => {
                 let payload = self.buffer.pop_bytes(*expected_size)?;
-                handle_hardware_source(*disc_id, payload).map_err(DecoderErrorInt::MalformedPacket)
+                handle_hardware_source(*disc_id, payload, self.arch)
+                    .map_err(DecoderErrorInt::MalformedPacket)
             }
             PacketStub::LocalTimestamp { data_relation } => {
-                let payload = self.buffer.pop_payload()?;
+                let payload = self.buffer.pop_payload(4)?;
                 Ok(TracePacket::LocalTimestamp1 {
                     data_relation: data_relation.clone(),
                     // MAGIC(27): c.f. Appendix D4.2.4
                     ts: extract_timestamp(payload, 27) as u32,
                 })
             }
+            PacketStub::Extension { page } => {
+                let payload = self.buffer.pop_payload(4)?;
+                // The payload continues the page number above bits[2:0]
+                let page = (extract_timestamp(payload, 27) << 3) as u32 | u32::from(*page);
+                match u8::try_from(page) {
+                    // Ports above 255 do not exist
+                    Ok(page) if page > 7 && self.stimulus_page.is_some() => {
+                        Err(MalformedPacket::InvalidExtension { page: page.into() }.into())
+                    }
+                    Ok(page) => Ok(TracePacket::Extension { page }),
+                    Err(_) => Err(MalformedPacket::InvalidExtension { page }.into()),
+                }
+            }
             PacketStub::GlobalTimestamp1 => {
-                let payload = self.buffer.pop_payload()?;
+                let payload = self.buffer.pop_payload(4)?;
                 #[bitmatch]
                 let "?wc?_????" = payload.last().unwrap();
 
@@ -634,10 +1747,10 @@ where
                 })
             }
             PacketStub::GlobalTimestamp2 => {
-                let payload = self.buffer.pop_payload()?;
+                let payload = self.buffer.pop_payload(6)?;
                 Ok(TracePacket::GlobalTimestamp2 {
                     ts: extract_timestamp(
-                        payload.to_vec(),
+                        payload,
                         match payload.len() {
                             4 => 47 - 26, // 48 bit timestamp
                             6 => 63 - 26, // 64 bit timestamp
@@ -666,8 +1779,17 @@ where
     }
 }
 
+/// Returns the exception number of the given exception. (Table B1-4)
+fn exception_number(exception: &VectActive) -> u16 {
+    match exception {
+        VectActive::ThreadMode => 0,
+        VectActive::Exception(ex) => (ex.irqn() as i16 + 16) as u16,
+        VectActive::Interrupt { irqn } => *irqn + 16,
+    }
+}
+
 // TODO template this for u32, u64?
-fn extract_timestamp(payload: Vec<u8>, max_len: u64) -> u64 {
+fn extract_timestamp(payload: &[u8], max_len: u64) -> u64 {
     // Decode the first N - 1 payload bytes
     let (rtail, head) = payload.split_at(payload.len() - 1);
     let mut ts: u64 = 0;
@@ -686,7 +1808,7 @@ fn extract_timestamp(payload: Vec<u8>, max_len: u64) -> u64 {
 /// Decodes the first byte of a packet, the header, into a complete packet or a packet stub.
 #[allow(clippy::bad_bit_mask)]
 #[bitmatch]
-fn decode_header(header: u8) -> Result<HeaderVariant, MalformedPacket> {
+fn decode_header(header: u8, arch: ArchVersion) -> Result<HeaderVariant, MalformedPacket> {
     fn translate_ss(ss: u8) -> Option<usize> {
         // See (Appendix D4.2.8, Table D4-4)
         Some(
@@ -739,6 +1861,10 @@ fn decode_header(header: u8) -> Result<HeaderVariant, MalformedPacket> {
             // Extension packet
             packet(TracePacket::Extension { page: p })
         }
+        "1ppp_1000" if arch == ArchVersion::V8M => {
+            // Extension packet with payload
+            stub(PacketStub::Extension { page: p })
+        }
 
         // Source packet category
         "aaaa_a0ss" => {
@@ -779,31 +1905,32 @@ fn decode_header(header: u8) -> Result<HeaderVariant, MalformedPacket> {
 
 /// Decodes the payload of a hardware source packet.
 #[bitmatch]
-fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket, MalformedPacket> {
+fn handle_hardware_source(
+    disc_id: u8,
+    payload: Payload,
+    arch: ArchVersion,
+) -> Result<TracePacket, MalformedPacket> {
     match disc_id {
         0 => {
             // event counter wrap
 
             if payload.len() != 1 {
-                return Err(MalformedPacket::InvalidHardwarePacket { disc_id, payload });
+                return Err(MalformedPacket::InvalidHardwarePacket {
+                    disc_id,
+                    payload: payload.to_vec(),
+                });
             }
 
-            #[bitmatch]
-            let "??yf_lsec" = payload[0];
-            Ok(TracePacket::EventCounterWrap {
-                cyc: y != 0,
-                fold: f != 0,
-                lsu: l != 0,
-                sleep: s != 0,
-                exc: e != 0,
-                cpi: c != 0,
-            })
+            Ok(DwtCounterWrap::from_bits_truncate(payload[0]).into())
         }
         1 => {
             // exception trace
 
             if payload.len() != 2 {
-                return Err(MalformedPacket::InvalidHardwarePacket { disc_id, payload });
+                return Err(MalformedPacket::InvalidHardwarePacket {
+                    disc_id,
+                    payload: payload.to_vec(),
+                });
             }
 
             #[bitmatch]
@@ -837,9 +1964,11 @@ fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket,
             match payload.len() {
                 1 if payload[0] == 0 => Ok(TracePacket::PCSample { pc: None }),
                 4 => Ok(TracePacket::PCSample {
-                    pc: Some(u32::from_le_bytes(payload.try_into().unwrap())),
+                    pc: Some(u32::from_le_bytes(payload[..].try_into().unwrap())),
+                }),
+                _ => Err(MalformedPacket::InvalidPCSampleSize {
+                    payload: payload.to_vec(),
                 }),
-                _ => Err(MalformedPacket::InvalidPCSampleSize { payload }),
             }
         }
         8..=23 => {
@@ -848,15 +1977,20 @@ fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket,
             let "???t_tccd" = disc_id; // we have already masked out bit[2:0]
             let comparator = c;
 
+            let v8m = arch == ArchVersion::V8M;
             match (t, d, payload.len()) {
+                (0b01, 0, 1) if v8m && payload[0] == 1 => {
+                    // match packet
+                    Ok(TracePacket::DataTraceMatch { comparator })
+                }
                 (0b01, 0, 4) => {
                     // PC value packet
                     Ok(TracePacket::DataTracePC {
                         comparator,
-                        pc: u32::from_le_bytes(payload.try_into().unwrap()),
+                        pc: u32::from_le_bytes(payload[..].try_into().unwrap()),
                     })
                 }
-                (0b01, 1, 2) => {
+                (0b01, 1, n) if n == 2 || (n == 4 && v8m) => {
                     // address packet
                     Ok(TracePacket::DataTraceAddress {
                         comparator,
@@ -875,7 +2009,10 @@ fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket,
                         value: payload,
                     })
                 }
-                _ => Err(MalformedPacket::InvalidHardwarePacket { disc_id, payload }),
+                _ => Err(MalformedPacket::InvalidHardwarePacket {
+                    disc_id,
+                    payload: payload.to_vec(),
+                }),
             }
         }
         _ => unreachable!(), // we already verify the discriminator when we decode the header
@@ -889,7 +2026,8 @@ mod decoder_buffer_utils {
     #[test]
     fn buffer_pop_bytes() {
         let bytes: &[u8] = &[0b1000_0000, 0b1010_0000, 0b1000_0100, 0b0110_0000];
-        let mut decoder = Decoder::new(bytes, DecoderOptions { ignore_eof: false });
+        let mut decoder = PacketDecoder::new(DecoderOptions::default());
+        decoder.feed(bytes);
 
         assert_eq!(decoder.buffer.pop_bytes(3).unwrap().len(), 3);
     }
@@ -903,9 +2041,10 @@ mod decoder_buffer_utils {
             0b1000_0100,
             0b0110_0000
         ];
-        let mut decoder = Decoder::new(payload, DecoderOptions { ignore_eof: false });
+        let mut decoder = PacketDecoder::new(DecoderOptions::default());
+        decoder.feed(payload);
 
-        assert_eq!(decoder.buffer.pop_payload().unwrap(), payload);
+        assert_eq!(decoder.buffer.pop_payload(4).unwrap(), payload);
     }
 
     #[test]
@@ -918,7 +2057,7 @@ mod decoder_buffer_utils {
             0b0000_0000,
         ].to_vec();
 
-        assert_eq!(super::extract_timestamp(ts, 25), 0);
+        assert_eq!(super::extract_timestamp(&ts, 25), 0);
 
         #[rustfmt::skip]
         let ts: Vec<u8> = [
@@ -929,7 +2068,7 @@ mod decoder_buffer_utils {
         ].to_vec();
 
         assert_eq!(
-            super::extract_timestamp(ts, 27),
+            super::extract_timestamp(&ts, 27),
             0b1111111_0011111_0000111_0000001,
         );
 
@@ -942,7 +2081,7 @@ mod decoder_buffer_utils {
         ].to_vec();
 
         assert_eq!(
-            super::extract_timestamp(ts, 25),
+            super::extract_timestamp(&ts, 25),
             0b11111_0011111_0000111_0000001,
         );
     }
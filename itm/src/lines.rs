@@ -0,0 +1,312 @@
+//! Splitting of instrumentation payloads into lines of text.
+
+use super::{DecoderError, Timestamp, TimestampedTracePackets, TracePacket};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::iter;
+use std::string::FromUtf8Error;
+
+/// [`LineSplitter`](LineSplitter) and [`Lines`](Lines) configuration.
+#[derive(Debug, Clone)]
+pub struct LinesOptions {
+    /// Bytes that terminate a line. The delimiter itself is not part of
+    /// the line. Defaults to `\n`.
+    pub delimiters: Vec<u8>,
+
+    /// Whether to replace invalid UTF-8 sequences with `U+FFFD
+    /// REPLACEMENT CHARACTER`. If not set, such lines are reported as
+    /// [`LinesError::InvalidUtf8`](LinesError::InvalidUtf8). Defaults
+    /// to `true`.
+    pub lossy: bool,
+}
+
+impl Default for LinesOptions {
+    fn default() -> Self {
+        Self {
+            delimiters: vec![b'\n'],
+            lossy: true,
+        }
+    }
+}
+
+/// A line of text written to a stimulus port, without its delimiter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Line {
+    /// Stimulus port the text was written to.
+    pub port: u8,
+
+    /// The text itself.
+    pub text: String,
+
+    /// Timestamp of the packet that completed the line, if the packets
+    /// were timestamped.
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Set of errors that can occur while splitting lines.
+#[derive(Debug, thiserror::Error)]
+pub enum LinesError {
+    /// The packets could not be decoded.
+    #[error(transparent)]
+    Decoder(#[from] DecoderError),
+
+    /// A line is not valid UTF-8, and
+    /// [`LinesOptions::lossy`](LinesOptions::lossy) is not set.
+    #[error("Line on port {port} is not valid UTF-8: {error}")]
+    InvalidUtf8 {
+        /// Stimulus port the line was written to.
+        port: u8,
+
+        /// The conversion error, which holds the bytes of the line.
+        error: FromUtf8Error,
+    },
+}
+
+/// Accumulates payloads per stimulus port and splits them into
+/// [`Line`](Line)s. Payloads are [pushed](Self::push) as they are
+/// decoded, and complete lines are [pulled](Self::pull) out.
+///
+/// ```
+/// use itm::{LineSplitter, LinesOptions};
+///
+/// let mut lines = LineSplitter::new(LinesOptions::default());
+/// lines.push(0, b"he");
+/// lines.push(1, b"x\n");
+/// lines.push(0, b"y\n");
+/// let port_1 = lines.pull().unwrap().unwrap();
+/// assert_eq!((port_1.port, port_1.text.as_str()), (1, "x"));
+/// let port_0 = lines.pull().unwrap().unwrap();
+/// assert_eq!((port_0.port, port_0.text.as_str()), (0, "hey"));
+/// assert!(lines.pull().is_none());
+/// ```
+pub struct LineSplitter {
+    options: LinesOptions,
+    partial: BTreeMap<u8, Vec<u8>>,
+    complete: VecDeque<(u8, Vec<u8>)>,
+}
+
+impl LineSplitter {
+    pub fn new(options: LinesOptions) -> Self {
+        Self {
+            options,
+            partial: BTreeMap::new(),
+            complete: VecDeque::new(),
+        }
+    }
+
+    /// Appends the payload of an
+    /// [`Instrumentation`](TracePacket::Instrumentation) packet to the
+    /// line of its port.
+    pub fn push(&mut self, port: u8, payload: &[u8]) {
+        let line = self.partial.entry(port).or_default();
+        for b in payload {
+            if self.options.delimiters.contains(b) {
+                self.complete.push_back((port, std::mem::take(line)));
+            } else {
+                line.push(*b);
+            }
+        }
+    }
+
+    /// Completes all non-empty unterminated lines, e.g. when the trace
+    /// stream has ended.
+    pub fn finish(&mut self) {
+        for (port, line) in self.partial.iter_mut() {
+            if !line.is_empty() {
+                self.complete.push_back((*port, std::mem::take(line)));
+            }
+        }
+    }
+
+    /// Pulls the next complete line, in the order of completion. The
+    /// returned line is not timestamped.
+    pub fn pull(&mut self) -> Option<Result<Line, LinesError>> {
+        let (port, bytes) = self.complete.pop_front()?;
+        let text = if self.options.lossy {
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        } else {
+            String::from_utf8(bytes).map_err(|error| LinesError::InvalidUtf8 { port, error })
+        };
+        Some(text.map(|text| Line {
+            port,
+            text,
+            timestamp: None,
+        }))
+    }
+}
+
+/// An item that [`Lines`](Lines) can split into lines: either a single
+/// [`TracePacket`](TracePacket), or a set of
+/// [`TimestampedTracePackets`](TimestampedTracePackets), whose timestamp
+/// the yielded lines are tagged with.
+pub trait LinesInput {
+    type Packets: IntoIterator<Item = TracePacket>;
+
+    /// Splits the item into its packets and their timestamp.
+    fn into_packets(self) -> (Self::Packets, Option<Timestamp>);
+}
+
+impl LinesInput for TracePacket {
+    type Packets = iter::Once<TracePacket>;
+
+    fn into_packets(self) -> (Self::Packets, Option<Timestamp>) {
+        (iter::once(self), None)
+    }
+}
+
+impl LinesInput for TimestampedTracePackets {
+    type Packets = Vec<TracePacket>;
+
+    fn into_packets(self) -> (Self::Packets, Option<Timestamp>) {
+        (self.packets, Some(self.timestamp))
+    }
+}
+
+/// Iterator adapter that yield the [`Line`](Line)s written to all
+/// stimulus ports, from an iterator over either
+/// [`TracePacket`](TracePacket)s, e.g. [`Singles`](super::Singles), or
+/// [`TimestampedTracePackets`](TimestampedTracePackets), e.g.
+/// [`Timestamps`](super::Timestamps). Unterminated lines are yielded
+/// when the inner iterator ends. See also
+/// [`LineSplitter`](LineSplitter).
+///
+/// ```
+/// use itm::{Lines, LinesOptions};
+///
+/// // Instrumentation packets on port 0 with payloads "hi" and "\n"
+/// let stream = [0b0000_0010, b'h', b'i', 0b0000_0001, b'\n'];
+/// let lines: Vec<_> = Lines::new(itm::decode_all(&stream), LinesOptions::default())
+///     .map(|line| line.unwrap().text)
+///     .collect();
+/// assert_eq!(lines, ["hi"]);
+/// ```
+pub struct Lines<I> {
+    inner: I,
+    splitter: LineSplitter,
+    timestamp: Option<Timestamp>,
+    done: bool,
+}
+
+impl<I, T> Lines<I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    pub fn new(inner: I, options: LinesOptions) -> Self {
+        Self {
+            inner,
+            splitter: LineSplitter::new(options),
+            timestamp: None,
+            done: false,
+        }
+    }
+}
+
+impl<I, T> Iterator for Lines<I>
+where
+    I: Iterator<Item = Result<T, DecoderError>>,
+    T: LinesInput,
+{
+    type Item = Result<Line, LinesError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.splitter.pull() {
+                return Some(line.map(|line| Line {
+                    timestamp: self.timestamp.clone(),
+                    ..line
+                }));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.inner.next() {
+                None => {
+                    self.done = true;
+                    self.splitter.finish();
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                Some(Ok(item)) => {
+                    let (packets, timestamp) = item.into_packets();
+                    self.timestamp = timestamp;
+                    for packet in packets {
+                        if let TracePacket::Instrumentation { port, payload } = packet {
+                            self.splitter.push(port, &payload);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ticks;
+    use std::time::Duration;
+
+    fn instrumentation(port: u8, payload: &[u8]) -> TracePacket {
+        TracePacket::Instrumentation {
+            port,
+            payload: payload.try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let options = LinesOptions {
+            delimiters: vec![b'\n', 0],
+            lossy: false,
+        };
+        let packets = [
+            instrumentation(0, b"ab\nc"),
+            instrumentation(1, &[0xff, 0]),
+            instrumentation(0, b"d\0e"),
+        ];
+        let lines: Vec<_> = Lines::new(packets.iter().cloned().map(Ok), options).collect();
+        let texts: Vec<_> = lines
+            .iter()
+            .map(|line| match line {
+                Ok(line) => Ok((line.port, line.text.as_str())),
+                Err(LinesError::InvalidUtf8 { port, error }) => Err((*port, error.as_bytes())),
+                Err(e) => panic!("{}", e),
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                Ok((0, "ab")),
+                Err((1, &[0xff][..])),
+                Ok((0, "cd")),
+                Ok((0, "e"))
+            ]
+        );
+
+        let set = TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_millis(1)),
+            packets: vec![instrumentation(1, &[0xff, b'\n'])],
+            malformed_packets: vec![],
+            ticks: Ticks {
+                ticks: 0,
+                frequency: 1,
+            },
+            consumed_packets: 1,
+            overflow: None,
+            delta: Duration::ZERO,
+        };
+        let lines: Vec<_> = Lines::new(iter::once(Ok(set)), LinesOptions::default())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            lines,
+            [Line {
+                port: 1,
+                text: "\u{fffd}".to_string(),
+                timestamp: Some(Timestamp::Sync(Duration::from_millis(1))),
+            }]
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Import of logic-analyzer captures of the SWO pin.
+//!
+//! When no SWO capture probe is at hand, the trace stream can be
+//! recovered from a digital capture of the SWO pin. The capture is read
+//! into [`Edge`]s, the level changes of the pin, which a bit-level front
+//! end, e.g. [`nrz::decode`](crate::nrz::decode), turns into the byte
+//! stream for the packet decoder. Captures can be read from
+//! [samples](edges) of the pin, or from the files of logic-analyzer
+//! software. Supported are
+//!
+//! - CSV exports of the Saleae Logic software, with a time column in
+//!   seconds followed by a column per channel, and a row per level
+//!   change ([`saleae_csv`]), and
+//! - sigrok session files (`.sr`), as written by PulseView and
+//!   `sigrok-cli`, with the `sigrok` feature ([`sigrok`]).
+//!
+//! ```
+//! use itm::logic::{saleae_csv, Edge};
+//! use std::time::Duration;
+//!
+//! let csv = "Time [s],Channel 0,Channel 1\n0.000000,1,0\n0.000010,0,0\n0.000020,1,1\n";
+//! let edges = saleae_csv(csv.as_bytes(), "Channel 0").unwrap();
+//! assert_eq!(
+//!     edges,
+//!     [
+//!         Edge { time: Duration::ZERO, level: true },
+//!         Edge { time: Duration::from_micros(10), level: false },
+//!         Edge { time: Duration::from_micros(20), level: true },
+//!     ]
+//! );
+//! ```
+
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+/// A change of the level of a digital signal: from `time`, relative to
+/// the start of the capture, the signal is at `level` (`true` for high).
+/// The first edge of a capture holds the initial level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub time: Duration,
+    pub level: bool,
+}
+
+/// Errors that can occur while importing a logic-analyzer capture.
+#[derive(Debug, thiserror::Error)]
+pub enum LogicError {
+    #[error("Failed to read capture: {0}")]
+    Io(#[from] io::Error),
+
+    #[cfg(feature = "sigrok")]
+    #[error("Failed to read sigrok session file: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("Capture has no channel {0:?}")]
+    NoSuchChannel(String),
+
+    #[error("Malformed capture: {0}")]
+    Malformed(String),
+}
+
+/// Converts the samples of a digital signal, taken at `sample_rate`
+/// samples per second, into its edges.
+pub fn edges<I: IntoIterator<Item = bool>>(samples: I, sample_rate: u64) -> Vec<Edge> {
+    let mut edges: Vec<Edge> = vec![];
+    for (sample, level) in samples.into_iter().enumerate() {
+        if edges.last().map(|e| e.level) != Some(level) {
+            let nanos = sample as u128 * 1_000_000_000 / u128::from(sample_rate);
+            edges.push(Edge {
+                time: Duration::from_nanos(nanos as u64),
+                level,
+            });
+        }
+    }
+    edges
+}
+
+/// Selects a channel by name, or, failing that, by 0-based index.
+fn select(names: &[&str], channel: &str) -> Result<usize, LogicError> {
+    names
+        .iter()
+        .position(|name| name.trim() == channel)
+        .or_else(|| channel.parse().ok().filter(|i| *i < names.len()))
+        .ok_or_else(|| LogicError::NoSuchChannel(channel.to_string()))
+}
+
+/// Reads the edges of `channel` from a Saleae Logic CSV export.
+/// `channel` is a column name, e.g. `"Channel 0"`, or the 0-based index
+/// of a channel column.
+pub fn saleae_csv<R: BufRead>(input: R, channel: &str) -> Result<Vec<Edge>, LogicError> {
+    let mut lines = input.lines();
+    let header = match lines.next() {
+        Some(header) => header?,
+        None => return Err(LogicError::Malformed("empty CSV file".to_string())),
+    };
+    let names: Vec<&str> = header.split(',').skip(1).collect();
+    let column = select(&names, channel)? + 1;
+
+    let mut edges: Vec<Edge> = vec![];
+    for (row, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let malformed = || LogicError::Malformed(format!("row {}: {:?}", row + 2, line));
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        let seconds: f64 = values[0].parse().map_err(|_| malformed())?;
+        if seconds.is_nan() || seconds < 0.0 {
+            return Err(malformed());
+        }
+        let level = match values.get(column) {
+            Some(&"0") => false,
+            Some(&"1") => true,
+            _ => return Err(malformed()),
+        };
+        if edges.last().map(|e| e.level) != Some(level) {
+            edges.push(Edge {
+                time: Duration::from_secs_f64(seconds),
+                level,
+            });
+        }
+    }
+    Ok(edges)
+}
+
+/// Reads the edges of `channel` from a sigrok session file. `channel`
+/// is a probe name, e.g. `"D0"`, or the 0-based index of a probe.
+#[cfg(feature = "sigrok")]
+pub fn sigrok<R: io::Read + io::Seek>(input: R, channel: &str) -> Result<Vec<Edge>, LogicError> {
+    use std::io::Read;
+
+    let mut archive = zip::ZipArchive::new(input)?;
+    let mut metadata = String::new();
+    archive.by_name("metadata")?.read_to_string(&mut metadata)?;
+
+    // The first device section describes the logic capture.
+    let mut samplerate = None;
+    let mut unitsize = 1;
+    let mut capturefile = None;
+    let mut probes: Vec<(usize, String)> = vec![];
+    let mut section = "";
+    for line in metadata.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+        if section != "device 1" {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match key {
+            "samplerate" => samplerate = Some(parse_samplerate(value)?),
+            "unitsize" => {
+                unitsize = value
+                    .parse()
+                    .map_err(|_| LogicError::Malformed(format!("unitsize {:?}", value)))?
+            }
+            "capturefile" => capturefile = Some(value.to_string()),
+            _ => {
+                if let Some(i) = key
+                    .strip_prefix("probe")
+                    .and_then(|i| i.parse().ok())
+                    .filter(|i| *i > 0)
+                {
+                    probes.push((i, value.to_string()));
+                }
+            }
+        }
+    }
+    let samplerate =
+        samplerate.ok_or_else(|| LogicError::Malformed("no samplerate".to_string()))?;
+    let capturefile =
+        capturefile.ok_or_else(|| LogicError::Malformed("no capturefile".to_string()))?;
+    probes.sort();
+    let names: Vec<&str> = probes.iter().map(|(_, name)| name.as_str()).collect();
+    let bit = probes[select(&names, channel)?].0 - 1;
+    if bit >= 8 * unitsize {
+        return Err(LogicError::Malformed(format!(
+            "probe {} exceeds unitsize {}",
+            bit + 1,
+            unitsize
+        )));
+    }
+
+    // Samples are split into chunks named <capturefile>-1,
+    // <capturefile>-2, etc., or held in <capturefile> itself.
+    let mut chunks: Vec<(u64, String)> = archive
+        .file_names()
+        .filter_map(|name| {
+            let n = name.strip_prefix(capturefile.as_str())?;
+            match n.strip_prefix('-') {
+                Some(n) => Some((n.parse().ok()?, name.to_string())),
+                None if n.is_empty() => Some((0, name.to_string())),
+                None => None,
+            }
+        })
+        .collect();
+    chunks.sort();
+
+    let mut data = vec![];
+    for (_, name) in chunks {
+        archive.by_name(&name)?.read_to_end(&mut data)?;
+    }
+    let samples = data
+        .chunks_exact(unitsize)
+        .map(|unit| unit[bit / 8] & (1 << (bit % 8)) != 0);
+    Ok(edges(samples, samplerate))
+}
+
+/// Parses a sample rate of sigrok metadata, e.g. `"24 MHz"`, into Hz.
+#[cfg(feature = "sigrok")]
+fn parse_samplerate(s: &str) -> Result<u64, LogicError> {
+    let malformed = || LogicError::Malformed(format!("samplerate {:?}", s));
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    };
+    let scale = match unit {
+        "" | "Hz" => 1.0,
+        "kHz" => 1e3,
+        "MHz" => 1e6,
+        "GHz" => 1e9,
+        _ => return Err(malformed()),
+    };
+    let value: f64 = value.parse().map_err(|_| malformed())?;
+    let rate = (value * scale).round() as u64;
+    if rate == 0 {
+        return Err(malformed());
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saleae() {
+        let csv = "Time [s], Channel 0, SWO\n0.0,0,1\n1e-6,1,1\n2e-6,1,0\n3e-6,0,1\n";
+        let edges = saleae_csv(csv.as_bytes(), "SWO").unwrap();
+        assert_eq!(edges, saleae_csv(csv.as_bytes(), "1").unwrap());
+        let times: Vec<_> = edges.iter().map(|e| (e.time.as_nanos(), e.level)).collect();
+        assert_eq!(times, [(0, true), (2000, false), (3000, true)]);
+
+        assert!(matches!(
+            saleae_csv(csv.as_bytes(), "D3"),
+            Err(LogicError::NoSuchChannel(_))
+        ));
+        assert!(matches!(
+            saleae_csv("Time [s],Channel 0\n0.0,x\n".as_bytes(), "0"),
+            Err(LogicError::Malformed(_))
+        ));
+    }
+
+    #[cfg(feature = "sigrok")]
+    #[test]
+    fn sigrok_session() {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default();
+        zip.start_file("version", options).unwrap();
+        zip.write_all(b"2").unwrap();
+        zip.start_file("metadata", options).unwrap();
+        zip.write_all(
+            b"[global]\nsigrok version=0.5.2\n\n[device 1]\ncapturefile=logic-1\ntotal probes=2\nsamplerate=1 MHz\nprobe1=D0\nprobe2=SWO\nunitsize=1\n",
+        )
+        .unwrap();
+        zip.start_file("logic-1-2", options).unwrap();
+        zip.write_all(&[0b10, 0b00, 0b10]).unwrap();
+        zip.start_file("logic-1-1", options).unwrap();
+        zip.write_all(&[0b10, 0b10, 0b01]).unwrap();
+        let session = zip.finish().unwrap();
+
+        let edges = sigrok(session.clone(), "SWO").unwrap();
+        let times: Vec<_> = edges.iter().map(|e| (e.time.as_nanos(), e.level)).collect();
+        assert_eq!(
+            times,
+            [
+                (0, true),
+                (2000, false),
+                (3000, true),
+                (4000, false),
+                (5000, true)
+            ]
+        );
+        assert_eq!(sigrok(session.clone(), "0").unwrap().len(), 3);
+        assert!(sigrok(session, "D7").is_err());
+    }
+}
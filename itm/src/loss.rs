@@ -0,0 +1,347 @@
+//! Detection of data lost in transport.
+//!
+//! Some transports, e.g. UDP or a flaky serial link, may drop bytes of
+//! the trace stream without the target knowing about it. Unlike an
+//! [`Overflow`](TracePacket::Overflow), such a loss is not reported in
+//! the stream itself. [`LossDetector`] instead infers it from
+//!
+//! - implausible headers: runs of [`MalformedPacket`](crate::MalformedPacket)s
+//!   and of instrumentation packets on disabled stimulus ports;
+//! - the sync cadence: the target emits a [`Sync`](TracePacket::Sync)
+//!   packet at a fixed interval if so configured, so a longer interval
+//!   means that a sync packet was lost; and
+//! - timestamp continuity: a local timestamp larger than the target can
+//!   plausibly produce means that the data in between was lost.
+//!
+//! and reports it as an explicit [`Loss`] so that analyses can mark the
+//! affected interval as unreliable.
+
+use super::{Decoder, DecoderError, DecoderErrorInt, TracePacket};
+
+use std::collections::VecDeque;
+use std::io::Read;
+
+/// Size of a [`Sync`](TracePacket::Sync) packet in bytes.
+const SYNC_BYTES: usize = 6;
+
+/// [`LossDetector`] configuration.
+#[derive(Clone)]
+pub struct LossDetectionConfiguration {
+    /// Mask of the enabled stimulus ports (c.f. `ITM_TER`).
+    /// Instrumentation packets on other ports, including those of
+    /// [tracked](crate::DecoderOptions::track_stimulus_page) pages
+    /// above 0, are considered implausible.
+    pub stimulus_ports: u32,
+
+    /// Number of local timestamp ticks between two periodic
+    /// [`Sync`](TracePacket::Sync) packets (c.f. `DWT_CTRL.SYNCTAP`),
+    /// if periodic synchronization is enabled.
+    pub sync_period: Option<u64>,
+
+    /// Largest local timestamp value the target can plausibly emit.
+    pub max_timestamp_delta: Option<u32>,
+}
+
+/// The reason a [`Loss`] was detected.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LossCause {
+    /// A run of malformed or otherwise implausible packets was
+    /// decoded.
+    Implausible {
+        /// The number of implausible packets in the run.
+        packets: usize,
+    },
+
+    /// More time passed between two [`Sync`](TracePacket::Sync)
+    /// packets than the configured sync period.
+    MissedSync {
+        /// The estimated number of lost sync packets.
+        syncs: usize,
+    },
+
+    /// A local timestamp exceeded the configured maximum.
+    TimestampDiscontinuity {
+        /// The local timestamp value.
+        delta: u32,
+    },
+}
+
+/// A region of the trace stream that is estimated to have been lost.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Loss {
+    /// Byte offset into the stream at which the loss was detected.
+    pub offset: usize,
+
+    /// Estimated number of lost or corrupted bytes. For
+    /// [`MissedSync`](LossCause::MissedSync) this is a lower bound.
+    pub estimated_bytes: usize,
+
+    /// Why the loss was detected.
+    pub cause: LossCause,
+}
+
+/// An item yielded by [`LossDetector`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Observation {
+    /// A plausible packet.
+    Packet(TracePacket),
+
+    /// Data was lost before the next packet.
+    Loss(Loss),
+}
+
+/// Iterator that yield [`Observation`]s. See the [module
+/// documentation](self).
+pub struct LossDetector<R>
+where
+    R: Read,
+{
+    decoder: Decoder<R>,
+    options: LossDetectionConfiguration,
+    pending: VecDeque<Observation>,
+
+    /// Start offset and number of packets of the current run of
+    /// implausible packets.
+    implausible: Option<(usize, usize)>,
+
+    /// Local timestamp ticks since the last sync packet.
+    ticks_since_sync: u64,
+
+    /// Bytes of plausible packets and local timestamp ticks observed so
+    /// far, from which the byte rate of the stream is estimated.
+    plausible_bytes: usize,
+    ticks: u64,
+}
+
+impl<R> LossDetector<R>
+where
+    R: Read,
+{
+    pub(super) fn new(decoder: Decoder<R>, options: LossDetectionConfiguration) -> Self {
+        Self {
+            decoder,
+            options,
+            pending: VecDeque::new(),
+            implausible: None,
+            ticks_since_sync: 0,
+            plausible_bytes: 0,
+            ticks: 0,
+        }
+    }
+
+    fn is_plausible(&self, packet: &TracePacket) -> bool {
+        match packet {
+            TracePacket::Instrumentation { port, .. } => {
+                *port < 32 && self.options.stimulus_ports & (1 << port) != 0
+            }
+            _ => true,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        (self.decoder.bit_offset() / 8) as usize
+    }
+
+    fn end_implausible_run(&mut self, end: usize) {
+        if let Some((offset, packets)) = self.implausible.take() {
+            self.pending.push_back(Observation::Loss(Loss {
+                offset,
+                estimated_bytes: end - offset,
+                cause: LossCause::Implausible { packets },
+            }));
+        }
+    }
+
+    fn check_continuity(&mut self, offset: usize, packet: &TracePacket) {
+        let delta = match packet {
+            TracePacket::LocalTimestamp1 { ts, .. } => *ts,
+            TracePacket::LocalTimestamp2 { ts } => (*ts).into(),
+            TracePacket::Sync => {
+                let ticks = std::mem::take(&mut self.ticks_since_sync);
+                if let Some(period) = self.options.sync_period.filter(|p| *p > 0) {
+                    // Ticks only undercount elapsed time (the target may
+                    // idle without emitting timestamps), so exceeding the
+                    // period means that a sync was not received.
+                    let syncs = (ticks / period) as usize;
+                    if syncs > 0 {
+                        self.pending.push_back(Observation::Loss(Loss {
+                            offset,
+                            estimated_bytes: syncs * SYNC_BYTES,
+                            cause: LossCause::MissedSync { syncs },
+                        }));
+                    }
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(max) = self.options.max_timestamp_delta {
+            if delta > max {
+                let rate = if self.ticks == 0 {
+                    0.0
+                } else {
+                    self.plausible_bytes as f64 / self.ticks as f64
+                };
+                self.pending.push_back(Observation::Loss(Loss {
+                    offset,
+                    estimated_bytes: ((delta - max) as f64 * rate) as usize,
+                    cause: LossCause::TimestampDiscontinuity { delta },
+                }));
+                return;
+            }
+        }
+
+        self.ticks_since_sync += u64::from(delta);
+        self.ticks += u64::from(delta);
+    }
+}
+
+impl<R> Iterator for LossDetector<R>
+where
+    R: Read,
+{
+    type Item = Result<Observation, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(observation) = self.pending.pop_front() {
+                return Some(Ok(observation));
+            }
+
+            let start = self.offset();
+            let packet = match self.decoder.next_single() {
+                Ok(packet) if self.is_plausible(&packet) => packet,
+                Ok(_)
+                | Err(DecoderErrorInt::MalformedPacket(_))
+                | Err(DecoderErrorInt::Resynchronized { .. }) => {
+                    self.implausible.get_or_insert((start, 0)).1 += 1;
+                    continue;
+                }
+                Err(DecoderErrorInt::Eof) => {
+                    self.end_implausible_run(start);
+                    return self.pending.pop_front().map(Ok);
+                }
+                Err(DecoderErrorInt::Io(io)) => return Some(Err(DecoderError::Io(io))),
+            };
+
+            self.end_implausible_run(start);
+            self.check_continuity(start, &packet);
+            self.plausible_bytes += self.offset() - start;
+            self.pending.push_back(Observation::Packet(packet));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DecoderOptions;
+
+    #[test]
+    fn detect_loss() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Sync
+            0, 0, 0, 0, 0, 0b1000_0000,
+            // Instrumentation, port 1, 1-byte payload
+            0b0000_1001, 0x41,
+            // LTS2, ts = 6
+            0b0110_0000,
+            // Instrumentation, port 31 (disabled), 1-byte payload
+            0b1111_1001, 0x41,
+            // Invalid hardware source packet
+            0b1111_1111,
+            // LTS1, ts = 300
+            0b1100_0000, 0b1010_1100, 0b0000_0010,
+            // Sync
+            0, 0, 0, 0, 0, 0b1000_0000,
+        ];
+        let decoder = Decoder::new(stream, DecoderOptions::default());
+        let observations: Vec<Observation> = decoder
+            .detect_loss(LossDetectionConfiguration {
+                stimulus_ports: 0b10,
+                sync_period: Some(4),
+                max_timestamp_delta: Some(100),
+            })
+            .map(|o| o.unwrap())
+            .collect();
+
+        assert_eq!(
+            observations,
+            [
+                Observation::Packet(TracePacket::Sync),
+                Observation::Packet(TracePacket::Instrumentation {
+                    port: 1,
+                    payload: [0x41].into(),
+                }),
+                Observation::Packet(TracePacket::LocalTimestamp2 { ts: 6 }),
+                Observation::Loss(Loss {
+                    offset: 9,
+                    estimated_bytes: 3,
+                    cause: LossCause::Implausible { packets: 2 },
+                }),
+                // 9 bytes per 6 ticks
+                Observation::Loss(Loss {
+                    offset: 12,
+                    estimated_bytes: 300,
+                    cause: LossCause::TimestampDiscontinuity { delta: 300 },
+                }),
+                Observation::Packet(TracePacket::LocalTimestamp1 {
+                    ts: 300,
+                    data_relation: crate::TimestampDataRelation::Sync,
+                }),
+                Observation::Loss(Loss {
+                    offset: 15,
+                    estimated_bytes: 6,
+                    cause: LossCause::MissedSync { syncs: 1 },
+                }),
+                Observation::Packet(TracePacket::Sync),
+            ]
+        );
+    }
+
+    #[test]
+    fn stimulus_pages() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Sync
+            0, 0, 0, 0, 0, 0b1000_0000,
+            // Extension, page 7
+            0b0111_1000,
+            // Instrumentation, port 7 * 32 + 1, 1-byte payload
+            0b0000_1001, 0x41,
+        ];
+        let decoder = Decoder::new(
+            stream,
+            DecoderOptions {
+                track_stimulus_page: true,
+                ..Default::default()
+            },
+        );
+        let observations: Vec<Observation> = decoder
+            .detect_loss(LossDetectionConfiguration {
+                stimulus_ports: u32::MAX,
+                sync_period: None,
+                max_timestamp_delta: None,
+            })
+            .map(|o| o.unwrap())
+            .collect();
+
+        assert_eq!(
+            observations,
+            [
+                Observation::Packet(TracePacket::Sync),
+                Observation::Packet(TracePacket::Extension { page: 7 }),
+                Observation::Loss(Loss {
+                    offset: 7,
+                    estimated_bytes: 2,
+                    cause: LossCause::Implausible { packets: 1 },
+                }),
+            ]
+        );
+    }
+}
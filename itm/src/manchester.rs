@@ -0,0 +1,204 @@
+//! Manchester bit-level front end.
+//!
+//! In Manchester mode, the SWO pin encodes each bit as a transition in
+//! the middle of the bit period: a 1 as a falling transition (high, then
+//! low), and a 0 as a rising one. The line idles low. The trace stream
+//! is sent in messages of whole bytes, least significant bit first, each
+//! preceded by a start bit (a 1), and followed by at least one bit period
+//! of idle.
+//!
+//! As the bit rate of the target need not be known, the
+//! [`ManchesterDecoder`] recovers the clock: the bit period is measured
+//! on the start bit of each message, and is then tracked across the
+//! mid-bit transitions of the message.
+//!
+//! ```
+//! use itm::logic::Edge;
+//! use itm::manchester::decode;
+//! use std::time::Duration;
+//!
+//! // 0x03 at 1 MBd: start bit, 1, 1, 0, 0, 0, 0, 0, 0
+//! let times = [
+//!     0, 500, 1000, 1500, 2000, 2500, 3500, 4000, 4500, 5000, 5500, 6000, 6500, 7000, 7500, 8000,
+//!     8500, 9000,
+//! ];
+//! let edges: Vec<Edge> = times
+//!     .iter()
+//!     .enumerate()
+//!     .map(|(i, ns)| Edge { time: Duration::from_nanos(*ns), level: i % 2 == 0 })
+//!     .collect();
+//! assert_eq!(decode(&edges), [0x03]);
+//! ```
+
+use crate::logic::Edge;
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Waiting for the rising edge of a start bit.
+    Idle,
+
+    /// In the first half of the start bit, which began at the given
+    /// time.
+    Start(Duration),
+
+    /// Receiving the bits of a message.
+    Message {
+        /// Time of the latest mid-bit transition.
+        mid: Duration,
+
+        /// Current estimate of the bit period.
+        period: Duration,
+
+        /// Bits of the byte being received, and their number.
+        byte: u8,
+        bits: u32,
+    },
+}
+
+/// Decodes the bytes of a Manchester-encoded signal from its edges.
+/// Edges are [pushed](Self::push) in order, and the decoded bytes are
+/// [pulled](Self::pull) out. See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct ManchesterDecoder {
+    state: State,
+    level: bool,
+    bytes: VecDeque<u8>,
+}
+
+impl Default for ManchesterDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManchesterDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            level: false,
+            bytes: VecDeque::new(),
+        }
+    }
+
+    /// Processes an edge. Edges that do not change the level are
+    /// ignored.
+    pub fn push(&mut self, edge: Edge) {
+        if edge.level == self.level {
+            return;
+        }
+        self.level = edge.level;
+
+        self.state = match self.state {
+            State::Idle if edge.level => State::Start(edge.time),
+            State::Idle => State::Idle,
+            State::Start(start) => {
+                // The falling transition in the middle of the start bit
+                let period = (edge.time - start) * 2;
+                State::Message {
+                    mid: edge.time,
+                    period,
+                    byte: 0,
+                    bits: 0,
+                }
+            }
+            State::Message {
+                mid,
+                period,
+                mut byte,
+                mut bits,
+            } => {
+                let since = edge.time - mid;
+                if since < period * 3 / 4 {
+                    // A transition between two bits of the same value
+                    self.state = State::Message {
+                        mid,
+                        period,
+                        byte,
+                        bits,
+                    };
+                    return;
+                }
+                if since > period * 5 / 4 {
+                    // The line was idle: the message has ended, and this
+                    // edge starts the next one.
+                    self.state = State::Idle;
+                    self.level = !edge.level;
+                    return self.push(edge);
+                }
+                byte |= u8::from(!edge.level) << bits;
+                bits += 1;
+                if bits == 8 {
+                    self.bytes.push_back(byte);
+                    byte = 0;
+                    bits = 0;
+                }
+                State::Message {
+                    mid: edge.time,
+                    // Track the clock of the target
+                    period: (period * 7 + since) / 8,
+                    byte,
+                    bits,
+                }
+            }
+        };
+    }
+
+    /// Pulls the next decoded byte.
+    pub fn pull(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
+
+/// Decodes the bytes of a complete Manchester-encoded signal.
+pub fn decode(edges: &[Edge]) -> Vec<u8> {
+    let mut decoder = ManchesterDecoder::new();
+    let mut bytes = vec![];
+    for edge in edges {
+        decoder.push(*edge);
+        bytes.extend(std::iter::from_fn(|| decoder.pull()));
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic;
+
+    /// Samples the Manchester encoding of messages, at `samples` samples
+    /// per bit, with `idle` bit periods of idle before each message.
+    fn encode(messages: &[&[u8]], samples: usize, idle: usize) -> Vec<bool> {
+        let mut signal = vec![];
+        for message in messages {
+            signal.resize(signal.len() + idle * samples, false);
+            let bits = std::iter::once(true).chain(
+                message
+                    .iter()
+                    .flat_map(|b| (0..8).map(move |i| (b >> i) & 1 == 1)),
+            );
+            for bit in bits {
+                signal.resize(signal.len() + samples / 2, bit);
+                signal.resize(signal.len() + samples - samples / 2, !bit);
+            }
+        }
+        signal.resize(signal.len() + idle * samples, false);
+        signal
+    }
+
+    #[test]
+    fn messages() {
+        let messages: [&[u8]; 3] = [&[0x03, 0x68], &[0xff, 0x00, 0xa5], &[0x01]];
+        // 10 samples per bit
+        let signal = encode(&messages, 10, 3);
+        let edges = logic::edges(signal, 10_000_000);
+        assert_eq!(decode(&edges), messages.concat());
+
+        // Another bit rate, with uneven half bits
+        let signal = encode(&messages, 9, 2);
+        let edges = logic::edges(signal, 10_000_000);
+        assert_eq!(decode(&edges), messages.concat());
+    }
+}
@@ -0,0 +1,130 @@
+//! MessagePack output of decoded packets.
+//!
+//! [`MsgpackWriter`] writes serializable items, e.g. [`TracePacket`]s
+//! or [`TimestampedTracePackets`], as consecutive MessagePack objects.
+//! Structs are written as maps with their field names, so that the
+//! objects have the same structure as their `serde` representation in
+//! other formats, e.g. JSON.
+//!
+//! ```
+//! use itm::msgpack::{read, MsgpackWriter};
+//! use itm::TracePacket;
+//!
+//! let packets = [TracePacket::Sync, TracePacket::PCSample { pc: Some(0x100) }];
+//! let mut msgpack = MsgpackWriter::new(vec![]);
+//! for packet in packets.iter() {
+//!     msgpack.write(packet).unwrap();
+//! }
+//! let bytes = msgpack.into_inner();
+//! let decoded: Vec<TracePacket> = read(&bytes[..]).collect::<Result<_, _>>().unwrap();
+//! assert_eq!(decoded, packets);
+//! ```
+//!
+//! [`TracePacket`]: crate::TracePacket
+//! [`TimestampedTracePackets`]: crate::TimestampedTracePackets
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::io;
+use std::marker::PhantomData;
+
+pub use rmp_serde::decode::Error as DecodeError;
+pub use rmp_serde::encode::Error as EncodeError;
+
+/// Writes items as consecutive MessagePack objects. See the [module
+/// documentation](self).
+pub struct MsgpackWriter<W: io::Write> {
+    out: W,
+}
+
+impl<W: io::Write> MsgpackWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Writes an item.
+    pub fn write<T: Serialize>(&mut self, item: &T) -> Result<(), EncodeError> {
+        rmp_serde::encode::write_named(&mut self.out, item)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Unwraps the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads the objects written by a [`MsgpackWriter`] until the end of
+/// `input`.
+pub fn read<T, R>(input: R) -> MsgpackReader<T, R>
+where
+    T: DeserializeOwned,
+    R: io::BufRead,
+{
+    MsgpackReader {
+        input,
+        _item: PhantomData,
+    }
+}
+
+/// Iterator over consecutive MessagePack objects. See [`read`].
+pub struct MsgpackReader<T, R> {
+    input: R,
+    _item: PhantomData<T>,
+}
+
+impl<T, R> Iterator for MsgpackReader<T, R>
+where
+    T: DeserializeOwned,
+    R: io::BufRead,
+{
+    type Item = Result<T, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.input.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(rmp_serde::from_read(&mut self.input)),
+            Err(e) => Some(Err(DecodeError::InvalidMarkerRead(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Ticks, Timestamp, TimestampedTracePackets, TracePacket};
+    use std::time::Duration;
+
+    #[test]
+    fn timestamped() {
+        let set = TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_micros(5)),
+            ticks: Ticks {
+                ticks: 80,
+                frequency: 16_000_000,
+            },
+            delta: Duration::from_micros(5),
+            packets: vec![TracePacket::Instrumentation {
+                port: 1,
+                payload: [0xde, 0xad].into(),
+            }],
+            malformed_packets: vec![],
+            consumed_packets: 2,
+            overflow: None,
+        };
+        let mut msgpack = MsgpackWriter::new(vec![]);
+        msgpack.write(&set).unwrap();
+        msgpack.write(&set).unwrap();
+        let bytes = msgpack.into_inner();
+
+        let decoded: Vec<TimestampedTracePackets> =
+            read(&bytes[..]).collect::<Result<_, _>>().unwrap();
+        assert_eq!(decoded, [set.clone(), set]);
+        assert!(read::<TracePacket, _>(&bytes[..3]).next().unwrap().is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! NRZ (UART) bit-level front end.
+//!
+//! In NRZ mode, the SWO pin outputs the trace stream UART-style: each
+//! byte is framed by a low start bit and a high stop bit, with the data
+//! bits least significant first, and the line idles high.
+//! [`NrzDecoder`] recovers the bytes from the [`Edge`]s of the pin, e.g.
+//! as [imported](crate::logic) from a logic-analyzer capture, given the
+//! bit rate. Each bit is sampled three times around its middle, timed
+//! from the falling edge of the start bit, and the majority of the
+//! samples taken, so that glitches near the sampling point are
+//! tolerated. If the bit rate is not known, it can be
+//! [detected](detect_baud) from the signal.
+//!
+//! ```
+//! use itm::logic::Edge;
+//! use itm::nrz::decode;
+//! use std::time::Duration;
+//!
+//! // 0x03 at 1 MBd: start bit, 1, 1, 0, 0, 0, 0, 0, 0, stop bit
+//! let edge = |us, level| Edge { time: Duration::from_micros(us), level };
+//! let edges = [edge(0, true), edge(5, false), edge(6, true), edge(8, false), edge(14, true)];
+//! assert_eq!(decode(&edges, 1_000_000), [0x03]);
+//! ```
+
+use crate::logic::Edge;
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A byte that was not followed by a high stop bit, e.g. because the
+/// bit rate is wrong, or the line was disturbed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Framing error at {time:?}: byte {byte:#04x} has no stop bit")]
+pub struct FramingError {
+    /// Time of the falling edge of the start bit.
+    pub time: Duration,
+
+    /// The byte as received.
+    pub byte: u8,
+}
+
+/// Decodes the bytes of an NRZ-encoded signal from its edges. Edges are
+/// [pushed](Self::push) in order, and the decoded bytes are
+/// [pulled](Self::pull) out. A byte is decoded once an edge after its
+/// stop bit has been pushed, or the decoder is [finished](Self::finish).
+/// See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct NrzDecoder {
+    bit: Duration,
+    /// Level of the line before the first pending edge.
+    level: bool,
+    /// Edges that may be sampled by the current or a later frame.
+    edges: VecDeque<Edge>,
+    /// Falling edge of the start bit of the current frame.
+    start: Option<Duration>,
+    bytes: VecDeque<Result<u8, FramingError>>,
+}
+
+impl NrzDecoder {
+    /// Creates a decoder of a signal of `baud` bits per second.
+    pub fn new(baud: u32) -> Self {
+        Self {
+            bit: Duration::from_secs(1) / baud,
+            level: true,
+            edges: VecDeque::new(),
+            start: None,
+            bytes: VecDeque::new(),
+        }
+    }
+
+    /// Processes an edge. Edges that do not change the level are
+    /// ignored.
+    pub fn push(&mut self, edge: Edge) {
+        let level = self.edges.back().map_or(self.level, |e| e.level);
+        if edge.level != level {
+            self.edges.push_back(edge);
+            self.process(Some(edge.time));
+        }
+    }
+
+    /// Decodes the frame in progress, if any, assuming that the line
+    /// keeps its level after the last edge. Call at the end of the
+    /// signal.
+    pub fn finish(&mut self) {
+        self.process(None);
+    }
+
+    /// Pulls the next decoded byte, or framing error.
+    pub fn pull(&mut self) -> Option<Result<u8, FramingError>> {
+        self.bytes.pop_front()
+    }
+
+    /// Decodes the frames that end before `horizon`, up to which the
+    /// level of the line is known; if `None`, all frames.
+    fn process(&mut self, horizon: Option<Duration>) {
+        loop {
+            let start = match self.start {
+                Some(start) => start,
+                None => {
+                    // Skip to the falling edge of the next start bit.
+                    while let Some(edge) = self.edges.front() {
+                        if !edge.level {
+                            break;
+                        }
+                        self.level = edge.level;
+                        self.edges.pop_front();
+                    }
+                    match self.edges.front() {
+                        Some(edge) => *self.start.insert(edge.time),
+                        None => return,
+                    }
+                }
+            };
+
+            // The last sample of the stop bit
+            let end = self.sample_time(start, 9, 2);
+            if matches!(horizon, Some(horizon) if horizon <= end) {
+                return;
+            }
+            self.start = None;
+
+            if self.sample(start, 0) {
+                // A glitch, not a start bit: skip its falling edge.
+                let edge = self.edges.pop_front().unwrap();
+                self.level = edge.level;
+                continue;
+            }
+            let byte = (1..=8).fold(0u8, |byte, n| {
+                byte | (u8::from(self.sample(start, n)) << (n - 1))
+            });
+            self.bytes.push_back(if self.sample(start, 9) {
+                Ok(byte)
+            } else {
+                Err(FramingError { time: start, byte })
+            });
+
+            // The next start bit begins after the stop bit sample.
+            while let Some(edge) = self.edges.front() {
+                if edge.time > end {
+                    break;
+                }
+                self.level = edge.level;
+                self.edges.pop_front();
+            }
+        }
+    }
+
+    /// Time of sample `i` (0, 1 or 2) of bit `n` of the frame that
+    /// starts at `start`: at 3/8, 1/2 and 5/8 of the bit.
+    fn sample_time(&self, start: Duration, n: u32, i: u32) -> Duration {
+        start + self.bit * n + self.bit * (3 + i) / 8
+    }
+
+    /// The majority of the samples of bit `n` of the frame that starts
+    /// at `start`.
+    fn sample(&self, start: Duration, n: u32) -> bool {
+        let high = (0..3)
+            .filter(|i| self.level_at(self.sample_time(start, n, *i)))
+            .count();
+        high >= 2
+    }
+
+    /// Level of the line at `time`.
+    fn level_at(&self, time: Duration) -> bool {
+        self.edges
+            .iter()
+            .take_while(|e| e.time <= time)
+            .last()
+            .map_or(self.level, |e| e.level)
+    }
+}
+
+/// Decodes the bytes of a complete NRZ-encoded signal at `baud` bits
+/// per second. Bytes without a stop bit are dropped.
+pub fn decode(edges: &[Edge], baud: u32) -> Vec<u8> {
+    let mut decoder = NrzDecoder::new(baud);
+    let mut bytes = vec![];
+    for edge in edges {
+        decoder.push(*edge);
+        bytes.extend(std::iter::from_fn(|| decoder.pull()).flatten());
+    }
+    decoder.finish();
+    bytes.extend(std::iter::from_fn(|| decoder.pull()).flatten());
+    bytes
+}
+
+/// Maximum number of low periods [`detect_baud`] considers.
+const DETECT_RUNS: usize = 10_000;
+
+/// Estimates the bit rate of an NRZ-encoded signal of trace data.
+///
+/// The estimate is based on the durations for which the line is low,
+/// which, unlike idle periods, are whole numbers of bits: from a single
+/// bit, e.g. the start bit of an instrumentation packet header such as
+/// `0x01`, up to nine bits, the start bit and data bits of each `0x00`
+/// byte of a [synchronization](crate::TracePacket::Sync) packet. The bit
+/// period is taken to be the longest period of which (nearly) all low
+/// durations are multiples of one to nine, which is then refined over
+/// all of them. Returns `None` if no bit period fits, e.g. because the
+/// signal is not NRZ-encoded, or holds too few edges.
+pub fn detect_baud(edges: &[Edge]) -> Option<u32> {
+    let runs: Vec<f64> = edges
+        .windows(2)
+        .filter(|pair| !pair[0].level)
+        .map(|pair| (pair[1].time - pair[0].time).as_secs_f64())
+        .take(DETECT_RUNS)
+        .collect();
+    if runs.len() < 8 {
+        return None;
+    }
+    let shortest = runs.iter().copied().fold(f64::INFINITY, f64::min);
+    let longest = runs.iter().copied().fold(0.0, f64::max);
+
+    // Number of bits of a low duration, if it is close enough to a
+    // multiple of `period`.
+    let bits = |run: f64, period: f64| {
+        let bits = run / period;
+        let whole = bits.round();
+        if (1.0..=9.0).contains(&whole) && (bits - whole).abs() < 0.25 {
+            Some(whole)
+        } else {
+            None
+        }
+    };
+    // The shortest low duration is likely a single bit, and the longest
+    // the nine bits of a zero byte, but need not be.
+    let period = (1..=9)
+        .flat_map(|k| [shortest / k as f64, longest / k as f64])
+        .filter(|period| {
+            let fits = runs
+                .iter()
+                .filter(|run| bits(**run, *period).is_some())
+                .count();
+            // Tolerate some glitches.
+            fits * 20 >= runs.len() * 19
+        })
+        .fold(0.0, f64::max);
+    if period == 0.0 {
+        return None;
+    }
+
+    let (total, bits) = runs
+        .iter()
+        .filter_map(|run| Some((run, bits(*run, period)?)))
+        .fold((0.0, 0.0), |(total, bits), (run, n)| {
+            (total + run, bits + n)
+        });
+    Some((bits / total).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic;
+
+    /// Samples the NRZ encoding of `bytes` at `samples` samples per
+    /// bit, with a bit of idle before each byte. `stop` is the level of
+    /// the stop bits.
+    fn encode(bytes: &[u8], samples: usize, stop: bool) -> Vec<bool> {
+        let mut signal = vec![];
+        for byte in bytes {
+            let bits = [true, false]
+                .into_iter()
+                .chain((0..8).map(|i| (byte >> i) & 1 == 1))
+                .chain(Some(stop));
+            for bit in bits {
+                signal.resize(signal.len() + samples, bit);
+            }
+        }
+        signal
+    }
+
+    #[test]
+    fn frames() {
+        let bytes = [0x03, 0x68, 0x00, 0xff, 0x55];
+        let signal = encode(&bytes, 16, true);
+        assert_eq!(decode(&logic::edges(signal, 16_000_000), 1_000_000), bytes);
+
+        // A glitch in the middle of a bit
+        let mut signal = encode(&bytes, 16, true);
+        signal[16 * 13 + 8] = !signal[16 * 13 + 8];
+        assert_eq!(decode(&logic::edges(signal, 16_000_000), 1_000_000), bytes);
+
+        // A glitch on an idle line is no start bit.
+        let mut signal = vec![true; 16];
+        signal[5] = false;
+        signal.extend(encode(&[0x41], 16, true));
+        assert_eq!(decode(&logic::edges(signal, 16_000_000), 1_000_000), [0x41]);
+    }
+
+    #[test]
+    fn baud() {
+        // Synchronization packet, instrumentation packets and a local
+        // timestamp, at 2.25 MBd
+        let bytes = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, b'h', 0x01, b'i', 0xc0, 0x12, 0x03, 0xef,
+            0xbe, 0xad, 0xde,
+        ];
+        // Within 0.5% of `expected`
+        let close = |baud: Option<u32>, expected: i64| {
+            let baud = baud.unwrap();
+            assert!((baud as i64 - expected).abs() < expected / 200, "{}", baud);
+        };
+        let signal = encode(&bytes, 40, true);
+        let edges = logic::edges(signal, 90_000_000);
+        close(detect_baud(&edges), 2_250_000);
+        assert_eq!(decode(&edges, 2_250_000), bytes);
+
+        // Without a zero byte, at a sample rate that is not a multiple
+        let signal = encode(&bytes[6..], 16, true);
+        let edges = logic::edges(signal, 9_000_000);
+        close(detect_baud(&edges), 562_500);
+
+        assert_eq!(detect_baud(&edges[..4]), None);
+    }
+
+    #[test]
+    fn framing_error() {
+        let signal = encode(&[0x41], 16, false);
+        let mut decoder = NrzDecoder::new(1_000_000);
+        for edge in logic::edges(signal, 16_000_000) {
+            decoder.push(edge);
+        }
+        assert_eq!(decoder.pull(), None);
+        decoder.finish();
+        assert_eq!(
+            decoder.pull(),
+            Some(Err(FramingError {
+                time: Duration::from_micros(1),
+                byte: 0x41
+            }))
+        );
+        assert_eq!(decoder.pull(), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! Parquet export of decoded packets.
+//!
+//! [`ParquetWriter`] writes packets, optionally timestamped, as rows of
+//! a Parquet file with typed columns, so that large captures can be
+//! loaded into e.g. Polars, pandas or DuckDB efficiently. The columns
+//! are:
+//!
+//! | column       | type               | content                                                 |
+//! |--------------|--------------------|---------------------------------------------------------|
+//! | `seq`        | `INT64`            | sequence number of the packet                           |
+//! | `time_ns`    | `INT64`, null      | timestamp offset from trace clock start, in nanoseconds |
+//! | `quality`    | `UTF8`, null       | timestamp quality, see [`Field::Quality`]               |
+//! | `kind`       | `UTF8`             | packet kind, see [`TracePacket::kind`]                  |
+//! | `port`       | `INT32`, null      | stimulus port of instrumentation packets                |
+//! | `comparator` | `INT32`, null      | DWT comparator of data trace packets                    |
+//! | `address`    | `INT64`, null      | PC value of PC sample and data trace PC packets         |
+//! | `exception`  | `UTF8`, null       | exception of exception trace packets                    |
+//! | `action`     | `UTF8`, null       | `entered`, `exited` or `returned`                       |
+//! | `payload`    | `BYTE_ARRAY`, null | payload of instrumentation and data trace packets       |
+//! | `value`      | `UTF8`, null       | packet value as text, see [`Field::Value`]              |
+//!
+//! Columns are Snappy-compressed.
+
+use crate::{ExceptionType, Field, Timestamp, TracePacket};
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+
+use std::io;
+use std::sync::Arc;
+
+pub use parquet::errors::ParquetError;
+
+const SCHEMA: &str = "
+message packet {
+    REQUIRED INT64 seq;
+    OPTIONAL INT64 time_ns;
+    OPTIONAL BYTE_ARRAY quality (UTF8);
+    REQUIRED BYTE_ARRAY kind (UTF8);
+    OPTIONAL INT32 port;
+    OPTIONAL INT32 comparator;
+    OPTIONAL INT64 address;
+    OPTIONAL BYTE_ARRAY exception (UTF8);
+    OPTIONAL BYTE_ARRAY action (UTF8);
+    OPTIONAL BYTE_ARRAY payload;
+    OPTIONAL BYTE_ARRAY value (UTF8);
+}
+";
+
+/// Options of a [`ParquetWriter`].
+#[derive(Debug, Clone)]
+pub struct ParquetOptions {
+    /// Number of rows per row group. Rows are buffered in memory until
+    /// a row group is complete.
+    pub row_group_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            row_group_size: 1 << 16,
+        }
+    }
+}
+
+/// The buffered rows of a row group, by column.
+#[derive(Default)]
+struct Columns {
+    seq: Vec<Option<i64>>,
+    time_ns: Vec<Option<i64>>,
+    quality: Vec<Option<ByteArray>>,
+    kind: Vec<Option<ByteArray>>,
+    port: Vec<Option<i32>>,
+    comparator: Vec<Option<i32>>,
+    address: Vec<Option<i64>>,
+    exception: Vec<Option<ByteArray>>,
+    action: Vec<Option<ByteArray>>,
+    payload: Vec<Option<ByteArray>>,
+    value: Vec<Option<ByteArray>>,
+}
+
+/// Writes packets as rows of a Parquet file. The file is complete once
+/// the writer is [closed](Self::close). See the [module
+/// documentation](self).
+pub struct ParquetWriter<W: io::Write + Send> {
+    writer: SerializedFileWriter<W>,
+    options: ParquetOptions,
+    columns: Columns,
+}
+
+impl<W: io::Write + Send> ParquetWriter<W> {
+    pub fn new(out: W, options: ParquetOptions) -> Result<Self, ParquetError> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(Self {
+            writer: SerializedFileWriter::new(out, schema, Arc::new(properties))?,
+            options,
+            columns: Columns::default(),
+        })
+    }
+
+    /// Writes the row of a packet, with its sequence number and
+    /// (optional) timestamp.
+    pub fn row(
+        &mut self,
+        seq: u64,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> Result<(), ParquetError> {
+        let text = |s: String| (!s.is_empty()).then(|| ByteArray::from(s.into_bytes()));
+        let c = &mut self.columns;
+        c.seq.push(Some(seq as i64));
+        c.time_ns
+            .push(timestamp.map(|ts| ts.offset().as_nanos() as i64));
+        c.quality
+            .push(text(Field::Quality.extract(seq, packet, timestamp)));
+        c.kind.push(Some(packet.kind().into()));
+        c.port.push(packet.port().map(i32::from));
+        c.comparator.push(match packet {
+            TracePacket::DataTracePC { comparator, .. }
+            | TracePacket::DataTraceAddress { comparator, .. }
+            | TracePacket::DataTraceMatch { comparator }
+            | TracePacket::DataTraceValue { comparator, .. } => Some(i32::from(*comparator)),
+            _ => None,
+        });
+        c.address.push(match packet {
+            TracePacket::PCSample { pc: Some(pc) } | TracePacket::DataTracePC { pc, .. } => {
+                Some(i64::from(*pc))
+            }
+            _ => None,
+        });
+        let (exception, action) = match packet {
+            TracePacket::ExceptionTrace { exception, action } => (
+                text(ExceptionType::from(*exception).to_string()),
+                text(format!("{:?}", action).to_lowercase()),
+            ),
+            _ => (None, None),
+        };
+        c.exception.push(exception);
+        c.action.push(action);
+        c.payload.push(match packet {
+            TracePacket::Instrumentation { payload, .. }
+            | TracePacket::DataTraceAddress { data: payload, .. }
+            | TracePacket::DataTraceValue { value: payload, .. } => {
+                Some(ByteArray::from(payload.to_vec()))
+            }
+            _ => None,
+        });
+        c.value
+            .push(text(Field::Value.extract(seq, packet, timestamp)));
+
+        if c.seq.len() >= self.options.row_group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group.
+    pub fn flush(&mut self) -> Result<(), ParquetError> {
+        if self.columns.seq.is_empty() {
+            return Ok(());
+        }
+        let c = std::mem::take(&mut self.columns);
+        let mut group = self.writer.next_row_group()?;
+        column::<Int64Type, _>(&mut group, c.seq)?;
+        column::<Int64Type, _>(&mut group, c.time_ns)?;
+        column::<ByteArrayType, _>(&mut group, c.quality)?;
+        column::<ByteArrayType, _>(&mut group, c.kind)?;
+        column::<Int32Type, _>(&mut group, c.port)?;
+        column::<Int32Type, _>(&mut group, c.comparator)?;
+        column::<Int64Type, _>(&mut group, c.address)?;
+        column::<ByteArrayType, _>(&mut group, c.exception)?;
+        column::<ByteArrayType, _>(&mut group, c.action)?;
+        column::<ByteArrayType, _>(&mut group, c.payload)?;
+        column::<ByteArrayType, _>(&mut group, c.value)?;
+        group.close()?;
+        Ok(())
+    }
+
+    /// Writes the buffered rows and the file footer, and returns the
+    /// underlying writer.
+    pub fn close(mut self) -> Result<W, ParquetError> {
+        self.flush()?;
+        self.writer.into_inner()
+    }
+}
+
+/// Writes the next column of a row group.
+fn column<T, W>(
+    group: &mut SerializedRowGroupWriter<'_, W>,
+    values: Vec<Option<T::T>>,
+) -> Result<(), ParquetError>
+where
+    T: DataType,
+    W: io::Write + Send,
+{
+    let mut writer = group
+        .next_column()?
+        .expect("row group has fewer columns than the schema");
+    let typed = writer.typed::<T>();
+    let levels: Vec<i16> = values.iter().map(|v| i16::from(v.is_some())).collect();
+    let values: Vec<T::T> = values.into_iter().flatten().collect();
+    if typed.get_descriptor().max_def_level() == 0 {
+        typed.write_batch(&values, None, None)?;
+    } else {
+        typed.write_batch(&values, Some(&levels), None)?;
+    }
+    writer.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExceptionAction;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::time::Duration;
+
+    #[test]
+    fn roundtrip() {
+        let packets = [
+            TracePacket::Instrumentation {
+                port: 3,
+                payload: [b'h', b'i'].into(),
+            },
+            TracePacket::PCSample { pc: Some(0x100) },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::from_name("SysTick").unwrap().into(),
+                action: ExceptionAction::Entered,
+            },
+        ];
+        let mut writer = ParquetWriter::new(vec![], ParquetOptions { row_group_size: 2 }).unwrap();
+        for (seq, packet) in packets.iter().enumerate() {
+            let ts = Timestamp::Sync(Duration::from_micros(seq as u64));
+            writer.row(seq as u64, packet, Some(&ts)).unwrap();
+        }
+        let file = writer.close().unwrap();
+
+        let reader = SerializedFileReader::new(bytes::Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                r#"{seq: 0, time_ns: 0, quality: "sync", kind: "instrumentation", port: 3, comparator: null, address: null, exception: null, action: null, payload: [104, 105], value: "6869"}"#,
+                r#"{seq: 1, time_ns: 1000, quality: "sync", kind: "pc-sample", port: null, comparator: null, address: 256, exception: null, action: null, payload: null, value: "0x00000100"}"#,
+                r#"{seq: 2, time_ns: 2000, quality: "sync", kind: "exception-trace", port: null, comparator: null, address: null, exception: "SysTick", action: "entered", payload: null, value: "SysTick Entered"}"#,
+            ]
+        );
+    }
+}
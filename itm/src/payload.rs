@@ -0,0 +1,213 @@
+//! Inline storage of packet payloads.
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use core::convert::{TryFrom, TryInto};
+use core::fmt;
+use core::ops::Deref;
+
+/// Maximum payload size of a source packet. (Appendix D4.2.8, Table
+/// D4-4)
+const MAX_LEN: usize = 4;
+
+/// The payload of a source packet, e.g. the data written to an ITM
+/// stimulus port. Stored inline, so that decoding a packet does not
+/// allocate. Dereferences to `[u8]`.
+///
+/// ```
+/// use itm::Payload;
+///
+/// let payload = Payload::from([0x41, 0x42]);
+/// assert_eq!(&payload[..], b"AB");
+/// assert!(Payload::new(&[0; 5]).is_none());
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Payload {
+    len: u8,
+    bytes: [u8; MAX_LEN],
+}
+
+impl Payload {
+    /// Copies `bytes` into a payload. Returns `None` if `bytes` is
+    /// longer than 4 bytes.
+    pub fn new(bytes: &[u8]) -> Option<Payload> {
+        if bytes.len() > MAX_LEN {
+            return None;
+        }
+        let mut payload = Payload {
+            len: bytes.len() as u8,
+            bytes: [0; MAX_LEN],
+        };
+        payload.bytes[..bytes.len()].copy_from_slice(bytes);
+        Some(payload)
+    }
+
+    /// The payload as a byte, if it is a single byte.
+    pub fn as_u8(&self) -> Option<u8> {
+        match **self {
+            [b] => Some(b),
+            _ => None,
+        }
+    }
+
+    /// The payload as a halfword, if it is two bytes long.
+    pub fn as_u16(&self, endianness: Endianness) -> Option<u16> {
+        let bytes = (**self).try_into().ok()?;
+        Some(match endianness {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    /// The payload as a word, if it is four bytes long.
+    pub fn as_u32(&self, endianness: Endianness) -> Option<u32> {
+        let bytes = (**self).try_into().ok()?;
+        Some(match endianness {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// The payload as a string. Invalid UTF-8 sequences are replaced
+    /// with `U+FFFD`. Note that a multi-byte character may be split
+    /// across payloads.
+    pub fn as_str_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self)
+    }
+}
+
+/// Byte order of a multi-byte payload. Cortex-M targets write
+/// stimulus ports, and the DWT outputs data values, in
+/// [`Little`](Endianness::Little) endian byte order unless configured
+/// as big endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endianness {
+    /// Least significant byte first.
+    Little,
+
+    /// Most significant byte first.
+    Big,
+}
+
+// `#[default]` requires Rust 1.62
+#[allow(clippy::derivable_impls)]
+impl Default for Endianness {
+    fn default() -> Self {
+        Endianness::Little
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len.into()]
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl IntoIterator for Payload {
+    type Item = u8;
+    type IntoIter = core::iter::Take<core::array::IntoIter<u8, MAX_LEN>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self.bytes).take(self.len.into())
+    }
+}
+
+impl<'a> IntoIterator for &'a Payload {
+    type Item = &'a u8;
+    type IntoIter = core::slice::Iter<'a, u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Arrays that fit in a payload.
+macro_rules! from_array {
+    ($($n:literal),*) => {
+        $(
+            impl From<[u8; $n]> for Payload {
+                fn from(bytes: [u8; $n]) -> Self {
+                    Payload::new(&bytes).unwrap()
+                }
+            }
+        )*
+    };
+}
+from_array!(0, 1, 2, 3, 4);
+
+/// The slice is longer than 4 bytes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+#[cfg_attr(feature = "std", error("Payload of {0} bytes exceeds 4 bytes"))]
+pub struct PayloadTooLong(pub usize);
+
+impl TryFrom<&[u8]> for Payload {
+    type Error = PayloadTooLong;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Payload::new(bytes).ok_or(PayloadTooLong(bytes.len()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Payload {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Payload {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bytes = alloc::vec::Vec::<u8>::deserialize(d)?;
+        Payload::new(&bytes).ok_or_else(|| {
+            serde::de::Error::invalid_length(bytes.len(), &"a payload of at most 4 bytes")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload() {
+        let payload = Payload::from([1, 2, 3]);
+        assert_eq!(payload.len(), 3);
+        assert_eq!(
+            payload.into_iter().collect::<alloc::vec::Vec<u8>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(alloc::format!("{:?}", payload), "[1, 2, 3]");
+        assert_eq!(Payload::try_from(&[0; 5][..]), Err(PayloadTooLong(5)));
+    }
+
+    #[test]
+    fn interpret() {
+        let payload = Payload::from([0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(payload.as_u32(Endianness::Little), Some(0x1234_5678));
+        assert_eq!(payload.as_u32(Endianness::Big), Some(0x7856_3412));
+        assert_eq!(payload.as_u16(Endianness::Little), None);
+        assert_eq!(payload.as_u8(), None);
+
+        let payload = Payload::from([0x34, 0x12]);
+        assert_eq!(payload.as_u16(Endianness::Little), Some(0x1234));
+        assert_eq!(Payload::from([0x41]).as_u8(), Some(0x41));
+        assert_eq!(Payload::from(*b"h\xffi").as_str_lossy(), "h\u{fffd}i");
+    }
+}
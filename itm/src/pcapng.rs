@@ -0,0 +1,439 @@
+//! pcapng capture files of the raw trace stream.
+//!
+//! [`PcapngWriter`] archives the raw trace data in the
+//! [pcapng](https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html)
+//! format, as network tooling does: each chunk of data that arrived at
+//! the host is stored in an Enhanced Packet Block, with its arrival time
+//! in nanoseconds, on an interface of link type `LINKTYPE_USER0` (147).
+//! Optionally, the stream offsets of decoded packets (as reported by
+//! [`PacketDecoder::pull_annotated`](crate::PacketDecoder::pull_annotated))
+//! are stored in custom blocks, so that packet boundaries can be
+//! inspected without decoding.
+//!
+//! Captures are read back with [`read`], e.g. to decode them again:
+//!
+//! ```
+//! use itm::pcapng::{read, Block, PcapngOptions, PcapngWriter};
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let time = UNIX_EPOCH + Duration::from_nanos(1_600_000_000_123_456_789);
+//! let mut pcapng = PcapngWriter::new(vec![], PcapngOptions::default()).unwrap();
+//! pcapng.chunk(&[0x03, b'h'], time).unwrap();
+//! let capture = pcapng.into_inner();
+//!
+//! let blocks: Vec<Block> = read(&capture[..]).collect::<Result<_, _>>().unwrap();
+//! assert_eq!(
+//!     blocks,
+//!     [Block::Chunk {
+//!         time,
+//!         data: vec![0x03, b'h'],
+//!     }]
+//! );
+//! ```
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+/// A custom block that may be copied to new files.
+const CUSTOM: u32 = 0x0000_0bad;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const LINKTYPE_USER0: u16 = 147;
+const OPT_END: u16 = 0;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+
+/// Errors that can occur while reading a pcapng capture.
+#[derive(Debug, thiserror::Error)]
+pub enum PcapngError {
+    #[error("Failed to read capture: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Not a pcapng capture")]
+    NotPcapng,
+
+    #[error("Malformed {0} block")]
+    Malformed(&'static str),
+}
+
+/// Options of a [`PcapngWriter`].
+#[derive(Debug, Clone)]
+pub struct PcapngOptions {
+    /// Name of the capture interface, e.g. the serial device.
+    pub interface: String,
+
+    /// Private Enterprise Number of the custom blocks that hold packet
+    /// boundaries. [`read`] only reports the boundaries of custom
+    /// blocks with this number.
+    pub pen: u32,
+}
+
+impl Default for PcapngOptions {
+    fn default() -> Self {
+        Self {
+            interface: "swo".to_string(),
+            pen: 0,
+        }
+    }
+}
+
+/// Writes the trace stream as a pcapng capture. See the [module
+/// documentation](self).
+pub struct PcapngWriter<W: Write> {
+    out: W,
+    pen: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and the description of the capture
+    /// interface.
+    pub fn new(mut out: W, options: PcapngOptions) -> io::Result<Self> {
+        let mut shb = vec![];
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length: unspecified
+        shb.extend_from_slice(&u64::MAX.to_le_bytes());
+        block(&mut out, SECTION_HEADER, &shb)?;
+
+        let mut idb = vec![];
+        idb.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // Snap length: unlimited
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        option(&mut idb, IF_NAME, options.interface.as_bytes());
+        // Nanosecond resolution
+        option(&mut idb, IF_TSRESOL, &[9]);
+        option(&mut idb, OPT_END, &[]);
+        block(&mut out, INTERFACE_DESCRIPTION, &idb)?;
+
+        Ok(Self {
+            out,
+            pen: options.pen,
+        })
+    }
+
+    /// Writes a chunk of trace data that arrived at `time`.
+    pub fn chunk(&mut self, data: &[u8], time: SystemTime) -> io::Result<()> {
+        let ns = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let mut epb = Vec::with_capacity(20 + data.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((ns >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ns as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(data);
+        pad(&mut epb);
+        block(&mut self.out, ENHANCED_PACKET, &epb)
+    }
+
+    /// Writes the stream offsets at which decoded packets start.
+    pub fn boundaries(&mut self, offsets: &[u64]) -> io::Result<()> {
+        let mut cb = Vec::with_capacity(4 + 8 * offsets.len());
+        cb.extend_from_slice(&self.pen.to_le_bytes());
+        for offset in offsets {
+            cb.extend_from_slice(&offset.to_le_bytes());
+        }
+        block(&mut self.out, CUSTOM, &cb)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Unwraps the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// A [`Read`] wrapper that writes each chunk of data read to a
+/// [`PcapngWriter`], with the host time at which it arrived.
+pub struct PcapngTee<R: Read, W: Write> {
+    reader: R,
+    writer: PcapngWriter<W>,
+}
+
+impl<R: Read, W: Write> PcapngTee<R, W> {
+    pub fn new(reader: R, writer: PcapngWriter<W>) -> Self {
+        Self { reader, writer }
+    }
+
+    /// Returns a reference to the underlying [`Read`].
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the writer, e.g. to write packet
+    /// [boundaries](PcapngWriter::boundaries).
+    pub fn writer_mut(&mut self) -> &mut PcapngWriter<W> {
+        &mut self.writer
+    }
+}
+
+impl<R: Read, W: Write> Read for PcapngTee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n > 0 {
+            self.writer.chunk(&buf[..n], SystemTime::now())?;
+        }
+        Ok(n)
+    }
+}
+
+/// A block of a capture, as written by [`PcapngWriter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    /// A chunk of trace data, and the time it arrived at the host.
+    Chunk { time: SystemTime, data: Vec<u8> },
+
+    /// Stream offsets at which decoded packets start.
+    Boundaries(Vec<u64>),
+}
+
+/// Reads the [`Block`]s of a pcapng capture, with boundaries of custom
+/// blocks with the [default](PcapngOptions::default) Private Enterprise
+/// Number. Blocks of other types are skipped.
+pub fn read<R: Read>(input: R) -> Blocks<R> {
+    read_with(input, PcapngOptions::default().pen)
+}
+
+/// Like [`read`], but with boundaries of custom blocks with `pen`.
+pub fn read_with<R: Read>(input: R, pen: u32) -> Blocks<R> {
+    Blocks {
+        input,
+        pen,
+        big_endian: None,
+        resolutions: vec![],
+    }
+}
+
+/// Iterator over the blocks of a pcapng capture. See [`read`].
+pub struct Blocks<R: Read> {
+    input: R,
+    pen: u32,
+    /// Byte order of the current section. `None` before the first
+    /// section header.
+    big_endian: Option<bool>,
+    /// Timestamp resolution of each interface of the current section,
+    /// in units per second.
+    resolutions: Vec<u64>,
+}
+
+impl<R: Read> Blocks<R> {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        match self.big_endian {
+            Some(true) => u16::from_be_bytes(bytes),
+            _ => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        match self.big_endian {
+            Some(true) => u32::from_be_bytes(bytes),
+            _ => u32::from_le_bytes(bytes),
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let bytes = bytes[..8].try_into().unwrap();
+        match self.big_endian {
+            Some(true) => u64::from_be_bytes(bytes),
+            _ => u64::from_le_bytes(bytes),
+        }
+    }
+
+    /// Reads the next block, returning `None` at the end of the input.
+    fn block(&mut self) -> Result<Option<(u32, Vec<u8>)>, PcapngError> {
+        let mut header = [0; 8];
+        match self.input.read_exact(&mut header[..4]) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        self.input.read_exact(&mut header[4..])?;
+
+        let raw_type = u32::from_le_bytes(header[..4].try_into().unwrap());
+        if raw_type == SECTION_HEADER {
+            let mut magic = [0; 4];
+            self.input.read_exact(&mut magic)?;
+            self.big_endian = match u32::from_le_bytes(magic) {
+                BYTE_ORDER_MAGIC => Some(false),
+                m if m.swap_bytes() == BYTE_ORDER_MAGIC => Some(true),
+                _ => return Err(PcapngError::NotPcapng),
+            };
+            self.resolutions.clear();
+            let len = self.u32(&header[4..]) as usize;
+            if len < 16 || len & 3 != 0 {
+                return Err(PcapngError::Malformed("section header"));
+            }
+            let mut body = vec![0; len - 16];
+            self.input.read_exact(&mut body)?;
+            self.input.read_exact(&mut [0; 4])?;
+            return Ok(Some((SECTION_HEADER, body)));
+        }
+        if self.big_endian.is_none() {
+            return Err(PcapngError::NotPcapng);
+        }
+
+        let len = self.u32(&header[4..]) as usize;
+        if len < 12 || len & 3 != 0 {
+            return Err(PcapngError::Malformed("block"));
+        }
+        let mut body = vec![0; len - 8];
+        self.input.read_exact(&mut body)?;
+        body.truncate(len - 12);
+        Ok(Some((self.u32(&header), body)))
+    }
+
+    fn interface(&mut self, body: &[u8]) -> Result<(), PcapngError> {
+        if body.len() < 8 {
+            return Err(PcapngError::Malformed("interface description"));
+        }
+        let mut resolution = 1_000_000;
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = self.u16(options);
+            let len = self.u16(&options[2..]) as usize;
+            let value = options
+                .get(4..4 + len)
+                .ok_or(PcapngError::Malformed("interface description"))?;
+            match (code, value) {
+                (OPT_END, _) => break,
+                (IF_TSRESOL, [r]) if r & 0x80 == 0 => resolution = 10u64.pow(u32::from(*r)),
+                (IF_TSRESOL, [r]) => resolution = 1 << (r & 0x7f),
+                _ => (),
+            }
+            options = options.get((4 + len + 3) & !3..).unwrap_or(&[]);
+        }
+        self.resolutions.push(resolution);
+        Ok(())
+    }
+
+    fn chunk(&self, body: &[u8]) -> Result<Block, PcapngError> {
+        if body.len() < 20 {
+            return Err(PcapngError::Malformed("enhanced packet"));
+        }
+        let resolution = *self
+            .resolutions
+            .get(self.u32(body) as usize)
+            .ok_or(PcapngError::Malformed("enhanced packet"))?;
+        let ts = u64::from(self.u32(&body[4..])) << 32 | u64::from(self.u32(&body[8..]));
+        let len = self.u32(&body[12..]) as usize;
+        let data = body
+            .get(20..20 + len)
+            .ok_or(PcapngError::Malformed("enhanced packet"))?;
+        let since_epoch = Duration::from_secs(ts / resolution)
+            + Duration::from_nanos(
+                ((ts % resolution) as u128 * 1_000_000_000 / resolution as u128) as u64,
+            );
+        Ok(Block::Chunk {
+            time: UNIX_EPOCH + since_epoch,
+            data: data.to_vec(),
+        })
+    }
+}
+
+impl<R: Read> Iterator for Blocks<R> {
+    type Item = Result<Block, PcapngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (block_type, body) = match self.block() {
+                Ok(Some(block)) => block,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    if let Err(e) = self.interface(&body) {
+                        return Some(Err(e));
+                    }
+                }
+                ENHANCED_PACKET => return Some(self.chunk(&body)),
+                CUSTOM if body.len() >= 4 && self.u32(&body) == self.pen => {
+                    let offsets = body[4..]
+                        .chunks_exact(8)
+                        .map(|offset| self.u64(offset))
+                        .collect();
+                    return Some(Ok(Block::Boundaries(offsets)));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Writes a block with its length fields. The block is written at
+/// once, so that an unbuffered capture file only ends in a partial
+/// block if the write fails.
+fn block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let len = (12 + body.len()) as u32;
+    let mut block = Vec::with_capacity(len as usize);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&len.to_le_bytes());
+    block.extend_from_slice(body);
+    block.extend_from_slice(&len.to_le_bytes());
+    out.write_all(&block)
+}
+
+/// Appends an option, padded to 32 bits.
+fn option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_le_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    body.extend_from_slice(value);
+    pad(body);
+}
+
+/// Pads a block body to 32 bits.
+fn pad(body: &mut Vec<u8>) {
+    body.resize((body.len() + 3) & !3, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_all, TracePacket};
+
+    #[test]
+    fn roundtrip() {
+        let stream = [0x03, b'h', 0x03, b'i', 0x03, b'!'];
+        let mut tee = PcapngTee::new(
+            &stream[..],
+            PcapngWriter::new(vec![], PcapngOptions::default()).unwrap(),
+        );
+        let mut buf = [0; 4];
+        while tee.read(&mut buf).unwrap() > 0 {}
+        tee.writer_mut().boundaries(&[0, 2, 4]).unwrap();
+        let capture = tee.writer.into_inner();
+
+        let blocks: Vec<Block> = read(&capture[..]).collect::<Result<_, _>>().unwrap();
+        let data: Vec<u8> = blocks
+            .iter()
+            .flat_map(|block| match block {
+                Block::Chunk { data, .. } => data.clone(),
+                Block::Boundaries(_) => vec![],
+            })
+            .collect();
+        assert_eq!(data, stream);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[2], Block::Boundaries(vec![0, 2, 4]));
+        assert!(decode_all(&data)
+            .all(|packet| matches!(packet, Ok(TracePacket::Instrumentation { port: 0, .. }))));
+
+        assert!(matches!(
+            read(&b"not a capture"[..]).next(),
+            Some(Err(PcapngError::NotPcapng))
+        ));
+        // Boundaries of other enterprises are skipped
+        assert_eq!(read_with(&capture[..], 1).count(), 2);
+    }
+}
@@ -0,0 +1,176 @@
+//! Names and metadata of stimulus ports.
+
+use super::TracePacket;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+/// How the data written to a stimulus port is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum PortEncoding {
+    /// Lines of text. See [`Lines`](crate::Lines).
+    Text,
+
+    /// Raw binary data.
+    Binary,
+
+    /// COBS frames. See [`cobs`](crate::cobs).
+    Cobs,
+}
+
+/// Metadata of a stimulus port.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PortInfo {
+    /// Name of the port, e.g. `log` or `adc_samples`.
+    pub name: String,
+
+    /// How the data written to the port is encoded, if known.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub encoding: Option<PortEncoding>,
+
+    /// Free-form description of the port.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: Option<String>,
+}
+
+impl PortInfo {
+    /// Metadata of a port with only a name.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            encoding: None,
+            description: None,
+        }
+    }
+}
+
+/// A registry of [`PortInfo`](PortInfo) by stimulus port number, with
+/// which ports can be presented by name.
+///
+/// ```
+/// use itm::{PortInfo, PortMap, TracePacket};
+///
+/// let mut ports = PortMap::new();
+/// ports.insert(0, PortInfo::named("log"));
+/// assert_eq!(ports.label(0), "log");
+/// assert_eq!(ports.label(1), "1");
+/// assert_eq!(ports.port("log"), Some(0));
+///
+/// let packet = TracePacket::Instrumentation {
+///     port: 0,
+///     payload: [0x41].into(),
+/// };
+/// assert_eq!(
+///     ports.display(&packet).to_string(),
+///     "Instrumentation port 0 (log): 41"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct PortMap {
+    ports: BTreeMap<u8, PortInfo>,
+}
+
+impl PortMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the metadata of `port`, returning its previous
+    /// metadata, if any.
+    pub fn insert(&mut self, port: u8, info: PortInfo) -> Option<PortInfo> {
+        self.ports.insert(port, info)
+    }
+
+    /// The metadata of `port`, if any.
+    pub fn get(&self, port: u8) -> Option<&PortInfo> {
+        self.ports.get(&port)
+    }
+
+    /// The name of `port`, if any.
+    pub fn name(&self, port: u8) -> Option<&str> {
+        self.get(port).map(|info| info.name.as_str())
+    }
+
+    /// The encoding of `port`, if known.
+    pub fn encoding(&self, port: u8) -> Option<PortEncoding> {
+        self.get(port).and_then(|info| info.encoding)
+    }
+
+    /// The name of `port`, or its number if it has none.
+    pub fn label(&self, port: u8) -> String {
+        match self.name(port) {
+            Some(name) => name.to_string(),
+            None => port.to_string(),
+        }
+    }
+
+    /// The number of the port with the given name.
+    pub fn port(&self, name: &str) -> Option<u8> {
+        self.iter()
+            .find(|(_, info)| info.name == name)
+            .map(|(port, _)| port)
+    }
+
+    /// Iterates over all registered ports, in order of port number.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &PortInfo)> {
+        self.ports.iter().map(|(port, info)| (*port, info))
+    }
+
+    /// Displays the packet as its [`Display`](fmt::Display)
+    /// implementation does, with the name of the port of an
+    /// [`Instrumentation`](TracePacket::Instrumentation) packet added.
+    pub fn display<'a>(&'a self, packet: &'a TracePacket) -> impl fmt::Display + 'a {
+        Named {
+            ports: self,
+            packet,
+        }
+    }
+}
+
+impl FromIterator<(u8, PortInfo)> for PortMap {
+    fn from_iter<T: IntoIterator<Item = (u8, PortInfo)>>(iter: T) -> Self {
+        Self {
+            ports: iter.into_iter().collect(),
+        }
+    }
+}
+
+struct Named<'a> {
+    ports: &'a PortMap,
+    packet: &'a TracePacket,
+}
+
+impl fmt::Display for Named<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.packet, self.packet.port()) {
+            (TracePacket::Instrumentation { payload, .. }, Some(port)) => {
+                match self.ports.name(port) {
+                    Some(name) => write!(f, "Instrumentation port {} ({}):", port, name)?,
+                    None => write!(f, "Instrumentation port {}:", port)?,
+                }
+                for b in payload.iter() {
+                    write!(f, " {:02x}", b)?;
+                }
+                Ok(())
+            }
+            (packet, _) => fmt::Display::fmt(packet, f),
+        }
+    }
+}
+
+impl TracePacket {
+    /// The stimulus port of an
+    /// [`Instrumentation`](TracePacket::Instrumentation) packet.
+    pub fn port(&self) -> Option<u8> {
+        match self {
+            TracePacket::Instrumentation { port, .. } => Some(*port),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! SWO capture through [probe-rs](https://probe.rs).
+//!
+//! Rather than only capturing SWO with the probe, this attaches to the
+//! target as a debugger and also sets up its TPIU and ITM for SWO, so
+//! that neither OpenOCD nor the firmware has to. Any probe supported by probe-rs
+//! will do. The probe is [found](find) by USB VID:PID or serial number,
+//! and [attached](attach) to the target, after which the captured
+//! stream is read from the returned [`SwoCapture`]:
+//!
+//! ```no_run
+//! use itm::probe::{self, CaptureOptions};
+//! use itm::{Decoder, DecoderOptions};
+//!
+//! let probe = probe::find(None)?;
+//! let capture = probe::attach(
+//!     &probe,
+//!     &CaptureOptions {
+//!         chip: "STM32F411RETx".to_string(),
+//!         trace_clock: 16_000_000,
+//!         baud_rate: 2_000_000,
+//!         speed: None,
+//!     },
+//! )?;
+//! for packet in Decoder::new(capture, DecoderOptions::default()).singles() {
+//!     println!("{:?}", packet);
+//! }
+//! # Ok::<(), probe::ProbeError>(())
+//! ```
+//!
+//! The stimulus ports and DWT of the target are left as they are, i.e.
+//! as configured by the firmware.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use probe_rs::architecture::arm::SwoConfig;
+use probe_rs::{DebugProbeInfo, Probe, Session};
+use thiserror::Error;
+
+/// Delay between polls of a target that had no SWO data.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Index of the core whose TPIU is set up.
+const CORE: usize = 0;
+
+/// Possible errors on [`find`] and [`attach`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ProbeError {
+    #[error("Probe error: {0}")]
+    Probe(#[from] probe_rs::DebugProbeError),
+
+    #[error("Target error: {0}")]
+    Target(#[from] probe_rs::Error),
+
+    #[error("No probe matches {0:?}")]
+    NoSuchProbe(String),
+
+    #[error("Several probes match {selector:?}: {}", matches.join(", "))]
+    AmbiguousProbe {
+        selector: String,
+        matches: Vec<String>,
+    },
+
+    #[error("The trace clock of {trace_clock} Hz cannot be divided down to {baud_rate} Bd")]
+    UnreachableBaudRate { trace_clock: u32, baud_rate: u32 },
+}
+
+/// A debug probe connected to the system.
+#[derive(Debug, Clone)]
+pub struct ProbeInfo(DebugProbeInfo);
+
+impl ProbeInfo {
+    /// Whether the probe is selected by `selector`: a `VID:PID` pair in
+    /// hexadecimal, e.g. `0483:374b`, or a case-insensitive substring of
+    /// the serial number.
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Some((vid, pid)) = selector.split_once(':') {
+            if let (Ok(vid), Ok(pid)) = (u16::from_str_radix(vid, 16), u16::from_str_radix(pid, 16))
+            {
+                return self.0.vendor_id == vid && self.0.product_id == pid;
+            }
+        }
+        let selector = selector.to_lowercase();
+        matches!(&self.0.serial_number, Some(serial_number) if serial_number.to_lowercase().contains(&selector))
+    }
+}
+
+impl From<DebugProbeInfo> for ProbeInfo {
+    fn from(info: DebugProbeInfo) -> Self {
+        Self(info)
+    }
+}
+
+impl std::fmt::Display for ProbeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:04x} {}",
+            self.0.vendor_id, self.0.product_id, self.0.identifier
+        )?;
+        if let Some(serial_number) = &self.0.serial_number {
+            write!(f, " (serial number {})", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the debug probes connected to the system that probe-rs
+/// supports.
+pub fn probes() -> Vec<ProbeInfo> {
+    Probe::list_all().into_iter().map(ProbeInfo).collect()
+}
+
+/// Selects the single probe of `probes` that [matches](ProbeInfo::matches)
+/// `selector`, or the single probe if there is no selector.
+pub fn select(probes: Vec<ProbeInfo>, selector: Option<&str>) -> Result<ProbeInfo, ProbeError> {
+    let mut matches: Vec<ProbeInfo> = probes
+        .into_iter()
+        .filter(|probe| selector.iter().all(|selector| probe.matches(selector)))
+        .collect();
+    let selector = selector.unwrap_or("").to_string();
+    match matches.len() {
+        0 => Err(ProbeError::NoSuchProbe(selector)),
+        1 => Ok(matches.remove(0)),
+        _ => Err(ProbeError::AmbiguousProbe {
+            selector,
+            matches: matches.iter().map(ProbeInfo::to_string).collect(),
+        }),
+    }
+}
+
+/// Finds the single probe connected to the system that
+/// [matches](ProbeInfo::matches) `selector`, or the single probe if
+/// there is no selector.
+pub fn find(selector: Option<&str>) -> Result<ProbeInfo, ProbeError> {
+    select(probes(), selector)
+}
+
+/// How the target is attached to and SWO captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureOptions {
+    /// Name of the target chip, as known to probe-rs, e.g.
+    /// `STM32F411RETx`.
+    pub chip: String,
+
+    /// Frequency of the TPIU clock in Hz, usually the core clock.
+    pub trace_clock: u32,
+
+    /// SWO bit rate, in bits per second, which `trace_clock` must be a
+    /// multiple of.
+    pub baud_rate: u32,
+
+    /// Speed of the debug interface in kHz, or the default of the probe.
+    pub speed: Option<u32>,
+}
+
+/// The SWO prescaler, i.e. the value of `TPIU_ACPR`, that divides
+/// `trace_clock` down to exactly `baud_rate`.
+fn prescaler(trace_clock: u32, baud_rate: u32) -> Result<u32, ProbeError> {
+    match trace_clock.checked_div(baud_rate) {
+        Some(divisor) if divisor > 0 && divisor * baud_rate == trace_clock => Ok(divisor - 1),
+        _ => Err(ProbeError::UnreachableBaudRate {
+            trace_clock,
+            baud_rate,
+        }),
+    }
+}
+
+/// An ongoing SWO capture. Reads block until SWO data is available.
+/// SWO is disabled on drop, after which the target keeps running.
+pub struct SwoCapture {
+    session: Session,
+    buffer: Vec<u8>,
+    /// Number of bytes of `buffer` already read.
+    read: usize,
+}
+
+impl std::fmt::Debug for SwoCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwoCapture").finish()
+    }
+}
+
+/// Opens `probe`, attaches to the target, and sets up its TPIU for NRZ
+/// SWO at the bit rate of `options`, with the ITM enabled.
+pub fn attach(probe: &ProbeInfo, options: &CaptureOptions) -> Result<SwoCapture, ProbeError> {
+    prescaler(options.trace_clock, options.baud_rate)?;
+    let mut opened = probe.0.open()?;
+    if let Some(speed) = options.speed {
+        opened.set_speed(speed)?;
+    }
+    let mut session = opened.attach(options.chip.as_str())?;
+    let config = SwoConfig::new(options.trace_clock)
+        .set_baud(options.baud_rate)
+        .set_mode_uart()
+        .set_continuous_formatting(false);
+    session.setup_swv(CORE, &config)?;
+    Ok(SwoCapture {
+        session,
+        buffer: vec![],
+        read: 0,
+    })
+}
+
+impl Read for SwoCapture {
+    #[allow(clippy::io_other_error)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.buffer.len() {
+            self.buffer = self
+                .session
+                .read_swo()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, ProbeError::from(e)))?;
+            self.read = 0;
+            if self.buffer.is_empty() {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.read);
+        buf[..n].copy_from_slice(&self.buffer[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl Drop for SwoCapture {
+    fn drop(&mut self) {
+        let _ = self.session.disable_swv(CORE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use probe_rs::DebugProbeType;
+
+    fn probe(vid: u16, pid: u16, serial_number: &str) -> ProbeInfo {
+        DebugProbeInfo::new(
+            "Probe",
+            vid,
+            pid,
+            Some(serial_number.to_string()),
+            DebugProbeType::CmsisDap,
+        )
+        .into()
+    }
+
+    #[test]
+    fn prescalers() {
+        assert_eq!(prescaler(16_000_000, 2_000_000).unwrap(), 7);
+        assert_eq!(prescaler(2_000_000, 2_000_000).unwrap(), 0);
+        for (trace_clock, baud_rate) in [(80_000_000, 3_000_000), (1_000_000, 2_000_000), (1, 0)] {
+            assert!(matches!(
+                prescaler(trace_clock, baud_rate),
+                Err(ProbeError::UnreachableBaudRate { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn selection() {
+        let probes = vec![
+            probe(0x0483, 0x374b, "066DFF485"),
+            probe(0x0d28, 0x0204, "0240000034"),
+        ];
+        assert!(probes[0].matches("0483:374b"));
+        assert!(!probes[0].matches("0483:3748"));
+        assert!(probes[1].matches("00003"));
+        assert_eq!(
+            probes[0].to_string(),
+            "0483:374b Probe (serial number 066DFF485)"
+        );
+
+        assert!(select(probes.clone(), Some("066dff"))
+            .unwrap()
+            .matches("0483:374b"));
+        assert!(matches!(
+            select(probes.clone(), Some("1366:0101")),
+            Err(ProbeError::NoSuchProbe(_))
+        ));
+        assert!(matches!(
+            select(probes.clone(), Some("0")),
+            Err(ProbeError::AmbiguousProbe { .. })
+        ));
+        assert!(matches!(
+            select(probes, None),
+            Err(ProbeError::AmbiguousProbe { .. })
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+//! Repair of captures with a corrupt tail.
+//!
+//! A capture that was cut short, e.g. by a power loss of the target or
+//! the host, often ends in a region of garbage that decodes into a long
+//! run of [`MalformedPacket`](crate::MalformedPacket)s. [`trim_corrupt_tail`]
+//! locates the end of the consistently decodable part of a capture so
+//! that such a region can be cut off.
+
+use super::{Decoder, DecoderErrorInt, DecoderOptions, TracePacket};
+
+use std::collections::VecDeque;
+
+/// Number of most recent packets over which the error density is
+/// measured.
+const WINDOW: usize = 16;
+
+/// Number of malformed packets within [`WINDOW`] at which the stream is
+/// considered corrupt.
+const MAX_ERRORS: usize = 4;
+
+/// Number of consecutive well-formed packets after a
+/// [`Sync`](TracePacket::Sync) packet at which a corrupt stream is
+/// considered recovered.
+const RECOVERY: usize = 16;
+
+/// Result of [`trim_corrupt_tail`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrimmedCapture {
+    /// Length in bytes of the consistent part of the capture.
+    pub len: usize,
+
+    /// Number of well-formed packets in the consistent part.
+    pub packets: usize,
+
+    /// Number of well-formed packets in the trimmed tail.
+    pub dropped_packets: usize,
+
+    /// Number of malformed packets in the trimmed tail.
+    pub dropped_errors: usize,
+}
+
+/// Finds the last position in `capture` up to which decoding is
+/// consistent.
+///
+/// The capture is considered corrupt from the first malformed packet of
+/// a run in which at least 4 out of 16 consecutive packets are
+/// malformed, unless the stream later recovers with a
+/// [`Sync`](TracePacket::Sync) packet followed by 16 well-formed
+/// packets. Malformed packets and incomplete packets at the very end of
+/// the capture are always trimmed.
+pub fn trim_corrupt_tail(capture: &[u8]) -> TrimmedCapture {
+    /// A position in the capture: the bit offset, and the number of
+    /// well-formed and malformed packets before it.
+    #[derive(Clone, Copy)]
+    struct Position {
+        offset: u64,
+        packets: usize,
+        errors: usize,
+    }
+
+    let mut decoder = Decoder::new(capture, DecoderOptions::default());
+    let mut pos = Position {
+        offset: 0,
+        packets: 0,
+        errors: 0,
+    };
+
+    // Start positions of the most recent packets, and whether they
+    // were malformed.
+    let mut window: VecDeque<(Position, bool)> = VecDeque::with_capacity(WINDOW + 1);
+    // End of the last well-formed packet.
+    let mut good_end = pos;
+    // Start of the corrupt region, if any.
+    let mut cut: Option<Position> = None;
+    // Number of well-formed packets since a Sync in the corrupt region.
+    let mut recovery: Option<usize> = None;
+
+    loop {
+        let start = pos;
+        let malformed = match decoder.next_single() {
+            Ok(packet) => {
+                pos.packets += 1;
+                recovery = match (cut, recovery, packet) {
+                    (Some(_), _, TracePacket::Sync) => Some(0),
+                    (Some(_), Some(n), _) => Some(n + 1),
+                    _ => None,
+                };
+                false
+            }
+            Err(DecoderErrorInt::MalformedPacket(_))
+            | Err(DecoderErrorInt::Resynchronized { .. }) => {
+                pos.errors += 1;
+                recovery = None;
+                true
+            }
+            Err(DecoderErrorInt::Eof) | Err(DecoderErrorInt::Io(_)) => break,
+        };
+        pos.offset = decoder.bit_offset();
+        if !malformed {
+            good_end = pos;
+        }
+
+        window.push_back((start, malformed));
+        if window.len() > WINDOW {
+            window.pop_front();
+        }
+
+        if recovery >= Some(RECOVERY) {
+            cut = None;
+            recovery = None;
+            window.clear();
+        } else if cut.is_none() && window.iter().filter(|(_, m)| *m).count() >= MAX_ERRORS {
+            cut = window.iter().find(|(_, m)| *m).map(|(start, _)| *start);
+        }
+    }
+
+    let end = cut.unwrap_or(good_end);
+    TrimmedCapture {
+        len: ((end.offset + 7) >> 3) as usize,
+        packets: end.packets,
+        dropped_packets: pos.packets - end.packets,
+        dropped_errors: pos.errors - end.errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trim_corrupt_tail() {
+        #[rustfmt::skip]
+        let mut capture = vec![
+            // Instrumentation, 4-byte payload
+            0b0000_1011, 0x01, 0x02, 0x03, 0x04,
+            // Invalid hardware source packet
+            0b1111_1111,
+        ];
+        // Overflow
+        capture.extend([0b0111_0000; 16].iter());
+        let consistent = capture.len();
+
+        // Power loss: garbage followed by a truncated packet
+        capture.extend([0xff; 32].iter());
+        capture.extend([0b0000_1011, 0x01].iter());
+
+        assert_eq!(
+            super::trim_corrupt_tail(&capture),
+            TrimmedCapture {
+                len: consistent,
+                packets: 17,
+                dropped_packets: 0,
+                dropped_errors: 32,
+            }
+        );
+
+        // A consistent capture is left as is, save for a trailing
+        // malformed packet.
+        assert_eq!(
+            super::trim_corrupt_tail(&capture[..6]),
+            TrimmedCapture {
+                len: 5,
+                packets: 1,
+                dropped_packets: 0,
+                dropped_errors: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn trim_truncated() {
+        let mut capture = vec![];
+        for i in 0..32u8 {
+            // Instrumentation, 4-byte payload
+            capture.extend([0b0000_1011, i, i, i, i].iter());
+        }
+        let consistent = capture.len();
+
+        // A capture cut short within a packet only loses that packet.
+        let truncated = &capture[..consistent - 2];
+        assert_eq!(
+            super::trim_corrupt_tail(truncated),
+            TrimmedCapture {
+                len: consistent - 5,
+                packets: 31,
+                dropped_packets: 0,
+                dropped_errors: 0,
+            }
+        );
+
+        // A stream that recovers after a corrupt region is kept whole.
+        let mut recovered = capture.clone();
+        recovered.extend([0xff; 8].iter());
+        recovered.extend([0, 0, 0, 0, 0, 0x80].iter());
+        recovered.extend(&capture);
+        assert_eq!(super::trim_corrupt_tail(&recovered).len, recovered.len());
+
+        // One that does not is cut at the start of the corrupt region,
+        // even if well-formed packets follow.
+        let mut corrupt = capture.clone();
+        corrupt.extend([0xff; 8].iter());
+        corrupt.extend(&capture[..15]);
+        assert_eq!(
+            super::trim_corrupt_tail(&corrupt),
+            TrimmedCapture {
+                len: consistent,
+                packets: 32,
+                dropped_packets: 3,
+                dropped_errors: 8,
+            }
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Typed decoding of binary data written to stimulus ports.
+//!
+//! Binary telemetry is commonly written to a dedicated stimulus port as
+//! a stream of fixed-size records, e.g. little-endian `u32` samples or
+//! a packed `struct { u16, u16 }`. A [`SchemaDecoder`] reassembles such
+//! [`Record`]s from the [`Instrumentation`](TracePacket::Instrumentation)
+//! payloads of each port that a [`Schema`] is registered for.
+//!
+//! ```
+//! use itm::schema::{FieldType, Schema, SchemaDecoder, Value};
+//! use itm::{Endianness, TracePacket};
+//!
+//! let mut decoder = SchemaDecoder::new();
+//! decoder.register(
+//!     5,
+//!     Schema {
+//!         fields: vec![FieldType::U16, FieldType::U16],
+//!         endianness: Endianness::Little,
+//!     },
+//! );
+//! decoder.push(&TracePacket::Instrumentation {
+//!     port: 5,
+//!     payload: [0x01, 0x00, 0x02, 0x00].into(),
+//! });
+//! let record = decoder.pull().unwrap();
+//! assert_eq!(record.values, [Value::U16(1), Value::U16(2)]);
+//! ```
+
+use super::{Endianness, TracePacket};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+
+/// The type of a field of a [`Schema`](Schema).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl FieldType {
+    /// The size of the field in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => 8,
+        }
+    }
+
+    /// Decodes a field from exactly [`size`](Self::size) bytes.
+    fn decode(&self, bytes: &[u8], endianness: Endianness) -> Value {
+        macro_rules! decode {
+            ($variant:ident, $ty:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                Value::$variant(match endianness {
+                    Endianness::Little => <$ty>::from_le_bytes(bytes),
+                    Endianness::Big => <$ty>::from_be_bytes(bytes),
+                })
+            }};
+        }
+
+        match self {
+            FieldType::U8 => decode!(U8, u8),
+            FieldType::I8 => decode!(I8, i8),
+            FieldType::U16 => decode!(U16, u16),
+            FieldType::I16 => decode!(I16, i16),
+            FieldType::U32 => decode!(U32, u32),
+            FieldType::I32 => decode!(I32, i32),
+            FieldType::U64 => decode!(U64, u64),
+            FieldType::I64 => decode!(I64, i64),
+            FieldType::F32 => decode!(F32, f32),
+            FieldType::F64 => decode!(F64, f64),
+        }
+    }
+}
+
+/// The layout of the records written to a stimulus port: a packed
+/// sequence of fields. A schema of a single field describes a stream
+/// of scalar samples.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Schema {
+    /// The fields of a record, in order.
+    pub fields: Vec<FieldType>,
+
+    /// Byte order of multi-byte fields.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub endianness: Endianness,
+}
+
+impl Schema {
+    /// The size of a record in bytes.
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(FieldType::size).sum()
+    }
+}
+
+/// A decoded field value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
+    U8(u8),
+    I8(i8),
+    U16(u16),
+    I16(i16),
+    U32(u32),
+    I32(i32),
+    U64(u64),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::U8(v) => v.fmt(f),
+            Value::I8(v) => v.fmt(f),
+            Value::U16(v) => v.fmt(f),
+            Value::I16(v) => v.fmt(f),
+            Value::U32(v) => v.fmt(f),
+            Value::I32(v) => v.fmt(f),
+            Value::U64(v) => v.fmt(f),
+            Value::I64(v) => v.fmt(f),
+            Value::F32(v) => v.fmt(f),
+            Value::F64(v) => v.fmt(f),
+        }
+    }
+}
+
+/// A record decoded according to the [`Schema`](Schema) of its port.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record {
+    /// Stimulus port the record was written to.
+    pub port: u8,
+
+    /// The values of the fields of the schema, in order.
+    pub values: Vec<Value>,
+}
+
+/// Decodes the records written to the ports that a
+/// [`Schema`](Schema) is registered for. Packets are
+/// [pushed](Self::push) as they are decoded, and complete records are
+/// [pulled](Self::pull) out. See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct SchemaDecoder {
+    ports: BTreeMap<u8, (Schema, Vec<u8>)>,
+    complete: VecDeque<Record>,
+}
+
+impl SchemaDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the schema of the records written to `port`,
+    /// discarding any partial record of a previous schema.
+    pub fn register(&mut self, port: u8, schema: Schema) {
+        self.ports.insert(port, (schema, Vec::new()));
+    }
+
+    /// The schema registered for `port`, if any.
+    pub fn schema(&self, port: u8) -> Option<&Schema> {
+        self.ports.get(&port).map(|(schema, _)| schema)
+    }
+
+    /// Appends the payload of the packet to the record of its port, if
+    /// it is an [`Instrumentation`](TracePacket::Instrumentation)
+    /// packet on a port with a registered schema. Returns whether the
+    /// packet was consumed.
+    pub fn push(&mut self, packet: &TracePacket) -> bool {
+        let (port, payload) = match packet {
+            TracePacket::Instrumentation { port, payload } => (*port, payload),
+            _ => return false,
+        };
+        let (schema, buffer) = match self.ports.get_mut(&port) {
+            Some(entry) => entry,
+            None => return false,
+        };
+
+        buffer.extend_from_slice(payload);
+        let size = schema.size();
+        if size == 0 {
+            buffer.clear();
+            return true;
+        }
+        while buffer.len() >= size {
+            let mut offset = 0;
+            let values = schema
+                .fields
+                .iter()
+                .map(|field| {
+                    let value =
+                        field.decode(&buffer[offset..offset + field.size()], schema.endianness);
+                    offset += field.size();
+                    value
+                })
+                .collect();
+            buffer.drain(..size);
+            self.complete.push_back(Record { port, values });
+        }
+        true
+    }
+
+    /// Pulls the next complete record, in the order of completion.
+    pub fn pull(&mut self) -> Option<Record> {
+        self.complete.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn records() {
+        let mut decoder = SchemaDecoder::new();
+        decoder.register(
+            3,
+            Schema {
+                fields: vec![FieldType::U32],
+                endianness: Endianness::Little,
+            },
+        );
+        decoder.register(
+            4,
+            Schema {
+                fields: vec![FieldType::I8, FieldType::U16, FieldType::F32],
+                endianness: Endianness::Big,
+            },
+        );
+
+        let packets = [
+            (3, [0x78, 0x56].into()),
+            (4, [0xff, 0x12, 0x34].into()),
+            (3, [0x34, 0x12, 0x01, 0x00].into()),
+            (4, [0x3f, 0x80, 0x00, 0x00].into()),
+            (1, [0x00].into()),
+        ];
+        let consumed: Vec<bool> = packets
+            .iter()
+            .map(|(port, payload)| {
+                decoder.push(&TracePacket::Instrumentation {
+                    port: *port,
+                    payload: *payload,
+                })
+            })
+            .collect();
+        assert_eq!(consumed, [true, true, true, true, false]);
+        assert!(!decoder.push(&TracePacket::Overflow));
+
+        let records: Vec<Record> = core::iter::from_fn(|| decoder.pull()).collect();
+        assert_eq!(
+            records,
+            [
+                Record {
+                    port: 3,
+                    values: vec![Value::U32(0x1234_5678)],
+                },
+                Record {
+                    port: 4,
+                    values: vec![Value::I8(-1), Value::U16(0x1234), Value::F32(1.0)],
+                },
+            ]
+        );
+    }
+}
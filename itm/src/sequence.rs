@@ -0,0 +1,95 @@
+//! Sequence numbering of emitted packets and events.
+//!
+//! Timestamps of distinct packets may tie, and not all packets are
+//! timestamped. When the same packets are written to more than one
+//! output, a [`Sequenced::seq`] number correlates their records
+//! exactly.
+
+/// An item together with its position in the emitted stream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sequenced<T> {
+    /// Monotonically increasing sequence number, starting at 0.
+    pub seq: u64,
+
+    /// The emitted item.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub item: T,
+}
+
+/// Iterator adapter that numbers the items of the inner iterator. See
+/// [`Sequenced`].
+pub struct Sequence<I> {
+    inner: I,
+    next: u64,
+}
+
+impl<I> Sequence<I>
+where
+    I: Iterator,
+{
+    pub fn new(inner: I) -> Self {
+        Self { inner, next: 0 }
+    }
+
+    /// Sequence number of the next emitted item.
+    pub fn next_seq(&self) -> u64 {
+        self.next
+    }
+}
+
+impl<I> Iterator for Sequence<I>
+where
+    I: Iterator,
+{
+    type Item = Sequenced<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        let seq = self.next;
+        self.next += 1;
+        Some(Sequenced { seq, item })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence() {
+        let mut it = Sequence::new(["a", "b", "c"].iter().copied().filter(|s| *s != "b"));
+        assert_eq!(it.next(), Some(Sequenced { seq: 0, item: "a" }));
+        assert_eq!(it.next_seq(), 1);
+        assert_eq!(it.next(), Some(Sequenced { seq: 1, item: "c" }));
+        assert_eq!(it.next(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        use crate::TracePacket;
+
+        let packets = [
+            Sequenced {
+                seq: 0,
+                item: TracePacket::Sync,
+            },
+            Sequenced {
+                seq: 1,
+                item: TracePacket::PCSample { pc: Some(0x100) },
+            },
+        ];
+        let json: Vec<String> = packets
+            .iter()
+            .map(|packet| serde_json::to_string(packet).unwrap())
+            .collect();
+        assert_eq!(
+            json,
+            [
+                r#"{"seq":0,"Sync":null}"#,
+                r#"{"seq":1,"PCSample":{"pc":256}}"#
+            ]
+        );
+    }
+}
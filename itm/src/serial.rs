@@ -1,10 +1,24 @@
 //! Convenience module for serial device configuration.
 //!
-//! This module exposes a single function, [`configure`], used to
-//! configure a serial device with a wanted baud rate so that the device
-//! can be used with this crate. This functionality is used downstream
-//! in `itm-decode` and `cargo-rtic-scope`.
+//! This module exposes two backends to configure a serial device with a
+//! wanted baud rate so that the device can be used with this crate:
+//!
+//! - [`configure`], with the `serial` feature, which configures an
+//!   opened device via termios. Linux only.
+//! - [`open`], with the `serialport` feature, which opens and configures
+//!   a device via the `serialport` crate, on Linux, macOS and Windows.
+//!   With it, the available devices can be [listed](devices), and
+//!   [found](find) by USB VID:PID or name.
+//!
+//! This functionality is used downstream in `itm-decode` and
+//! `cargo-rtic-scope`.
 
+#[cfg(feature = "serialport")]
+mod portable;
+#[cfg(feature = "serialport")]
+pub use portable::{devices, find, open, select, DeviceInfo, SerialDevice, UsbInfo};
+
+#[cfg(feature = "serial")]
 use nix::{
     fcntl::{self, FcntlArg, OFlag},
     libc,
@@ -13,10 +27,13 @@ use nix::{
         SetArg, SpecialCharacterIndices as CC,
     },
 };
+#[cfg(feature = "serial")]
 use std::fs;
+#[cfg(feature = "serial")]
 use std::os::unix::io::AsRawFd;
 use thiserror::Error;
 
+#[cfg(feature = "serial")]
 mod ioctl {
     use super::libc;
     use nix::{ioctl_none_bad, ioctl_read_bad, ioctl_write_int_bad, ioctl_write_ptr_bad};
@@ -26,14 +43,46 @@ mod ioctl {
     ioctl_read_bad!(fionread, libc::FIONREAD, libc::c_int);
     ioctl_write_ptr_bad!(tiocmset, libc::TIOCMSET, libc::c_int);
     ioctl_write_int_bad!(tcflsh, libc::TCFLSH);
+    ioctl_read_bad!(tcgets2, libc::TCGETS2, libc::termios2);
+    ioctl_write_ptr_bad!(tcsets2, libc::TCSETS2, libc::termios2);
 }
 
-/// Possible errors on [`configure`].
+/// Possible errors on [`configure`], [`open`] and [`find`].
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum SerialError {
     #[error("Error configuring serial device: {0}")]
     General(String),
+
+    #[error("Baud rate {requested} is not achievable by the serial device; the nearest achievable rate is {nearest}")]
+    BaudRate { requested: u32, nearest: u32 },
+
+    #[error("No serial device matches {0:?}")]
+    NoSuchDevice(String),
+
+    #[error("Several serial devices match {selector:?}: {}", matches.join(", "))]
+    AmbiguousDevice {
+        selector: String,
+        matches: Vec<String>,
+    },
+}
+
+/// Maximum deviation, in percent, of the baud rate of the device from
+/// the requested rate. The trace source has its own clock error, so the
+/// total should stay well within what a UART tolerates.
+const BAUD_RATE_TOLERANCE: u64 = 1;
+
+/// Checks that the rate `actual` that the device was set to is close
+/// enough to the `requested` one.
+fn check_baud_rate(requested: u32, actual: u32) -> Result<(), SerialError> {
+    let deviation = u64::from(requested.max(actual) - requested.min(actual)) * 100;
+    if deviation > u64::from(requested) * BAUD_RATE_TOLERANCE {
+        return Err(SerialError::BaudRate {
+            requested,
+            nearest: actual,
+        });
+    }
+    Ok(())
 }
 
 /// Opens and configures the given `device`.
@@ -43,18 +92,22 @@ pub enum SerialError {
 /// $ screen <device> <baud rate>
 /// ```
 ///
+/// Any integer baud rate can be requested: rates without a POSIX
+/// `B`-constant, e.g. 2.25 MBd, are set via `termios2` and `BOTHER`. If
+/// the device cannot get close enough to the requested rate,
+/// [`SerialError::BaudRate`] reports the nearest one it can.
+///
 /// TODO ensure POSIX compliance, see termios(3)
 /// TODO We are currently using line disciple 0. Is that correct?
+#[cfg(feature = "serial")]
 pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
     use SerialError as Error;
 
-    // ensure a valid baud rate was requested
-    let baud_rate: BaudRate = ArbitraryBaudRate(baud_rate)
-        .try_into()
-        .map_err(|_| Error::General(format!("{} is not a valid baud rate", baud_rate)))?;
-    if baud_rate == BaudRate::B0 {
+    if baud_rate == 0 {
         return Err(Error::General("baud rate cannot be 0".to_string()));
     }
+    // Rates without a B-constant are set via termios2 below.
+    let standard: Option<BaudRate> = ArbitraryBaudRate(baud_rate).try_into().ok();
 
     unsafe {
         let fd = device.as_raw_fd();
@@ -154,12 +207,14 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             | LocalFlags::PENDIN
             | LocalFlags::NOFLSH);
 
-        termios::cfsetspeed(&mut settings, baud_rate).map_err(|e| {
-            Error::General(format!(
-                "Failed to configure device baud rate: cfsetspeed = {}",
-                e
-            ))
-        })?;
+        if let Some(standard) = standard {
+            termios::cfsetspeed(&mut settings, standard).map_err(|e| {
+                Error::General(format!(
+                    "Failed to configure device baud rate: cfsetspeed = {}",
+                    e
+                ))
+            })?;
+        }
 
         settings.control_chars[CC::VTIME as usize] = 2;
         settings.control_chars[CC::VMIN as usize] = 100;
@@ -172,6 +227,34 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             ))
         })?;
 
+        let mut settings2: libc::termios2 = std::mem::zeroed();
+        ioctl::tcgets2(fd, &mut settings2).map_err(|e| {
+            Error::General(format!(
+                "Failed to read terminal settings of device: tcgets2 = {}",
+                e
+            ))
+        })?;
+        if standard.is_none() {
+            settings2.c_cflag &= !libc::CBAUD;
+            settings2.c_cflag |= libc::BOTHER;
+            settings2.c_ispeed = baud_rate;
+            settings2.c_ospeed = baud_rate;
+            ioctl::tcsets2(fd, &settings2).map_err(|e| {
+                Error::General(format!(
+                    "Failed to configure device baud rate: tcsets2 = {}",
+                    e
+                ))
+            })?;
+            // The driver rounds the rate to what its clock allows.
+            ioctl::tcgets2(fd, &mut settings2).map_err(|e| {
+                Error::General(format!(
+                    "Failed to read terminal settings of device: tcgets2 = {}",
+                    e
+                ))
+            })?;
+        }
+        check_baud_rate(baud_rate, settings2.c_ospeed)?;
+
         let mut flags: libc::c_int = 0;
         ioctl::tiocmget(fd, &mut flags).map_err(|e| {
             Error::General(format!(
@@ -205,6 +288,20 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn baud_rate_tolerance() {
+        assert!(check_baud_rate(2_250_000, 2_250_000).is_ok());
+        assert!(check_baud_rate(2_250_000, 2_240_000).is_ok());
+        assert!(matches!(
+            check_baud_rate(2_250_000, 2_000_000),
+            Err(SerialError::BaudRate {
+                requested: 2_250_000,
+                nearest: 2_000_000
+            })
+        ));
+    }
+
+    #[cfg(feature = "serial")]
     #[test]
     fn u32_to_baud_rate() {
         assert_eq!(
@@ -0,0 +1,239 @@
+//! Cross-platform backend via the `serialport` crate.
+
+use super::SerialError;
+
+use std::io::{self, Read};
+use std::time::Duration;
+
+use ::serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+/// How long a single read of the device waits for data before retrying.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// An opened and configured serial device. Reads block until data is
+/// available.
+pub struct SerialDevice {
+    port: Box<dyn SerialPort>,
+}
+
+impl std::fmt::Debug for SerialDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SerialDevice")
+            .field("name", &self.port.name())
+            .finish()
+    }
+}
+
+impl SerialDevice {
+    /// The underlying port, e.g. to query or change its settings.
+    pub fn port(&self) -> &dyn SerialPort {
+        self.port.as_ref()
+    }
+}
+
+impl Read for SerialDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.port.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Opens the given `device`, e.g. `/dev/ttyUSB0` or `COM3`, and
+/// configures it for `baud_rate` bits per second, 8N1, without flow
+/// control, with DTR and RTS asserted. Unlike `configure`, available on
+/// Linux, macOS and Windows. As with `configure`, any integer baud rate
+/// can be requested, within what the device can achieve.
+pub fn open(device: &str, baud_rate: u32) -> Result<SerialDevice, SerialError> {
+    use SerialError as Error;
+
+    if baud_rate == 0 {
+        return Err(Error::General("baud rate cannot be 0".to_string()));
+    }
+
+    let mut port = ::serialport::new(device, baud_rate)
+        .data_bits(DataBits::Eight)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .flow_control(FlowControl::None)
+        .timeout(READ_TIMEOUT)
+        .dtr_on_open(true)
+        .open()
+        .map_err(|e| Error::General(format!("Failed to open {}: {}", device, e)))?;
+    // The driver rounds the rate to what its clock allows.
+    let actual = port
+        .baud_rate()
+        .map_err(|e| Error::General(format!("Failed to read device baud rate: {}", e)))?;
+    super::check_baud_rate(baud_rate, actual)?;
+    port.write_request_to_send(true)
+        .map_err(|e| Error::General(format!("Failed to apply modem bits to device: {}", e)))?;
+
+    // Flush all pending input, just in case.
+    port.clear(::serialport::ClearBuffer::Input)
+        .map_err(|e| Error::General(format!("Failed to flush input of device: {}", e)))?;
+
+    Ok(SerialDevice { port })
+}
+
+/// USB identification of a serial device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbInfo {
+    /// Vendor ID.
+    pub vid: u16,
+
+    /// Product ID.
+    pub pid: u16,
+
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+/// A serial device available on the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Path or name of the device, e.g. `/dev/ttyACM0` or `COM3`, as
+    /// given to [`open`].
+    pub path: String,
+
+    /// USB identification, if the device is a USB device.
+    pub usb: Option<UsbInfo>,
+}
+
+impl DeviceInfo {
+    /// Whether the device is selected by `selector`: a `VID:PID` pair
+    /// in hexadecimal, e.g. `0483:374b`, or a case-insensitive substring
+    /// of the path, manufacturer, product or serial number.
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Some(usb) = &self.usb {
+            if let Some((vid, pid)) = selector.split_once(':') {
+                if let (Ok(vid), Ok(pid)) =
+                    (u16::from_str_radix(vid, 16), u16::from_str_radix(pid, 16))
+                {
+                    return usb.vid == vid && usb.pid == pid;
+                }
+            }
+        }
+        let selector = selector.to_lowercase();
+        let usb = self.usb.iter().flat_map(|usb| {
+            [&usb.manufacturer, &usb.product, &usb.serial_number]
+                .into_iter()
+                .flatten()
+        });
+        std::iter::once(&self.path)
+            .chain(usb)
+            .any(|field| field.to_lowercase().contains(&selector))
+    }
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(usb) = &self.usb {
+            write!(f, " {:04x}:{:04x}", usb.vid, usb.pid)?;
+            for field in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                write!(f, " {}", field)?;
+            }
+            if let Some(serial_number) = &usb.serial_number {
+                write!(f, " (serial number {})", serial_number)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists the serial devices available on the system.
+pub fn devices() -> Result<Vec<DeviceInfo>, SerialError> {
+    let ports = ::serialport::available_ports()
+        .map_err(|e| SerialError::General(format!("Failed to list serial devices: {}", e)))?;
+    Ok(ports
+        .into_iter()
+        .map(|port| DeviceInfo {
+            path: port.port_name,
+            usb: match port.port_type {
+                ::serialport::SerialPortType::UsbPort(usb) => Some(UsbInfo {
+                    vid: usb.vid,
+                    pid: usb.pid,
+                    manufacturer: usb.manufacturer,
+                    product: usb.product,
+                    serial_number: usb.serial_number,
+                }),
+                _ => None,
+            },
+        })
+        .collect())
+}
+
+/// Selects the single device of `devices` that [matches](DeviceInfo::matches)
+/// `selector`.
+pub fn select(devices: Vec<DeviceInfo>, selector: &str) -> Result<DeviceInfo, SerialError> {
+    let mut matches: Vec<DeviceInfo> = devices
+        .into_iter()
+        .filter(|device| device.matches(selector))
+        .collect();
+    match matches.len() {
+        0 => Err(SerialError::NoSuchDevice(selector.to_string())),
+        1 => Ok(matches.remove(0)),
+        _ => Err(SerialError::AmbiguousDevice {
+            selector: selector.to_string(),
+            matches: matches.into_iter().map(|device| device.path).collect(),
+        }),
+    }
+}
+
+/// Finds the single serial device available on the system that
+/// [matches](DeviceInfo::matches) `selector`, so that scripts need not
+/// hardcode device paths.
+pub fn find(selector: &str) -> Result<DeviceInfo, SerialError> {
+    select(devices()?, selector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(path: &str, vid: u16, pid: u16, product: &str) -> DeviceInfo {
+        DeviceInfo {
+            path: path.to_string(),
+            usb: Some(UsbInfo {
+                vid,
+                pid,
+                manufacturer: None,
+                product: Some(product.to_string()),
+                serial_number: Some("0672FF".to_string()),
+            }),
+        }
+    }
+
+    #[test]
+    fn selection() {
+        let devices = vec![
+            device("/dev/ttyACM0", 0x0483, 0x374b, "STLINK-V3"),
+            device("/dev/ttyACM1", 0x1366, 0x0105, "J-Link"),
+            DeviceInfo {
+                path: "/dev/ttyS0".to_string(),
+                usb: None,
+            },
+        ];
+        let path = |selector| select(devices.clone(), selector).map(|device| device.path);
+        assert_eq!(path("0483:374B").unwrap(), "/dev/ttyACM0");
+        assert_eq!(path("j-link").unwrap(), "/dev/ttyACM1");
+        assert_eq!(path("ttyS").unwrap(), "/dev/ttyS0");
+        assert!(matches!(
+            path("1234:5678"),
+            Err(SerialError::NoSuchDevice(_))
+        ));
+        assert!(matches!(
+            path("0672ff"),
+            Err(SerialError::AmbiguousDevice { matches, .. }) if matches.len() == 2
+        ));
+
+        assert_eq!(
+            devices[0].to_string(),
+            "/dev/ttyACM0 0483:374b STLINK-V3 (serial number 0672FF)"
+        );
+    }
+}
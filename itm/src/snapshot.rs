@@ -0,0 +1,187 @@
+//! Checkpointing of the decoder state.
+
+use super::{
+    ArchVersion, Buffer, DecoderError, DecoderErrorInt, PacketDecoder, RecoveryPolicy, Skip,
+    TracePacket,
+};
+
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use super::Decoder;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+/// The state of a decoder, from which decoding can be resumed. See
+/// [`PacketDecoder::save_state`]. With the `serde` feature, a snapshot
+/// can be persisted, e.g. to resume decoding after a restart.
+///
+/// A snapshot holds the data that was fed to or read by the decoder
+/// but not yet decoded, so decoding must be resumed with the data that
+/// follows the last fed or read byte.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecoderSnapshot {
+    /// Data yet to be decoded.
+    bytes: Vec<u8>,
+
+    /// Number of bits of the first byte of `bytes` that have already
+    /// been decoded.
+    cursor: u8,
+
+    /// Number of bits decoded before `bytes`.
+    consumed: u64,
+
+    sync: Option<usize>,
+    recovery: RecoveryPolicy,
+    arch: ArchVersion,
+    stimulus_page: Option<u8>,
+    skip: Option<Skip>,
+    resynchronized: bool,
+    peeked: Option<Result<TracePacket, DecoderError>>,
+    max_buffered: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    lenient_sync: bool,
+
+    /// See [`Decoder::save_state`].
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    ignore_eof: bool,
+}
+
+impl PacketDecoder {
+    /// Takes a snapshot of the decoder state. An annotation in
+    /// progress (see [`pull_annotated`](Self::pull_annotated)) is not
+    /// part of the snapshot.
+    pub fn save_state(&self) -> DecoderSnapshot {
+        let buffer = &self.buffer;
+        DecoderSnapshot {
+            bytes: buffer
+                .bytes
+                .iter()
+                .skip(buffer.cursor / 8)
+                .copied()
+                .collect(),
+            cursor: (buffer.cursor % 8) as u8,
+            consumed: buffer.consumed() - (buffer.cursor % 8) as u64,
+            sync: self.sync,
+            recovery: self.recovery,
+            arch: self.arch,
+            stimulus_page: self.stimulus_page,
+            skip: self.skip.clone(),
+            resynchronized: self.resynchronized,
+            peeked: self.peeked.as_ref().map(|peeked| match peeked {
+                Ok(packet) => Ok(packet.clone()),
+                Err(e) => Err(e.clone_public()),
+            }),
+            max_buffered: self.max_buffered,
+            lenient_sync: self.lenient_sync,
+            ignore_eof: false,
+        }
+    }
+
+    /// Creates a decoder that resumes decoding from a snapshot.
+    pub fn restore(snapshot: DecoderSnapshot) -> PacketDecoder {
+        let mut buffer = Buffer::new();
+        buffer.push_bytes(&snapshot.bytes);
+        buffer.cursor = snapshot.cursor.into();
+        buffer.mark = buffer.cursor;
+        buffer.dropped = snapshot.consumed;
+
+        PacketDecoder {
+            buffer,
+            sync: snapshot.sync,
+            recovery: snapshot.recovery,
+            arch: snapshot.arch,
+            stimulus_page: snapshot.stimulus_page,
+            skip: snapshot.skip,
+            resynchronized: snapshot.resynchronized,
+            peeked: snapshot
+                .peeked
+                .map(|peeked| peeked.map_err(DecoderErrorInt::from)),
+            max_buffered: snapshot.max_buffered,
+            lenient_sync: snapshot.lenient_sync,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Decoder<R>
+where
+    R: Read,
+{
+    /// Takes a snapshot of the decoder state. See
+    /// [`PacketDecoder::save_state`].
+    pub fn save_state(&self) -> DecoderSnapshot {
+        DecoderSnapshot {
+            ignore_eof: self.ignore_eof,
+            ..self.decoder.save_state()
+        }
+    }
+
+    /// Creates a decoder that resumes decoding from a snapshot, reading
+    /// from `reader`.
+    pub fn restore(reader: R, snapshot: DecoderSnapshot) -> Decoder<R> {
+        Decoder {
+            reader,
+            ignore_eof: snapshot.ignore_eof,
+            discontinuity: None,
+            decoder: PacketDecoder::restore(snapshot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DecoderOptions, PacketDecoder, RecoveryPolicy, TracePacket};
+
+    #[test]
+    fn save_and_restore() {
+        #[rustfmt::skip]
+        let stream: &[u8] = &[
+            // Overflow
+            0b0111_0000,
+            // Sync, shifted by 4 bits, followed by an Overflow
+            0, 0, 0, 0, 0, 0b0000_1000, 0b0111_0000,
+            // Invalid hardware source packet, garbage
+            0b1111_1111, 0b0000_1011,
+            // Sync
+            0, 0, 0, 0, 0, 0b1000_0000,
+        ];
+        let mut decoder = PacketDecoder::new(DecoderOptions {
+            recovery: RecoveryPolicy::SkipToSync,
+            ..Default::default()
+        });
+
+        let mut expected = vec![];
+        decoder.feed(stream);
+        while let Some(packet) = decoder.pull().transpose() {
+            expected.push(packet.map_err(|e| e.to_string()));
+        }
+
+        // Restore at every split of the stream
+        for split in 0..stream.len() {
+            let mut decoder = PacketDecoder::new(DecoderOptions {
+                recovery: RecoveryPolicy::SkipToSync,
+                ..Default::default()
+            });
+            let mut packets = vec![];
+            decoder.feed(&stream[..split]);
+            while let Some(packet) = decoder.pull().transpose() {
+                packets.push(packet.map_err(|e| e.to_string()));
+            }
+
+            let snapshot = decoder.save_state();
+            #[cfg(feature = "serde")]
+            let snapshot =
+                serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+            let mut decoder = PacketDecoder::restore(snapshot);
+            decoder.feed(&stream[split..]);
+            while let Some(packet) = decoder.pull().transpose() {
+                packets.push(packet.map_err(|e| e.to_string()));
+            }
+            assert_eq!(packets, expected, "split at {}", split);
+            assert_eq!(decoder.bit_offset(), 8 * stream.len() as u64);
+        }
+        assert_eq!(expected[0], Ok(TracePacket::Overflow));
+    }
+}
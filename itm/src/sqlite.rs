@@ -0,0 +1,282 @@
+//! SQLite trace database export.
+//!
+//! [`SqliteWriter`] writes packets, optionally timestamped, and the
+//! results of some analyses into an SQLite database, so that captures
+//! can be queried with SQL. The database has the tables:
+//!
+//! - `packets`: one row per packet, with the columns `seq`, `time_ns`
+//!   (the timestamp offset from trace clock start), `quality`, `kind`,
+//!   `port`, `comparator`, `address` (the PC value), `exception`,
+//!   `action` (`entered`, `exited` or `returned`), `payload` and `value`
+//!   (see [`Field::Value`]). Columns that do not apply to a packet are
+//!   null.
+//! - `exceptions`: one row per [`HandlerExecution`], with the columns
+//!   `exception`, `start_ns`, `end_ns`, `time_ns`, `preempted_by` (a
+//!   comma-separated list), `preempting` and `latency_ns`. Only
+//!   timestamped packets contribute.
+//! - `samples`: one row per [`PCSample`](TracePacket::PCSample) and
+//!   [`DataTracePC`](TracePacket::DataTracePC) packet, with the columns
+//!   `seq`, `time_ns`, `address` (null while sleeping) and `function`,
+//!   if a [`SymbolTable`] is given.
+//!
+//! ```
+//! use itm::sqlite::SqliteWriter;
+//! use itm::TracePacket;
+//!
+//! let mut db = SqliteWriter::new(rusqlite::Connection::open_in_memory().unwrap()).unwrap();
+//! db.row(0, &TracePacket::PCSample { pc: Some(0x100) }, None).unwrap();
+//! let conn = db.close().unwrap();
+//! let address: i64 = conn
+//!     .query_row("SELECT address FROM samples", [], |row| row.get(0))
+//!     .unwrap();
+//! assert_eq!(address, 0x100);
+//! ```
+
+use crate::analysis::exceptions::{HandlerExecution, HandlerOptions, HandlerTracker};
+use crate::symbols::SymbolTable;
+use crate::{ExceptionType, Field, Timestamp, TracePacket};
+
+use rusqlite::{params, Connection};
+
+use std::path::Path;
+
+pub use rusqlite::Error as SqliteError;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS packets (
+    seq INTEGER PRIMARY KEY,
+    time_ns INTEGER,
+    quality TEXT,
+    kind TEXT NOT NULL,
+    port INTEGER,
+    comparator INTEGER,
+    address INTEGER,
+    exception TEXT,
+    action TEXT,
+    payload BLOB,
+    value TEXT
+);
+CREATE TABLE IF NOT EXISTS exceptions (
+    exception TEXT NOT NULL,
+    start_ns INTEGER NOT NULL,
+    end_ns INTEGER NOT NULL,
+    time_ns INTEGER NOT NULL,
+    preempted_by TEXT NOT NULL,
+    preempting TEXT,
+    latency_ns INTEGER
+);
+CREATE TABLE IF NOT EXISTS samples (
+    seq INTEGER NOT NULL,
+    time_ns INTEGER,
+    address INTEGER,
+    function TEXT
+);
+";
+
+/// Writes packets and analysis results into an SQLite database. All
+/// rows are inserted in a single transaction, which is committed when
+/// the writer is [closed](Self::close). See the [module
+/// documentation](self).
+pub struct SqliteWriter {
+    conn: Connection,
+    handlers: HandlerTracker,
+    symbols: Option<SymbolTable>,
+}
+
+impl SqliteWriter {
+    /// Creates the tables, if they do not exist yet, in the database of
+    /// `conn`, e.g. as opened with [`Connection::open`].
+    pub fn new(conn: Connection) -> Result<Self, SqliteError> {
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+        Ok(Self {
+            conn,
+            handlers: HandlerTracker::new(HandlerOptions::default()),
+            symbols: None,
+        })
+    }
+
+    /// Opens, or creates, the database file at `path`. See
+    /// [`new`](Self::new).
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SqliteError> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Resolves the addresses of the `samples` table to functions.
+    pub fn symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Inserts the row of a packet, with its sequence number and
+    /// (optional) timestamp, and the rows of any analysis results it
+    /// completes.
+    pub fn row(
+        &mut self,
+        seq: u64,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> Result<(), SqliteError> {
+        let text = |s: String| if s.is_empty() { None } else { Some(s) };
+        let time_ns = timestamp.map(|ts| ts.offset().as_nanos() as i64);
+        let comparator = match packet {
+            TracePacket::DataTracePC { comparator, .. }
+            | TracePacket::DataTraceAddress { comparator, .. }
+            | TracePacket::DataTraceMatch { comparator }
+            | TracePacket::DataTraceValue { comparator, .. } => Some(*comparator),
+            _ => None,
+        };
+        let address = match packet {
+            TracePacket::PCSample { pc: Some(pc) } | TracePacket::DataTracePC { pc, .. } => {
+                Some(*pc)
+            }
+            _ => None,
+        };
+        let (exception, action) = match packet {
+            TracePacket::ExceptionTrace { exception, action } => (
+                Some(ExceptionType::from(*exception).to_string()),
+                Some(format!("{:?}", action).to_lowercase()),
+            ),
+            _ => (None, None),
+        };
+        let payload = match packet {
+            TracePacket::Instrumentation { payload, .. }
+            | TracePacket::DataTraceAddress { data: payload, .. }
+            | TracePacket::DataTraceValue { value: payload, .. } => Some(payload.to_vec()),
+            _ => None,
+        };
+        self.conn
+            .prepare_cached(
+                "INSERT INTO packets VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )?
+            .execute(params![
+                seq as i64,
+                time_ns,
+                text(Field::Quality.extract(seq, packet, timestamp)),
+                packet.kind(),
+                packet.port(),
+                comparator,
+                address,
+                exception,
+                action,
+                payload,
+                text(Field::Value.extract(seq, packet, timestamp)),
+            ])?;
+
+        if let TracePacket::PCSample { .. } | TracePacket::DataTracePC { .. } = packet {
+            let function = address
+                .zip(self.symbols.as_ref())
+                .and_then(|(address, symbols)| symbols.lookup(address));
+            self.conn
+                .prepare_cached("INSERT INTO samples VALUES (?1, ?2, ?3, ?4)")?
+                .execute(params![seq as i64, time_ns, address, function])?;
+        }
+
+        if let Some(timestamp) = timestamp {
+            self.handlers.push(packet, timestamp);
+            while let Some(execution) = self.handlers.pull() {
+                self.execution(&execution)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execution(&mut self, execution: &HandlerExecution) -> Result<(), SqliteError> {
+        let preempted_by: Vec<String> = execution
+            .preempted_by
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        self.conn
+            .prepare_cached("INSERT INTO exceptions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?
+            .execute(params![
+                execution.exception.to_string(),
+                execution.start.as_nanos() as i64,
+                execution.end.as_nanos() as i64,
+                execution.time.as_nanos() as i64,
+                preempted_by.join(","),
+                execution.preempting.map(|e| e.to_string()),
+                execution.latency.map(|l| l.as_nanos() as i64),
+            ])?;
+        Ok(())
+    }
+
+    /// Commits the inserted rows, and returns the connection.
+    pub fn close(self) -> Result<Connection, SqliteError> {
+        self.conn.execute_batch("COMMIT")?;
+        Ok(self.conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExceptionAction;
+    use std::time::Duration;
+
+    #[test]
+    fn tables() {
+        let systick = ExceptionType::from_name("SysTick").unwrap();
+        let packets = [
+            TracePacket::ExceptionTrace {
+                exception: systick.into(),
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::PCSample { pc: Some(0x104) },
+            TracePacket::ExceptionTrace {
+                exception: systick.into(),
+                action: ExceptionAction::Exited,
+            },
+            TracePacket::PCSample { pc: None },
+        ];
+        let symbols = [("SysTick".to_string(), 0x100..0x110)]
+            .into_iter()
+            .collect();
+        let mut db = SqliteWriter::new(Connection::open_in_memory().unwrap())
+            .unwrap()
+            .symbols(symbols);
+        for (seq, packet) in packets.iter().enumerate() {
+            let ts = Timestamp::Sync(Duration::from_micros(10 * seq as u64));
+            db.row(seq as u64, packet, Some(&ts)).unwrap();
+        }
+        let conn = db.close().unwrap();
+
+        let kinds: Vec<(String, Option<String>)> = conn
+            .prepare("SELECT kind, action FROM packets ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            kinds,
+            [
+                ("exception-trace".to_string(), Some("entered".to_string())),
+                ("pc-sample".to_string(), None),
+                ("exception-trace".to_string(), Some("exited".to_string())),
+                ("pc-sample".to_string(), None),
+            ]
+        );
+
+        let execution: (String, i64, i64) = conn
+            .query_row(
+                "SELECT exception, start_ns, end_ns FROM exceptions",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(execution, ("SysTick".to_string(), 0, 20_000));
+
+        let samples: Vec<(Option<i64>, Option<String>)> = conn
+            .prepare("SELECT address, function FROM samples ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            samples,
+            [(Some(0x104), Some("SysTick".to_string())), (None, None)]
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! SWO capture from ST-Link debug probes.
+//!
+//! ST-Link/V2, V2-1 and V3 probes capture SWO into a buffer of their
+//! own, which is drained over a dedicated USB bulk endpoint after the
+//! number of buffered bytes has been queried with an ST-Link command.
+//! [`open`] puts the probe in SWD debug mode and starts the capture;
+//! the captured stream is then read from the returned [`SwoCapture`]:
+//!
+//! ```no_run
+//! use itm::stlink;
+//! use itm::{Decoder, DecoderOptions};
+//!
+//! let probe = stlink::find(None)?;
+//! let capture = stlink::open(&probe, 2_000_000)?;
+//! for packet in Decoder::new(capture, DecoderOptions::default()).singles() {
+//!     println!("{:?}", packet);
+//! }
+//! # Ok::<(), stlink::StLinkError>(())
+//! ```
+//!
+//! As with [`cmsis_dap`](crate::cmsis_dap), only the probe is
+//! configured: the target must set up its TPIU for NRZ at the same bit
+//! rate.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use rusb::{Context, UsbContext};
+use thiserror::Error;
+
+/// How long a command may take to be answered.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Delay between polls of a probe that had no SWO data.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Size of the trace buffer the probe is asked to capture into.
+const TRACE_BUFFER_SIZE: u16 = 4096;
+
+/// Length of a command block.
+const COMMAND_LEN: usize = 16;
+
+const STLINK_VID: u16 = 0x0483;
+
+/// Product ID of ST-Link/V2, which uses other endpoints than later
+/// versions.
+const STLINK_V2_PID: u16 = 0x3748;
+
+/// Product IDs of ST-Link/V2-1 and V3.
+const STLINK_PIDS: [u16; 7] = [0x374b, 0x3752, 0x374d, 0x374e, 0x374f, 0x3753, 0x3754];
+
+/// Command bytes.
+const GET_CURRENT_MODE: u8 = 0xf5;
+const DFU_COMMAND: u8 = 0xf3;
+const DFU_EXIT: u8 = 0x07;
+const SWIM_COMMAND: u8 = 0xf4;
+const SWIM_EXIT: u8 = 0x01;
+const DEBUG_COMMAND: u8 = 0xf2;
+const DEBUG_ENTER_SWD: [u8; 2] = [0x30, 0xa3];
+const START_TRACE_RX: u8 = 0x40;
+const STOP_TRACE_RX: u8 = 0x41;
+const GET_TRACE_NB: u8 = 0x42;
+
+/// Modes reported by GET_CURRENT_MODE.
+const MODE_DFU: u8 = 0x00;
+const MODE_DEBUG: u8 = 0x02;
+const MODE_SWIM: u8 = 0x03;
+
+/// Status of a successful debug command.
+const STATUS_OK: u8 = 0x80;
+
+/// Possible errors on [`probes`], [`find`] and [`open`].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum StLinkError {
+    #[error("USB error: {0}")]
+    Usb(#[from] rusb::Error),
+
+    #[error("No ST-Link matches {0:?}")]
+    NoSuchProbe(String),
+
+    #[error("Several ST-Links match {selector:?}: {}", matches.join(", "))]
+    AmbiguousProbe {
+        selector: String,
+        matches: Vec<String>,
+    },
+
+    #[error("The ST-Link failed command {command:#04x} with status {status:#04x}")]
+    Failed { command: u8, status: u8 },
+
+    #[error("Malformed response to command {0:#04x}")]
+    MalformedResponse(u8),
+}
+
+/// An ST-Link connected to the system.
+#[derive(Debug, Clone)]
+pub struct ProbeInfo {
+    /// Product ID.
+    pub pid: u16,
+
+    pub serial_number: Option<String>,
+
+    device: rusb::Device<Context>,
+}
+
+impl ProbeInfo {
+    /// Whether the probe is selected by `selector`: a `VID:PID` pair in
+    /// hexadecimal, e.g. `0483:374b`, or a case-insensitive substring of
+    /// the serial number.
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Some((vid, pid)) = selector.split_once(':') {
+            if let (Ok(vid), Ok(pid)) = (u16::from_str_radix(vid, 16), u16::from_str_radix(pid, 16))
+            {
+                return vid == STLINK_VID && self.pid == pid;
+            }
+        }
+        let selector = selector.to_lowercase();
+        matches!(&self.serial_number, Some(serial_number) if serial_number.to_lowercase().contains(&selector))
+    }
+
+    /// Endpoints of the command, response and trace data.
+    fn endpoints(&self) -> (u8, u8, u8) {
+        if self.pid == STLINK_V2_PID {
+            (0x02, 0x81, 0x83)
+        } else {
+            (0x01, 0x81, 0x82)
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bus {:03} device {:03} {:04x}:{:04x} ST-Link",
+            self.device.bus_number(),
+            self.device.address(),
+            STLINK_VID,
+            self.pid
+        )?;
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, " (serial number {})", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the ST-Links connected to the system.
+pub fn probes() -> Result<Vec<ProbeInfo>, StLinkError> {
+    let mut probes = vec![];
+    for device in Context::new()?.devices()?.iter() {
+        let descriptor = device.device_descriptor()?;
+        let pid = descriptor.product_id();
+        if descriptor.vendor_id() != STLINK_VID
+            || !(pid == STLINK_V2_PID || STLINK_PIDS.contains(&pid))
+        {
+            continue;
+        }
+        // The serial number cannot be read without permissions.
+        let serial_number = device.open().ok().and_then(|handle| {
+            let language = *handle.read_languages(COMMAND_TIMEOUT).ok()?.first()?;
+            handle
+                .read_serial_number_string(language, &descriptor, COMMAND_TIMEOUT)
+                .ok()
+        });
+        probes.push(ProbeInfo {
+            pid,
+            serial_number,
+            device,
+        });
+    }
+    Ok(probes)
+}
+
+/// Selects the single probe of `probes` that [matches](ProbeInfo::matches)
+/// `selector`, or the single probe if there is no selector.
+pub fn select(probes: Vec<ProbeInfo>, selector: Option<&str>) -> Result<ProbeInfo, StLinkError> {
+    let mut matches: Vec<ProbeInfo> = probes
+        .into_iter()
+        .filter(|probe| selector.iter().all(|selector| probe.matches(selector)))
+        .collect();
+    let selector = selector.unwrap_or("").to_string();
+    match matches.len() {
+        0 => Err(StLinkError::NoSuchProbe(selector)),
+        1 => Ok(matches.remove(0)),
+        _ => Err(StLinkError::AmbiguousProbe {
+            selector,
+            matches: matches.iter().map(ProbeInfo::to_string).collect(),
+        }),
+    }
+}
+
+/// Finds the single ST-Link connected to the system that
+/// [matches](ProbeInfo::matches) `selector`, or the single ST-Link if
+/// there is no selector.
+pub fn find(selector: Option<&str>) -> Result<ProbeInfo, StLinkError> {
+    select(probes()?, selector)
+}
+
+/// A command block holding `bytes`.
+fn command_block(bytes: &[u8]) -> [u8; COMMAND_LEN] {
+    let mut command = [0; COMMAND_LEN];
+    command[..bytes.len()].copy_from_slice(bytes);
+    command
+}
+
+/// The command that starts capturing at `baud_rate`.
+fn start_trace(baud_rate: u32) -> [u8; COMMAND_LEN] {
+    let mut bytes = vec![DEBUG_COMMAND, START_TRACE_RX];
+    bytes.extend_from_slice(&TRACE_BUFFER_SIZE.to_le_bytes());
+    bytes.extend_from_slice(&baud_rate.to_le_bytes());
+    command_block(&bytes)
+}
+
+/// Checks the status `response` to `command`.
+fn check_status(command: &[u8], response: &[u8]) -> Result<(), StLinkError> {
+    match response {
+        [STATUS_OK, ..] => Ok(()),
+        [status, ..] => Err(StLinkError::Failed {
+            command: command[1],
+            status: *status,
+        }),
+        [] => Err(StLinkError::MalformedResponse(command[1])),
+    }
+}
+
+/// An ongoing SWO capture. Reads block until SWO data is available.
+/// The capture is stopped on drop.
+pub struct SwoCapture {
+    handle: rusb::DeviceHandle<Context>,
+    command: u8,
+    response: u8,
+    trace: u8,
+    buffer: Vec<u8>,
+    /// Range of `buffer` yet to be read.
+    pending: std::ops::Range<usize>,
+}
+
+impl std::fmt::Debug for SwoCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwoCapture").finish()
+    }
+}
+
+/// Opens `probe`, puts it in SWD debug mode if it is not already, and
+/// starts capturing SWO at `baud_rate`.
+pub fn open(probe: &ProbeInfo, baud_rate: u32) -> Result<SwoCapture, StLinkError> {
+    let handle = probe.device.open()?;
+    handle.claim_interface(0)?;
+    let (command, response, trace) = probe.endpoints();
+    let capture = SwoCapture {
+        handle,
+        command,
+        response,
+        trace,
+        buffer: vec![0; usize::from(TRACE_BUFFER_SIZE)],
+        pending: 0..0,
+    };
+
+    let mode = capture.transact(&command_block(&[GET_CURRENT_MODE]), 2)?;
+    match mode.first() {
+        Some(&MODE_DEBUG) => (),
+        Some(mode) => {
+            match *mode {
+                MODE_DFU => capture.send(&command_block(&[DFU_COMMAND, DFU_EXIT]))?,
+                MODE_SWIM => capture.send(&command_block(&[SWIM_COMMAND, SWIM_EXIT]))?,
+                _ => (),
+            }
+            let enter = command_block(&[DEBUG_COMMAND, DEBUG_ENTER_SWD[0], DEBUG_ENTER_SWD[1]]);
+            check_status(&enter, &capture.transact(&enter, 2)?)?;
+        }
+        None => return Err(StLinkError::MalformedResponse(GET_CURRENT_MODE)),
+    }
+
+    // A previous capture may not have been stopped.
+    let stop = command_block(&[DEBUG_COMMAND, STOP_TRACE_RX]);
+    capture.transact(&stop, 2)?;
+    let start = start_trace(baud_rate);
+    check_status(&start, &capture.transact(&start, 2)?)?;
+
+    Ok(capture)
+}
+
+impl SwoCapture {
+    /// Sends `command`, which has no response.
+    fn send(&self, command: &[u8]) -> Result<(), StLinkError> {
+        self.handle
+            .write_bulk(self.command, command, COMMAND_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Sends `command` and returns its response of `len` bytes.
+    fn transact(&self, command: &[u8], len: usize) -> Result<Vec<u8>, StLinkError> {
+        self.send(command)?;
+        let mut response = vec![0; len];
+        let n = self
+            .handle
+            .read_bulk(self.response, &mut response, COMMAND_TIMEOUT)?;
+        response.truncate(n);
+        Ok(response)
+    }
+
+    /// Fills the buffer with the SWO data the probe has captured so
+    /// far, if any.
+    fn fill(&mut self) -> Result<(), StLinkError> {
+        let response = self.transact(&command_block(&[DEBUG_COMMAND, GET_TRACE_NB]), 2)?;
+        let available = match response[..] {
+            [lo, hi] => usize::from(u16::from_le_bytes([lo, hi])),
+            _ => return Err(StLinkError::MalformedResponse(GET_TRACE_NB)),
+        };
+        if available == 0 {
+            thread::sleep(POLL_INTERVAL);
+            return Ok(());
+        }
+        let n = available.min(self.buffer.len());
+        let n = self
+            .handle
+            .read_bulk(self.trace, &mut self.buffer[..n], COMMAND_TIMEOUT)?;
+        self.pending = 0..n;
+        Ok(())
+    }
+}
+
+impl Read for SwoCapture {
+    #[allow(clippy::io_other_error)]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            self.fill()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        let n = buf.len().min(self.pending.len());
+        let start = self.pending.start;
+        buf[..n].copy_from_slice(&self.buffer[start..start + n]);
+        self.pending.start += n;
+        Ok(n)
+    }
+}
+
+impl Drop for SwoCapture {
+    fn drop(&mut self) {
+        let _ = self.transact(&command_block(&[DEBUG_COMMAND, STOP_TRACE_RX]), 2);
+        let _ = self.handle.release_interface(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            start_trace(2_000_000),
+            [0xf2, 0x40, 0x00, 0x10, 0x80, 0x84, 0x1e, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert!(check_status(&start_trace(0), &[STATUS_OK, 0]).is_ok());
+        assert!(matches!(
+            check_status(&start_trace(0), &[0x08, 0]),
+            Err(StLinkError::Failed {
+                command: START_TRACE_RX,
+                status: 0x08
+            })
+        ));
+        assert!(check_status(&start_trace(0), &[]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! [proptest](https://docs.rs/proptest) strategies that generate
+//! [`TracePacket`]s and trace streams, for property tests of code that
+//! consumes them.
+//!
+//! ```
+//! use itm::{strategy, ArchVersion, DecoderOptions, PacketDecoder};
+//! use proptest::prelude::*;
+//!
+//! proptest!(|((packets, stream) in strategy::stream(ArchVersion::V7M, 0..16))| {
+//!     let mut decoder = PacketDecoder::new(DecoderOptions::default());
+//!     decoder.feed(&stream);
+//!     let (decoded, error) = decoder.pull_all();
+//!     prop_assert!(error.is_none());
+//!     prop_assert_eq!(decoded, packets);
+//! });
+//! ```
+
+use super::{
+    ArchVersion, ExceptionAction, MemoryAccessType, Payload, TimestampDataRelation, TracePacket,
+    VectActive,
+};
+
+use proptest::collection::{vec, SizeRange};
+use proptest::prelude::*;
+
+fn payload(sizes: &'static [usize]) -> impl Strategy<Value = Payload> {
+    prop::sample::select(sizes)
+        .prop_flat_map(|len| vec(any::<u8>(), len))
+        .prop_map(|bytes| Payload::new(&bytes).unwrap())
+}
+
+fn exception() -> impl Strategy<Value = VectActive> {
+    (0..512u16).prop_filter_map("reserved exception number", VectActive::from)
+}
+
+fn action() -> impl Strategy<Value = ExceptionAction> {
+    prop_oneof![
+        Just(ExceptionAction::Entered),
+        Just(ExceptionAction::Exited),
+        Just(ExceptionAction::Returned),
+    ]
+}
+
+fn data_relation() -> impl Strategy<Value = TimestampDataRelation> {
+    prop_oneof![
+        Just(TimestampDataRelation::Sync),
+        Just(TimestampDataRelation::UnknownDelay),
+        Just(TimestampDataRelation::AssocEventDelay),
+        Just(TimestampDataRelation::UnknownAssocEventDelay),
+    ]
+}
+
+fn access_type() -> impl Strategy<Value = MemoryAccessType> {
+    prop_oneof![Just(MemoryAccessType::Read), Just(MemoryAccessType::Write)]
+}
+
+/// Generates packets that can be [encoded](TracePacket::encode) and are
+/// decoded back into the same packet for the given architecture
+/// version. [`Unknown`](TracePacket::Unknown) packets are not
+/// generated.
+pub fn packet(arch: ArchVersion) -> BoxedStrategy<TracePacket> {
+    let v8m = arch == ArchVersion::V8M;
+    let common = prop_oneof![
+        Just(TracePacket::Sync),
+        Just(TracePacket::Overflow),
+        (0..1u32 << 28, data_relation())
+            .prop_map(|(ts, data_relation)| TracePacket::LocalTimestamp1 { ts, data_relation }),
+        (1..=6u8).prop_map(|ts| TracePacket::LocalTimestamp2 { ts }),
+        (0..1u64 << 26, any::<bool>(), any::<bool>())
+            .prop_map(|(ts, wrap, clkch)| TracePacket::GlobalTimestamp1 { ts, wrap, clkch }),
+        (0..1u64 << 38).prop_map(|ts| TracePacket::GlobalTimestamp2 { ts }),
+        (if v8m { 0..=255u8 } else { 0..=7u8 }).prop_map(|page| TracePacket::Extension { page }),
+        (0..32u8, payload(&[1, 2, 4]))
+            .prop_map(|(port, payload)| TracePacket::Instrumentation { port, payload }),
+        any::<[bool; 6]>().prop_map(|[cyc, fold, lsu, sleep, exc, cpi]| {
+            TracePacket::EventCounterWrap {
+                cyc,
+                fold,
+                lsu,
+                sleep,
+                exc,
+                cpi,
+            }
+        }),
+        (exception(), action())
+            .prop_map(|(exception, action)| TracePacket::ExceptionTrace { exception, action }),
+        any::<Option<u32>>().prop_map(|pc| TracePacket::PCSample { pc }),
+        (0..4u8, any::<u32>())
+            .prop_map(|(comparator, pc)| TracePacket::DataTracePC { comparator, pc }),
+        (0..4u8, payload(if v8m { &[2, 4] } else { &[2] }))
+            .prop_map(|(comparator, data)| TracePacket::DataTraceAddress { comparator, data }),
+        (0..4u8, access_type(), payload(&[1, 2, 4])).prop_map(
+            |(comparator, access_type, value)| TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            }
+        ),
+    ];
+
+    if v8m {
+        prop_oneof![
+            14 => common,
+            1 => (0..4u8).prop_map(|comparator| TracePacket::DataTraceMatch { comparator }),
+        ]
+        .boxed()
+    } else {
+        common.boxed()
+    }
+}
+
+/// Generates sequences of [`packet`]s.
+pub fn packets(arch: ArchVersion, size: impl Into<SizeRange>) -> BoxedStrategy<Vec<TracePacket>> {
+    vec(packet(arch), size).boxed()
+}
+
+/// Generates sequences of [`packet`]s together with the trace stream
+/// they are encoded in.
+pub fn stream(
+    arch: ArchVersion,
+    size: impl Into<SizeRange>,
+) -> BoxedStrategy<(Vec<TracePacket>, Vec<u8>)> {
+    packets(arch, size)
+        .prop_map(|packets| {
+            let stream = packets.iter().flat_map(|p| p.encode().unwrap()).collect();
+            (packets, stream)
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DecoderOptions, PacketDecoder, RecoveryPolicy};
+
+    fn recovery() -> impl Strategy<Value = RecoveryPolicy> {
+        prop_oneof![
+            Just(RecoveryPolicy::Continue),
+            Just(RecoveryPolicy::SkipToSync),
+            Just(RecoveryPolicy::ReportUnknown),
+        ]
+    }
+
+    fn arch() -> impl Strategy<Value = ArchVersion> {
+        prop_oneof![Just(ArchVersion::V7M), Just(ArchVersion::V8M)]
+    }
+
+    proptest! {
+        /// Arbitrary bytes, biased towards continuation bytes, whose
+        /// runs make up overlong payloads, never panic the decoder.
+        #[test]
+        fn arbitrary_bytes(
+            bytes in vec(prop_oneof![3 => any::<u8>(), 1 => 0x80u8..], 0..1024),
+            chunk in 1usize..64,
+            recovery in recovery(),
+            arch in arch(),
+        ) {
+            for _ in crate::decode_all(&bytes) {}
+
+            let mut decoder = PacketDecoder::new(DecoderOptions {
+                recovery,
+                arch,
+                ..Default::default()
+            });
+            for chunk in bytes.chunks(chunk) {
+                decoder.feed(chunk);
+                while let Ok(Some(_)) | Err(_) = decoder.pull() {}
+            }
+        }
+
+        #[test]
+        fn round_trip_armv8m((packets, stream) in stream(ArchVersion::V8M, 0..32)) {
+            let mut decoder = PacketDecoder::new(DecoderOptions {
+                arch: ArchVersion::V8M,
+                ..Default::default()
+            });
+            decoder.feed(&stream);
+            let (decoded, error) = decoder.pull_all();
+            prop_assert!(error.is_none());
+            prop_assert_eq!(decoded, packets);
+        }
+    }
+}
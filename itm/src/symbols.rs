@@ -0,0 +1,1075 @@
+//! Resolution of addresses to the functions that contain them.
+//!
+//! A [`SymbolTable`] maps address ranges to function names, so that the
+//! program counter values of e.g. [`PCSample`](crate::TracePacket::PCSample)
+//! packets can be presented by function. It is built from a list of
+//! symbols, or, with the `elf` feature, from the symbol table of the
+//! firmware ELF file.
+//!
+//! With the `elf` feature, a [`Symbolizer`] additionally resolves
+//! addresses to [`SourceFrame`]s from the DWARF debug information of
+//! the firmware ELF file: the function, source file and line, including
+//! the frames of inlined functions.
+//!
+//! A [`VariableTable`] likewise resolves data addresses, e.g. those that
+//! DWT comparators watch, to the global variables that contain them, and
+//! by their [`VariableType`]s, to the struct member or array element,
+//! e.g. `motor_state.speed`. With the `elf` feature, it is read from the
+//! DWARF debug information of the firmware ELF file. A [`VariableType`]
+//! also [formats](VariableType::format_value) the traced data values of
+//! its variables: as signed or unsigned integers of its width, floats,
+//! enumeration variants, and structs of bit fields. Without debug
+//! information, the type of a value can be given by name, e.g. `i16`.
+//!
+//! ```
+//! use itm::symbols::SymbolTable;
+//!
+//! let symbols: SymbolTable = [
+//!     ("main".to_string(), 0x0800_0100..0x0800_0180),
+//!     ("SysTick".to_string(), 0x0800_0200..0x0800_0210),
+//! ]
+//! .into_iter()
+//! .collect();
+//! assert_eq!(symbols.lookup(0x0800_0104), Some("main"));
+//! assert_eq!(symbols.lookup(0x0800_0180), None);
+//!
+//! use itm::symbols::{Member, Variable, VariableTable, VariableType};
+//!
+//! let u16: VariableType = "u16".parse().unwrap();
+//! let variables: VariableTable = [Variable {
+//!     name: "motor_state".to_string(),
+//!     address: 0x2000_0010,
+//!     ty: VariableType::Struct {
+//!         name: "MotorState".to_string(),
+//!         size: 8,
+//!         members: vec![
+//!             Member {
+//!                 name: "speed".to_string(),
+//!                 offset: 0,
+//!                 bits: None,
+//!                 ty: u16.clone(),
+//!             },
+//!             Member {
+//!                 name: "currents".to_string(),
+//!                 offset: 2,
+//!                 bits: None,
+//!                 ty: VariableType::Array {
+//!                     element: Box::new(u16),
+//!                     len: 3,
+//!                 },
+//!             },
+//!         ],
+//!     },
+//! }]
+//! .into_iter()
+//! .collect();
+//! let variable = variables.lookup(0x2000_0016).unwrap();
+//! assert_eq!(variable.path, "motor_state.currents[2]");
+//! assert_eq!(variable.offset, 0);
+//! assert!(variables.lookup(0x2000_0018).is_none());
+//!
+//! let motor_state = variables.lookup_value(0x2000_0010, 8).unwrap();
+//! assert_eq!(motor_state.path, "motor_state");
+//! assert_eq!(
+//!     motor_state.ty.format_value(&[0xb0, 0x04, 1, 0, 2, 0, 3, 0]),
+//!     "{speed: 1200, currents: [1, 2, 3]}"
+//! );
+//! ```
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A function symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    name: String,
+    range: Range<u32>,
+}
+
+/// Function names by address range. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    /// Sorted by start address.
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function that occupies `range`. The lowest bit of
+    /// the addresses, which is set for Thumb functions, is ignored.
+    pub fn insert(&mut self, name: String, range: Range<u32>) {
+        let range = (range.start & !1)..(range.end & !1);
+        let i = self
+            .symbols
+            .partition_point(|s| s.range.start <= range.start);
+        self.symbols.insert(i, Symbol { name, range });
+    }
+
+    /// The name of the function that contains `address`, if any.
+    pub fn lookup(&self, address: u32) -> Option<&str> {
+        // Aliases share a start address, so only the symbols that start
+        // at the closest start address can contain it
+        let i = self.symbols.partition_point(|s| s.range.start <= address);
+        let start = self.symbols.get(i.checked_sub(1)?)?.range.start;
+        self.symbols[..i]
+            .iter()
+            .rev()
+            .take_while(|s| s.range.start == start)
+            .find(|s| s.range.contains(&address))
+            .map(|s| s.name.as_str())
+    }
+
+    /// The number of registered functions.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Whether no functions are registered.
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Reads the function symbols of an ELF file.
+    #[cfg(feature = "elf")]
+    pub fn from_elf(elf: &[u8]) -> Result<Self, object::Error> {
+        use object::{Object, ObjectSymbol, SymbolKind};
+
+        let file = object::File::parse(elf)?;
+        Ok(file
+            .symbols()
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.size() > 0)
+            .filter_map(|symbol| {
+                let start = u32::try_from(symbol.address()).ok()?;
+                let end = start.checked_add(u32::try_from(symbol.size()).ok()?)?;
+                Some((symbol.name().ok()?.to_string(), start..end))
+            })
+            .collect())
+    }
+}
+
+impl FromIterator<(String, Range<u32>)> for SymbolTable {
+    fn from_iter<T: IntoIterator<Item = (String, Range<u32>)>>(iter: T) -> Self {
+        let mut symbols: Vec<Symbol> = iter
+            .into_iter()
+            .map(|(name, range)| Symbol {
+                name,
+                range: (range.start & !1)..(range.end & !1),
+            })
+            .collect();
+        symbols.sort_by_key(|s| s.range.start);
+        Self { symbols }
+    }
+}
+
+/// A function frame of an address, as resolved from debug information
+/// by a [`Symbolizer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourceFrame {
+    /// The demangled name of the function, if known.
+    pub function: Option<String>,
+
+    /// The source file, if known.
+    pub file: Option<String>,
+
+    /// The line in the source file, if known.
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for SourceFrame {
+    /// Formats the frame as e.g. `main at src/main.rs:12`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.function.as_deref().unwrap_or("??"))?;
+        if let Some(file) = &self.file {
+            write!(f, " at {}", file)?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set of errors that can occur while loading an ELF file.
+#[cfg(feature = "elf")]
+#[derive(Debug, thiserror::Error)]
+pub enum ElfError {
+    /// The file is not a valid ELF file.
+    #[error("Failed to parse ELF file: {0}")]
+    Object(#[from] object::Error),
+
+    /// The debug information of the file is malformed.
+    #[error("Failed to parse DWARF debug information: {0}")]
+    Dwarf(#[from] addr2line::gimli::Error),
+}
+
+/// Resolves addresses to [`SourceFrame`]s, from the debug information
+/// of an ELF file, to functions, from its symbol table, and to
+/// variables, from its debug information. See the
+/// [module documentation](self).
+#[cfg(feature = "elf")]
+pub struct Symbolizer {
+    context: addr2line::Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    symbols: SymbolTable,
+    variables: VariableTable,
+}
+
+#[cfg(feature = "elf")]
+impl Symbolizer {
+    /// Reads the debug information and symbol table of an ELF file.
+    pub fn from_elf(elf: &[u8]) -> Result<Self, ElfError> {
+        let file = object::File::parse(elf)?;
+        Ok(Self {
+            context: addr2line::Context::new(&file)?,
+            symbols: SymbolTable::from_elf(elf)?,
+            variables: VariableTable::from_elf(elf)?,
+        })
+    }
+
+    /// The symbol table of the ELF file.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The global variables of the ELF file.
+    pub fn variables(&self) -> &VariableTable {
+        &self.variables
+    }
+
+    /// The function frames of `address`, from the innermost inlined
+    /// function to its outermost, non-inlined caller. Falls back to the
+    /// symbol table if the address is not covered by debug information.
+    /// Empty if the address could not be resolved at all.
+    pub fn frames(&self, address: u32) -> Vec<SourceFrame> {
+        let mut frames = vec![];
+        if let Ok(mut iter) = self
+            .context
+            .find_frames(u64::from(address & !1))
+            .skip_all_loads()
+        {
+            while let Ok(Some(frame)) = iter.next() {
+                let function = frame
+                    .function
+                    .as_ref()
+                    .and_then(|name| name.demangle().ok())
+                    .map(|name| name.into_owned());
+                let (file, line) = match &frame.location {
+                    Some(location) => (location.file.map(str::to_string), location.line),
+                    None => (None, None),
+                };
+                frames.push(SourceFrame {
+                    function,
+                    file,
+                    line,
+                });
+            }
+        }
+
+        match frames.first_mut() {
+            Some(SourceFrame { function: None, .. }) | None => {
+                if let Some(name) = self.symbols.lookup(address) {
+                    let name = addr2line::demangle_auto(name.into(), None).into_owned();
+                    match frames.first_mut() {
+                        Some(frame) => frame.function = Some(name),
+                        None => frames.push(SourceFrame {
+                            function: Some(name),
+                            file: None,
+                            line: None,
+                        }),
+                    }
+                }
+            }
+            Some(_) => (),
+        }
+        frames
+    }
+}
+
+/// The type of a variable, as far as it is needed to resolve addresses
+/// to struct members and array elements, and to format its values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableType {
+    /// A type whose values are formatted as hex, e.g. a pointer.
+    Scalar { name: String, size: u32 },
+
+    /// A number, boolean or character.
+    Base {
+        name: String,
+        size: u32,
+        encoding: Encoding,
+    },
+
+    /// An enumeration of the given variants and their values.
+    Enum {
+        name: String,
+        size: u32,
+        variants: Vec<(String, i64)>,
+    },
+
+    /// A struct or a union.
+    Struct {
+        name: String,
+        size: u32,
+        members: Vec<Member>,
+    },
+
+    /// An array of `len` elements.
+    Array {
+        element: Box<VariableType>,
+        len: u32,
+    },
+}
+
+impl VariableType {
+    /// The size of the type, in bytes.
+    pub fn size(&self) -> u32 {
+        match self {
+            VariableType::Scalar { size, .. }
+            | VariableType::Base { size, .. }
+            | VariableType::Enum { size, .. }
+            | VariableType::Struct { size, .. } => *size,
+            VariableType::Array { element, len } => element.size().saturating_mul(*len),
+        }
+    }
+
+    /// Whether values of the type are sign-extended.
+    fn is_signed(&self) -> bool {
+        match self {
+            VariableType::Base { encoding, .. } => *encoding == Encoding::Signed,
+            VariableType::Enum { variants, .. } => variants.iter().any(|(_, value)| *value < 0),
+            _ => false,
+        }
+    }
+
+    /// Formats a value of the type from its little-endian bytes, e.g.
+    /// `-3`, `1.5`, `Running` or `{ready: true, mode: 2}`. Members and
+    /// elements that are not wholly in `bytes` are left out.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        let size = self.size() as usize;
+        let bytes = match size {
+            0 => bytes,
+            size => &bytes[..size.min(bytes.len())],
+        };
+        let hex = || format!("{:#x}", unsigned(bytes));
+        match self {
+            VariableType::Scalar { .. } => hex(),
+            VariableType::Base { encoding, .. } => match encoding {
+                Encoding::Unsigned => unsigned(bytes).to_string(),
+                Encoding::Signed => signed(bytes).to_string(),
+                Encoding::Float => match bytes.len() {
+                    4 => f32::from_bits(unsigned(bytes) as u32).to_string(),
+                    8 => f64::from_bits(unsigned(bytes)).to_string(),
+                    _ => hex(),
+                },
+                Encoding::Boolean => (unsigned(bytes) != 0).to_string(),
+                Encoding::Char => match char::from_u32(unsigned(bytes) as u32) {
+                    Some(c) if bytes.len() > 1 || c.is_ascii() => format!("{:?}", c),
+                    _ => unsigned(bytes).to_string(),
+                },
+            },
+            VariableType::Enum { variants, .. } => {
+                let value = match self.is_signed() {
+                    true => signed(bytes),
+                    false => unsigned(bytes) as i64,
+                };
+                match variants.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            VariableType::Struct { members, .. } => {
+                let members: Vec<String> = members
+                    .iter()
+                    .filter_map(|member| {
+                        Some(format!("{}: {}", member.name, member.format_value(bytes)?))
+                    })
+                    .collect();
+                match members.is_empty() {
+                    true => hex(),
+                    false => format!("{{{}}}", members.join(", ")),
+                }
+            }
+            VariableType::Array { element, .. } => match element.size() as usize {
+                0 => hex(),
+                size => {
+                    let elements: Vec<String> = bytes
+                        .chunks_exact(size)
+                        .map(|element_bytes| element.format_value(element_bytes))
+                        .collect();
+                    format!("[{}]", elements.join(", "))
+                }
+            },
+        }
+    }
+}
+
+/// A type [named](VariableType::from_str) `u8`, `u16`, `u32` or `u64`,
+/// `i8` to `i64`, `f32`, `f64`, `bool` or `char`, e.g. `u8`, optionally
+/// followed by the variants of an enumeration of that width, e.g.
+/// `u8{Idle=0,Running=1}`.
+impl FromStr for VariableType {
+    type Err = UnknownType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || UnknownType(s.to_string());
+        let (name, variants) = match s.split_once('{') {
+            Some((name, variants)) => (name, Some(variants.strip_suffix('}').ok_or_else(unknown)?)),
+            None => (s, None),
+        };
+        let (encoding, size) = match name {
+            "u8" => (Encoding::Unsigned, 1),
+            "u16" => (Encoding::Unsigned, 2),
+            "u32" => (Encoding::Unsigned, 4),
+            "u64" => (Encoding::Unsigned, 8),
+            "i8" => (Encoding::Signed, 1),
+            "i16" => (Encoding::Signed, 2),
+            "i32" => (Encoding::Signed, 4),
+            "i64" => (Encoding::Signed, 8),
+            "f32" => (Encoding::Float, 4),
+            "f64" => (Encoding::Float, 8),
+            "bool" => (Encoding::Boolean, 1),
+            "char" => (Encoding::Char, 4),
+            _ => return Err(unknown()),
+        };
+        let name = name.to_string();
+        match variants {
+            Some(variants) if matches!(encoding, Encoding::Unsigned | Encoding::Signed) => {
+                Ok(VariableType::Enum {
+                    name,
+                    size,
+                    variants: variants
+                        .split(',')
+                        .map(|variant| {
+                            let (name, value) = variant.split_once('=')?;
+                            Some((name.trim().to_string(), value.trim().parse().ok()?))
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(unknown)?,
+                })
+            }
+            Some(_) => Err(unknown()),
+            None => Ok(VariableType::Base {
+                name,
+                size,
+                encoding,
+            }),
+        }
+    }
+}
+
+/// A type name that could not be parsed. See [`VariableType::from_str`].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Unknown type {0:?}; expected e.g. u16, i32, f32, bool, char or u8{{Idle=0,Running=1}}")]
+pub struct UnknownType(pub String);
+
+/// The encoding of a [base type](VariableType::Base).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Unsigned,
+    Signed,
+    Float,
+    Boolean,
+    Char,
+}
+
+/// Bits of a bit field [`Member`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitField {
+    /// Offset of the lowest bit from the offset of the member.
+    pub shift: u32,
+
+    /// Number of bits.
+    pub width: u32,
+}
+
+/// The little-endian unsigned value of up to 8 bytes.
+fn unsigned(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .rev()
+        .fold(0, |acc, b| acc << 8 | u64::from(*b))
+}
+
+/// The little-endian signed value of up to 8 bytes.
+fn signed(bytes: &[u8]) -> i64 {
+    let bits = 64 - 8 * bytes.len().min(8) as u32;
+    match bits {
+        64 => 0,
+        bits => ((unsigned(bytes) << bits) as i64) >> bits,
+    }
+}
+
+/// A member of a [struct](VariableType::Struct).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+
+    /// Offset of the member from the start of the struct, in bytes.
+    pub offset: u32,
+
+    /// The bits of the member from its offset, if it is a bit field.
+    pub bits: Option<BitField>,
+
+    pub ty: VariableType,
+}
+
+impl Member {
+    /// Formats the value of the member from the little-endian bytes of
+    /// its struct, if they wholly contain it.
+    fn format_value(&self, bytes: &[u8]) -> Option<String> {
+        let offset = self.offset as usize;
+        let bits = match self.bits {
+            Some(bits) => bits,
+            None => {
+                let end = offset.checked_add(self.ty.size() as usize)?;
+                return Some(self.ty.format_value(bytes.get(offset..end)?));
+            }
+        };
+        let end_bit = bits.shift + bits.width;
+        let end = offset.checked_add((end_bit / 8 + u32::from(end_bit % 8 > 0)) as usize)?;
+        let mask = match bits.width {
+            0..=63 => (1 << bits.width) - 1,
+            _ => !0,
+        };
+        let mut value = unsigned(bytes.get(offset..end)?) >> bits.shift & mask;
+        if self.ty.is_signed()
+            && (1..64).contains(&bits.width)
+            && value >> (bits.width - 1) & 1 == 1
+        {
+            value |= !mask;
+        }
+        let size = (self.ty.size() as usize).clamp(1, 8);
+        Some(self.ty.format_value(&value.to_le_bytes()[..size]))
+    }
+}
+
+/// A global variable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// The name of the variable, qualified by its namespace, if any,
+    /// e.g. `app::MOTOR_STATE`.
+    pub name: String,
+
+    pub address: u32,
+
+    pub ty: VariableType,
+}
+
+/// The innermost variable, struct member or array element that contains
+/// an address, as resolved by [`VariableTable::lookup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableRef<'a> {
+    /// The path of the member or element from its variable, e.g.
+    /// `motor_state.currents[2]`.
+    pub path: String,
+
+    pub ty: &'a VariableType,
+
+    /// Offset of the address from the start of the member or element,
+    /// in bytes.
+    pub offset: u32,
+}
+
+/// Global variables by address. See the [module documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VariableTable {
+    /// Sorted by address.
+    variables: Vec<Variable>,
+}
+
+impl VariableTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a variable. Variables at the address of a registered
+    /// variable are ignored.
+    pub fn insert(&mut self, variable: Variable) {
+        let i = self
+            .variables
+            .partition_point(|v| v.address < variable.address);
+        if self.variables.get(i).map(|v| v.address) != Some(variable.address) {
+            self.variables.insert(i, variable);
+        }
+    }
+
+    /// The innermost variable, struct member or array element that
+    /// contains `address`, if any. Bit fields are not descended into,
+    /// as they share their bytes.
+    pub fn lookup(&self, address: u32) -> Option<VariableRef<'_>> {
+        self.find(address, 0)
+    }
+
+    /// The outermost variable, struct member or array element that
+    /// starts at `address` and fits a value of `len` bytes, e.g. as
+    /// traced by a comparator, or else the [innermost](Self::lookup)
+    /// one that contains `address`, if any.
+    pub fn lookup_value(&self, address: u32, len: u32) -> Option<VariableRef<'_>> {
+        self.find(address, len)
+    }
+
+    fn find(&self, address: u32, len: u32) -> Option<VariableRef<'_>> {
+        let i = self.variables.partition_point(|v| v.address <= address);
+        let variable = self.variables.get(i.checked_sub(1)?)?;
+        let mut offset = address - variable.address;
+        // A variable of unknown or zero size only contains its address
+        if offset >= variable.ty.size().max(1) {
+            return None;
+        }
+
+        let mut path = variable.name.clone();
+        let mut ty = &variable.ty;
+        loop {
+            if offset == 0 && (1..=len).contains(&ty.size()) {
+                break;
+            }
+            match ty {
+                VariableType::Struct { members, .. } => {
+                    match members.iter().find(|m| {
+                        m.bits.is_none() && offset >= m.offset && offset - m.offset < m.ty.size()
+                    }) {
+                        Some(member) => {
+                            path.push('.');
+                            path.push_str(&member.name);
+                            offset -= member.offset;
+                            ty = &member.ty;
+                        }
+                        None => break,
+                    }
+                }
+                VariableType::Array { element, len } => {
+                    let size = element.size();
+                    if size == 0 || offset / size >= *len {
+                        break;
+                    }
+                    let _ = write!(path, "[{}]", offset / size);
+                    offset %= size;
+                    ty = element;
+                }
+                _ => break,
+            }
+        }
+        Some(VariableRef { path, ty, offset })
+    }
+
+    /// The number of registered variables.
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Whether no variables are registered.
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    /// Reads the global variables, i.e. those at fixed addresses, from
+    /// the DWARF debug information of an ELF file.
+    #[cfg(feature = "elf")]
+    pub fn from_elf(elf: &[u8]) -> Result<Self, ElfError> {
+        use addr2line::gimli;
+        use object::{Object, ObjectSection};
+        use std::borrow::Cow;
+
+        let file = object::File::parse(elf)?;
+        let endian = match file.is_little_endian() {
+            true => gimli::RunTimeEndian::Little,
+            false => gimli::RunTimeEndian::Big,
+        };
+        let sections = gimli::Dwarf::load(|id| -> Result<Cow<[u8]>, object::Error> {
+            Ok(match file.section_by_name(id.name()) {
+                Some(section) => section.uncompressed_data()?,
+                None => Cow::Borrowed(&[][..]),
+            })
+        })?;
+        let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+
+        let mut table = Self::new();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut tree = unit.entries_tree(None)?;
+            DwarfUnit {
+                dwarf: &dwarf,
+                unit: &unit,
+                types: Default::default(),
+            }
+            .walk(tree.root()?, &mut vec![], &mut table)?;
+        }
+        Ok(table)
+    }
+}
+
+impl FromIterator<Variable> for VariableTable {
+    fn from_iter<T: IntoIterator<Item = Variable>>(iter: T) -> Self {
+        let mut variables: Vec<Variable> = iter.into_iter().collect();
+        variables.sort_by_key(|v| v.address);
+        variables.dedup_by_key(|v| v.address);
+        Self { variables }
+    }
+}
+
+#[cfg(feature = "elf")]
+type DwarfReader<'a> = addr2line::gimli::EndianSlice<'a, addr2line::gimli::RunTimeEndian>;
+
+#[cfg(feature = "elf")]
+type DwarfEntry<'abbrev, 'unit, 'a> =
+    addr2line::gimli::DebuggingInformationEntry<'abbrev, 'unit, DwarfReader<'a>>;
+
+/// Nesting of types beyond which types are not descended into, so that
+/// malformed debug information cannot recurse without bound.
+#[cfg(feature = "elf")]
+const MAX_TYPE_DEPTH: usize = 32;
+
+/// Reads the variables and their types from a unit of DWARF debug
+/// information.
+#[cfg(feature = "elf")]
+struct DwarfUnit<'a, 'b> {
+    dwarf: &'b addr2line::gimli::Dwarf<DwarfReader<'a>>,
+    unit: &'b addr2line::gimli::Unit<DwarfReader<'a>>,
+    /// Types read so far, by offset.
+    types: std::collections::HashMap<addr2line::gimli::UnitOffset, VariableType>,
+}
+
+#[cfg(feature = "elf")]
+impl<'a, 'b> DwarfUnit<'a, 'b> {
+    /// Registers the variables of `node`, and of the namespaces in it.
+    fn walk(
+        &mut self,
+        node: addr2line::gimli::EntriesTreeNode<DwarfReader<'a>>,
+        namespace: &mut Vec<String>,
+        table: &mut VariableTable,
+    ) -> addr2line::gimli::Result<()> {
+        use addr2line::gimli;
+
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                gimli::DW_TAG_namespace => {
+                    let name = self.name(child.entry())?;
+                    namespace.push(name.unwrap_or_else(|| "{anon}".to_string()));
+                    self.walk(child, namespace, table)?;
+                    namespace.pop();
+                }
+                gimli::DW_TAG_variable => {
+                    if let Some(variable) = self.variable(child.entry(), namespace)? {
+                        table.insert(variable);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a variable, unless it is not at a fixed address.
+    fn variable(
+        &mut self,
+        entry: &DwarfEntry<'_, '_, 'a>,
+        namespace: &[String],
+    ) -> addr2line::gimli::Result<Option<Variable>> {
+        use addr2line::gimli;
+
+        let address = match entry.attr_value(gimli::DW_AT_location)? {
+            Some(gimli::AttributeValue::Exprloc(expression)) => {
+                let mut operations = expression.operations(self.unit.encoding());
+                match (operations.next()?, operations.next()?) {
+                    (Some(gimli::Operation::Address { address }), None) => address,
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        let (address, name) = match (u32::try_from(address), self.name(entry)?) {
+            (Ok(address), Some(name)) => (address, name),
+            _ => return Ok(None),
+        };
+        let mut path = namespace.to_vec();
+        path.push(name);
+        Ok(Some(Variable {
+            name: path.join("::"),
+            address,
+            ty: self.type_of(entry, 0)?,
+        }))
+    }
+
+    /// An attribute of `entry`, or of its declaration if it is the
+    /// definition of a declared entry.
+    fn attr(
+        &self,
+        entry: &DwarfEntry<'_, '_, 'a>,
+        name: addr2line::gimli::DwAt,
+    ) -> addr2line::gimli::Result<Option<addr2line::gimli::AttributeValue<DwarfReader<'a>>>> {
+        use addr2line::gimli;
+
+        if let Some(value) = entry.attr_value(name)? {
+            return Ok(Some(value));
+        }
+        match entry.attr_value(gimli::DW_AT_specification)? {
+            Some(gimli::AttributeValue::UnitRef(offset)) => {
+                self.unit.entry(offset)?.attr_value(name)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn name(&self, entry: &DwarfEntry<'_, '_, 'a>) -> addr2line::gimli::Result<Option<String>> {
+        match self.attr(entry, addr2line::gimli::DW_AT_name)? {
+            Some(value) => Ok(Some(
+                self.dwarf
+                    .attr_string(self.unit, value)?
+                    .to_string_lossy()
+                    .into_owned(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// The type of `entry`, e.g. a variable or a struct member.
+    fn type_of(
+        &mut self,
+        entry: &DwarfEntry<'_, '_, 'a>,
+        depth: usize,
+    ) -> addr2line::gimli::Result<VariableType> {
+        match self.attr(entry, addr2line::gimli::DW_AT_type)? {
+            Some(addr2line::gimli::AttributeValue::UnitRef(offset)) if depth < MAX_TYPE_DEPTH => {
+                self.read_type(offset, depth + 1)
+            }
+            _ => Ok(VariableType::Scalar {
+                name: String::new(),
+                size: 0,
+            }),
+        }
+    }
+
+    fn read_type(
+        &mut self,
+        offset: addr2line::gimli::UnitOffset,
+        depth: usize,
+    ) -> addr2line::gimli::Result<VariableType> {
+        use addr2line::gimli;
+
+        if let Some(ty) = self.types.get(&offset) {
+            return Ok(ty.clone());
+        }
+        let unit = self.unit;
+        let entry = unit.entry(offset)?;
+        let name = self.name(&entry)?.unwrap_or_default();
+        let size = constant(entry.attr_value(gimli::DW_AT_byte_size)?).unwrap_or(0);
+        let ty = match entry.tag() {
+            gimli::DW_TAG_base_type => match entry.attr_value(gimli::DW_AT_encoding)? {
+                Some(gimli::AttributeValue::Encoding(encoding)) => {
+                    let encoding = match encoding {
+                        gimli::DW_ATE_unsigned => Encoding::Unsigned,
+                        gimli::DW_ATE_signed => Encoding::Signed,
+                        gimli::DW_ATE_float => Encoding::Float,
+                        gimli::DW_ATE_boolean => Encoding::Boolean,
+                        gimli::DW_ATE_signed_char
+                        | gimli::DW_ATE_unsigned_char
+                        | gimli::DW_ATE_UTF => Encoding::Char,
+                        _ => Encoding::Unsigned,
+                    };
+                    VariableType::Base {
+                        name,
+                        size,
+                        encoding,
+                    }
+                }
+                _ => VariableType::Scalar { name, size },
+            },
+            gimli::DW_TAG_enumeration_type => {
+                let mut variants = vec![];
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let enumerator = child.entry();
+                    if enumerator.tag() != gimli::DW_TAG_enumerator {
+                        continue;
+                    }
+                    let value = match enumerator.attr_value(gimli::DW_AT_const_value)? {
+                        Some(gimli::AttributeValue::Sdata(value)) => value,
+                        Some(value) => match value.udata_value() {
+                            Some(value) => value as i64,
+                            None => continue,
+                        },
+                        None => continue,
+                    };
+                    variants.push((self.name(enumerator)?.unwrap_or_default(), value));
+                }
+                VariableType::Enum {
+                    name,
+                    size,
+                    variants,
+                }
+            }
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_atomic_type
+            | gimli::DW_TAG_restrict_type => self.type_of(&entry, depth)?,
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+                let mut members = vec![];
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let member = child.entry();
+                    // Static members are declared as members too
+                    if member.tag() != gimli::DW_TAG_member
+                        || member.attr_value(gimli::DW_AT_declaration)?.is_some()
+                    {
+                        continue;
+                    }
+                    let location = match member.attr_value(gimli::DW_AT_data_member_location)? {
+                        Some(gimli::AttributeValue::Exprloc(expression)) => {
+                            let mut operations = expression.operations(unit.encoding());
+                            match operations.next()? {
+                                Some(gimli::Operation::PlusConstant { value }) => {
+                                    u32::try_from(value).ok()
+                                }
+                                _ => None,
+                            }
+                        }
+                        // Members of unions have no location
+                        value => Some(constant(value).unwrap_or(0)),
+                    };
+                    let ty = self.type_of(member, depth)?;
+                    let (offset, bits) = match constant(member.attr_value(gimli::DW_AT_bit_size)?) {
+                        Some(width) => {
+                            let start = match (
+                                constant(member.attr_value(gimli::DW_AT_data_bit_offset)?),
+                                constant(member.attr_value(gimli::DW_AT_bit_offset)?),
+                            ) {
+                                (Some(start), _) => Some(start),
+                                // Before DWARF 4, the offset is of the
+                                // most significant bit from that of the
+                                // storage unit at the location
+                                (None, Some(msb)) => {
+                                    let storage =
+                                        constant(member.attr_value(gimli::DW_AT_byte_size)?)
+                                            .unwrap_or_else(|| ty.size());
+                                    (location.unwrap_or(0) * 8 + storage * 8)
+                                        .checked_sub(msb + width)
+                                }
+                                (None, None) => location.map(|location| location * 8),
+                            };
+                            match start {
+                                Some(start) => (
+                                    Some(start / 8),
+                                    Some(BitField {
+                                        shift: start % 8,
+                                        width,
+                                    }),
+                                ),
+                                None => (None, None),
+                            }
+                        }
+                        None => (location, None),
+                    };
+                    if let Some(offset) = offset {
+                        members.push(Member {
+                            name: self.name(member)?.unwrap_or_default(),
+                            offset,
+                            bits,
+                            ty,
+                        });
+                    }
+                }
+                VariableType::Struct {
+                    name,
+                    size,
+                    members,
+                }
+            }
+            gimli::DW_TAG_array_type => {
+                let element = self.type_of(&entry, depth)?;
+                let mut lens = vec![];
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let subrange = child.entry();
+                    if subrange.tag() != gimli::DW_TAG_subrange_type {
+                        continue;
+                    }
+                    let count = constant(subrange.attr_value(gimli::DW_AT_count)?);
+                    let upper = constant(subrange.attr_value(gimli::DW_AT_upper_bound)?);
+                    let lower = constant(subrange.attr_value(gimli::DW_AT_lower_bound)?);
+                    lens.push(match (count, upper) {
+                        (Some(count), _) => count,
+                        (None, Some(upper)) => (upper + 1).saturating_sub(lower.unwrap_or(0)),
+                        (None, None) => 0,
+                    });
+                }
+                // The first subrange is the outermost dimension
+                lens.into_iter()
+                    .rev()
+                    .fold(element, |element, len| VariableType::Array {
+                        element: Box::new(element),
+                        len,
+                    })
+            }
+            _ => VariableType::Scalar { name, size },
+        };
+        self.types.insert(offset, ty.clone());
+        Ok(ty)
+    }
+}
+
+/// The value of a constant attribute, if it fits 32 bits.
+#[cfg(feature = "elf")]
+fn constant(value: Option<addr2line::gimli::AttributeValue<DwarfReader<'_>>>) -> Option<u32> {
+    u32::try_from(value?.udata_value()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_values() {
+        let ty = |name: &str| name.parse::<VariableType>().unwrap();
+        assert_eq!(ty("i16").format_value(&[0xfd, 0xff]), "-3");
+        assert_eq!(ty("u16").format_value(&[0xfd, 0xff]), "65533");
+        assert_eq!(ty("f32").format_value(&1.5f32.to_le_bytes()), "1.5");
+        assert_eq!(ty("bool").format_value(&[2]), "true");
+        assert_eq!(ty("char").format_value(&[0x41, 0, 0, 0]), "'A'");
+        assert_eq!(ty("i8{Reverse=-1,Idle=0}").format_value(&[0xff]), "Reverse");
+        assert_eq!(ty("u8{Idle=0}").format_value(&[7]), "7");
+        assert_eq!(
+            "u8{Idle}".parse::<VariableType>(),
+            Err(UnknownType("u8{Idle}".to_string()))
+        );
+
+        // struct { bool ready : 1; int mode : 3; u8 level; }
+        let bit_field = |name: &str, shift, width, ty| Member {
+            name: name.to_string(),
+            offset: 0,
+            bits: Some(BitField { shift, width }),
+            ty,
+        };
+        let status = VariableType::Struct {
+            name: "status".to_string(),
+            size: 2,
+            members: vec![
+                bit_field("ready", 0, 1, ty("bool")),
+                bit_field("mode", 1, 3, ty("i32")),
+                Member {
+                    name: "level".to_string(),
+                    offset: 1,
+                    bits: None,
+                    ty: ty("u8"),
+                },
+            ],
+        };
+        assert_eq!(
+            status.format_value(&[0b1101, 42]),
+            "{ready: true, mode: -2, level: 42}"
+        );
+        assert_eq!(status.format_value(&[0b0010]), "{ready: false, mode: 1}");
+    }
+}
@@ -0,0 +1,545 @@
+//! Starting and stopping output on packet conditions.
+//!
+//! Long live captures are mostly uninteresting. A [`Trigger`] passes on
+//! only the window of packets between a start condition and a stop
+//! condition, e.g. from the entry of a `HardFault` handler, optionally
+//! preceded by a number of packets of context from before the start.
+//!
+//! A [`Condition`] is written as a packet kind, as in
+//! [`TracePacket::kind`] or by its variant name, followed by any of:
+//!
+//! - for exception traces, an exception name (see
+//!   [`ExceptionFilter`](crate::ExceptionFilter)) and an action:
+//!   `entered`, `exited` or `returned`, e.g.
+//!   `ExceptionTrace HardFault entered`;
+//! - `port N` of instrumentation packets, and `comparator N` of data
+//!   trace packets;
+//! - `payload == V` of instrumentation packets, `value == V` of data
+//!   trace values, `address == V` of data trace addresses, and
+//!   `pc == V` of PC samples and data trace PC values. Payloads and
+//!   values are compared as little-endian numbers, e.g.
+//!   `Instrumentation port 2 payload == 0xDEAD`.
+//!
+//! A [`FlightRecorder`] instead keeps the latest packets in memory, so
+//! that they can be dumped when a condition is satisfied.
+//!
+//! ```
+//! use itm::trigger::{Condition, Trigger};
+//! use itm::TracePacket;
+//! use std::collections::BTreeMap;
+//!
+//! let instrumentation = |port, payload: [u8; 2]| TracePacket::Instrumentation {
+//!     port,
+//!     payload: payload.into(),
+//! };
+//! let stop = Condition::parse("Instrumentation port 2 payload == 0xDEAD", &BTreeMap::new()).unwrap();
+//! let trigger = Trigger::new(None, Some(stop), 0);
+//! let packets: Vec<_> = trigger
+//!     .over(vec![
+//!         instrumentation(1, [0xad, 0xde]),
+//!         instrumentation(2, [0xad, 0xde]),
+//!         instrumentation(1, [0x41, 0x42]),
+//!     ])
+//!     .collect();
+//! assert_eq!(packets.len(), 2);
+//! ```
+
+use super::filter::{parse_exception, KINDS};
+use super::{
+    exception_number, DecoderError, ExceptionAction, Sequenced, TimestampedTracePackets,
+    TracePacket,
+};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// A condition that could not be parsed.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Invalid trigger condition {condition:?}: {reason}")]
+pub struct InvalidCondition {
+    pub condition: String,
+    pub reason: String,
+}
+
+/// A condition on packets. See the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    kind: &'static str,
+    exception: Option<u16>,
+    action: Option<ExceptionAction>,
+    /// Stimulus port or comparator.
+    number: Option<u8>,
+    /// Payload, value, address or PC.
+    value: Option<u64>,
+}
+
+impl Condition {
+    /// Parses a condition, resolving device interrupt names by
+    /// `irq_names`, a map of names to interrupt numbers.
+    pub fn parse(s: &str, irq_names: &BTreeMap<String, u16>) -> Result<Self, InvalidCondition> {
+        let invalid = |reason: &str| InvalidCondition {
+            condition: s.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut words = s.split_whitespace();
+        let kind = words
+            .next()
+            .ok_or_else(|| invalid("expected a packet kind"))?;
+        let kind = KINDS
+            .iter()
+            .find(|k| {
+                k.replace('-', "")
+                    .eq_ignore_ascii_case(&kind.replace('-', ""))
+            })
+            .ok_or_else(|| invalid("unknown packet kind"))?;
+        let mut condition = Condition {
+            kind,
+            exception: None,
+            action: None,
+            number: None,
+            value: None,
+        };
+
+        while let Some(word) = words.next() {
+            let applies = match word {
+                "entered" | "exited" | "returned" => {
+                    condition.action = Some(match word {
+                        "entered" => ExceptionAction::Entered,
+                        "exited" => ExceptionAction::Exited,
+                        _ => ExceptionAction::Returned,
+                    });
+                    *kind == "exception-trace"
+                }
+                "port" | "comparator" => {
+                    let number = words.next().and_then(parse_number);
+                    let number = number.filter(|n| *n <= u8::MAX as u64);
+                    condition.number =
+                        Some(number.ok_or_else(|| invalid("expected a number 0-255"))? as u8);
+                    match word {
+                        "port" => *kind == "instrumentation",
+                        _ => kind.starts_with("data-trace-"),
+                    }
+                }
+                "payload" | "value" | "address" | "pc" => {
+                    if words.next() != Some("==") {
+                        return Err(invalid("expected =="));
+                    }
+                    let value = words.next().and_then(parse_number);
+                    condition.value = Some(value.ok_or_else(|| invalid("expected a number"))?);
+                    match word {
+                        "payload" => *kind == "instrumentation",
+                        "value" => *kind == "data-trace-value",
+                        "address" => *kind == "data-trace-address",
+                        _ => *kind == "pc-sample" || *kind == "data-trace-pc",
+                    }
+                }
+                name if *kind == "exception-trace" && condition.exception.is_none() => {
+                    condition.exception = Some(
+                        parse_exception(name, irq_names)
+                            .ok_or_else(|| invalid("unknown exception"))?,
+                    );
+                    true
+                }
+                _ => return Err(invalid(&format!("unexpected {:?}", word))),
+            };
+            if !applies {
+                return Err(invalid(&format!(
+                    "{:?} does not apply to {} packets",
+                    word, kind
+                )));
+            }
+        }
+        Ok(condition)
+    }
+
+    /// Whether `packet` satisfies the condition.
+    pub fn matches(&self, packet: &TracePacket) -> bool {
+        if packet.kind() != self.kind {
+            return false;
+        }
+        let (exception, action, number, value) = match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                (Some(exception_number(exception)), Some(action), None, None)
+            }
+            TracePacket::Instrumentation { port, payload } => {
+                (None, None, Some(*port), Some(le(payload)))
+            }
+            TracePacket::PCSample { pc } => (None, None, None, pc.map(u64::from)),
+            TracePacket::DataTracePC { comparator, pc } => {
+                (None, None, Some(*comparator), Some(*pc as u64))
+            }
+            TracePacket::DataTraceAddress { comparator, data }
+            | TracePacket::DataTraceValue {
+                comparator,
+                value: data,
+                ..
+            } => (None, None, Some(*comparator), Some(le(data))),
+            TracePacket::DataTraceMatch { comparator } => (None, None, Some(*comparator), None),
+            _ => (None, None, None, None),
+        };
+        self.exception.iter().all(|e| exception == Some(*e))
+            && self.action.iter().all(|a| action == Some(a))
+            && self.number.iter().all(|n| number == Some(*n))
+            && self.value.iter().all(|v| value == Some(*v))
+    }
+}
+
+/// Parses a decimal, or `0x`-prefixed hexadecimal, number.
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// The value of little-endian bytes.
+fn le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .rev()
+        .fold(0, |value, b| value << 8 | *b as u64)
+}
+
+/// An item that a [`Trigger`] passes on or drops: a packet, a set of
+/// timestamped packets, or a result of either.
+pub trait Packets {
+    /// The packets that conditions are evaluated on.
+    fn packets(&self) -> &[TracePacket];
+
+    /// Number of bytes of the trace stream that the packets were
+    /// decoded from, as [encoded](TracePacket::encode).
+    fn encoded_len(&self) -> usize {
+        self.packets()
+            .iter()
+            .map(|packet| packet.encode().map_or(0, |bytes| bytes.len()))
+            .sum()
+    }
+}
+
+impl Packets for TracePacket {
+    fn packets(&self) -> &[TracePacket] {
+        core::slice::from_ref(self)
+    }
+}
+
+impl Packets for TimestampedTracePackets {
+    fn packets(&self) -> &[TracePacket] {
+        &self.packets
+    }
+}
+
+impl<T: Packets> Packets for Result<T, DecoderError> {
+    fn packets(&self) -> &[TracePacket] {
+        match self {
+            Ok(item) => item.packets(),
+            Err(_) => &[],
+        }
+    }
+}
+
+impl<T: Packets> Packets for Sequenced<T> {
+    fn packets(&self) -> &[TracePacket] {
+        self.item.packets()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.item.encoded_len()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Armed,
+    Triggered,
+    Stopped,
+}
+
+/// Passes on the items from the first that contains a packet that
+/// satisfies the start condition up to and including the first that
+/// contains one that satisfies the stop condition. Without a start
+/// condition, items are passed on from the first. See the [module
+/// documentation](self).
+///
+/// Items are [pushed](Self::push) in, and those passed on are
+/// [pulled](Self::pull) out in order.
+pub struct Trigger<T> {
+    start: Option<Condition>,
+    stop: Option<Condition>,
+    state: State,
+    /// Number of items before the start to pass on as context.
+    context: usize,
+    /// Items passed on, or while armed, the latest `context` items.
+    items: VecDeque<T>,
+}
+
+impl<T: Packets> Trigger<T> {
+    /// Passes on items from `start` to `stop`, with `context` items
+    /// before the start.
+    pub fn new(start: Option<Condition>, stop: Option<Condition>, context: usize) -> Self {
+        Self {
+            state: match start {
+                Some(_) => State::Armed,
+                None => State::Triggered,
+            },
+            start,
+            stop,
+            context,
+            items: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        let matches = |condition: &Option<Condition>| match condition {
+            Some(condition) => item.packets().iter().any(|p| condition.matches(p)),
+            None => false,
+        };
+        match self.state {
+            State::Armed if matches(&self.start) => {
+                self.state = match matches(&self.stop) {
+                    true => State::Stopped,
+                    false => State::Triggered,
+                };
+            }
+            State::Armed => {
+                if self.items.len() == self.context {
+                    self.items.pop_front();
+                }
+                if self.context > 0 {
+                    self.items.push_back(item);
+                }
+                return;
+            }
+            State::Triggered if matches(&self.stop) => self.state = State::Stopped,
+            State::Triggered => (),
+            State::Stopped => return,
+        }
+        self.items.push_back(item);
+    }
+
+    /// Returns the next item that is passed on, if any.
+    pub fn pull(&mut self) -> Option<T> {
+        match self.state {
+            State::Armed => None,
+            State::Triggered | State::Stopped => self.items.pop_front(),
+        }
+    }
+
+    /// Whether the stop condition has been satisfied, after which no
+    /// more items are passed on.
+    pub fn stopped(&self) -> bool {
+        self.state == State::Stopped
+    }
+
+    /// Passes on the items of `iter`, which ends once the stop condition
+    /// has been satisfied.
+    pub fn over<I>(self, iter: I) -> Triggered<I::IntoIter>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        Triggered {
+            inner: iter.into_iter(),
+            trigger: self,
+        }
+    }
+}
+
+/// Iterator of the items passed on by a [`Trigger`]. See
+/// [`Trigger::over`].
+pub struct Triggered<I: Iterator> {
+    inner: I,
+    trigger: Trigger<I::Item>,
+}
+
+impl<I: Iterator> Triggered<I> {
+    /// Returns a reference to the underlying iterator.
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<I> Iterator for Triggered<I>
+where
+    I: Iterator,
+    I::Item: Packets,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.trigger.pull() {
+                return Some(item);
+            }
+            if self.trigger.stopped() {
+                return None;
+            }
+            self.trigger.push(self.inner.next()?);
+        }
+    }
+}
+
+/// Keeps the latest items in memory, up to an age or a number of
+/// [encoded](Packets::encoded_len) bytes, like a flight recorder, so
+/// that they can be [dumped](Self::dump) after the fact, e.g. when a
+/// [`Condition`] is satisfied.
+///
+/// ```
+/// use itm::trigger::FlightRecorder;
+/// use itm::TracePacket;
+/// use std::time::Duration;
+///
+/// let mut recorder = FlightRecorder::new(Some(Duration::from_secs(1)), None);
+/// recorder.push(Duration::from_millis(0), TracePacket::Sync);
+/// recorder.push(Duration::from_millis(500), TracePacket::Overflow);
+/// recorder.push(Duration::from_millis(1200), TracePacket::Overflow);
+/// assert_eq!(recorder.dump(), [TracePacket::Overflow, TracePacket::Overflow]);
+/// assert!(recorder.is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlightRecorder<T> {
+    max_age: Option<Duration>,
+    max_bytes: Option<usize>,
+    /// Items, with the time at which they were pushed and their size.
+    items: VecDeque<(Duration, usize, T)>,
+    /// Total size of `items`.
+    bytes: usize,
+}
+
+impl<T: Packets> FlightRecorder<T> {
+    /// Keeps the items pushed within `max_age` of the latest, and up to
+    /// `max_bytes` bytes of them. The latest item is always kept.
+    pub fn new(max_age: Option<Duration>, max_bytes: Option<usize>) -> Self {
+        Self {
+            max_age,
+            max_bytes,
+            items: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Records `item` at time `at`, and forgets the items that thus
+    /// fall out of the limits. Times are relative to any fixed point,
+    /// e.g. the start of the capture, and must not decrease.
+    pub fn push(&mut self, at: Duration, item: T) {
+        let size = item.encoded_len();
+        self.items.push_back((at, size, item));
+        self.bytes += size;
+        while self.items.len() > 1 {
+            let (pushed, size, _) = &self.items[0];
+            let old = self
+                .max_age
+                .iter()
+                .any(|age| at.saturating_sub(*pushed) > *age);
+            let full = self.max_bytes.iter().any(|max| self.bytes > *max);
+            if !old && !full {
+                break;
+            }
+            self.bytes -= size;
+            self.items.pop_front();
+        }
+    }
+
+    /// Returns the recorded items, oldest first, and forgets them.
+    pub fn dump(&mut self) -> Vec<T> {
+        self.bytes = 0;
+        self.items.drain(..).map(|(_, _, item)| item).collect()
+    }
+
+    /// Number of recorded items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectActive;
+
+    fn trace(number: u16, action: ExceptionAction) -> TracePacket {
+        TracePacket::ExceptionTrace {
+            exception: VectActive::from(number).unwrap(),
+            action,
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let mut irq_names = BTreeMap::new();
+        irq_names.insert("USART1".to_string(), 37);
+        let parse = |s| Condition::parse(s, &irq_names);
+
+        let hard_fault = parse("ExceptionTrace HardFault entered").unwrap();
+        assert!(hard_fault.matches(&trace(3, ExceptionAction::Entered)));
+        assert!(!hard_fault.matches(&trace(3, ExceptionAction::Exited)));
+        assert!(!hard_fault.matches(&trace(15, ExceptionAction::Entered)));
+        assert!(parse("exception-trace USART1")
+            .unwrap()
+            .matches(&trace(16 + 37, ExceptionAction::Returned)));
+
+        let pc = parse("pc-sample pc == 0x08000000").unwrap();
+        assert!(pc.matches(&TracePacket::PCSample {
+            pc: Some(0x0800_0000)
+        }));
+        assert!(!pc.matches(&TracePacket::PCSample { pc: None }));
+
+        for invalid in [
+            "",
+            "Instrumentations",
+            "Instrumentation port 256",
+            "Instrumentation pc == 1",
+            "Instrumentation payload 1",
+            "ExceptionTrace SysTock",
+        ] {
+            assert!(parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn window() {
+        let packet = |port| TracePacket::Instrumentation {
+            port,
+            payload: [0].into(),
+        };
+        let start = Condition::parse("Instrumentation port 1", &BTreeMap::new()).unwrap();
+        let stop = Condition::parse("Instrumentation port 2", &BTreeMap::new()).unwrap();
+        let ports = |trigger: Trigger<TracePacket>, ports: &[u8]| -> Vec<u8> {
+            trigger
+                .over(ports.iter().map(|p| packet(*p)))
+                .map(|p| p.port().unwrap())
+                .collect()
+        };
+
+        let trigger = Trigger::new(Some(start.clone()), Some(stop.clone()), 2);
+        assert_eq!(ports(trigger, &[3, 4, 5, 1, 3, 2, 3]), [4, 5, 1, 3, 2]);
+        let trigger = Trigger::new(Some(start.clone()), None, 0);
+        assert_eq!(ports(trigger, &[3, 1, 2, 3]), [1, 2, 3]);
+        let trigger = Trigger::new(Some(start), Some(stop), 5);
+        assert_eq!(ports(trigger, &[3, 4]), [] as [u8; 0]);
+    }
+
+    #[test]
+    fn flight_recorder() {
+        // 2 bytes each
+        let packet = |port| TracePacket::Instrumentation {
+            port,
+            payload: [0].into(),
+        };
+        let mut recorder = FlightRecorder::new(None, Some(5));
+        for port in 0..4 {
+            recorder.push(Duration::ZERO, packet(port));
+        }
+        assert_eq!(recorder.len(), 2);
+        assert_eq!(recorder.dump(), [packet(2), packet(3)]);
+
+        // The latest item is kept regardless of the limits
+        let mut recorder = FlightRecorder::new(Some(Duration::ZERO), Some(1));
+        recorder.push(Duration::ZERO, packet(0));
+        recorder.push(Duration::from_secs(1), packet(1));
+        assert_eq!(recorder.dump(), [packet(1)]);
+    }
+}
@@ -0,0 +1,318 @@
+//! Packets grouped by category, mirroring the structure of Appendix
+//! D4 of the ARMv7-M Architecture Reference Manual.
+//!
+//! [`Packet`] holds the same information as the flat
+//! [`TracePacket`](crate::TracePacket), and converts from and into it,
+//! so that both can be used side by side during a migration. The enums
+//! of this module are `#[non_exhaustive]`, so that packets added in
+//! future versions of the specification are not a breaking change.
+//!
+//! ```
+//! use itm::v2::{HardwarePacket, Packet, SourcePacket};
+//! use itm::{DecoderOptions, PacketDecoder};
+//!
+//! let mut decoder = PacketDecoder::new(DecoderOptions::default());
+//! decoder.feed(&[0b0001_0101, 0x00]); // PC sample, sleeping
+//! let packet = Packet::from(decoder.pull().unwrap().unwrap());
+//! assert_eq!(
+//!     packet,
+//!     Packet::Source(SourcePacket::Hardware(HardwarePacket::PCSample { pc: None }))
+//! );
+//! ```
+
+use super::{
+    DwtCounterWrap, ExceptionAction, MemoryAccessType, Payload, TimestampDataRelation, TracePacket,
+    VectActive,
+};
+
+use alloc::vec::Vec;
+
+/// A packet of any category. (Appendix D4.2)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Packet {
+    /// See [`TracePacket::Sync`]. (Appendix D4.2.1)
+    Sync,
+
+    /// A protocol packet. (Appendix D4.2.2)
+    Protocol(ProtocolPacket),
+
+    /// A source packet. (Appendix D4.2.7)
+    Source(SourcePacket),
+
+    /// See [`TracePacket::Unknown`].
+    Unknown {
+        /// The first byte of the packet.
+        header: u8,
+
+        /// The bytes of the packet after the header.
+        payload: Vec<u8>,
+    },
+}
+
+/// A packet that controls the decoding of the trace stream. (Appendix
+/// D4.2.2)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ProtocolPacket {
+    /// See [`TracePacket::Overflow`]. (Appendix D4.2.3)
+    Overflow,
+
+    /// See [`TracePacket::LocalTimestamp1`]. (Appendix D4.2.4)
+    LocalTimestamp1 {
+        /// Timestamp value.
+        ts: u32,
+
+        /// Relation of `ts` to the corresponding data packet.
+        data_relation: TimestampDataRelation,
+    },
+
+    /// See [`TracePacket::LocalTimestamp2`]. (Appendix D4.2.4)
+    LocalTimestamp2 {
+        /// Timestamp value, 1-6.
+        ts: u8,
+    },
+
+    /// See [`TracePacket::GlobalTimestamp1`]. (Appendix D4.2.5)
+    GlobalTimestamp1 {
+        /// Lower-order bits of the timestamp; bits\[25:0\].
+        ts: u64,
+
+        /// Set if the higher-order bits have changed.
+        wrap: bool,
+
+        /// Set if the clock has changed.
+        clkch: bool,
+    },
+
+    /// See [`TracePacket::GlobalTimestamp2`]. (Appendix D4.2.5)
+    GlobalTimestamp2 {
+        /// Higher-order bits of the timestamp value.
+        ts: u64,
+    },
+
+    /// See [`TracePacket::Extension`]. (Appendix D4.2.6)
+    Extension {
+        /// Source port page number.
+        page: u8,
+    },
+}
+
+/// A packet that carries trace data. (Appendix D4.2.7)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SourcePacket {
+    /// See [`TracePacket::Instrumentation`]. (Appendix D4.2.8)
+    Instrumentation {
+        /// Stimulus port number.
+        port: u8,
+
+        /// Data written to the stimulus port.
+        payload: Payload,
+    },
+
+    /// A hardware source packet, generated by the DWT. (Appendix D4.3)
+    Hardware(HardwarePacket),
+}
+
+/// A packet generated by the DWT. (Appendix D4.3)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum HardwarePacket {
+    /// See [`TracePacket::EventCounterWrap`]. (Appendix D4.3.1)
+    EventCounterWrap(DwtCounterWrap),
+
+    /// See [`TracePacket::ExceptionTrace`]. (Appendix D4.3.2)
+    ExceptionTrace {
+        /// The exception.
+        exception: VectActive,
+
+        /// What happened to the exception.
+        action: ExceptionAction,
+    },
+
+    /// See [`TracePacket::PCSample`]. (Appendix D4.3.3)
+    PCSample {
+        /// The value of the PC. `None` if the processor was sleeping.
+        pc: Option<u32>,
+    },
+
+    /// See [`TracePacket::DataTracePC`]. (Appendix D4.3.4)
+    DataTracePC {
+        /// The comparator that matched.
+        comparator: u8,
+
+        /// The matched PC value.
+        pc: u32,
+    },
+
+    /// See [`TracePacket::DataTraceAddress`]. (Appendix D4.3.4)
+    DataTraceAddress {
+        /// The comparator that matched.
+        comparator: u8,
+
+        /// The matched data address.
+        data: Payload,
+    },
+
+    /// See [`TracePacket::DataTraceMatch`]. ARMv8-M only.
+    DataTraceMatch {
+        /// The comparator that matched.
+        comparator: u8,
+    },
+
+    /// See [`TracePacket::DataTraceValue`]. (Appendix D4.3.4)
+    DataTraceValue {
+        /// The comparator that matched.
+        comparator: u8,
+
+        /// Whether the data was read or written.
+        access_type: MemoryAccessType,
+
+        /// The data value.
+        value: Payload,
+    },
+}
+
+impl From<TracePacket> for Packet {
+    fn from(packet: TracePacket) -> Self {
+        use HardwarePacket as H;
+        use ProtocolPacket as P;
+
+        let hardware = |packet| Packet::Source(SourcePacket::Hardware(packet));
+        match packet {
+            TracePacket::Sync => Packet::Sync,
+            TracePacket::Overflow => Packet::Protocol(P::Overflow),
+            TracePacket::LocalTimestamp1 { ts, data_relation } => {
+                Packet::Protocol(P::LocalTimestamp1 { ts, data_relation })
+            }
+            TracePacket::LocalTimestamp2 { ts } => Packet::Protocol(P::LocalTimestamp2 { ts }),
+            TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+                Packet::Protocol(P::GlobalTimestamp1 { ts, wrap, clkch })
+            }
+            TracePacket::GlobalTimestamp2 { ts } => Packet::Protocol(P::GlobalTimestamp2 { ts }),
+            TracePacket::Extension { page } => Packet::Protocol(P::Extension { page }),
+            TracePacket::Instrumentation { port, payload } => {
+                Packet::Source(SourcePacket::Instrumentation { port, payload })
+            }
+            TracePacket::EventCounterWrap { .. } => {
+                hardware(H::EventCounterWrap(packet.counter_wrap().unwrap()))
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                hardware(H::ExceptionTrace { exception, action })
+            }
+            TracePacket::PCSample { pc } => hardware(H::PCSample { pc }),
+            TracePacket::DataTracePC { comparator, pc } => {
+                hardware(H::DataTracePC { comparator, pc })
+            }
+            TracePacket::DataTraceAddress { comparator, data } => {
+                hardware(H::DataTraceAddress { comparator, data })
+            }
+            TracePacket::DataTraceMatch { comparator } => {
+                hardware(H::DataTraceMatch { comparator })
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => hardware(H::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            }),
+            TracePacket::Unknown { header, payload } => Packet::Unknown { header, payload },
+        }
+    }
+}
+
+impl From<Packet> for TracePacket {
+    fn from(packet: Packet) -> Self {
+        use HardwarePacket as H;
+        use ProtocolPacket as P;
+
+        match packet {
+            Packet::Sync => TracePacket::Sync,
+            Packet::Protocol(packet) => match packet {
+                P::Overflow => TracePacket::Overflow,
+                P::LocalTimestamp1 { ts, data_relation } => {
+                    TracePacket::LocalTimestamp1 { ts, data_relation }
+                }
+                P::LocalTimestamp2 { ts } => TracePacket::LocalTimestamp2 { ts },
+                P::GlobalTimestamp1 { ts, wrap, clkch } => {
+                    TracePacket::GlobalTimestamp1 { ts, wrap, clkch }
+                }
+                P::GlobalTimestamp2 { ts } => TracePacket::GlobalTimestamp2 { ts },
+                P::Extension { page } => TracePacket::Extension { page },
+            },
+            Packet::Source(SourcePacket::Instrumentation { port, payload }) => {
+                TracePacket::Instrumentation { port, payload }
+            }
+            Packet::Source(SourcePacket::Hardware(packet)) => match packet {
+                H::EventCounterWrap(wraps) => wraps.into(),
+                H::ExceptionTrace { exception, action } => {
+                    TracePacket::ExceptionTrace { exception, action }
+                }
+                H::PCSample { pc } => TracePacket::PCSample { pc },
+                H::DataTracePC { comparator, pc } => TracePacket::DataTracePC { comparator, pc },
+                H::DataTraceAddress { comparator, data } => {
+                    TracePacket::DataTraceAddress { comparator, data }
+                }
+                H::DataTraceMatch { comparator } => TracePacket::DataTraceMatch { comparator },
+                H::DataTraceValue {
+                    comparator,
+                    access_type,
+                    value,
+                } => TracePacket::DataTraceValue {
+                    comparator,
+                    access_type,
+                    value,
+                },
+            },
+            Packet::Unknown { header, payload } => TracePacket::Unknown { header, payload },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let packets = [
+            TracePacket::Sync,
+            TracePacket::LocalTimestamp2 { ts: 3 },
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: [0x41].into(),
+            },
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: false,
+                sleep: false,
+                exc: false,
+                cpi: true,
+            },
+            TracePacket::DataTraceMatch { comparator: 2 },
+            TracePacket::Unknown {
+                header: 0xff,
+                payload: [1].to_vec(),
+            },
+        ];
+        for packet in packets.iter() {
+            assert_eq!(TracePacket::from(Packet::from(packet.clone())), *packet);
+        }
+
+        assert_eq!(
+            Packet::from(packets[3].clone()),
+            Packet::Source(SourcePacket::Hardware(HardwarePacket::EventCounterWrap(
+                DwtCounterWrap::CYC | DwtCounterWrap::CPI
+            )))
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Anchoring of decoded timestamps to the host wall clock.
+//!
+//! A [`Timestamp`](Timestamp) is an offset from the start of the trace
+//! clock. Given the wall-clock time at which the trace clock started,
+//! e.g. the moment the capture started, a [`WallClock`] converts
+//! timestamps to wall-clock times, so that trace data can be correlated
+//! with host-side logs.
+//!
+//! ```
+//! use itm::wall_clock::WallClock;
+//! use itm::Timestamp;
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let clock = WallClock::new(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+//! let ts = Timestamp::Sync(Duration::from_micros(1_500_250));
+//! assert_eq!(clock.rfc3339(&ts), "2020-09-13T12:26:41.500250000Z");
+//! ```
+//!
+//! With the `chrono` feature, a [`WallClock`] converts from a
+//! `chrono::DateTime` and to a `chrono::DateTime<Utc>`.
+
+use super::Timestamp;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Converts [`Timestamp`](Timestamp)s to wall-clock times. See the
+/// [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallClock {
+    /// The wall-clock time at which the trace clock started.
+    pub epoch: SystemTime,
+}
+
+impl WallClock {
+    pub fn new(epoch: SystemTime) -> Self {
+        Self { epoch }
+    }
+
+    /// Anchors the trace clock start to the current time. Suitable for
+    /// live captures, where decoding starts with the capture.
+    pub fn now() -> Self {
+        Self::new(SystemTime::now())
+    }
+
+    /// The wall-clock time of a timestamp. For timestamps of unknown
+    /// delay, the time of the current (upper bound) timestamp is
+    /// returned.
+    pub fn at(&self, timestamp: &Timestamp) -> SystemTime {
+        self.epoch + timestamp.offset()
+    }
+
+    /// The wall-clock time of a timestamp in RFC 3339 format. See
+    /// [`rfc3339`](rfc3339).
+    pub fn rfc3339(&self, timestamp: &Timestamp) -> String {
+        rfc3339(self.at(timestamp))
+    }
+
+    /// The wall-clock time of a timestamp.
+    #[cfg(feature = "chrono")]
+    pub fn date_time(&self, timestamp: &Timestamp) -> chrono::DateTime<chrono::Utc> {
+        self.at(timestamp).into()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for WallClock {
+    fn from(epoch: chrono::DateTime<Tz>) -> Self {
+        Self::new(epoch.into())
+    }
+}
+
+/// Formats a time in RFC 3339 format, in UTC with nanosecond precision,
+/// e.g. `2020-09-13T12:26:40.000000000Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    };
+    let days = secs.div_euclid(86_400);
+    let secs_of_day = secs.rem_euclid(86_400);
+
+    // Civil date from days since 1970-01-01, per
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        nanos
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn format() {
+        let cases = [
+            (0, 0, "1970-01-01T00:00:00.000000000Z"),
+            (951_782_400, 1, "2000-02-29T00:00:00.000000001Z"),
+            (1_709_251_199, 999_999_999, "2024-02-29T23:59:59.999999999Z"),
+            (4_102_444_800, 0, "2100-01-01T00:00:00.000000000Z"),
+        ];
+        for (secs, nanos, s) in cases.iter() {
+            let time = UNIX_EPOCH + Duration::new(*secs, *nanos);
+            assert_eq!(rfc3339(time), *s);
+        }
+        assert_eq!(
+            rfc3339(UNIX_EPOCH - Duration::from_nanos(1)),
+            "1969-12-31T23:59:59.999999999Z"
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Alerts on traced data values.
+//!
+//! A DWT comparator that watches a variable emits a
+//! [`DataTraceValue`](TracePacket::DataTraceValue) packet whenever the
+//! variable is accessed. A [`Watcher`] evaluates [`Watch`] expressions
+//! on these values, and raises an [`Alert`] when one fires, so that a
+//! variable can be monitored live without a separate script.
+//!
+//! An expression is `value` followed by a comparison operator (`<`,
+//! `<=`, `>`, `>=`, `==` or `!=`) and a number, or `value changed`,
+//! optionally preceded by `comparator N` to only watch comparator `N`,
+//! e.g. `comparator 1 value > 1000`. Values are read as little-endian
+//! unsigned numbers. A comparison fires when it becomes true, i.e. at
+//! the first value of a comparator that satisfies it after one that
+//! did not; `value changed` fires at each value that differs from the
+//! previous of the comparator.
+//!
+//! ```
+//! use itm::watch::{Watch, Watcher};
+//! use itm::{MemoryAccessType, TracePacket};
+//!
+//! let mut watcher = Watcher::new(vec!["comparator 1 value > 1000".parse().unwrap()]);
+//! for value in [999u16, 1200, 1300, 900, 1001] {
+//!     watcher.push(&TracePacket::DataTraceValue {
+//!         comparator: 1,
+//!         access_type: MemoryAccessType::Write,
+//!         value: value.to_le_bytes().into(),
+//!     });
+//! }
+//! let values: Vec<_> = std::iter::from_fn(|| watcher.pull())
+//!     .map(|alert| alert.value)
+//!     .collect();
+//! assert_eq!(values, [1200, 1001]);
+//! ```
+
+use super::TracePacket;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// A watch expression that could not be parsed.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Invalid watch expression {0:?}; expected e.g. \"comparator 1 value > 1000\" or \"value changed\"")]
+pub struct InvalidWatch(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Test {
+    Lt(u64),
+    Le(u64),
+    Gt(u64),
+    Ge(u64),
+    Eq(u64),
+    Ne(u64),
+    Changed,
+}
+
+/// A watch expression. See the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    expression: String,
+    comparator: Option<u8>,
+    test: Test,
+}
+
+impl FromStr for Watch {
+    type Err = InvalidWatch;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidWatch(s.to_string());
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        let comparator = match words[..] {
+            ["comparator", n, ..] => {
+                let n = n.parse().map_err(|_| invalid())?;
+                words.drain(..2);
+                Some(n)
+            }
+            _ => None,
+        };
+        let test = match words[..] {
+            ["value", "changed"] => Test::Changed,
+            ["value", op, n] => {
+                let n = match n.strip_prefix("0x").or_else(|| n.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => n.parse(),
+                }
+                .map_err(|_| invalid())?;
+                match op {
+                    "<" => Test::Lt(n),
+                    "<=" => Test::Le(n),
+                    ">" => Test::Gt(n),
+                    ">=" => Test::Ge(n),
+                    "==" => Test::Eq(n),
+                    "!=" => Test::Ne(n),
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Watch {
+            expression: words.join(" "),
+            comparator,
+            test,
+        })
+    }
+}
+
+impl Watch {
+    /// Whether the comparison holds for `value`. Always true for
+    /// `value changed`.
+    fn holds(&self, value: u64) -> bool {
+        match self.test {
+            Test::Lt(n) => value < n,
+            Test::Le(n) => value <= n,
+            Test::Gt(n) => value > n,
+            Test::Ge(n) => value >= n,
+            Test::Eq(n) => value == n,
+            Test::Ne(n) => value != n,
+            Test::Changed => true,
+        }
+    }
+}
+
+/// A fired [`Watch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// The expression of the watch, without the comparator, e.g.
+    /// `value > 1000`.
+    pub expression: String,
+
+    /// The comparator of the value.
+    pub comparator: u8,
+
+    /// The value that fired the watch.
+    pub value: u64,
+
+    /// The previous value of the comparator, if any.
+    pub previous: Option<u64>,
+}
+
+/// Writes e.g. `comparator 1 value > 1000: 1200 (was 999)`.
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "comparator {} {}: {}",
+            self.comparator, self.expression, self.value
+        )?;
+        if let Some(previous) = self.previous {
+            write!(f, " (was {})", previous)?;
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates [`Watch`] expressions on the data values of pushed
+/// packets. Packets are [pushed](Self::push) in, and alerts are
+/// [pulled](Self::pull) out in order. See the [module
+/// documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct Watcher {
+    watches: Vec<Watch>,
+    /// The latest value of each comparator.
+    values: BTreeMap<u8, u64>,
+    alerts: VecDeque<Alert>,
+}
+
+impl Watcher {
+    pub fn new(watches: Vec<Watch>) -> Self {
+        Self {
+            watches,
+            ..Self::default()
+        }
+    }
+
+    /// Evaluates the watches on the value of a
+    /// [`DataTraceValue`](TracePacket::DataTraceValue) packet. Other
+    /// packets are ignored.
+    pub fn push(&mut self, packet: &TracePacket) {
+        let (comparator, value) = match packet {
+            TracePacket::DataTraceValue {
+                comparator, value, ..
+            } => (
+                *comparator,
+                value
+                    .iter()
+                    .take(8)
+                    .rev()
+                    .fold(0, |acc, b| acc << 8 | *b as u64),
+            ),
+            _ => return,
+        };
+        let previous = self.values.insert(comparator, value);
+        for watch in self.watches.iter() {
+            if watch.comparator.iter().any(|c| *c != comparator) {
+                continue;
+            }
+            let fired = match (watch.test, previous) {
+                (Test::Changed, previous) => previous.iter().any(|p| *p != value),
+                (_, previous) => watch.holds(value) && !previous.iter().any(|p| watch.holds(*p)),
+            };
+            if fired {
+                self.alerts.push_back(Alert {
+                    expression: watch.expression.clone(),
+                    comparator,
+                    value,
+                    previous,
+                });
+            }
+        }
+    }
+
+    /// Returns the next alert, if any.
+    pub fn pull(&mut self) -> Option<Alert> {
+        self.alerts.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryAccessType;
+
+    fn value(comparator: u8, value: u8) -> TracePacket {
+        TracePacket::DataTraceValue {
+            comparator,
+            access_type: MemoryAccessType::Read,
+            value: [value].into(),
+        }
+    }
+
+    #[test]
+    fn watches() {
+        let mut watcher = Watcher::new(vec![
+            "value changed".parse().unwrap(),
+            "comparator 2 value == 0x10".parse().unwrap(),
+        ]);
+        for (comparator, v) in [(1, 1), (2, 0x10), (1, 1), (1, 2), (2, 0x10)] {
+            watcher.push(&value(comparator, v));
+        }
+        let alerts: Vec<_> = std::iter::from_fn(|| watcher.pull())
+            .map(|alert| alert.to_string())
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                "comparator 2 value == 0x10: 16",
+                "comparator 1 value changed: 2 (was 1)"
+            ]
+        );
+
+        for invalid in [
+            "",
+            "value",
+            "value > x",
+            "value => 1",
+            "value > -1",
+            "value > 0x",
+            "value > 1 and more",
+            "values > 1",
+            "value changes",
+            "comparator value changed",
+            "comparator 256 value changed",
+            "comparator 1",
+            "comparator 1 comparator 2 value changed",
+        ] {
+            assert_eq!(
+                invalid.parse::<Watch>(),
+                Err(InvalidWatch(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons() {
+        let fires = |expression: &str, values: &[u8]| -> Vec<u64> {
+            let mut watcher = Watcher::new(vec![expression.parse().unwrap()]);
+            for v in values {
+                watcher.push(&value(0, *v));
+            }
+            std::iter::from_fn(|| watcher.pull())
+                .map(|alert| alert.value)
+                .collect()
+        };
+        let values = [1, 2, 3, 2, 1, 3];
+        assert_eq!(fires("value < 2", &values), [1, 1]);
+        assert_eq!(fires("value <= 2", &values), [1, 2]);
+        assert_eq!(fires("value > 2", &values), [3, 3]);
+        assert_eq!(fires("value >= 2", &values), [2, 3]);
+        assert_eq!(fires("value == 2", &values), [2, 2]);
+        assert_eq!(fires("value != 2", &values), [1, 3, 1]);
+        assert_eq!(fires("value  ==  0X3", &values), [3, 3]);
+        assert_eq!(fires("value changed", &[1, 1, 2, 2, 1]), [2, 1]);
+
+        // Values are little-endian, of up to 8 bytes.
+        let mut watcher = Watcher::new(vec!["value == 0x0102030405060708".parse().unwrap()]);
+        watcher.push(&TracePacket::DataTraceValue {
+            comparator: 0,
+            access_type: MemoryAccessType::Write,
+            value: [8, 7, 6, 5].into(),
+        });
+        assert_eq!(watcher.pull(), None);
+
+        // Other packets are ignored.
+        let mut watcher = Watcher::new(vec!["value changed".parse().unwrap()]);
+        watcher.push(&value(0, 1));
+        watcher.push(&TracePacket::Sync);
+        watcher.push(&value(0, 1));
+        assert_eq!(watcher.pull(), None);
+    }
+}
@@ -3,7 +3,7 @@ use itm::*;
 #[test]
 fn eof() {
     let empty: &[u8] = &[];
-    let decoder = Decoder::new(empty, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(empty, DecoderOptions::default());
 
     assert!(decoder.singles().next().is_none());
 }
@@ -13,7 +13,7 @@ fn decode_sync_packet() {
     let mut trace_data: Vec<u8> = [0; 47 / 8].to_vec();
     trace_data.push(1 << 7);
 
-    let decoder = Decoder::new(trace_data.as_slice(), DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(trace_data.as_slice(), DecoderOptions::default());
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
         TracePacket::Sync
@@ -23,7 +23,7 @@ fn decode_sync_packet() {
 #[test]
 fn decode_overflow_packet() {
     let overflow: &[u8] = &[0b0111_0000];
-    let decoder = Decoder::new(overflow, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(overflow, DecoderOptions::default());
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
         TracePacket::Overflow
@@ -40,7 +40,7 @@ fn decode_local_timestamp_packets() {
         // LTS2
         0b0101_0000,
     ];
-    let mut decoder = Decoder::new(lts, DecoderOptions { ignore_eof: false }).singles();
+    let mut decoder = Decoder::new(lts, DecoderOptions::default()).singles();
 
     for packet in [
         TracePacket::LocalTimestamp1 {
@@ -80,7 +80,7 @@ fn decode_global_timestamp_packets() {
         0b1111_0100,
         0b0000_0111,
     ];
-    let mut decoder = Decoder::new(gts, DecoderOptions { ignore_eof: false }).singles();
+    let mut decoder = Decoder::new(gts, DecoderOptions::default()).singles();
 
     for packet in [
         TracePacket::GlobalTimestamp1 {
@@ -105,7 +105,7 @@ fn decode_global_timestamp_packets() {
 #[test]
 fn decode_extention_packet() {
     let ext: &[u8] = &[0b0111_1000];
-    let decoder = Decoder::new(ext, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(ext, DecoderOptions::default());
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
         TracePacket::Extension { page: 0b111 }
@@ -121,7 +121,7 @@ fn decode_instrumentation_packet() {
         0b0011_1111,
         0b1111_1111,
     ];
-    let decoder = Decoder::new(instr, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(instr, DecoderOptions::default());
 
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
@@ -133,7 +133,7 @@ fn decode_instrumentation_packet() {
                     0b0000_1111,
                     0b0011_1111,
                     0b1111_1111,
-                ].to_vec(),
+                ].into(),
         }
     );
 }
@@ -145,7 +145,7 @@ fn decode_eventcounterwrap_packet() {
             0b0000_0101,
             0b0010_1010
         ];
-    let decoder = Decoder::new(event, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(event, DecoderOptions::default());
 
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
@@ -168,7 +168,7 @@ fn decode_exceptiontrace_packet() {
             0b0010_0000,
             0b0011_0000
         ];
-    let decoder = Decoder::new(excpt, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(excpt, DecoderOptions::default());
 
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
@@ -192,7 +192,7 @@ fn decode_pcsample_packet() {
         0b0001_0101,
         0b0000_0000,
     ];
-    let mut decoder = Decoder::new(samples, DecoderOptions { ignore_eof: false }).singles();
+    let mut decoder = Decoder::new(samples, DecoderOptions::default()).singles();
 
     for packet in [
         TracePacket::PCSample {
@@ -216,7 +216,7 @@ fn decode_datatracepc_packet() {
         0b0011_1111,
         0b1111_1111,
     ];
-    let decoder = Decoder::new(pc, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(pc, DecoderOptions::default());
 
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
@@ -235,7 +235,7 @@ fn decode_datatraceaddress_packet() {
             0b0000_0011,
             0b0000_1111,
         ];
-    let decoder = Decoder::new(address, DecoderOptions { ignore_eof: false });
+    let decoder = Decoder::new(address, DecoderOptions::default());
 
     assert_eq!(
         decoder.singles().next().unwrap().unwrap(),
@@ -245,7 +245,7 @@ fn decode_datatraceaddress_packet() {
                 data: [
                     0b0000_0011,
                     0b0000_1111,
-                ].to_vec(),
+                ].into(),
         }
     );
 }
@@ -267,7 +267,7 @@ fn decode_datatracevalue_packet() {
         0b1010_1101,
         0b0000_0011,
     ];
-    let mut decoder = Decoder::new(payloads, DecoderOptions { ignore_eof: false }).singles();
+    let mut decoder = Decoder::new(payloads, DecoderOptions::default()).singles();
 
     for packet in [
         TracePacket::DataTraceValue {
@@ -279,7 +279,7 @@ fn decode_datatracevalue_packet() {
                     0b0000_1111,
                     0b0011_1111,
                     0b1111_1111,
-                ].to_vec(),
+                ].into(),
         },
         TracePacket::DataTraceValue {
             comparator: 0b10,
@@ -288,7 +288,7 @@ fn decode_datatracevalue_packet() {
                 value: [
                     0b0000_0011,
                     0b0000_1111,
-                ].to_vec(),
+                ].into(),
         },
         TracePacket::DataTraceValue {
             comparator: 0b10,
@@ -296,7 +296,7 @@ fn decode_datatracevalue_packet() {
             #[rustfmt::skip]
                 value: [
                     0b0000_0011,
-                ].to_vec(),
+                ].into(),
         },
     ]
     .iter()
@@ -305,3 +305,742 @@ fn decode_datatracevalue_packet() {
         assert_eq!(decoder.next().unwrap().unwrap(), packet);
     }
 }
+
+#[test]
+fn decode_armv8m_packets() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Extension, page 0b1_0101
+        0b1101_1000, 0b0000_0010,
+        // Data trace match, comparator 2
+        0b0110_0101, 0b0000_0001,
+        // Data trace data address, comparator 1, full address
+        0b0101_1111, 0xef, 0xbe, 0xad, 0xde,
+    ];
+    let decode = |arch| {
+        Decoder::new(
+            stream,
+            DecoderOptions {
+                arch,
+                ..Default::default()
+            },
+        )
+        .singles()
+    };
+
+    let packets = [
+        TracePacket::Extension { page: 0b1_0101 },
+        TracePacket::DataTraceMatch { comparator: 2 },
+        TracePacket::DataTraceAddress {
+            comparator: 1,
+            data: [0xef, 0xbe, 0xad, 0xde].into(),
+        },
+    ];
+    let mut decoder = decode(ArchVersion::V8M);
+    for packet in packets.iter() {
+        assert_eq!(&decoder.next().unwrap().unwrap(), packet);
+    }
+    assert!(decoder.next().is_none());
+
+    let encoded: Vec<u8> = packets.iter().flat_map(|p| p.encode().unwrap()).collect();
+    assert_eq!(encoded, stream);
+
+    // All of the above are invalid on ARMv7-M
+    assert!(decode(ArchVersion::V7M)
+        .filter_map(Result::ok)
+        .all(|packet| !matches!(
+            packet,
+            TracePacket::Extension { .. }
+                | TracePacket::DataTraceMatch { .. }
+                | TracePacket::DataTraceAddress { .. }
+        )));
+}
+
+#[test]
+fn overlong_payload() {
+    // LTS1, GTS1, GTS2 and (on ARMv8-M) Extension headers, each
+    // followed by more continuation bytes than the packet may have
+    for (header, max_len) in [
+        (0b1100_0000, 4),
+        (0b1001_0100, 4),
+        (0b1011_0100, 6),
+        (0b1000_1000, 4),
+    ] {
+        let mut stream = vec![header];
+        stream.extend([0xff; 12]);
+        stream.push(0x00);
+        let mut decoder = Decoder::new(
+            stream.as_slice(),
+            DecoderOptions {
+                arch: ArchVersion::V8M,
+                ..Default::default()
+            },
+        )
+        .singles();
+        match decoder.next().unwrap() {
+            Err(DecoderError::MalformedPacket(MalformedPacket::InvalidPayloadLength {
+                max_len: len,
+                payload,
+            })) if len == max_len && payload == vec![0xff; max_len] => (),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    // The rest of the stream is decoded as is: as 8 invalid headers,
+    // and the start of a synchronization packet
+    let mut stream = vec![0b1100_0000];
+    stream.extend([0xff; 12]);
+    stream.push(0x00);
+    let packets: Vec<_> = itm::decode_all(&stream).collect();
+    assert_eq!(packets.len(), 9);
+    assert!(packets.iter().all(Result::is_err));
+}
+
+#[test]
+fn track_stimulus_page() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x41,
+        // Extension, page 7
+        0b0111_1000,
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x42,
+        // Sync
+        0, 0, 0, 0, 0, 0b1000_0000,
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x43,
+    ];
+    let mut decoder = Decoder::new(
+        stream,
+        DecoderOptions {
+            track_stimulus_page: true,
+            ..Default::default()
+        },
+    )
+    .singles();
+
+    for packet in [
+        TracePacket::Instrumentation {
+            port: 1,
+            payload: [0x41].into(),
+        },
+        TracePacket::Extension { page: 7 },
+        TracePacket::Instrumentation {
+            port: 7 * 32 + 1,
+            payload: [0x42].into(),
+        },
+        TracePacket::Sync,
+        TracePacket::Instrumentation {
+            port: 1,
+            payload: [0x43].into(),
+        },
+    ]
+    .iter()
+    {
+        assert_eq!(&decoder.next().unwrap().unwrap(), packet);
+    }
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn stimulus_page_out_of_range() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Extension, page 8 (ARMv8-M)
+        0b1000_1000, 0x01,
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x42,
+        // Sync
+        0, 0, 0, 0, 0, 0b1000_0000,
+    ];
+    let instrumentation = TracePacket::Instrumentation {
+        port: 1,
+        payload: [0x42].into(),
+    };
+
+    for recovery in [
+        RecoveryPolicy::Continue,
+        RecoveryPolicy::SkipToSync,
+        RecoveryPolicy::ReportUnknown,
+    ] {
+        let packets: Vec<_> = Decoder::new(
+            stream,
+            DecoderOptions {
+                track_stimulus_page: true,
+                recovery,
+                arch: ArchVersion::V8M,
+                ..Default::default()
+            },
+        )
+        .singles()
+        .collect();
+        match recovery {
+            RecoveryPolicy::Continue => assert!(
+                matches!(
+                    &packets[..],
+                    [
+                        Err(DecoderError::MalformedPacket(
+                            MalformedPacket::InvalidExtension { page: 8 }
+                        )),
+                        Ok(packet),
+                        Ok(TracePacket::Sync),
+                    ] if *packet == instrumentation
+                ),
+                "{:?}",
+                packets
+            ),
+            RecoveryPolicy::SkipToSync => assert!(
+                matches!(
+                    &packets[..],
+                    [
+                        Err(DecoderError::Resynchronized {
+                            cause: MalformedPacket::InvalidExtension { page: 8 },
+                            ..
+                        }),
+                        Ok(TracePacket::Sync),
+                    ]
+                ),
+                "{:?}",
+                packets
+            ),
+            RecoveryPolicy::ReportUnknown => assert_eq!(
+                packets.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+                [
+                    TracePacket::Unknown {
+                        header: 0b1000_1000,
+                        payload: vec![0x01],
+                    },
+                    instrumentation.clone(),
+                    TracePacket::Sync,
+                ]
+            ),
+        }
+    }
+}
+
+#[test]
+fn long_sync_packet() {
+    // Far more zeros than there is stack to recurse on per bit
+    let mut stream = vec![0; 1 << 20];
+    stream.push(1 << 7);
+
+    let packets: Vec<_> = itm::decode_all(&stream).collect();
+    assert!(
+        matches!(packets[..], [Ok(TracePacket::Sync)]),
+        "{:?}",
+        packets
+    );
+}
+
+#[test]
+fn non_blocking() {
+    /// A [`std::io::Read`] that would block between chunks.
+    struct Chunks(Vec<&'static [u8]>, bool);
+
+    impl std::io::Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    // Instrumentation, port 1, 2-byte payload, split across chunks
+    let chunks = Chunks(vec![&[0b0000_1010, 0x41], &[0x42]], false);
+    let mut decoder = Decoder::new(chunks, DecoderOptions::default()).non_blocking();
+
+    assert_eq!(decoder.next().unwrap().unwrap(), Polled::NeedMoreData);
+    assert_eq!(decoder.next().unwrap().unwrap(), Polled::NeedMoreData);
+    assert_eq!(
+        decoder.next().unwrap().unwrap(),
+        Polled::Packet(TracePacket::Instrumentation {
+            port: 1,
+            payload: [0x41, 0x42].into(),
+        })
+    );
+    assert_eq!(decoder.next().unwrap().unwrap(), Polled::NeedMoreData);
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn pull_all() {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.feed(&[
+        // Overflow
+        0b0111_0000,
+        // Invalid hardware source packet
+        0b1111_1111,
+        // Overflow
+        0b0111_0000,
+        // LTS1 (truncated)
+        0b1100_0000, 0b1100_1001,
+    ]);
+
+    let (packets, error) = decoder.pull_all();
+    assert_eq!(packets, [TracePacket::Overflow]);
+    assert!(matches!(error, Some(DecoderError::MalformedPacket(_))));
+    let (packets, error) = decoder.pull_all();
+    assert_eq!(packets, [TracePacket::Overflow]);
+    assert!(error.is_none());
+}
+
+#[test]
+fn max_buffered() {
+    let mut decoder = PacketDecoder::new(DecoderOptions {
+        max_buffered: Some(8),
+        ..Default::default()
+    });
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Instrumentation, port 1, 4-byte payload
+        0b0000_1011, 0x01, 0x02, 0x03, 0x04,
+        // Instrumentation, port 1, 4-byte payload
+        0b0000_1011, 0x05, 0x06, 0x07, 0x08,
+        // A long run of zeros, then a Sync
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0b1000_0000,
+    ];
+
+    let mut packets = vec![];
+    let mut fed = 0;
+    while fed < stream.len() {
+        let n = decoder.feed(&stream[fed..]);
+        assert!(n <= 8);
+        fed += n;
+        while let Some(packet) = decoder.pull().unwrap() {
+            packets.push(packet);
+        }
+    }
+    assert_eq!(packets.len(), 3);
+    assert_eq!(packets[2], TracePacket::Sync);
+}
+
+#[test]
+fn max_buffered_below_largest_packet() {
+    let mut decoder = PacketDecoder::new(DecoderOptions {
+        max_buffered: Some(1),
+        ..Default::default()
+    });
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // GTS2, 6-byte payload
+        0b1011_0100, 0x81, 0x80, 0x80, 0x80, 0x80, 0x00,
+    ];
+
+    let mut fed = 0;
+    for _ in 0..stream.len() {
+        fed += decoder.feed(&stream[fed..]);
+        if let Some(packet) = decoder.pull().unwrap() {
+            assert!(matches!(packet, TracePacket::GlobalTimestamp2 { ts: 1 }));
+            assert_eq!(fed, stream.len());
+            return;
+        }
+    }
+    panic!("packet not decoded");
+}
+
+#[test]
+fn peek() {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    // Instrumentation, port 1, 2-byte payload (truncated)
+    decoder.feed(&[0b0000_1010, 0x41]);
+    assert_eq!(decoder.peek().unwrap(), None);
+
+    // Invalid hardware source packet
+    decoder.feed(&[0x42, 0b1111_1111]);
+    let packet = TracePacket::Instrumentation {
+        port: 1,
+        payload: [0x41, 0x42].into(),
+    };
+    assert_eq!(decoder.peek().unwrap(), Some(&packet));
+    assert_eq!(decoder.peek().unwrap(), Some(&packet));
+    let annotated = decoder.pull_annotated().unwrap();
+    assert_eq!(annotated.bytes, [0b0000_1010, 0x41, 0x42]);
+    assert_eq!(annotated.item.unwrap(), packet);
+
+    assert!(decoder.peek().is_err());
+    assert!(decoder.pull().is_err());
+    assert_eq!(decoder.pull().unwrap(), None);
+}
+
+#[test]
+fn pull_annotated() {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    #[rustfmt::skip]
+    decoder.feed(&[
+        // Overflow
+        0b0111_0000,
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x41,
+        // Invalid hardware source packet
+        0b1111_1111,
+        // Instrumentation, port 1, 2-byte payload (truncated)
+        0b0000_1010, 0x41,
+    ]);
+
+    let annotated = decoder.pull_annotated().unwrap();
+    assert_eq!(
+        (annotated.offset, annotated.bytes),
+        (0, [0b0111_0000].to_vec())
+    );
+    assert_eq!(annotated.item.unwrap(), TracePacket::Overflow);
+
+    let annotated = decoder.pull_annotated().unwrap();
+    assert_eq!(
+        (annotated.offset, annotated.bytes),
+        (1, [0b0000_1001, 0x41].to_vec())
+    );
+    assert!(annotated.item.is_ok());
+
+    let annotated = decoder.pull_annotated().unwrap();
+    assert_eq!((annotated.offset, annotated.bytes), (3, [0xff].to_vec()));
+    assert!(matches!(
+        annotated.item,
+        Err(DecoderError::MalformedPacket(
+            MalformedPacket::InvalidHardwareDisc { disc_id: 31, .. }
+        ))
+    ));
+
+    assert!(decoder.pull_annotated().is_none());
+    decoder.feed(&[0x42]);
+    let annotated = decoder.pull_annotated().unwrap();
+    assert_eq!(
+        (annotated.offset, annotated.bytes),
+        (4, [0b0000_1010, 0x41, 0x42].to_vec())
+    );
+    assert!(annotated.item.is_ok());
+}
+
+#[test]
+fn pull_with_context() {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    // Overflow, invalid hardware source packet, start of a sync packet
+    decoder.feed(&[0b0111_0000, 0b1111_1111, 0, 0]);
+    assert_eq!(
+        decoder.pull_with_context().unwrap(),
+        Some(TracePacket::Overflow)
+    );
+
+    let error = decoder.pull_with_context().unwrap_err();
+    assert_eq!((error.offset, error.state), (1, DecoderState::Decoding));
+    assert!(error
+        .to_string()
+        .contains("(at byte 1, while decoding); suggested recovery: check that"));
+
+    assert!(decoder.pull_with_context().unwrap().is_none());
+    assert_eq!(decoder.state(), DecoderState::Synchronizing);
+    // Too few zeros
+    decoder.feed(&[0b1000_0000]);
+    let error = decoder.pull_with_context().unwrap_err();
+    assert_eq!(
+        (error.offset, error.state),
+        (2, DecoderState::Synchronizing)
+    );
+    assert!(matches!(
+        error.error,
+        DecoderError::MalformedPacket(MalformedPacket::InvalidSync(_))
+    ));
+}
+
+#[test]
+fn lenient_sync() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Sync of 31 zeros
+        0, 0, 0, 0b1000_0000,
+        // Instrumentation, port 1, 1-byte payload
+        0b0000_1001, 0x41,
+    ];
+    let decode = |lenient_sync| {
+        Decoder::new(
+            stream,
+            DecoderOptions {
+                lenient_sync,
+                ..Default::default()
+            },
+        )
+        .singles()
+        .collect::<Vec<_>>()
+    };
+
+    assert!(matches!(
+        decode(false).first(),
+        Some(Err(DecoderError::MalformedPacket(
+            MalformedPacket::InvalidSync(31)
+        )))
+    ));
+    assert_eq!(
+        decode(true)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+        [
+            TracePacket::Sync,
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: [0x41].into(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn decode_all() {
+    #[rustfmt::skip]
+    let capture: &[u8] = &[
+        // Overflow
+        0b0111_0000,
+        // Invalid hardware source packet
+        0b1111_1111,
+        // LTS2, ts = 6
+        0b0110_0000,
+        // Truncated instrumentation packet
+        0b0000_1011, 0x01,
+    ];
+    let mut packets = itm::decode_all(capture);
+    assert_eq!(packets.next().unwrap().unwrap(), TracePacket::Overflow);
+    assert!(matches!(
+        packets.next(),
+        Some(Err(DecoderError::MalformedPacket(_)))
+    ));
+    assert_eq!(
+        packets.next().unwrap().unwrap(),
+        TracePacket::LocalTimestamp2 { ts: 6 }
+    );
+    assert!(packets.next().is_none());
+}
+
+#[test]
+fn skip_to_sync() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Invalid hardware source packet
+        0b1111_1111,
+        // garbage
+        0b0000_1011, 0x01, 0x02,
+        // Sync
+        0, 0, 0, 0, 0, 0b1000_0000,
+        // Overflow
+        0b0111_0000,
+    ];
+    let mut decoder = Decoder::new(
+        stream,
+        DecoderOptions {
+            recovery: RecoveryPolicy::SkipToSync,
+            ..Default::default()
+        },
+    )
+    .singles();
+
+    match decoder.next().unwrap() {
+        Err(DecoderError::Resynchronized {
+            cause: MalformedPacket::InvalidHardwareDisc { disc_id: 31, .. },
+            skipped: 3,
+        }) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+    assert_eq!(decoder.next().unwrap().unwrap(), TracePacket::Sync);
+    assert_eq!(decoder.next().unwrap().unwrap(), TracePacket::Overflow);
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn garbage() {
+    // Pseudo-random bytes (xorshift), with runs of continuation bytes
+    let mut state = 0x2545_f491_u32;
+    let mut garbage: Vec<u8> = (0..1 << 16)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    for run in garbage.chunks_mut(1000) {
+        for b in run.iter_mut().take(20) {
+            *b |= 0x80;
+        }
+    }
+    // Sync, overflow
+    let tail: &[u8] = &[0, 0, 0, 0, 0, 0b1000_0000, 0b0111_0000];
+
+    for recovery in [
+        RecoveryPolicy::Continue,
+        RecoveryPolicy::SkipToSync,
+        RecoveryPolicy::ReportUnknown,
+    ] {
+        for arch in [ArchVersion::V7M, ArchVersion::V8M] {
+            let options = || DecoderOptions {
+                recovery,
+                arch,
+                ..Default::default()
+            };
+            let stream = [garbage.as_slice(), tail].concat();
+            let packets: Vec<_> = Decoder::new(stream.as_slice(), options())
+                .singles()
+                .collect();
+            assert!(!packets.is_empty(), "{:?}", recovery);
+            if recovery == RecoveryPolicy::SkipToSync {
+                let packets: Vec<_> = packets.into_iter().rev().take(2).collect();
+                assert!(
+                    matches!(
+                        packets[..],
+                        [Ok(TracePacket::Overflow), Ok(TracePacket::Sync)]
+                    ),
+                    "{:?}",
+                    packets
+                );
+            }
+
+            // Fed in chunks that split packets
+            let mut decoder = PacketDecoder::new(options());
+            for chunk in stream.chunks(7) {
+                decoder.feed(chunk);
+                while let Ok(Some(_)) | Err(_) = decoder.pull() {}
+            }
+        }
+    }
+}
+
+#[test]
+fn resync() {
+    let mut decoder = PacketDecoder::new(DecoderOptions::default());
+    // Overflow, then the first half of an instrumentation packet
+    decoder.feed(&[0b0111_0000, 0b0000_1011, 0x41]);
+    assert_eq!(decoder.pull().unwrap(), Some(TracePacket::Overflow));
+    assert_eq!(decoder.pull().unwrap(), None);
+
+    decoder.resync();
+    #[rustfmt::skip]
+    decoder.feed(&[
+        // The middle of a packet
+        0x42, 0x43,
+        // Sync
+        0, 0, 0, 0, 0, 0b1000_0000,
+        // Overflow
+        0b0111_0000,
+    ]);
+    let (packets, error) = decoder.pull_all();
+    assert_eq!(packets, [TracePacket::Sync, TracePacket::Overflow]);
+    assert!(error.is_none());
+}
+
+#[test]
+fn report_unknown() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // Invalid header
+        0b0111_0100,
+        // Exception trace with invalid function
+        0b0000_1110, 0x0f, 0b0000_0000,
+        // Overflow
+        0b0111_0000,
+    ];
+    let mut decoder = Decoder::new(
+        stream,
+        DecoderOptions {
+            recovery: RecoveryPolicy::ReportUnknown,
+            ..Default::default()
+        },
+    )
+    .singles();
+
+    for packet in [
+        TracePacket::Unknown {
+            header: 0b0111_0100,
+            payload: vec![],
+        },
+        TracePacket::Unknown {
+            header: 0b0000_1110,
+            payload: vec![0x0f, 0b0000_0000],
+        },
+        TracePacket::Overflow,
+    ] {
+        assert_eq!(decoder.next().unwrap().unwrap(), packet);
+    }
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn report_unknown_overlong_payload() {
+    // LTS1 with more continuation bytes than the packet may have,
+    // overflow
+    let mut stream = vec![0b1100_0000];
+    stream.extend([0xff; 6]);
+    stream.push(0b0111_0000);
+    let packets: Vec<_> = Decoder::new(
+        stream.as_slice(),
+        DecoderOptions {
+            recovery: RecoveryPolicy::ReportUnknown,
+            ..Default::default()
+        },
+    )
+    .singles()
+    .collect::<Result<_, _>>()
+    .unwrap();
+    assert_eq!(
+        packets,
+        [
+            TracePacket::Unknown {
+                header: 0b1100_0000,
+                payload: vec![0xff; 4],
+            },
+            TracePacket::Unknown {
+                header: 0xff,
+                payload: vec![],
+            },
+            TracePacket::Unknown {
+                header: 0xff,
+                payload: vec![],
+            },
+            TracePacket::Overflow,
+        ]
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let stream: &[u8] = &[
+        // Instrumentation, port 1, 2-byte payload
+        0b0000_1010,
+        0x41,
+        0x42,
+        // Invalid hardware source packet
+        0b1111_1111,
+    ];
+    let mut decoder = Decoder::new(stream, DecoderOptions::default()).singles();
+
+    let packet = decoder.next().unwrap().unwrap();
+    let json = serde_json::to_string(&packet).unwrap();
+    assert_eq!(serde_json::from_str::<TracePacket>(&json).unwrap(), packet);
+
+    let error = decoder.next().unwrap().unwrap_err();
+    let json = serde_json::to_string(&error).unwrap();
+    match serde_json::from_str::<DecoderError>(&json).unwrap() {
+        DecoderError::MalformedPacket(MalformedPacket::InvalidHardwareDisc {
+            disc_id: 31,
+            size: 3,
+        }) => (),
+        res => panic!("unexpected {:?}", res),
+    }
+
+    let error = DecoderError::Io(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "early eof",
+    ));
+    let json = serde_json::to_string(&error).unwrap();
+    assert_eq!(json, r#"{"Io":"early eof"}"#);
+    match serde_json::from_str::<DecoderError>(&json).unwrap() {
+        DecoderError::Io(io) => assert_eq!(io.to_string(), "early eof"),
+        res => panic!("unexpected {:?}", res),
+    }
+}